@@ -0,0 +1,66 @@
+// Audio frame receive cost: the library parser, which copies each payload
+// into its own buffer before decoding, against `frame::parse_audio`, which
+// keeps the socket's buffer and decodes straight from it. Frames are 2 MiB of
+// synthetic s24le audio, fed as fresh Vecs the way the socket hands them
+// over. Run with `cargo bench --bench frame_parse`; prints throughput per
+// path and how much faster the zero-copy one is.
+
+use sendspin::protocol::client::BinaryFrame;
+use sendspin_rs_cli::frame::{self, AUDIO_CHUNK};
+use sendspin_rs_cli::pcm_layout::PcmLayout;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const FRAMES: usize = 200;
+const PAYLOAD: usize = 2 * 1024 * 1024 - 2 * 1024 * 1024 % 6; // Whole stereo s24le frames
+
+fn frames() -> Vec<Vec<u8>> {
+    (0..FRAMES)
+        .map(|i| {
+            let mut frame = vec![AUDIO_CHUNK];
+            frame.extend_from_slice(&(i as i64 * 10_000).to_be_bytes());
+            frame.extend((0..PAYLOAD).map(|b| (b * 31 + i) as u8));
+            frame
+        })
+        .collect()
+}
+
+/// Time `receive` over a fresh copy of the stream (copying it isn't timed)
+fn measure(mut receive: impl FnMut(Vec<u8>) -> usize) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    for frame in frames() {
+        let start = Instant::now();
+        black_box(receive(frame));
+        elapsed += start.elapsed();
+    }
+    elapsed
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let mib = (FRAMES * PAYLOAD) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<12} {:>8.2?}  {:>8.0} MiB/s",
+        name,
+        elapsed,
+        mib / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let copied = measure(|data| match BinaryFrame::from_bytes(&data) {
+        Ok(BinaryFrame::Audio(chunk)) => PcmLayout::S24le.decode(&chunk.data).len(),
+        _ => 0,
+    });
+    report("copied", copied);
+
+    let zero_copy = measure(|data| match frame::parse_audio(data) {
+        Ok(chunk) => PcmLayout::S24le.decode(&chunk.data).len(),
+        Err(_) => 0,
+    });
+    report("zero-copy", zero_copy);
+
+    println!(
+        "zero-copy is {:.2}x the copied path",
+        copied.as_secs_f64() / zero_copy.as_secs_f64()
+    );
+}
@@ -0,0 +1,179 @@
+// Audio hot path: what every chunk costs between the socket and the device,
+// measured with criterion so a change can be compared against a saved
+// baseline. Each group runs over realistic chunk sizes (5, 10, 20 and 40 ms
+// of 48 kHz stereo):
+//
+// - decode: PCM 16- and 24-bit to Samples, into a fresh buffer and a pooled one
+// - volume: gain into a copy (how it used to be done) against in place
+// - frame_parse: the library parser against the zero-copy one
+// - queue: buffers through the player's ring with the consumer on another thread
+//
+// Run with `cargo bench --bench hot_path`; `-- --save-baseline before` and
+// later `-- --baseline before` compare two versions.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sendspin::audio::{AudioBuffer, AudioFormat, Codec, Sample};
+use sendspin::protocol::client::BinaryFrame;
+use sendspin_rs_cli::frame::{self, AUDIO_CHUNK};
+use sendspin_rs_cli::pcm_layout::PcmLayout;
+use sendspin_rs_cli::pool::{in_place, SamplePool};
+use sendspin_rs_cli::ring;
+use sendspin_rs_cli::volume::apply_gain;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: usize = 48000;
+const CHANNELS: usize = 2;
+const CHUNK_MS: [usize; 4] = [5, 10, 20, 40];
+
+/// Interleaved samples in a chunk this long
+fn samples_in(ms: usize) -> usize {
+    SAMPLE_RATE * ms / 1000 * CHANNELS
+}
+
+/// A chunk of PCM bytes in `layout`
+fn pcm(layout: PcmLayout, ms: usize) -> Vec<u8> {
+    let bytes = match layout {
+        PcmLayout::S16le => 2,
+        PcmLayout::S24le => 3,
+        PcmLayout::S24_4le | PcmLayout::S32le => 4,
+    };
+    (0..samples_in(ms) * bytes)
+        .map(|b| (b * 31) as u8)
+        .collect()
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for ms in CHUNK_MS {
+        group.throughput(Throughput::Elements(samples_in(ms) as u64));
+        for (name, layout) in [("s16le", PcmLayout::S16le), ("s24le", PcmLayout::S24le)] {
+            let data = pcm(layout, ms);
+            group.bench_with_input(BenchmarkId::new(name, ms), &data, |b, data| {
+                b.iter(|| layout.decode(black_box(data)))
+            });
+            let pool = SamplePool::new();
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}_pooled", name), ms),
+                &data,
+                |b, data| {
+                    b.iter(|| {
+                        let samples = layout.decode_pooled(black_box(data), &pool);
+                        pool.give(black_box(samples));
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn volume(c: &mut Criterion) {
+    let mut group = c.benchmark_group("volume");
+    for ms in CHUNK_MS {
+        group.throughput(Throughput::Elements(samples_in(ms) as u64));
+        let chunk: Vec<Sample> = (0..samples_in(ms) as i32).map(Sample).collect();
+        group.bench_with_input(BenchmarkId::new("copy", ms), &chunk, |b, chunk| {
+            b.iter(|| {
+                let mut scaled = chunk.clone();
+                apply_gain(&mut scaled, black_box(0.3));
+                Arc::<[Sample]>::from(scaled)
+            })
+        });
+        let pool = SamplePool::new();
+        let mut buffer = Some(pool.copy_from(&chunk));
+        group.bench_function(BenchmarkId::new("in_place", ms), |b| {
+            b.iter(|| {
+                let samples = buffer.take().unwrap();
+                buffer = Some(in_place(samples, &pool, |s| apply_gain(s, black_box(0.3))));
+            })
+        });
+    }
+    group.finish();
+}
+
+fn frame_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_parse");
+    for ms in CHUNK_MS {
+        let mut message = vec![AUDIO_CHUNK];
+        message.extend_from_slice(&1_000_000i64.to_be_bytes());
+        message.extend(pcm(PcmLayout::S24le, ms));
+        group.throughput(Throughput::Bytes(message.len() as u64));
+        group.bench_with_input(BenchmarkId::new("copied", ms), &message, |b, message| {
+            b.iter_batched(
+                || message.clone(),
+                |data| match BinaryFrame::from_bytes(&data) {
+                    Ok(BinaryFrame::Audio(chunk)) => chunk.data.len(),
+                    _ => 0,
+                },
+                criterion::BatchSize::SmallInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("zero_copy", ms), &message, |b, message| {
+            b.iter_batched(
+                || message.clone(),
+                |data| frame::parse_audio(data).map_or(0, |chunk| chunk.data.len()),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Push `count` buffers through a ring to a consumer thread, waiting on
+/// either side when it's full or empty, as the network task and playback
+/// thread do
+fn through_ring(buffer: &AudioBuffer, count: usize) -> Duration {
+    let (mut tx, mut rx) = ring::ring(64);
+    let consumer = std::thread::spawn(move || {
+        let mut received = 0;
+        while received < count {
+            match rx.pop() {
+                Some(buffer) => {
+                    black_box(buffer);
+                    received += 1;
+                }
+                None => std::hint::spin_loop(),
+            }
+        }
+    });
+    let start = Instant::now();
+    for _ in 0..count {
+        let mut item = buffer.clone();
+        while let Err(rejected) = tx.push(item) {
+            item = rejected;
+            std::hint::spin_loop();
+        }
+    }
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+fn queue(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue");
+    for ms in CHUNK_MS {
+        let buffer = AudioBuffer {
+            timestamp: 0,
+            play_at: Instant::now(),
+            samples: (0..samples_in(ms) as i32).map(Sample).collect(),
+            format: AudioFormat {
+                codec: Codec::Pcm,
+                sample_rate: SAMPLE_RATE as u32,
+                channels: CHANNELS as u8,
+                bit_depth: 24,
+                codec_header: None,
+            },
+        };
+        group.throughput(Throughput::Elements(1));
+        group.bench_with_input(
+            BenchmarkId::new("enqueue_dequeue", ms),
+            &buffer,
+            |b, buffer| b.iter_custom(|iters| through_ring(buffer, iters as usize)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, volume, frame_parse, queue);
+criterion_main!(benches);
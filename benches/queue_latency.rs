@@ -0,0 +1,65 @@
+// Queue hand-off latency: the ring the player uses now against the
+// Mutex<VecDeque> it replaced, with a consumer polling like the playback
+// thread while a producer queues items. Run with `cargo bench --bench
+// queue_latency`; prints the median and 99th percentile per implementation.
+
+use sendspin_rs_cli::ring;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const ITEMS: usize = 20_000;
+const INTERVAL: Duration = Duration::from_micros(50);
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let median = latencies[latencies.len() / 2];
+    let p99 = latencies[latencies.len() * 99 / 100];
+    println!("{:<16} median {:>8.2?}  p99 {:>8.2?}", name, median, p99);
+}
+
+/// Producer queues a timestamp every INTERVAL; `take` is polled until it
+/// yields one, and the time since it was queued is recorded
+fn measure(
+    push: impl FnMut(Instant) + Send + 'static,
+    mut take: impl FnMut() -> Option<Instant>,
+) -> Vec<Duration> {
+    let mut push = push;
+    let producer = std::thread::spawn(move || {
+        for _ in 0..ITEMS {
+            push(Instant::now());
+            let until = Instant::now() + INTERVAL;
+            while Instant::now() < until {
+                std::hint::spin_loop();
+            }
+        }
+    });
+    let mut latencies = Vec::with_capacity(ITEMS);
+    while latencies.len() < ITEMS {
+        match take() {
+            Some(queued) => latencies.push(queued.elapsed()),
+            None => std::hint::spin_loop(),
+        }
+    }
+    producer.join().unwrap();
+    latencies
+}
+
+fn main() {
+    let queue = Arc::new(Mutex::new(VecDeque::new()));
+    let writer = Arc::clone(&queue);
+    let latencies = measure(
+        move |at| writer.lock().unwrap().push_back(at),
+        || queue.lock().unwrap().pop_front(),
+    );
+    report("Mutex<VecDeque>", latencies);
+
+    let (mut tx, mut rx) = ring::ring(4096);
+    let latencies = measure(
+        move |at| {
+            let _ = tx.push(at);
+        },
+        || rx.pop(),
+    );
+    report("SPSC ring", latencies);
+}
@@ -0,0 +1,258 @@
+// Artwork Reassembly
+//
+// An image can arrive split over several artwork binary frames on the same
+// channel. Chunks are concatenated per channel until the image is complete
+// (end marker for JPEG/PNG, declared length for WebP) or an empty
+// terminating chunk arrives, then the whole image is emitted with its
+// detected content type. Channels are assembled independently, so
+// interleaved frames can't mix.
+
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Images larger than this are dropped instead of buffered
+const MAX_IMAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Image format detected from the file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ContentType {
+    /// Detect the format from the first bytes of an image
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ContentType::Jpeg)
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ContentType::Png)
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(ContentType::Webp)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ContentType::Jpeg => "image/jpeg",
+            ContentType::Png => "image/png",
+            ContentType::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContentType::Jpeg => "jpg",
+            ContentType::Png => "png",
+            ContentType::Webp => "webp",
+        }
+    }
+
+    /// Whether `data` holds a whole image of this format
+    fn is_complete(&self, data: &[u8]) -> bool {
+        match self {
+            ContentType::Jpeg => data.len() > 4 && data.ends_with(&[0xFF, 0xD9]),
+            // IEND chunk type followed by its 4-byte CRC
+            ContentType::Png => {
+                data.len() >= 12 && &data[data.len() - 8..data.len() - 4] == b"IEND"
+            }
+            // RIFF size counts everything after the 8-byte header
+            ContentType::Webp => {
+                let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+                data.len() >= size + 8
+            }
+        }
+    }
+}
+
+/// A fully reassembled image
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub channel: u8,
+    pub timestamp: i64,
+    pub content_type: ContentType,
+    pub data: Vec<u8>,
+}
+
+struct Pending {
+    timestamp: i64,
+    data: Vec<u8>,
+}
+
+/// Per-channel chunk reassembly
+#[derive(Default)]
+pub struct ArtworkAssembler {
+    pending: HashMap<u8, Pending>,
+}
+
+impl ArtworkAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a chunk, returning the image once it is complete
+    pub fn push(&mut self, channel: u8, timestamp: i64, data: &[u8]) -> Option<Artwork> {
+        // An empty chunk terminates the image (or clears the channel)
+        if data.is_empty() {
+            let pending = self.pending.remove(&channel)?;
+            return Self::finish(channel, pending);
+        }
+
+        // A new timestamp starts a new image; drop any unfinished one
+        let pending = self.pending.entry(channel).or_insert_with(|| Pending {
+            timestamp,
+            data: Vec::new(),
+        });
+        if pending.timestamp != timestamp {
+            debug!(
+                "Artwork channel {}: discarding incomplete image ({} bytes)",
+                channel,
+                pending.data.len()
+            );
+            pending.timestamp = timestamp;
+            pending.data.clear();
+        }
+
+        if pending.data.len() + data.len() > MAX_IMAGE_BYTES {
+            debug!("Artwork channel {}: image too large, dropping", channel);
+            self.pending.remove(&channel);
+            return None;
+        }
+        pending.data.extend_from_slice(data);
+
+        let complete = ContentType::detect(&pending.data)
+            .is_some_and(|content_type| content_type.is_complete(&pending.data));
+        if complete {
+            let pending = self.pending.remove(&channel)?;
+            return Self::finish(channel, pending);
+        }
+        None
+    }
+
+    fn finish(channel: u8, pending: Pending) -> Option<Artwork> {
+        match ContentType::detect(&pending.data) {
+            Some(content_type) => Some(Artwork {
+                channel,
+                timestamp: pending.timestamp,
+                content_type,
+                data: pending.data,
+            }),
+            None => {
+                debug!(
+                    "Artwork channel {}: unknown image format ({} bytes), dropping",
+                    channel,
+                    pending.data.len()
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg() -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        data.extend(std::iter::repeat_n(0x42, 100));
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    fn png() -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(std::iter::repeat_n(0x17, 50));
+        data.extend([0, 0, 0, 0]);
+        data.extend(b"IEND");
+        data.extend([0xAE, 0x42, 0x60, 0x82]);
+        data
+    }
+
+    fn webp() -> Vec<u8> {
+        let body_len = 40u32;
+        let mut data = b"RIFF".to_vec();
+        data.extend((body_len + 4).to_le_bytes());
+        data.extend(b"WEBP");
+        data.extend(std::iter::repeat_n(0x99, body_len as usize));
+        data
+    }
+
+    #[test]
+    fn test_detect_content_type() {
+        assert_eq!(ContentType::detect(&jpeg()), Some(ContentType::Jpeg));
+        assert_eq!(ContentType::detect(&png()), Some(ContentType::Png));
+        assert_eq!(ContentType::detect(&webp()), Some(ContentType::Webp));
+        assert_eq!(ContentType::detect(b"GIF89a"), None);
+        assert_eq!(ContentType::Webp.mime_type(), "image/webp");
+    }
+
+    #[test]
+    fn test_reassemble_chunked_images() {
+        for image in [jpeg(), png(), webp()] {
+            let mut assembler = ArtworkAssembler::new();
+            let chunks: Vec<&[u8]> = image.chunks(17).collect();
+            let (last, rest) = chunks.split_last().unwrap();
+            for chunk in rest {
+                assert!(assembler.push(0, 1, chunk).is_none());
+            }
+            let artwork = assembler.push(0, 1, last).expect("image complete");
+            assert_eq!(artwork.data, image);
+        }
+    }
+
+    #[test]
+    fn test_interleaved_channels() {
+        let (a, b) = (jpeg(), png());
+        let mut assembler = ArtworkAssembler::new();
+        let mut done = Vec::new();
+        let (mut ca, mut cb) = (a.chunks(10), b.chunks(10));
+        loop {
+            let (next_a, next_b) = (ca.next(), cb.next());
+            if next_a.is_none() && next_b.is_none() {
+                break;
+            }
+            if let Some(chunk) = next_a {
+                done.extend(assembler.push(0, 5, chunk));
+            }
+            if let Some(chunk) = next_b {
+                done.extend(assembler.push(1, 5, chunk));
+            }
+        }
+
+        assert_eq!(done.len(), 2);
+        let jpeg_art = done.iter().find(|art| art.channel == 0).unwrap();
+        let png_art = done.iter().find(|art| art.channel == 1).unwrap();
+        assert_eq!(jpeg_art.content_type, ContentType::Jpeg);
+        assert_eq!(jpeg_art.data, a);
+        assert_eq!(png_art.content_type, ContentType::Png);
+        assert_eq!(png_art.data, b);
+    }
+
+    #[test]
+    fn test_empty_chunk_terminates() {
+        // JPEG without an end marker, closed by an empty chunk
+        let mut assembler = ArtworkAssembler::new();
+        assert!(assembler
+            .push(2, 9, &[0xFF, 0xD8, 0xFF, 0xE0, 1, 2, 3])
+            .is_none());
+        let artwork = assembler.push(2, 9, &[]).unwrap();
+        assert_eq!(artwork.data.len(), 7);
+
+        // Nothing pending: an empty chunk just clears the channel
+        assert!(assembler.push(2, 10, &[]).is_none());
+    }
+
+    #[test]
+    fn test_new_timestamp_discards_partial_image() {
+        let mut assembler = ArtworkAssembler::new();
+        let first = jpeg();
+        assembler.push(0, 1, &first[..20]);
+        let artwork = assembler.push(0, 2, &png()).unwrap();
+        assert_eq!(artwork.content_type, ContentType::Png);
+        assert_eq!(artwork.timestamp, 2);
+    }
+}
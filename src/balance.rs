@@ -0,0 +1,136 @@
+// Balance and Channel Swap
+//
+// Balance applies complementary gains to the front left/right channels
+// (-100 = left only, 0 = centered, 100 = right only). Swap exchanges the
+// front left/right samples of each interleaved frame. Both only touch the
+// first two channels, so surround channels pass through unchanged, and both
+// are no-ops on mono streams.
+//
+// They act on the channels as they go to the device: with `--mono` the
+// downmix comes first, so balance moves the mono mix between the speakers
+// of an asymmetric setup (a swap has nothing to exchange there).
+
+use sendspin::audio::Sample;
+use std::sync::Arc;
+
+/// Left/right gains for a balance in -100..=100
+pub fn balance_gains(balance: i8) -> (f32, f32) {
+    let b = balance.clamp(-100, 100) as f32 / 100.0;
+    let left = 1.0 - b.max(0.0);
+    let right = 1.0 + b.min(0.0);
+    (left, right)
+}
+
+/// Whether balance/swap have anything to do for this channel count
+pub fn applies_to(channels: usize) -> bool {
+    channels >= 2
+}
+
+/// Apply balance and optional L/R swap to interleaved samples
+pub fn apply(samples: &[Sample], channels: usize, balance: i8, swap: bool) -> Arc<[Sample]> {
+    if !applies_to(channels) {
+        return Arc::from(samples);
+    }
+
+    let (left_gain, right_gain) = balance_gains(balance);
+    let mut out = samples.to_vec();
+    for frame in out.chunks_exact_mut(channels) {
+        if swap {
+            frame.swap(0, 1);
+        }
+        if balance != 0 {
+            frame[0] = Sample((frame[0].0 as f32 * left_gain) as i32);
+            frame[1] = Sample((frame[1].0 as f32 * right_gain) as i32);
+        }
+    }
+    Arc::from(out)
+}
+
+/// Balance and swap for the f32 pipeline, in place
+pub fn apply_f32(samples: &mut [f32], channels: usize, balance: i8, swap: bool) {
+    if !applies_to(channels) {
+        return;
+    }
+
+    let (left_gain, right_gain) = balance_gains(balance);
+    for frame in samples.chunks_exact_mut(channels) {
+        if swap {
+            frame.swap(0, 1);
+        }
+        frame[0] *= left_gain;
+        frame[1] *= right_gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(samples: &[Sample]) -> Vec<i32> {
+        samples.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn test_balance_gains() {
+        assert_eq!(balance_gains(0), (1.0, 1.0));
+        assert_eq!(balance_gains(-100), (1.0, 0.0));
+        assert_eq!(balance_gains(100), (0.0, 1.0));
+        assert_eq!(balance_gains(50), (0.5, 1.0));
+        assert_eq!(balance_gains(-25), (1.0, 0.75));
+    }
+
+    #[test]
+    fn test_balance_stereo() {
+        let samples = [Sample(1000), Sample(1000), Sample(-2000), Sample(-2000)];
+        let out = apply(&samples, 2, 50, false);
+        assert_eq!(values(&out), vec![500, 1000, -1000, -2000]);
+    }
+
+    #[test]
+    fn test_swap_stereo() {
+        let samples = [Sample(1), Sample(2), Sample(3), Sample(4)];
+        let out = apply(&samples, 2, 0, true);
+        assert_eq!(values(&out), vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn test_swap_then_balance() {
+        // Balance refers to the output speakers, i.e. after the swap
+        let samples = [Sample(100), Sample(200)];
+        let out = apply(&samples, 2, -100, true);
+        assert_eq!(values(&out), vec![200, 0]);
+    }
+
+    #[test]
+    fn test_surround_channels_untouched() {
+        let samples = [
+            Sample(10),
+            Sample(20),
+            Sample(30),
+            Sample(40),
+            Sample(50),
+            Sample(60),
+        ];
+        let out = apply(&samples, 6, 100, true);
+        assert_eq!(values(&out), vec![0, 10, 30, 40, 50, 60]);
+    }
+
+    #[test]
+    fn test_f32_matches_integer_path() {
+        let mut samples = [0.5, 0.25, -0.5, -0.25];
+        apply_f32(&mut samples, 2, 50, true);
+        assert_eq!(samples, [0.125, 0.5, -0.125, -0.5]);
+
+        let mut mono = [0.5, 0.25];
+        apply_f32(&mut mono, 1, 100, true);
+        assert_eq!(mono, [0.5, 0.25]);
+    }
+
+    #[test]
+    fn test_mono_is_noop() {
+        let samples = [Sample(10), Sample(20)];
+        let out = apply(&samples, 1, 100, true);
+        assert_eq!(values(&out), vec![10, 20]);
+        assert!(!applies_to(1));
+    }
+}
@@ -0,0 +1,303 @@
+// Callback-Driven Output
+//
+// The default path sleeps until a buffer is due and pushes it at the device,
+// which then plays it on its own clock whenever its callback next runs: two
+// clocks stacked, and the device's buffering only estimated. With
+// `--scheduling callback` the cpal callback pulls samples from a lock-free
+// FIFO instead. Each callback reports when its first sample reaches the
+// speaker (`OutputCallbackInfo` playback vs. callback timestamps), so the
+// time the end of the FIFO will play is known exactly. A buffer due later
+// than that is preceded by the matching run of silence, and the player
+// queues it straight away instead of sleeping.
+//
+// The file backend honours the same scheduling on a virtual clock (its first
+// sample plays at the first buffer's play_at), which is how the tests compare
+// where audio lands under both paths.
+
+use crate::device;
+use crate::float;
+use crate::output::{DeviceFormat, OutputBackend};
+use crate::ring::{self, Consumer, Producer};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SizedSample, StreamConfig};
+use sendspin::audio::{AudioFormat, Sample};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// Gaps shorter than this are left alone rather than filled with a sliver
+/// of silence (clock jitter, not a real gap)
+pub const MIN_GAP: Duration = Duration::from_millis(1);
+
+/// Audio the FIFO holds ahead of the callback
+const FIFO_AHEAD: Duration = Duration::from_millis(200);
+
+/// Places writes on a device timeline by padding with silence
+#[derive(Debug)]
+pub struct Aligner {
+    sample_rate: u32,
+    target: Option<Instant>, // Where the next write should start
+}
+
+impl Aligner {
+    pub fn new(sample_rate: u32) -> Self {
+        Aligner {
+            sample_rate: sample_rate.max(1),
+            target: None,
+        }
+    }
+
+    /// Make the next write start playing at `at`
+    pub fn start_at(&mut self, at: Instant) {
+        self.target = Some(at);
+    }
+
+    /// The pending start time, cleared
+    pub fn take_target(&mut self) -> Option<Instant> {
+        self.target.take()
+    }
+
+    /// Frames of silence to queue before the next write, given when what's
+    /// already queued finishes playing; a late write gets none (it can't
+    /// be played early) and the target is used up either way
+    pub fn silence_frames(&mut self, queued_end: Instant) -> usize {
+        let Some(at) = self.take_target() else {
+            return 0;
+        };
+        let gap = at.saturating_duration_since(queued_end);
+        if gap < MIN_GAP {
+            return 0;
+        }
+        (gap.as_secs_f64() * self.sample_rate as f64).round() as usize
+    }
+}
+
+/// cpal stream fed by its callback from a FIFO, writes placed by play time
+pub struct CallbackOutput {
+    _stream: cpal::Stream,
+    fifo: Producer<f32>,
+    channels: usize,
+    sample_rate: u32,
+    base: Instant,           // Origin of `head_at`
+    head_at: Arc<AtomicU64>, // When the FIFO's next sample plays, ns after `base`
+    latency: Arc<AtomicU64>, // Callback to speaker, ns, as the last callback saw it
+    failed: Arc<AtomicBool>,
+    aligner: Aligner,
+    format: DeviceFormat,
+}
+
+impl CallbackOutput {
+    pub fn open(
+        host: Option<&str>,
+        format: &AudioFormat,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let host = match host {
+            Some(name) => device::open_host(name)?,
+            None => cpal::default_host(),
+        };
+        let device = host
+            .default_output_device()
+            .ok_or("no default output device on this host")?;
+        let device_name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+        let preferred = device
+            .default_output_config()
+            .ok()
+            .map(|config| config.sample_format());
+        let ranges: Vec<_> = device.supported_output_configs()?.collect();
+        let supported = device::select_config(&ranges, format, preferred)
+            .map_err(|e| format!("{}: {}", device_name, e))?;
+        let sample_format = supported.sample_format();
+        let device_format = DeviceFormat::from_cpal(sample_format)
+            .ok_or_else(|| format!("unsupported device sample format {:?}", sample_format))?;
+        let config: StreamConfig = supported.config();
+
+        let channels = (format.channels as usize).max(1);
+        let sample_rate = format.sample_rate.max(1);
+        let capacity = (sample_rate as u128 * FIFO_AHEAD.as_millis() / 1000) as usize * channels;
+        let (fifo, rx) = ring::ring(capacity);
+        let base = Instant::now();
+        let head_at = Arc::new(AtomicU64::new(0));
+        let latency = Arc::new(AtomicU64::new(0));
+        let failed = Arc::new(AtomicBool::new(false));
+        let shared = Shared {
+            base,
+            head_at: Arc::clone(&head_at),
+            latency: Arc::clone(&latency),
+            failed: Arc::clone(&failed),
+            channels,
+            sample_rate,
+        };
+
+        let stream = match device_format {
+            DeviceFormat::F32 => build_stream(&device, &config, rx, shared, |s: f32| s)?,
+            DeviceFormat::I16 => build_stream(&device, &config, rx, shared, |s: f32| {
+                (s as f64 * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+            })?,
+            DeviceFormat::I24 | DeviceFormat::I32 => {
+                build_stream(&device, &config, rx, shared, |s: f32| {
+                    (s as f64 * 2_147_483_648.0)
+                        .round()
+                        .clamp(i32::MIN as f64, i32::MAX as f64) as i32
+                })?
+            }
+        };
+        stream.play()?;
+        info!(
+            "cpal callback output on '{}': {} Hz, {} ch, {:?}",
+            device_name, sample_rate, channels, sample_format
+        );
+
+        Ok(CallbackOutput {
+            _stream: stream,
+            fifo,
+            channels,
+            sample_rate,
+            base,
+            head_at,
+            latency,
+            failed,
+            aligner: Aligner::new(sample_rate),
+            format: device_format,
+        })
+    }
+
+    /// When everything queued so far will have played
+    fn queued_end(&self) -> Instant {
+        let head = self.base + Duration::from_nanos(self.head_at.load(Ordering::Acquire));
+        // Before the first callback, or after an underrun, the FIFO's next
+        // sample goes out with the next callback
+        let latency = Duration::from_nanos(self.latency.load(Ordering::Relaxed));
+        let head = head.max(Instant::now() + latency);
+        let frames = (self.fifo.len() / self.channels) as u32;
+        head + device::frames_to_duration(frames, self.sample_rate)
+    }
+
+    /// Queue samples for the callback, waiting while the FIFO is full
+    fn push(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+        let silence = self.aligner.silence_frames(self.queued_end()) * self.channels;
+        let padding = std::iter::repeat_n(0.0, silence);
+        for mut sample in padding.chain(samples.iter().copied()) {
+            loop {
+                if self.failed.load(Ordering::Relaxed) {
+                    return Err("audio stream failed".into());
+                }
+                match self.fifo.push(sample) {
+                    Ok(()) => break,
+                    Err(rejected) => {
+                        sample = rejected;
+                        // Full: give the callback time to drain some
+                        std::thread::sleep(Duration::from_millis(2));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the callback shares with the writer
+struct Shared {
+    base: Instant,
+    head_at: Arc<AtomicU64>,
+    latency: Arc<AtomicU64>,
+    failed: Arc<AtomicBool>,
+    channels: usize,
+    sample_rate: u32,
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut fifo: Consumer<f32>,
+    shared: Shared,
+    convert: fn(f32) -> T,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + Send + 'static,
+{
+    let failed = Arc::clone(&shared.failed);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let now = Instant::now();
+            let timestamp = info.timestamp();
+            let latency = timestamp
+                .playback
+                .duration_since(&timestamp.callback)
+                .unwrap_or_default();
+            for out in data.iter_mut() {
+                // Underrun: play silence rather than stale data
+                *out = fifo.pop().map_or(T::EQUILIBRIUM, convert);
+            }
+            // The next sample taken from the FIFO plays after this whole callback
+            let frames = (data.len() / shared.channels) as u32;
+            let next = now + latency + device::frames_to_duration(frames, shared.sample_rate);
+            let ns = next.saturating_duration_since(shared.base).as_nanos() as u64;
+            shared.head_at.store(ns, Ordering::Release);
+            shared
+                .latency
+                .store(latency.as_nanos() as u64, Ordering::Relaxed);
+        },
+        move |e| {
+            error!("Audio stream error: {}", e);
+            failed.store(true, Ordering::Relaxed);
+        },
+        None,
+    )
+}
+
+impl OutputBackend for CallbackOutput {
+    fn name(&self) -> &'static str {
+        "cpal-callback"
+    }
+
+    fn sample_format(&self) -> DeviceFormat {
+        self.format
+    }
+
+    fn places_writes(&self) -> bool {
+        true
+    }
+
+    fn start_at(&mut self, at: Instant) {
+        self.aligner.start_at(at);
+    }
+
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+        self.push(&float::to_f32(samples))
+    }
+
+    fn write_f32(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut samples = samples.to_vec();
+        float::clamp(&mut samples);
+        self.push(&samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_is_filled_with_silence() {
+        let mut aligner = Aligner::new(48000);
+        let end = Instant::now();
+        aligner.start_at(end + Duration::from_millis(20));
+        assert_eq!(aligner.silence_frames(end), 960);
+        // Used up: the next write follows on directly
+        assert_eq!(aligner.silence_frames(end), 0);
+    }
+
+    #[test]
+    fn test_late_or_near_writes_get_no_silence() {
+        let mut aligner = Aligner::new(48000);
+        let end = Instant::now() + Duration::from_millis(50);
+        aligner.start_at(end - Duration::from_millis(5));
+        assert_eq!(aligner.silence_frames(end), 0);
+        aligner.start_at(end + MIN_GAP / 2);
+        assert_eq!(aligner.silence_frames(end), 0);
+        aligner.start_at(end + MIN_GAP);
+        assert_eq!(aligner.silence_frames(end), 48);
+    }
+}
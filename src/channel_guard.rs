@@ -0,0 +1,157 @@
+// Channel Mismatch Guard
+//
+// The output is opened for the channel count of the stream's first buffer.
+// A malformed stream can disagree with it later on: buffers announcing
+// another channel count mid-stream (a server bug), or a buffer whose samples
+// don't divide into whole frames. Written as-is, either one shifts the
+// interleaving, so every channel after it plays the wrong signal, or makes
+// the write fail.
+//
+// Buffers of another channel count are fitted to the output instead of
+// reopening it mid-stream: to mono with the `--mono` downmix, from mono by
+// copying it to every channel, surround to stereo with its stereo fold, and
+// anything else channel by channel, with extra output channels silent. A
+// buffer that isn't whole frames can't be placed at all and is dropped. The
+// warnings for both are throttled, since a broken stream repeats them for
+// every chunk.
+
+use crate::mono;
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use sendspin::audio::Sample;
+use std::time::{Duration, Instant};
+
+/// Shortest time between two warnings about the same problem
+pub const WARN_EVERY: Duration = Duration::from_secs(10);
+
+/// Whether `samples` interleaved samples are whole frames of `channels`
+pub fn whole_frames(samples: usize, channels: usize) -> bool {
+    channels > 0 && samples % channels == 0
+}
+
+/// Interleaved samples of `from` channels as `to` channels
+pub fn remap(samples: &[Sample], from: usize, to: usize) -> Vec<Sample> {
+    if from == to || from == 0 || to == 0 {
+        return samples.to_vec();
+    }
+    let frames = samples.chunks_exact(from);
+    let mut out = Vec::with_capacity(frames.len() * to);
+    let clamp = |value: f32| Sample((value.round() as i32).clamp(SAMPLE_MIN, SAMPLE_MAX));
+    if to == 1 {
+        let gains = mono::channel_gains(from, mono::DEFAULT_GAIN_DB);
+        for frame in frames {
+            let sum: f32 = frame.iter().zip(&gains).map(|(s, g)| s.0 as f32 * g).sum();
+            out.push(clamp(sum));
+        }
+    } else if from == 1 {
+        for frame in frames {
+            out.resize(out.len() + to, frame[0]);
+        }
+    } else if to == 2 {
+        let fold = mono::stereo_fold(from);
+        for frame in frames {
+            let (left, right) = frame
+                .iter()
+                .zip(&fold)
+                .fold((0.0, 0.0), |(left, right), (s, (l, r))| {
+                    (left + s.0 as f32 * l, right + s.0 as f32 * r)
+                });
+            out.push(clamp(left));
+            out.push(clamp(right));
+        }
+    } else {
+        for frame in frames {
+            let kept = from.min(to);
+            out.extend_from_slice(&frame[..kept]);
+            out.resize(out.len() + to - kept, Sample(0));
+        }
+    }
+    out
+}
+
+/// Lets a repeated warning through at most once per interval
+#[derive(Debug)]
+pub struct Throttle {
+    every: Duration,
+    last: Option<Instant>,
+    suppressed: u64, // Occurrences since the last warning
+}
+
+impl Throttle {
+    pub fn new(every: Duration) -> Self {
+        Throttle {
+            every,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// An occurrence at `now`: Some(occurrences held back since the last
+    /// warning) when it should be logged, None to stay quiet
+    pub fn hit(&mut self, now: Instant) -> Option<u64> {
+        if self
+            .last
+            .is_some_and(|last| now.saturating_duration_since(last) < self.every)
+        {
+            self.suppressed += 1;
+            return None;
+        }
+        self.last = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(values: &[i32]) -> Vec<Sample> {
+        values.iter().map(|&v| Sample(v)).collect()
+    }
+
+    fn values(samples: &[Sample]) -> Vec<i32> {
+        samples.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn test_remap_between_layouts() {
+        // Mono onto stereo: the same signal on both sides
+        assert_eq!(
+            values(&remap(&samples(&[100, -200]), 1, 2)),
+            vec![100, 100, -200, -200]
+        );
+        // Stereo onto mono: the --mono downmix, identical sides keep their level
+        let mono = values(&remap(&samples(&[1000, 1000, 1000, -1000]), 2, 1));
+        assert!((mono[0] - 1000).abs() <= 2, "{:?}", mono);
+        assert_eq!(mono[1], 0);
+        // 5.1 onto stereo: centre into both sides, LFE dropped
+        let stereo = values(&remap(&samples(&[0, 0, 1000, 1000, 0, 0]), 6, 2));
+        assert_eq!(stereo[0], stereo[1]);
+        assert!(stereo[0] > 0);
+        // Stereo onto a 4-channel output: the rest stay silent
+        assert_eq!(
+            values(&remap(&samples(&[1, 2, 3, 4]), 2, 4)),
+            vec![1, 2, 0, 0, 3, 4, 0, 0]
+        );
+        // 4 onto 3 channels: channel by channel
+        assert_eq!(values(&remap(&samples(&[1, 2, 3, 4]), 4, 3)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_whole_frames() {
+        assert!(whole_frames(1920, 2));
+        assert!(whole_frames(0, 2));
+        assert!(!whole_frames(1921, 2));
+        assert!(!whole_frames(6, 0));
+    }
+
+    #[test]
+    fn test_throttle_counts_what_it_held_back() {
+        let mut throttle = Throttle::new(WARN_EVERY);
+        let start = Instant::now();
+        assert_eq!(throttle.hit(start), Some(0));
+        assert_eq!(throttle.hit(start + Duration::from_secs(1)), None);
+        assert_eq!(throttle.hit(start + Duration::from_secs(9)), None);
+        assert_eq!(throttle.hit(start + Duration::from_secs(10)), Some(2));
+        assert_eq!(throttle.hit(start + Duration::from_secs(11)), None);
+    }
+}
@@ -0,0 +1,365 @@
+// Chunk Coalescing
+//
+// Some servers send audio in 5-10 ms chunks. Each one costs a queue slot, a
+// wakeup of the playback thread and a device write, which on a weak CPU adds
+// up to more than processing the audio itself. With `--coalesce-ms` the
+// player merges consecutive chunks into buffers of that length as they are
+// queued; it's off by default. A merged buffer keeps the timestamp and play time of its first
+// chunk; the others follow it without a gap, as they would have played anyway.
+//
+// Only a chunk that continues the pending buffer is merged into it: same
+// format, and both its timestamp and its play time where the pending buffer
+// ends (within the continuity check's rounding tolerance). Anything else sends
+// the partial buffer on first. The player also sends it on at stream
+// boundaries (stop, clear, new stream, drain), so the end of a stream is
+// neither held back nor merged into the next one.
+//
+// A server that sends chunks slowly, or close to their play time, would have
+// the first of them wait for the rest. An optional window
+// (`--coalesce-window-ms`) caps that: once the pending buffer's first chunk
+// has been held this long, the buffer goes on short. It is checked as chunks
+// arrive, and by a timer on the network side at the window's deadline, so the
+// last chunks before a pause in the stream don't wait for the next.
+
+use crate::continuity::TOLERANCE_US;
+use crate::pool::SamplePool;
+use sendspin::audio::{AudioBuffer, AudioFormat, Sample};
+use std::time::{Duration, Instant};
+
+/// Merged buffer length (ms) used unless `--coalesce-ms` says otherwise: off
+pub const DEFAULT_TARGET_MS: u64 = 0;
+
+/// Play time of `samples` interleaved samples in `format`
+fn length(samples: usize, format: &AudioFormat) -> Duration {
+    let frames = (samples / format.channels.max(1) as usize) as u64;
+    Duration::from_micros(frames * 1_000_000 / format.sample_rate.max(1) as u64)
+}
+
+/// A buffer being assembled from consecutive chunks
+struct Pending {
+    timestamp: i64,
+    play_at: Instant,
+    held_since: Instant, // When its first chunk arrived
+    format: AudioFormat,
+    samples: Vec<Sample>,
+}
+
+impl Pending {
+    fn duration(&self) -> Duration {
+        length(self.samples.len(), &self.format)
+    }
+
+    /// Whether `buffer` starts where this one ends
+    fn continues_with(&self, buffer: &AudioBuffer) -> bool {
+        let duration = self.duration();
+        let same_format = self.format.sample_rate == buffer.format.sample_rate
+            && self.format.channels == buffer.format.channels
+            && self.format.bit_depth == buffer.format.bit_depth;
+        let end_timestamp = self.timestamp + duration.as_micros() as i64;
+        let end_play_at = self.play_at + duration;
+        let play_at_offset = if buffer.play_at > end_play_at {
+            buffer.play_at - end_play_at
+        } else {
+            end_play_at - buffer.play_at
+        };
+        same_format
+            && (buffer.timestamp - end_timestamp).abs() <= TOLERANCE_US
+            && play_at_offset.as_micros() as i64 <= TOLERANCE_US
+    }
+
+    /// The merged buffer, copied into pooled memory; the merge buffer is
+    /// returned emptied for the next one
+    fn into_buffer(self, pool: &SamplePool) -> (AudioBuffer, Vec<Sample>) {
+        let mut samples = self.samples;
+        let buffer = AudioBuffer {
+            timestamp: self.timestamp,
+            play_at: self.play_at,
+            samples: pool.copy_from(&samples),
+            format: self.format,
+        };
+        samples.clear();
+        (buffer, samples)
+    }
+}
+
+/// Merges consecutive chunks into buffers of at least the target length
+pub struct Coalescer {
+    target: Duration,         // Zero = pass every chunk through as it is
+    window: Option<Duration>, // Longest a chunk waits for the rest, None = until the target
+    pending: Option<Pending>,
+    spare: Vec<Sample>, // Merge buffer kept between merged buffers
+    pool: SamplePool,   // Merged chunks go back here, merged buffers come from it
+}
+
+impl Coalescer {
+    pub fn new(target: Duration) -> Self {
+        Coalescer {
+            target,
+            window: None,
+            pending: None,
+            spare: Vec::new(),
+            pool: SamplePool::default(),
+        }
+    }
+
+    /// Share buffers with the decoder and player through `pool`
+    pub fn with_pool(mut self, pool: SamplePool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Send a pending buffer on short once its first chunk has waited this long
+    pub fn with_window(mut self, window: Option<Duration>) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Add a chunk; returns the buffers ready to be queued, in order
+    pub fn push(&mut self, buffer: AudioBuffer) -> Vec<AudioBuffer> {
+        self.push_at(buffer, Instant::now())
+    }
+
+    /// `push` for a chunk that arrived at `now`
+    fn push_at(&mut self, buffer: AudioBuffer, now: Instant) -> Vec<AudioBuffer> {
+        let mut ready = Vec::new();
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| !pending.continues_with(&buffer))
+        {
+            ready.extend(self.flush());
+        }
+
+        let pending = match self.pending.take() {
+            Some(mut pending) => {
+                pending.samples.extend_from_slice(&buffer.samples);
+                self.pool.give(buffer.samples);
+                pending
+            }
+            None if length(buffer.samples.len(), &buffer.format) >= self.target => {
+                // Long enough already: no need to copy it
+                ready.push(buffer);
+                return ready;
+            }
+            None => {
+                let mut samples = std::mem::take(&mut self.spare);
+                samples.extend_from_slice(&buffer.samples);
+                self.pool.give(buffer.samples);
+                Pending {
+                    timestamp: buffer.timestamp,
+                    play_at: buffer.play_at,
+                    held_since: now,
+                    samples,
+                    format: buffer.format,
+                }
+            }
+        };
+
+        let waited = self
+            .window
+            .is_some_and(|window| now.saturating_duration_since(pending.held_since) >= window);
+        if pending.duration() >= self.target || waited {
+            ready.push(self.finish(pending));
+        } else {
+            self.pending = Some(pending);
+        }
+        ready
+    }
+
+    /// The partial buffer, if any, to queue as it is
+    pub fn flush(&mut self) -> Option<AudioBuffer> {
+        let pending = self.pending.take()?;
+        Some(self.finish(pending))
+    }
+
+    /// When the partial buffer's first chunk will have waited out the
+    /// window, None without a window or a partial buffer
+    pub fn deadline(&self) -> Option<Instant> {
+        Some(self.pending.as_ref()?.held_since + self.window?)
+    }
+
+    /// The partial buffer once its first chunk has waited out the window,
+    /// for when no further chunk arrives to check it
+    pub fn flush_expired(&mut self, now: Instant) -> Option<AudioBuffer> {
+        if self.deadline()? > now {
+            return None;
+        }
+        self.flush()
+    }
+
+    fn finish(&mut self, pending: Pending) -> AudioBuffer {
+        let (buffer, spare) = pending.into_buffer(&self.pool);
+        self.spare = spare;
+        buffer
+    }
+
+    /// Wire bytes held in the partial buffer
+    pub fn pending_bytes(&self) -> usize {
+        self.pending.as_ref().map_or(0, |pending| {
+            pending.samples.len() * (pending.format.bit_depth as usize).div_ceil(8)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::PoolStats;
+    use sendspin::audio::Codec;
+
+    const CHUNK_US: i64 = 10_000; // 480 frames at 48 kHz
+    const TARGET: Duration = Duration::from_millis(40);
+
+    fn chunk(start: Instant, timestamp: i64, frames: usize) -> AudioBuffer {
+        AudioBuffer {
+            timestamp,
+            play_at: start + Duration::from_micros(timestamp as u64),
+            samples: (0..frames as i32 * 2).map(Sample).collect(),
+            format: AudioFormat {
+                codec: Codec::Pcm,
+                sample_rate: 48000,
+                channels: 2,
+                bit_depth: 24,
+                codec_header: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_merges_contiguous_chunks_to_target() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(TARGET);
+        let mut merged = Vec::new();
+        for i in 0..8 {
+            merged.extend(coalescer.push(chunk(start, i * CHUNK_US, 480)));
+        }
+        // Eight 10 ms chunks make two 40 ms buffers
+        assert_eq!(merged.len(), 2);
+        for (i, buffer) in merged.iter().enumerate() {
+            assert_eq!(buffer.samples.len(), 4 * 480 * 2);
+            assert_eq!(buffer.timestamp, i as i64 * 40_000);
+            assert_eq!(buffer.play_at, start + Duration::from_millis(40) * i as u32);
+        }
+        // The second chunk follows the first one's last sample
+        assert_eq!(
+            (merged[0].samples[959].0, merged[0].samples[960].0),
+            (959, 0)
+        );
+        assert!(coalescer.flush().is_none());
+    }
+
+    #[test]
+    fn test_gap_flushes_partial_buffer() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(TARGET);
+        assert!(coalescer.push(chunk(start, 0, 480)).is_empty());
+        assert!(coalescer.push(chunk(start, CHUNK_US, 480)).is_empty());
+        assert_eq!(coalescer.pending_bytes(), 2 * 480 * 2 * 3);
+
+        // 5 ms missing: the 20 ms so far goes on alone
+        let ready = coalescer.push(chunk(start, 25_000, 480));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].timestamp, 0);
+        assert_eq!(ready[0].samples.len(), 2 * 480 * 2);
+        assert_eq!(coalescer.flush().unwrap().timestamp, 25_000);
+
+        // Rounding in the timestamps is still contiguous
+        assert!(coalescer.push(chunk(start, 0, 480)).is_empty());
+        assert!(coalescer.push(chunk(start, CHUNK_US + 300, 480)).is_empty());
+    }
+
+    #[test]
+    fn test_play_time_jump_or_format_change_flushes() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(TARGET);
+        coalescer.push(chunk(start, 0, 480));
+        // Same timestamps, rescheduled 50 ms later (clock sync took over)
+        let mut late = chunk(start, CHUNK_US, 480);
+        late.play_at += Duration::from_millis(50);
+        assert_eq!(coalescer.push(late).len(), 1);
+
+        coalescer.flush();
+        coalescer.push(chunk(start, 0, 480));
+        let mut other = chunk(start, CHUNK_US, 480);
+        other.format.sample_rate = 44100;
+        assert_eq!(coalescer.push(other).len(), 1);
+    }
+
+    #[test]
+    fn test_window_sends_short_buffer_on() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(TARGET).with_window(Some(Duration::from_millis(15)));
+        // Chunks arriving 10 ms apart: the second one is past the window
+        assert!(coalescer.push_at(chunk(start, 0, 480), start).is_empty());
+        let ready = coalescer.push_at(
+            chunk(start, CHUNK_US, 480),
+            start + Duration::from_millis(20),
+        );
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].samples.len(), 2 * 480 * 2);
+        assert_eq!(coalescer.pending_bytes(), 0);
+
+        // Arriving in a burst, they still make full buffers
+        let later = start + Duration::from_secs(1);
+        let merged: Vec<_> = (2..6)
+            .flat_map(|i| coalescer.push_at(chunk(start, i * CHUNK_US, 480), later))
+            .collect();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].samples.len(), 4 * 480 * 2);
+    }
+
+    #[test]
+    fn test_window_expires_without_another_chunk() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(TARGET).with_window(Some(Duration::from_millis(15)));
+        assert!(coalescer.push_at(chunk(start, 0, 480), start).is_empty());
+        assert_eq!(
+            coalescer.deadline(),
+            Some(start + Duration::from_millis(15))
+        );
+        let early = start + Duration::from_millis(10);
+        assert!(coalescer.flush_expired(early).is_none());
+        let late = start + Duration::from_millis(15);
+        let held = coalescer.flush_expired(late).unwrap();
+        assert_eq!(held.samples.len(), 480 * 2);
+        assert!(coalescer.flush_expired(late).is_none());
+        assert_eq!(coalescer.deadline(), None);
+
+        // Without a window a partial buffer waits for the next chunk
+        let mut unlimited = Coalescer::new(TARGET);
+        unlimited.push_at(chunk(start, 0, 480), start);
+        assert_eq!(unlimited.deadline(), None);
+        assert!(unlimited
+            .flush_expired(start + Duration::from_secs(60))
+            .is_none());
+    }
+
+    #[test]
+    fn test_large_chunks_and_zero_target_pass_through() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(TARGET);
+        let ready = coalescer.push(chunk(start, 0, 2400)); // 50 ms
+        assert_eq!(ready.len(), 1);
+        assert_eq!(coalescer.pending_bytes(), 0);
+
+        let mut off = Coalescer::new(Duration::ZERO);
+        for i in 0..3 {
+            assert_eq!(off.push(chunk(start, i * CHUNK_US, 480)).len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_merged_buffers_come_from_the_pool() {
+        let start = Instant::now();
+        let pool = SamplePool::new();
+        let mut coalescer = Coalescer::new(TARGET).with_pool(pool.clone());
+        for i in 0..16 {
+            for buffer in coalescer.push(chunk(start, i * CHUNK_US, 480)) {
+                // Played: the player hands it back
+                pool.give(buffer.samples);
+            }
+        }
+        // Four merged buffers, one allocation
+        assert_eq!(pool.stats(), PoolStats { hits: 3, misses: 1 });
+    }
+}
@@ -1,17 +1,30 @@
 // Compatibility shim for Music Assistant server
 // Handles field name differences between sendspin-rs library and MA server
 
-use futures_util::stream::{SplitSink, SplitStream};
-use futures_util::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use crate::artwork::{Artwork, ArtworkAssembler};
+use crate::error::SendspinCliError;
+use crate::frame::{self, AudioFrame};
+use crate::negotiate;
+use crate::server_error;
+use crate::state_report::SharedRecent;
+use clap::ValueEnum;
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, Stream, StreamExt};
 use sendspin::protocol::messages::{ClientHello, Message};
 use sendspin::sync::ClockSync;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Error as WsError;
 use tokio_tungstenite::{
     connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
 };
+use tracing::{debug, error, info, trace, warn};
 
 /// WebSocket sender wrapper (local version for compatibility)
 pub struct CompatWsSender {
@@ -21,30 +34,338 @@ pub struct CompatWsSender {
 impl CompatWsSender {
     /// Send a message to the server
     pub async fn send_message(&self, msg: Message) -> Result<(), Box<dyn std::error::Error>> {
-        let json = serde_json::to_string(&msg)?;
+        self.send_text(serde_json::to_string(&msg)?).await
+    }
+
+    /// Send a message as JSON, for fields the library's types don't model
+    pub async fn send_json(
+        &self,
+        value: &serde_json::Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_text(serde_json::to_string(value)?).await
+    }
+
+    async fn send_text(&self, json: String) -> Result<(), Box<dyn std::error::Error>> {
         debug!("Sending message: {}", json);
 
         let mut tx = self.tx.lock().await;
         tx.send(WsMessage::Text(json)).await?;
         Ok(())
     }
+
+    /// Close the connection with a normal close frame
+    pub async fn close(&self) {
+        let mut tx = self.tx.lock().await;
+        if let Err(e) = tx.send(WsMessage::Close(None)).await {
+            debug!("Close frame not sent: {}", e);
+        }
+    }
+}
+
+/// Untyped view of a server text message
+///
+/// The sendspin message types drop fields they don't model (e.g. loudness
+/// metadata), so the raw payload is forwarded alongside the typed message.
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    pub msg_type: String,
+    pub payload: serde_json::Value,
+}
+
+/// What to do when decoding falls behind and the audio channel fills up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum AudioOverflow {
+    /// Discard the oldest chunks (live audio: stay current)
+    #[default]
+    DropOldest,
+    /// Stop reading from the socket until there is room again
+    Block,
+}
+
+/// Size and overflow policy of the audio channel
+#[derive(Debug, Clone, Copy)]
+pub struct AudioChannelConfig {
+    pub capacity: usize,
+    pub overflow: AudioOverflow,
+}
+
+impl Default for AudioChannelConfig {
+    fn default() -> Self {
+        AudioChannelConfig {
+            capacity: audio_channel_capacity(negotiate::max_queue_duration(
+                negotiate::DEFAULT_BUFFER_CAPACITY,
+            )),
+            overflow: AudioOverflow::DropOldest,
+        }
+    }
+}
+
+/// Chunk length the audio channel's capacity is counted in (what servers
+/// typically send)
+pub const TYPICAL_CHUNK: Duration = Duration::from_millis(20);
+
+/// Chunks covering `max_queue` of audio, so the channel holds at least as
+/// much as the player's queue can take from it
+pub fn audio_channel_capacity(max_queue: Duration) -> usize {
+    (max_queue.as_millis().div_ceil(TYPICAL_CHUNK.as_millis()) as usize).max(1)
+}
+
+/// Server messages buffered for the main loop; separate from audio, so
+/// commands never wait behind queued chunks. A full channel holds up the
+/// socket reader rather than dropping a command.
+const CONTROL_CHANNEL_CAPACITY: usize = 64;
+
+/// Audio channel overflow counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioChannelStats {
+    pub dropped_chunks: u64,     // Discarded by drop-oldest
+    pub backpressure_waits: u64, // Times the socket reader waited for room
+}
+
+#[derive(Debug, Default)]
+struct AudioChannelCounters {
+    dropped_chunks: AtomicU64,
+    backpressure_waits: AtomicU64,
+}
+
+enum AudioSender {
+    Ring(broadcast::Sender<AudioFrame>),
+    Queue(mpsc::Sender<AudioFrame>, Arc<AudioChannelCounters>),
+}
+
+impl AudioSender {
+    /// Queue a chunk, waiting for room in a full queue
+    #[cfg(test)]
+    async fn send(&self, chunk: AudioFrame) {
+        if let Some(chunk) = self.try_send(chunk) {
+            self.send_waiting(chunk).await;
+        }
+    }
+
+    /// Queue a chunk if there is room; a full queue hands it back to wait
+    fn try_send(&self, chunk: AudioFrame) -> Option<AudioFrame> {
+        match self {
+            // A full ring overwrites its oldest entry; the receiver reports the loss
+            AudioSender::Ring(tx) => {
+                let _ = tx.send(chunk);
+                None
+            }
+            AudioSender::Queue(tx, counters) => match tx.try_send(chunk) {
+                Err(mpsc::error::TrySendError::Full(chunk)) => {
+                    counters.backpressure_waits.fetch_add(1, Ordering::Relaxed);
+                    Some(chunk)
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Queue a chunk `try_send` handed back once there is room
+    async fn send_waiting(&self, chunk: AudioFrame) {
+        if let AudioSender::Queue(tx, _) = self {
+            let _ = tx.send(chunk).await;
+        }
+    }
+
+    /// Room for one chunk in the queue, None once the receiver is gone; the
+    /// ring never fills, so this never completes for it
+    async fn reserve(&self) -> Option<mpsc::Permit<'_, AudioFrame>> {
+        match self {
+            AudioSender::Ring(_) => std::future::pending().await,
+            AudioSender::Queue(tx, _) => tx.reserve().await.ok(),
+        }
+    }
+}
+
+/// Queue an audio chunk from the socket reader. With `--audio-overflow
+/// block` one chunk may wait for room while the reader goes on, so control
+/// messages right behind it still get through; a second chunk stops reading
+/// until the first is queued, which is what pushes back on the server.
+async fn deliver(audio_tx: &AudioSender, waiting: &mut Option<AudioFrame>, chunk: AudioFrame) {
+    if let Some(earlier) = waiting.take() {
+        audio_tx.send_waiting(earlier).await;
+    }
+    *waiting = audio_tx.try_send(chunk);
+}
+
+/// Receiving end of the bounded audio channel
+pub struct AudioReceiver {
+    rx: ReceiverKind,
+    counters: Arc<AudioChannelCounters>,
+}
+
+enum ReceiverKind {
+    Ring(broadcast::Receiver<AudioFrame>),
+    Queue(mpsc::Receiver<AudioFrame>),
+}
+
+impl AudioReceiver {
+    /// Next audio chunk, or None once the connection is gone
+    pub async fn recv(&mut self) -> Option<AudioFrame> {
+        match &mut self.rx {
+            ReceiverKind::Ring(rx) => loop {
+                match rx.recv().await {
+                    Ok(chunk) => return Some(chunk),
+                    Err(broadcast::error::RecvError::Lagged(dropped)) => {
+                        warn!("Audio channel full, dropped {} oldest chunks", dropped);
+                        self.counters
+                            .dropped_chunks
+                            .fetch_add(dropped, Ordering::Relaxed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+            ReceiverKind::Queue(rx) => rx.recv().await,
+        }
+    }
+
+    /// Overflow counters since the connection was made
+    pub fn stats(&self) -> AudioChannelStats {
+        AudioChannelStats {
+            dropped_chunks: self.counters.dropped_chunks.load(Ordering::Relaxed),
+            backpressure_waits: self.counters.backpressure_waits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn audio_channel(config: AudioChannelConfig) -> (AudioSender, AudioReceiver) {
+    let capacity = config.capacity.max(1);
+    let counters = Arc::new(AudioChannelCounters::default());
+    let (tx, rx) = match config.overflow {
+        AudioOverflow::DropOldest => {
+            let (tx, rx) = broadcast::channel(capacity);
+            (AudioSender::Ring(tx), ReceiverKind::Ring(rx))
+        }
+        AudioOverflow::Block => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (
+                AudioSender::Queue(tx, Arc::clone(&counters)),
+                ReceiverKind::Queue(rx),
+            )
+        }
+    };
+    (tx, AudioReceiver { rx, counters })
+}
+
+/// Why the connection to the server ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Disconnect {
+    /// The server sent a close frame (code is None if it gave no status)
+    Closed { code: Option<u16>, reason: String },
+    /// The socket failed
+    Error(String),
+    /// The stream ended without a close frame
+    Ended,
+}
+
+impl Disconnect {
+    fn from_close(frame: Option<CloseFrame<'_>>) -> Self {
+        match frame {
+            Some(frame) => Disconnect::Closed {
+                code: Some(u16::from(frame.code)),
+                reason: frame.reason.into_owned(),
+            },
+            None => Disconnect::Closed {
+                code: None,
+                reason: String::new(),
+            },
+        }
+    }
+
+    /// Whether reconnecting can help: the server going away or restarting
+    /// is transient, a rejection (protocol error, policy/auth) is not
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Disconnect::Closed {
+                code: Some(code), ..
+            } => !matches!(code, 1002 | 1003 | 1007 | 1008 | 1009 | 1010) && !is_rejection(*code),
+            _ => true,
+        }
+    }
+}
+
+/// An application close code carrying an HTTP client error (4000 + status,
+/// e.g. 4401 unauthorized), other than a timeout or rate limit
+fn is_rejection(code: u16) -> bool {
+    (4400..=4499).contains(&code) && !matches!(code, 4408 | 4429)
+}
+
+/// Name of a standard WebSocket close code
+fn close_code_name(code: u16) -> &'static str {
+    match code {
+        1000 => "normal",
+        1001 => "going away",
+        1002 => "protocol error",
+        1003 => "unsupported data",
+        1006 => "abnormal",
+        1007 => "invalid payload",
+        1008 => "policy violation",
+        1009 => "message too big",
+        1010 => "extension required",
+        1011 => "server error",
+        1012 => "service restart",
+        1013 => "try again later",
+        4000..=4999 => "application",
+        _ => "other",
+    }
 }
 
-/// Connect to Music Assistant server with field name compatibility fixes
+impl fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Disconnect::Closed { code: None, .. } => {
+                write!(f, "server closed the connection (no close code)")
+            }
+            Disconnect::Closed {
+                code: Some(code),
+                reason,
+            } => {
+                write!(
+                    f,
+                    "server closed the connection (code {} {}",
+                    code,
+                    close_code_name(*code)
+                )?;
+                if !reason.is_empty() {
+                    write!(f, ": \"{}\"", reason)?;
+                }
+                write!(f, ")")
+            }
+            Disconnect::Error(e) => write!(f, "WebSocket error: {}", e),
+            Disconnect::Ended => write!(f, "connection ended without a close frame"),
+        }
+    }
+}
+
+impl std::error::Error for Disconnect {}
+
+/// Channels and handles for an established server connection
+pub struct CompatConnection {
+    pub messages: Receiver<Message>,
+    pub raw_messages: Receiver<RawMessage>,
+    pub audio: AudioReceiver,
+    pub artwork: UnboundedReceiver<Artwork>,
+    pub clock_sync: Arc<tokio::sync::Mutex<ClockSync>>,
+    pub sender: CompatWsSender,
+    pub disconnect: oneshot::Receiver<Disconnect>, // Set when the message router stops
+    pub server_hello: serde_json::Value, // Raw server/hello payload, for fields the library drops
+}
+
+/// How long to wait for server/hello after sending the client hello
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connect to Music Assistant server with field name compatibility fixes;
+/// the router records what it receives in `recent`
 pub async fn connect_with_compat(
     url: &str,
     hello: ClientHello,
-) -> Result<
-    (
-        UnboundedReceiver<Message>,
-        UnboundedReceiver<sendspin::protocol::client::AudioChunk>,
-        Arc<tokio::sync::Mutex<ClockSync>>,
-        CompatWsSender,
-    ),
-    Box<dyn std::error::Error>,
-> {
+    audio_channel_config: AudioChannelConfig,
+    recent: SharedRecent,
+) -> Result<CompatConnection, SendspinCliError> {
     // Connect WebSocket manually
-    let (ws_stream, _) = connect_async(url).await?;
+    let (ws_stream, _) = connect_async(url)
+        .await
+        .map_err(SendspinCliError::Connect)?;
     let (mut write, read) = ws_stream.split();
 
     // Serialize the ClientHello normally
@@ -78,30 +399,63 @@ pub async fn connect_with_compat(
     debug!("Sending compatibility hello: {}", hello_string);
 
     // Send modified hello
-    write.send(WsMessage::Text(hello_string)).await?;
+    write
+        .send(WsMessage::Text(hello_string))
+        .await
+        .map_err(|e| SendspinCliError::Handshake(format!("sending hello: {}", e)))?;
 
-    // Wait for server hello
+    // Wait for server hello, skipping anything else the server sends first
     let mut read_temp = read;
     debug!("Waiting for server/hello...");
+    let deadline = tokio::time::Instant::now() + HELLO_TIMEOUT;
+    let hello_payload;
 
     loop {
-        if let Some(result) = read_temp.next().await {
+        let next = match tokio::time::timeout_at(deadline, read_temp.next()).await {
+            Ok(next) => next,
+            Err(_) => {
+                error!("No server/hello within {:?}", HELLO_TIMEOUT);
+                return Err(SendspinCliError::Handshake(format!(
+                    "no server/hello within {:?}",
+                    HELLO_TIMEOUT
+                )));
+            }
+        };
+        if let Some(result) = next {
             match result {
                 Ok(WsMessage::Text(text)) => {
                     debug!("Received text message: {}", text);
-                    let msg: Message = serde_json::from_str(&text)?;
-
-                    match msg {
-                        Message::ServerHello(server_hello) => {
+                    match serde_json::from_str::<Message>(&text) {
+                        Ok(Message::ServerHello(server_hello)) => {
+                            recent.lock().unwrap().push("server/hello", Instant::now());
                             info!(
                                 "Connected to server: {} ({})",
                                 server_hello.name, server_hello.server_id
                             );
+                            hello_payload = serde_json::from_str::<serde_json::Value>(&text)
+                                .ok()
+                                .and_then(|value| value.get("payload").cloned())
+                                .unwrap_or_default();
                             break;
                         }
-                        _ => {
-                            error!("Expected server/hello, got: {:?}", msg);
-                            return Err("Expected server/hello".into());
+                        Ok(msg) => {
+                            warn!("Expected server/hello, skipping: {:?}", msg);
+                        }
+                        Err(e) => {
+                            // A server refusing the hello may say why before closing
+                            let value = serde_json::from_str::<serde_json::Value>(&text)
+                                .unwrap_or_default();
+                            let msg_type = value.get("type").and_then(|t| t.as_str());
+                            let payload = value.get("payload").unwrap_or(&value);
+                            match msg_type
+                                .and_then(|t| server_error::server_error_from(t, payload))
+                            {
+                                Some(err) => error!("Server error before server/hello: {}", err),
+                                None => warn!(
+                                    "Skipping unparseable message while waiting for server/hello: {}",
+                                    e
+                                ),
+                            }
                         }
                     }
                 }
@@ -109,9 +463,10 @@ pub async fn connect_with_compat(
                     debug!("Received Ping/Pong, continuing to wait for server/hello");
                     continue;
                 }
-                Ok(WsMessage::Close(_)) => {
-                    error!("Server closed connection");
-                    return Err("Server closed connection".into());
+                Ok(WsMessage::Close(frame)) => {
+                    let disconnect = Disconnect::from_close(frame);
+                    error!("Before server/hello: {}", disconnect);
+                    return Err(SendspinCliError::Rejected(disconnect));
                 }
                 Ok(other) => {
                     debug!("Unexpected message type: {:?}", other);
@@ -119,12 +474,12 @@ pub async fn connect_with_compat(
                 }
                 Err(e) => {
                     error!("WebSocket error: {}", e);
-                    return Err(e.into());
+                    return Err(SendspinCliError::Handshake(e.to_string()));
                 }
             }
         } else {
             error!("Connection closed before receiving server/hello");
-            return Err("No server hello received".into());
+            return Err(SendspinCliError::Rejected(Disconnect::Ended));
         }
     }
 
@@ -132,57 +487,117 @@ pub async fn connect_with_compat(
     // We need to reconstruct the client state with the existing connection
     use tokio::sync::mpsc::unbounded_channel;
 
-    let (audio_tx, audio_rx) = unbounded_channel();
-    let (artwork_tx, _artwork_rx) = unbounded_channel();
+    info!(
+        "Audio channel: {} chunks, {} when full",
+        audio_channel_config.capacity,
+        match audio_channel_config.overflow {
+            AudioOverflow::DropOldest => "dropping oldest",
+            AudioOverflow::Block => "applying backpressure",
+        }
+    );
+    let (audio_tx, audio_rx) = audio_channel(audio_channel_config);
+    let (artwork_tx, artwork_rx) = unbounded_channel();
     let (visualizer_tx, _visualizer_rx) = unbounded_channel();
-    let (message_tx, message_rx) = unbounded_channel();
+    let (message_tx, message_rx) = mpsc::channel(CONTROL_CHANNEL_CAPACITY);
+    let (raw_tx, raw_rx) = mpsc::channel(CONTROL_CHANNEL_CAPACITY);
 
     let clock_sync = Arc::new(tokio::sync::Mutex::new(ClockSync::new()));
     let clock_sync_clone = Arc::clone(&clock_sync);
 
     // Spawn message router
+    let (disconnect_tx, disconnect_rx) = oneshot::channel();
     tokio::spawn(async move {
-        message_router(
+        let disconnect = message_router(
             read_temp,
             audio_tx,
             artwork_tx,
             visualizer_tx,
             message_tx,
+            raw_tx,
             clock_sync_clone,
+            recent,
         )
         .await;
+        let _ = disconnect_tx.send(disconnect);
     });
 
     let ws_sender = CompatWsSender {
         tx: Arc::new(tokio::sync::Mutex::new(write)),
     };
 
-    Ok((message_rx, audio_rx, clock_sync, ws_sender))
+    Ok(CompatConnection {
+        messages: message_rx,
+        raw_messages: raw_rx,
+        audio: audio_rx,
+        artwork: artwork_rx,
+        clock_sync,
+        sender: ws_sender,
+        disconnect: disconnect_rx,
+        server_hello: hello_payload,
+    })
 }
 
 // Copy of message_router from ProtocolClient
-async fn message_router(
-    mut read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    audio_tx: tokio::sync::mpsc::UnboundedSender<sendspin::protocol::client::AudioChunk>,
-    artwork_tx: tokio::sync::mpsc::UnboundedSender<sendspin::protocol::client::ArtworkChunk>,
+#[allow(clippy::too_many_arguments)]
+async fn message_router<S>(
+    mut read: S,
+    audio_tx: AudioSender,
+    artwork_tx: tokio::sync::mpsc::UnboundedSender<Artwork>,
     visualizer_tx: tokio::sync::mpsc::UnboundedSender<sendspin::protocol::client::VisualizerChunk>,
-    message_tx: tokio::sync::mpsc::UnboundedSender<Message>,
+    message_tx: mpsc::Sender<Message>,
+    raw_tx: mpsc::Sender<RawMessage>,
     _clock_sync: Arc<tokio::sync::Mutex<ClockSync>>,
-) {
+    recent: SharedRecent,
+) -> Disconnect
+where
+    S: Stream<Item = Result<WsMessage, WsError>> + Unpin,
+{
     use sendspin::protocol::client::BinaryFrame;
 
-    while let Some(msg) = read.next().await {
+    let mut artwork = ArtworkAssembler::new();
+    // Audio waiting for room in a full channel (--audio-overflow block)
+    let mut waiting: Option<AudioFrame> = None;
+
+    loop {
+        let msg = tokio::select! {
+            permit = audio_tx.reserve(), if waiting.is_some() => {
+                let chunk = waiting.take();
+                if let (Some(permit), Some(chunk)) = (permit, chunk) {
+                    permit.send(chunk);
+                }
+                continue;
+            }
+            msg = read.next() => msg,
+        };
+        let Some(msg) = msg else {
+            break;
+        };
         match msg {
             Ok(WsMessage::Binary(data)) => {
-                debug!("Received binary frame ({} bytes)", data.len());
+                trace!(bytes = data.len(), "Received binary frame");
+                // Audio takes the payload as-is; everything else goes to the library
+                let data = match frame::parse_audio(data) {
+                    Ok(chunk) => {
+                        trace!(
+                            ts = chunk.timestamp,
+                            bytes = chunk.data.len(),
+                            "Parsed audio chunk"
+                        );
+                        recent.lock().unwrap().push("audio", Instant::now());
+                        deliver(&audio_tx, &mut waiting, chunk).await;
+                        continue;
+                    }
+                    Err(data) => data,
+                };
                 match BinaryFrame::from_bytes(&data) {
                     Ok(BinaryFrame::Audio(chunk)) => {
-                        debug!(
-                            "Parsed audio chunk: timestamp={}, data_len={}",
-                            chunk.timestamp,
-                            chunk.data.len()
+                        trace!(
+                            ts = chunk.timestamp,
+                            bytes = chunk.data.len(),
+                            "Parsed audio chunk"
                         );
-                        let _ = audio_tx.send(chunk);
+                        recent.lock().unwrap().push("audio", Instant::now());
+                        deliver(&audio_tx, &mut waiting, AudioFrame::from(chunk)).await;
                     }
                     Ok(BinaryFrame::Artwork(chunk)) => {
                         debug!(
@@ -191,14 +606,20 @@ async fn message_router(
                             chunk.timestamp,
                             chunk.data.len()
                         );
-                        let _ = artwork_tx.send(chunk);
+                        recent.lock().unwrap().push("artwork", Instant::now());
+                        if let Some(image) =
+                            artwork.push(chunk.channel, chunk.timestamp, &chunk.data)
+                        {
+                            let _ = artwork_tx.send(image);
+                        }
                     }
                     Ok(BinaryFrame::Visualizer(chunk)) => {
-                        debug!(
-                            "Parsed visualizer chunk: timestamp={}, data_len={}",
-                            chunk.timestamp,
-                            chunk.data.len()
+                        trace!(
+                            ts = chunk.timestamp,
+                            bytes = chunk.data.len(),
+                            "Parsed visualizer chunk"
                         );
+                        recent.lock().unwrap().push("visualizer", Instant::now());
                         let _ = visualizer_tx.send(chunk);
                     }
                     Ok(BinaryFrame::Unknown { type_id, .. }) => {
@@ -211,10 +632,28 @@ async fn message_router(
             }
             Ok(WsMessage::Text(text)) => {
                 debug!("Received text message: {}", text);
-                match serde_json::from_str::<Message>(&text) {
+                let value = match serde_json::from_str::<serde_json::Value>(&text) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        debug!("Failed to parse message: {}", e);
+                        continue;
+                    }
+                };
+
+                if let Some(msg_type) = value.get("type").and_then(|t| t.as_str()) {
+                    recent.lock().unwrap().push(msg_type, Instant::now());
+                    let _ = raw_tx
+                        .send(RawMessage {
+                            msg_type: msg_type.to_string(),
+                            payload: value.get("payload").cloned().unwrap_or_default(),
+                        })
+                        .await;
+                }
+
+                match serde_json::from_value::<Message>(value) {
                     Ok(msg) => {
                         debug!("Parsed message: {:?}", msg);
-                        let _ = message_tx.send(msg);
+                        let _ = message_tx.send(msg).await;
                     }
                     Err(e) => {
                         debug!("Failed to parse message: {}", e);
@@ -224,15 +663,194 @@ async fn message_router(
             Ok(WsMessage::Ping(_)) | Ok(WsMessage::Pong(_)) => {
                 // Handled automatically
             }
-            Ok(WsMessage::Close(_)) => {
-                info!("Server closed connection");
-                break;
+            Ok(WsMessage::Close(frame)) => {
+                let disconnect = Disconnect::from_close(frame);
+                if disconnect.is_transient() {
+                    info!("{}", disconnect);
+                } else {
+                    error!("{}", disconnect);
+                }
+                return disconnect;
             }
             Err(e) => {
                 error!("WebSocket error: {}", e);
-                break;
+                return Disconnect::Error(e.to_string());
             }
             _ => {}
         }
     }
+    warn!("Server connection ended without a close frame");
+    Disconnect::Ended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn closed(code: u16, reason: &str) -> Disconnect {
+        Disconnect::Closed {
+            code: Some(code),
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_disconnect_is_transient() {
+        // Server shutting down or restarting: worth reconnecting
+        assert!(closed(1000, "").is_transient());
+        assert!(closed(1001, "shutdown").is_transient());
+        assert!(closed(1012, "").is_transient());
+        assert!(Disconnect::Ended.is_transient());
+        assert!(Disconnect::Error("reset by peer".to_string()).is_transient());
+
+        // Rejected: retrying would just be rejected again
+        assert!(!closed(1008, "unauthorized").is_transient());
+        assert!(!closed(1002, "").is_transient());
+        assert!(!closed(4401, "bad token").is_transient());
+        assert!(!closed(4403, "").is_transient());
+        // Application codes for a timeout or rate limit, or no HTTP status
+        assert!(closed(4429, "slow down").is_transient());
+        assert!(closed(4408, "").is_transient());
+        assert!(closed(4000, "").is_transient());
+    }
+
+    #[test]
+    fn test_disconnect_display() {
+        assert_eq!(
+            closed(1008, "unauthorized").to_string(),
+            "server closed the connection (code 1008 policy violation: \"unauthorized\")"
+        );
+        assert_eq!(
+            closed(1001, "").to_string(),
+            "server closed the connection (code 1001 going away)"
+        );
+        assert_eq!(
+            Disconnect::from_close(None).to_string(),
+            "server closed the connection (no close code)"
+        );
+    }
+
+    fn chunk(timestamp: i64) -> AudioFrame {
+        AudioFrame {
+            timestamp,
+            data: Bytes::from(vec![0u8; 3840]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_stays_bounded() {
+        let (tx, mut rx) = audio_channel(AudioChannelConfig {
+            capacity: 16,
+            overflow: AudioOverflow::DropOldest,
+        });
+        // A server far ahead of a consumer that hasn't read anything yet
+        for i in 0..10_000 {
+            tx.send(chunk(i)).await;
+        }
+        drop(tx);
+        let mut received = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            received.push(chunk.timestamp);
+        }
+        // Only the newest chunks were kept, the rest counted as dropped
+        assert_eq!(received, (10_000 - 16..10_000).collect::<Vec<_>>());
+        assert_eq!(rx.stats().dropped_chunks, 10_000 - 16);
+        assert_eq!(rx.stats().backpressure_waits, 0);
+    }
+
+    #[tokio::test]
+    async fn test_block_holds_producer_back() {
+        use std::sync::atomic::AtomicUsize;
+
+        let (tx, mut rx) = audio_channel(AudioChannelConfig {
+            capacity: 8,
+            overflow: AudioOverflow::Block,
+        });
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sent_clone = Arc::clone(&sent);
+        let producer = tokio::spawn(async move {
+            for i in 0..200 {
+                tx.send(chunk(i)).await;
+                sent_clone.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        // Throttled consumer: the producer can never get more than the
+        // channel's capacity (plus the chunk it is waiting to send) ahead
+        let mut received = 0;
+        while let Some(chunk) = rx.recv().await {
+            assert_eq!(chunk.timestamp, received as i64);
+            received += 1;
+            assert!(sent.load(Ordering::SeqCst) <= received + 8 + 1);
+            tokio::time::sleep(Duration::from_micros(200)).await;
+        }
+        producer.await.unwrap();
+        assert_eq!(received, 200);
+        assert!(rx.stats().backpressure_waits > 0);
+        assert_eq!(rx.stats().dropped_chunks, 0);
+    }
+
+    #[test]
+    fn test_channel_holds_what_the_queue_can() {
+        assert_eq!(audio_channel_capacity(Duration::from_secs(1)), 50);
+        assert_eq!(audio_channel_capacity(Duration::from_millis(5944)), 298);
+        assert_eq!(audio_channel_capacity(Duration::ZERO), 1);
+        assert_eq!(AudioChannelConfig::default().capacity, 298);
+    }
+
+    #[tokio::test]
+    async fn test_block_lets_commands_past_waiting_audio() {
+        use futures_util::stream;
+        use tokio::sync::mpsc::unbounded_channel;
+
+        let (audio_tx, mut audio_rx) = audio_channel(AudioChannelConfig {
+            capacity: 2,
+            overflow: AudioOverflow::Block,
+        });
+        let audio = |timestamp: i64| {
+            let mut frame = vec![frame::AUDIO_CHUNK];
+            frame.extend_from_slice(&timestamp.to_be_bytes());
+            frame.extend_from_slice(&[0; 16]);
+            Ok(WsMessage::Binary(frame))
+        };
+        let command =
+            r#"{"type":"server/command","payload":{"player":{"command":"volume","volume":40}}}"#;
+        // Two chunks fill the channel and a third waits for room; the
+        // command right behind it must not wait with it
+        let frames = vec![
+            audio(0),
+            audio(1),
+            audio(2),
+            Ok(WsMessage::Text(command.to_string())),
+        ];
+        let (message_tx, _message_rx) = mpsc::channel(CONTROL_CHANNEL_CAPACITY);
+        let (raw_tx, mut raw_rx) = mpsc::channel(CONTROL_CHANNEL_CAPACITY);
+        let (artwork_tx, _artwork_rx) = unbounded_channel();
+        let (visualizer_tx, _visualizer_rx) = unbounded_channel();
+        let router = tokio::spawn(message_router(
+            stream::iter(frames).chain(stream::pending()),
+            audio_tx,
+            artwork_tx,
+            visualizer_tx,
+            message_tx,
+            raw_tx,
+            Arc::new(tokio::sync::Mutex::new(ClockSync::new())),
+            crate::state_report::RecentMessages::shared(),
+        ));
+
+        // Nothing has read any audio yet
+        let raw = tokio::time::timeout(Duration::from_secs(1), raw_rx.recv())
+            .await
+            .expect("command held up behind audio")
+            .unwrap();
+        assert_eq!(raw.msg_type, "server/command");
+
+        // The waiting chunk is queued, in order, once there is room
+        for timestamp in 0..3 {
+            assert_eq!(audio_rx.recv().await.unwrap().timestamp, timestamp);
+        }
+        assert_eq!(audio_rx.stats().backpressure_waits, 1);
+        router.abort();
+    }
 }
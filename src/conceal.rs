@@ -0,0 +1,142 @@
+// Underrun Concealment
+//
+// When the queue runs dry mid-stream (the network stalled, the server fell
+// behind), the device would stop wherever the last buffer ended and start
+// again wherever the next one begins: a click at each edge, and on ALSA an
+// xrun to recover from. Instead the player keeps the device fed with silence
+// in the output's format until audio arrives. The first silence glides from
+// the last frame written down to zero, and the audio that ends the gap fades
+// in over as long, so an underrun is heard as a clean dropout.
+//
+// Silence goes out a chunk at a time, shortly before the written audio runs
+// out, so a buffer arriving mid-gap isn't pushed back by more than the chunk.
+// Each concealed gap counts as one underrun and the concealed time is added
+// up in the playback statistics.
+
+use crate::float;
+use sendspin::audio::Sample;
+use std::time::Duration;
+
+/// Fade at either edge of a concealed gap
+pub const EDGE: Duration = Duration::from_millis(5);
+
+/// How long before the written audio runs out the next silence is written
+pub const LEAD: Duration = Duration::from_millis(5);
+
+/// Silence written at a time while nothing arrives
+pub const CHUNK: Duration = Duration::from_millis(10);
+
+/// Silence for a gap in the audio, continuing from what was written last
+#[derive(Debug, Default)]
+pub struct Concealer {
+    last_frame: Vec<Sample>, // Last frame of audio written
+    active: bool,            // Silence written since that audio
+}
+
+impl Concealer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Audio was written: remember where it ended
+    pub fn wrote(&mut self, samples: &[Sample], channels: usize) {
+        let channels = channels.max(1);
+        if samples.len() >= channels {
+            self.last_frame.clear();
+            self.last_frame
+                .extend_from_slice(&samples[samples.len() - channels..]);
+        }
+    }
+
+    /// Same for the f32 pipeline
+    pub fn wrote_f32(&mut self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        if samples.len() >= channels {
+            self.last_frame = float::from_f32(&samples[samples.len() - channels..]);
+        }
+    }
+
+    /// Whether audio was written since the last reset; a gap before a
+    /// stream's first audio isn't one to conceal
+    pub fn follows_audio(&self) -> bool {
+        !self.last_frame.is_empty()
+    }
+
+    /// Whether silence was written since the last audio
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Audio is back; true when it ends a concealed gap (fade it in)
+    pub fn resume(&mut self) -> bool {
+        std::mem::take(&mut self.active)
+    }
+
+    /// `frames` frames of silence; the first after audio glides from its
+    /// last frame to zero over `edge` frames
+    pub fn silence(&mut self, frames: usize, channels: usize, edge: usize) -> Vec<Sample> {
+        let channels = channels.max(1);
+        let mut samples = vec![Sample(0); frames * channels];
+        if !self.active && self.last_frame.len() == channels {
+            let edge = edge.min(frames);
+            for (i, frame) in samples.chunks_exact_mut(channels).take(edge).enumerate() {
+                let gain = 1.0 - (i + 1) as f32 / edge as f32;
+                for (sample, last) in frame.iter_mut().zip(&self.last_frame) {
+                    *sample = Sample((last.0 as f32 * gain) as i32);
+                }
+            }
+        }
+        self.active = true;
+        samples
+    }
+
+    /// Forget the audio written so far (stop, new stream, output reopened)
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Frames covering `duration` at `sample_rate`
+pub fn frames(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64).round() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silence_glides_from_last_frame() {
+        let mut concealer = Concealer::new();
+        concealer.wrote(&[Sample(0), Sample(0), Sample(4000), Sample(-4000)], 2);
+        assert!(!concealer.is_active());
+
+        let first = concealer.silence(6, 2, 4);
+        let left: Vec<i32> = first.chunks(2).map(|frame| frame[0].0).collect();
+        let right: Vec<i32> = first.chunks(2).map(|frame| frame[1].0).collect();
+        assert_eq!(left, vec![3000, 2000, 1000, 0, 0, 0]);
+        assert_eq!(right, vec![-3000, -2000, -1000, 0, 0, 0]);
+        assert!(concealer.is_active());
+
+        // Later silence is plain zeros
+        assert!(concealer.silence(3, 2, 4).iter().all(|s| s.0 == 0));
+
+        // The audio that ends the gap fades in, once
+        assert!(concealer.resume());
+        assert!(!concealer.resume());
+    }
+
+    #[test]
+    fn test_silence_without_audio_is_zeros() {
+        let mut concealer = Concealer::new();
+        assert_eq!(concealer.silence(2, 2, 4), vec![Sample(0); 4]);
+
+        // A glide shorter than asked still reaches zero
+        concealer.reset();
+        concealer.wrote_f32(&[0.5, 0.5], 2);
+        let short = concealer.silence(2, 2, 4);
+        assert_eq!(short[2..], [Sample(0), Sample(0)]);
+        assert!(short[0].0 > 0);
+        assert_eq!(frames(Duration::from_millis(10), 48000), 480);
+    }
+}
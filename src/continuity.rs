@@ -0,0 +1,292 @@
+// Stream Continuity
+//
+// Audio chunks carry no sequence number, but each one's timestamp is its
+// position in the stream (server µs), so the next chunk should start where
+// the previous one ended. The client checks that as chunks are decoded:
+//
+// - A gap (chunks lost on the way) is filled with silence of the missing
+//   length, so the device timeline stays aligned and the fallback schedule
+//   (no clock sync yet) doesn't play the next chunk too early.
+// - A chunk that was already received in full is dropped; one that overlaps
+//   the previous chunk has the overlapping frames cut off.
+//
+// Differences under a millisecond are timestamp rounding, not loss. Gaps
+// over a second are a discontinuity the server meant (or a clock jump), not
+// something to fill with silence; the check follows the new timestamps.
+//
+// The counts run for the whole session; each stream's share is logged at its
+// end, and `--stats` shows the share of each interval.
+
+use sendspin::audio::Sample;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Timestamp differences treated as rounding
+pub const TOLERANCE_US: i64 = 1000;
+
+/// Largest gap filled with silence
+pub const MAX_FILL: Duration = Duration::from_secs(1);
+
+/// Gap and duplicate counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub gaps: u64,                // Holes in the timeline filled with silence
+    pub silence_inserted_us: u64, // Total length of that silence
+    pub duplicates: u64,          // Chunks dropped or trimmed as already received
+}
+
+impl StreamStats {
+    /// Counts since `earlier`, taken from the same tracker
+    pub fn since(&self, earlier: &StreamStats) -> StreamStats {
+        StreamStats {
+            gaps: self.gaps.saturating_sub(earlier.gaps),
+            silence_inserted_us: self
+                .silence_inserted_us
+                .saturating_sub(earlier.silence_inserted_us),
+            duplicates: self.duplicates.saturating_sub(earlier.duplicates),
+        }
+    }
+}
+
+/// What to do with an incoming chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Continuity {
+    Play,                   // Follows the previous chunk (or starts the stream)
+    Fill { frames: usize }, // Play this much silence first
+    Trim { from: i64 },     // Drop the frames before this timestamp
+    Drop,                   // Entirely received already
+}
+
+/// Length of `frames` at `sample_rate`, in µs
+fn frames_us(frames: usize, sample_rate: u32) -> i64 {
+    (frames as i64 * 1_000_000) / sample_rate.max(1) as i64
+}
+
+/// Frames covering `us` at `sample_rate`
+fn us_frames(us: i64, sample_rate: u32) -> usize {
+    ((us as f64 * sample_rate as f64 / 1e6).round() as i64).max(0) as usize
+}
+
+/// Expected timestamp of the next chunk, and the counters
+#[derive(Debug, Default)]
+pub struct ContinuityTracker {
+    next_timestamp: Option<i64>,
+    pub stats: StreamStats,    // Since the tracker was made
+    stream_start: StreamStats, // `stats` when the current stream started
+}
+
+impl ContinuityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget the position, e.g. after a seek or a pause
+    pub fn reset(&mut self) {
+        self.next_timestamp = None;
+    }
+
+    /// A new stream: forget the position and count from zero for it
+    pub fn new_stream(&mut self) {
+        self.reset();
+        self.stream_start = self.stats;
+    }
+
+    /// Counts of the current stream
+    pub fn stream_stats(&self) -> StreamStats {
+        self.stats.since(&self.stream_start)
+    }
+
+    /// Whether a chunk at `timestamp` starts where the previous one ended
+    pub fn continues(&self, timestamp: i64) -> bool {
+        self.next_timestamp
+            .is_some_and(|expected| (timestamp - expected).abs() <= TOLERANCE_US)
+    }
+
+    /// Check a chunk of `frames` frames at `timestamp` against the end of
+    /// the previous one
+    pub fn check(&mut self, timestamp: i64, frames: usize, sample_rate: u32) -> Continuity {
+        let end = timestamp + frames_us(frames, sample_rate);
+        let Some(expected) = self.next_timestamp else {
+            self.next_timestamp = Some(end);
+            return Continuity::Play;
+        };
+        let offset = timestamp - expected;
+
+        if offset > TOLERANCE_US && offset <= MAX_FILL.as_micros() as i64 {
+            self.next_timestamp = Some(end);
+            let frames = us_frames(offset, sample_rate);
+            self.stats.gaps += 1;
+            self.stats.silence_inserted_us += frames_us(frames, sample_rate) as u64;
+            Continuity::Fill { frames }
+        } else if offset < -TOLERANCE_US && offset >= -(MAX_FILL.as_micros() as i64) {
+            self.stats.duplicates += 1;
+            if end <= expected + TOLERANCE_US {
+                return Continuity::Drop;
+            }
+            self.next_timestamp = Some(end);
+            Continuity::Trim { from: expected }
+        } else {
+            // In step, or too far off to be loss: follow the new timestamps
+            self.next_timestamp = Some(end);
+            Continuity::Play
+        }
+    }
+}
+
+/// Apply the outcome of `check` to a decoded chunk: the timestamp and
+/// samples to play, None when the chunk is dropped
+pub fn realign(
+    continuity: Continuity,
+    timestamp: i64,
+    samples: Arc<[Sample]>,
+    channels: usize,
+    sample_rate: u32,
+) -> Option<(i64, Arc<[Sample]>)> {
+    let channels = channels.max(1);
+    match continuity {
+        Continuity::Play => Some((timestamp, samples)),
+        Continuity::Fill { frames } => {
+            let mut filled = vec![Sample(0); frames * channels];
+            filled.extend_from_slice(&samples);
+            Some((
+                timestamp - frames_us(frames, sample_rate),
+                Arc::from(filled),
+            ))
+        }
+        Continuity::Trim { from } => {
+            let skip = us_frames(from - timestamp, sample_rate).min(samples.len() / channels);
+            Some((
+                timestamp + frames_us(skip, sample_rate),
+                Arc::from(&samples[skip * channels..]),
+            ))
+        }
+        Continuity::Drop => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+    const CHUNK: usize = 960; // 20 ms
+
+    #[test]
+    fn test_contiguous_chunks_play() {
+        let mut tracker = ContinuityTracker::new();
+        for i in 0..100 {
+            assert_eq!(tracker.check(i * 20_000, CHUNK, RATE), Continuity::Play);
+        }
+        // Rounding in the server's timestamps isn't a gap
+        assert_eq!(tracker.check(2_000_300, CHUNK, RATE), Continuity::Play);
+        assert_eq!(tracker.stats, StreamStats::default());
+    }
+
+    #[test]
+    fn test_lost_chunks_are_filled_with_silence() {
+        let mut tracker = ContinuityTracker::new();
+        tracker.check(0, CHUNK, RATE);
+        // Two chunks lost: 40 ms of silence keeps the next one in place
+        assert_eq!(
+            tracker.check(60_000, CHUNK, RATE),
+            Continuity::Fill { frames: 1920 }
+        );
+        assert_eq!(tracker.check(80_000, CHUNK, RATE), Continuity::Play);
+        assert_eq!(tracker.stats.gaps, 1);
+        assert_eq!(tracker.stats.silence_inserted_us, 40_000);
+    }
+
+    #[test]
+    fn test_duplicates_dropped_and_overlaps_trimmed() {
+        let mut tracker = ContinuityTracker::new();
+        tracker.check(0, CHUNK, RATE);
+        tracker.check(20_000, CHUNK, RATE);
+        // The same chunk again
+        assert_eq!(tracker.check(20_000, CHUNK, RATE), Continuity::Drop);
+        // Starts 5 ms before the previous one ended
+        assert_eq!(
+            tracker.check(35_000, CHUNK, RATE),
+            Continuity::Trim { from: 40_000 }
+        );
+        assert_eq!(tracker.check(55_000, CHUNK, RATE), Continuity::Play);
+        assert_eq!(tracker.stats.duplicates, 2);
+    }
+
+    #[test]
+    fn test_large_jumps_and_reset_start_over() {
+        let mut tracker = ContinuityTracker::new();
+        tracker.check(0, CHUNK, RATE);
+        // 10 s ahead or behind: a discontinuity, not loss
+        assert_eq!(tracker.check(10_000_000, CHUNK, RATE), Continuity::Play);
+        assert_eq!(tracker.check(0, CHUNK, RATE), Continuity::Play);
+        assert_eq!(tracker.stats, StreamStats::default());
+
+        tracker.check(20_000, CHUNK, RATE);
+        tracker.reset();
+        assert_eq!(tracker.check(500_000, CHUNK, RATE), Continuity::Play);
+    }
+
+    #[test]
+    fn test_counts_per_stream() {
+        let mut tracker = ContinuityTracker::new();
+        tracker.check(0, CHUNK, RATE);
+        tracker.check(60_000, CHUNK, RATE);
+        assert_eq!(tracker.stream_stats().gaps, 1);
+
+        // The next stream starts from zero, the session keeps counting
+        tracker.new_stream();
+        assert_eq!(tracker.stream_stats(), StreamStats::default());
+        tracker.check(0, CHUNK, RATE);
+        tracker.check(0, CHUNK, RATE);
+        assert_eq!(tracker.stream_stats().duplicates, 1);
+        assert_eq!(tracker.stream_stats().gaps, 0);
+        assert_eq!(tracker.stats.gaps, 1);
+        assert_eq!(tracker.stats.duplicates, 1);
+    }
+
+    #[test]
+    fn test_continues() {
+        let mut tracker = ContinuityTracker::new();
+        // Nothing to continue before the first chunk
+        assert!(!tracker.continues(0));
+        tracker.check(0, CHUNK, RATE);
+        assert!(tracker.continues(20_000));
+        assert!(tracker.continues(20_400));
+        assert!(!tracker.continues(0));
+        assert!(!tracker.continues(5_000_000));
+        tracker.reset();
+        assert!(!tracker.continues(20_000));
+    }
+
+    #[test]
+    fn test_realign_chunks() {
+        let chunk: Arc<[Sample]> = (1..=8).map(Sample).collect();
+        // 2 stereo frames of silence at 1 kHz start 2 ms earlier
+        let (timestamp, samples) = realign(
+            Continuity::Fill { frames: 2 },
+            10_000,
+            chunk.clone(),
+            2,
+            1000,
+        )
+        .unwrap();
+        assert_eq!(timestamp, 8_000);
+        assert_eq!(samples.len(), 12);
+        assert!(samples[..4].iter().all(|s| s.0 == 0));
+        assert_eq!(samples[4].0, 1);
+
+        // The first 3 frames were already played
+        let (timestamp, samples) = realign(
+            Continuity::Trim { from: 13_000 },
+            10_000,
+            chunk.clone(),
+            2,
+            1000,
+        )
+        .unwrap();
+        assert_eq!(timestamp, 13_000);
+        assert_eq!(samples.iter().map(|s| s.0).collect::<Vec<_>>(), vec![7, 8]);
+
+        assert!(realign(Continuity::Drop, 10_000, chunk, 2, 1000).is_none());
+    }
+}
@@ -0,0 +1,190 @@
+// Crossfade Mixer
+//
+// When a new stream starts while the previous stream's tail is still queued,
+// the tail is kept as a second logical stream. Once the first buffer of the
+// new stream is due, the two are mixed sample-wise: the tail fades out while
+// the new stream fades in over the crossfade window (or the remaining tail,
+// whichever is shorter). Tail audio past the window is discarded.
+
+use sendspin::audio::{AudioBuffer, AudioFormat, Sample};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Whether two streams can be mixed without conversion
+pub fn formats_compatible(a: &AudioFormat, b: &AudioFormat) -> bool {
+    a.sample_rate == b.sample_rate && a.channels == b.channels
+}
+
+/// An in-progress crossfade from an outgoing tail into a new stream
+pub struct Crossfade {
+    tail: Vec<Sample>,
+    channels: usize,
+    fade_frames: usize,
+    position: usize, // frames mixed so far
+}
+
+impl Crossfade {
+    /// Start a crossfade over at most `max_frames` of the outgoing tail
+    pub fn new(outgoing: VecDeque<AudioBuffer>, channels: usize, max_frames: usize) -> Self {
+        let channels = channels.max(1);
+        let tail: Vec<Sample> = outgoing
+            .iter()
+            .flat_map(|buffer| buffer.samples.iter().copied())
+            .collect();
+        let fade_frames = (tail.len() / channels).min(max_frames);
+        Crossfade {
+            tail,
+            channels,
+            fade_frames,
+            position: 0,
+        }
+    }
+
+    /// Length of the fade in frames
+    pub fn fade_frames(&self) -> usize {
+        self.fade_frames
+    }
+
+    /// True once the whole fade window has been mixed
+    pub fn is_done(&self) -> bool {
+        self.position >= self.fade_frames
+    }
+
+    /// Mix the next incoming samples with the fading tail
+    pub fn mix(&mut self, incoming: &[Sample]) -> Arc<[Sample]> {
+        let mut out = incoming.to_vec();
+        for frame in out.chunks_exact_mut(self.channels) {
+            if self.is_done() {
+                break;
+            }
+            // Linear envelopes: incoming rises 0 → 1 while the tail falls 1 → 0
+            let t = self.position as f32 / self.fade_frames as f32;
+            let tail_frame = &self.tail[self.position * self.channels..];
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let mixed = sample.0 as f32 * t + tail_frame[ch].0 as f32 * (1.0 - t);
+                *sample = Sample(mixed.round() as i32);
+            }
+            self.position += 1;
+        }
+        Arc::from(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+    use std::time::Instant;
+
+    const RATE: u32 = 1000;
+
+    fn format() -> AudioFormat {
+        AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate: RATE,
+            channels: 2,
+            bit_depth: 16,
+            codec_header: None,
+        }
+    }
+
+    /// Stereo sine tone, `frames` long
+    fn tone(freq: f32, amplitude: f32, frames: usize) -> Vec<Sample> {
+        (0..frames)
+            .flat_map(|i| {
+                let v = (2.0 * std::f32::consts::PI * freq * i as f32 / RATE as f32).sin();
+                let s = Sample((v * amplitude) as i32);
+                [s, s]
+            })
+            .collect()
+    }
+
+    fn buffer(samples: Vec<Sample>) -> AudioBuffer {
+        AudioBuffer {
+            timestamp: 0,
+            play_at: Instant::now(),
+            samples: Arc::from(samples.into_boxed_slice()),
+            format: format(),
+        }
+    }
+
+    /// Peak absolute value of a window of stereo frames
+    fn peak(samples: &[Sample], frames: std::ops::Range<usize>) -> i32 {
+        samples[frames.start * 2..frames.end * 2]
+            .iter()
+            .map(|s| s.0.abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fade_length_limited_by_tail() {
+        let tail = VecDeque::from(vec![buffer(tone(50.0, 1000.0, 100))]);
+        let fade = Crossfade::new(tail, 2, 500);
+        assert_eq!(fade.fade_frames(), 100);
+
+        let tail = VecDeque::from(vec![buffer(tone(50.0, 1000.0, 100))]);
+        let fade = Crossfade::new(tail, 2, 40);
+        assert_eq!(fade.fade_frames(), 40);
+    }
+
+    #[test]
+    fn test_tail_fades_out() {
+        // Outgoing 50 Hz tone into silence: the envelope must fall linearly to zero
+        let tail = VecDeque::from(vec![
+            buffer(tone(50.0, 10000.0, 200)),
+            buffer(tone(50.0, 10000.0, 200)),
+        ]);
+        let mut fade = Crossfade::new(tail, 2, 400);
+        let out = fade.mix(&vec![Sample(0); 800]);
+
+        let start = peak(&out, 0..40);
+        let middle = peak(&out, 180..220);
+        let end = peak(&out, 360..400);
+        assert!(start > 9000, "start peak {}", start);
+        assert!((4000..6000).contains(&middle), "middle peak {}", middle);
+        assert!(end < 1100, "end peak {}", end);
+        assert!(fade.is_done());
+    }
+
+    #[test]
+    fn test_incoming_fades_in_across_buffers() {
+        let tail = VecDeque::from(vec![buffer(vec![Sample(0); 400])]);
+        let mut fade = Crossfade::new(tail, 2, 200);
+
+        // Incoming tone arrives as two buffers; the ramp must continue across them
+        let first = fade.mix(&tone(50.0, 10000.0, 100));
+        let second = fade.mix(&tone(50.0, 10000.0, 100));
+        assert!(peak(&first, 0..20) < 1100);
+        assert!(peak(&second, 80..100) > 9000);
+        assert!(fade.is_done());
+
+        // Once done, incoming audio passes through untouched
+        let after = fade.mix(&[Sample(1234), Sample(-1234)]);
+        assert_eq!(after[0].0, 1234);
+        assert_eq!(after[1].0, -1234);
+    }
+
+    #[test]
+    fn test_equal_level_tones_keep_constant_level() {
+        // Identical in-phase material on both sides sums to the original level
+        let tail = VecDeque::from(vec![buffer(tone(50.0, 10000.0, 200))]);
+        let mut fade = Crossfade::new(tail, 2, 200);
+        let incoming = tone(50.0, 10000.0, 200);
+        let out = fade.mix(&incoming);
+
+        for (mixed, original) in out.iter().zip(incoming.iter()) {
+            assert!((mixed.0 - original.0).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_formats_compatible() {
+        let a = format();
+        let mut b = format();
+        b.bit_depth = 24;
+        assert!(formats_compatible(&a, &b));
+        b.sample_rate = 48000;
+        assert!(!formats_compatible(&a, &b));
+    }
+}
@@ -0,0 +1,795 @@
+// cpal Hosts and Devices
+//
+// cpal can drive several audio APIs ("hosts") on one platform, e.g. ALSA and
+// JACK on Linux or WASAPI and ASIO on Windows. The cpal output is built here,
+// on the requested host or the default one: a cpal stream on that host's
+// default device, fed from a short sample buffer that `write` fills
+// (blocking while it is full). A stream error (e.g. the device was
+// unplugged) makes later writes fail, so the player notices and starts
+// device recovery. With `--device-buffer` the stream asks cpal for a fixed
+// buffer size.
+//
+// The stream uses the device's default sample format when it can carry the
+// audio. For an f32 device the buffer holds f32 and the player switches to
+// its float pipeline, so nothing is rounded to integers on the way.
+//
+// `--list-formats` prints every config range each output device reports
+// (channels, rate range, sample format), marks the ones the player can't
+// write, and shows which candidate rates hello would advertise for the
+// device, so a failed negotiation can be traced to what the device offers.
+// With `--alsa-device` it lists only the device of that name.
+
+use crate::float;
+use crate::negotiate::{self, DeviceRates};
+use crate::output::{DeviceFormat, OutputBackend};
+use clap::ValueEnum;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BufferSize, SampleFormat, SizedSample, StreamConfig, SupportedBufferSize,
+    SupportedStreamConfig, SupportedStreamConfigRange,
+};
+use sendspin::audio::{AudioFormat, Sample};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// How far `write` may run ahead of the device callback
+const BUFFER_AHEAD: Duration = Duration::from_millis(50);
+
+/// Requested device buffer size, "1024" (frames) or "20ms"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceBuffer {
+    Frames(u32),
+    Millis(u32),
+}
+
+impl FromStr for DeviceBuffer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (value, millis) = match s.strip_suffix("ms") {
+            Some(ms) => (ms.trim(), true),
+            None => (s, false),
+        };
+        let value: u32 = value
+            .parse()
+            .map_err(|_| format!("invalid buffer size '{}' (use frames or e.g. 20ms)", s))?;
+        if value == 0 {
+            return Err("buffer size must be greater than 0".to_string());
+        }
+        Ok(if millis {
+            DeviceBuffer::Millis(value)
+        } else {
+            DeviceBuffer::Frames(value)
+        })
+    }
+}
+
+impl DeviceBuffer {
+    /// Size in frames at a sample rate
+    pub fn frames(self, sample_rate: u32) -> u32 {
+        match self {
+            DeviceBuffer::Frames(frames) => frames,
+            DeviceBuffer::Millis(ms) => (sample_rate as u64 * ms as u64 / 1000).max(1) as u32,
+        }
+    }
+}
+
+/// Playing time of a number of frames
+pub fn frames_to_duration(frames: u32, sample_rate: u32) -> Duration {
+    Duration::from_micros(frames as u64 * 1_000_000 / sample_rate.max(1) as u64)
+}
+
+/// cpal buffer size for a request, None if the device's range excludes it
+pub fn fixed_buffer_size(frames: u32, supported: &SupportedBufferSize) -> Option<BufferSize> {
+    match *supported {
+        SupportedBufferSize::Range { min, max } if frames < min || frames > max => None,
+        // Range includes it, or unknown: let the device decide when building
+        _ => Some(BufferSize::Fixed(frames)),
+    }
+}
+
+/// Names of the hosts compiled into this build
+pub fn host_names() -> Vec<&'static str> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| id.name())
+        .collect()
+}
+
+/// Match a requested host name (case-insensitive) against the available ones
+pub fn match_host<'a>(requested: &str, available: &[&'a str]) -> Result<&'a str, String> {
+    available
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(requested))
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "audio host '{}' not available (valid hosts: {})",
+                requested,
+                available.join(", ")
+            )
+        })
+}
+
+/// Open a cpal host by name
+pub fn open_host(requested: &str) -> Result<cpal::Host, Box<dyn std::error::Error>> {
+    let name = match_host(requested, &host_names())?;
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name() == name)
+        .ok_or("audio host disappeared")?;
+    Ok(cpal::host_from_id(id)?)
+}
+
+/// Sample rate ranges of the host's default output device for a channel count
+pub fn probe_rates(
+    host: Option<&str>,
+    channels: u8,
+) -> Result<DeviceRates, Box<dyn std::error::Error>> {
+    let host = match host {
+        Some(name) => open_host(name)?,
+        None => cpal::default_host(),
+    };
+    let device = host
+        .default_output_device()
+        .ok_or("no default output device")?;
+    let ranges = device
+        .supported_output_configs()?
+        .filter(|range| {
+            range.channels() == channels as u16 && format_preference(range.sample_format()) > 0
+        })
+        .map(|range| (range.min_sample_rate().0, range.max_sample_rate().0))
+        .collect();
+    Ok(DeviceRates { ranges })
+}
+
+/// Rate the host's default output device runs at (its mixer's rate on a
+/// shared-mode host); other rates it accepts are resampled by the OS
+pub fn native_rate(host: Option<&str>) -> Result<u32, Box<dyn std::error::Error>> {
+    let host = match host {
+        Some(name) => open_host(name)?,
+        None => cpal::default_host(),
+    };
+    let device = host
+        .default_output_device()
+        .ok_or("no default output device")?;
+    Ok(device.default_output_config()?.sample_rate().0)
+}
+
+/// Name of the host's default output device
+pub fn default_output_name(host: Option<&str>) -> Option<String> {
+    let host = match host {
+        Some(name) => open_host(name).ok()?,
+        None => cpal::default_host(),
+    };
+    host.default_output_device()?.name().ok()
+}
+
+/// Print every output device, grouped by host
+pub fn list_devices() {
+    for id in cpal::available_hosts() {
+        println!("{}:", id.name());
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(e) => {
+                println!("  (unavailable: {})", e);
+                continue;
+            }
+        };
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        match host.output_devices() {
+            Ok(devices) => {
+                for device in devices {
+                    let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+                    let marker = if Some(&name) == default_name.as_ref() {
+                        " (default)"
+                    } else {
+                        ""
+                    };
+                    println!("  {}{}", name, marker);
+                }
+            }
+            Err(e) => println!("  (cannot list devices: {})", e),
+        }
+    }
+}
+
+/// How `--list-formats` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListStyle {
+    Text, // Readable, one block per device
+    Json, // One JSON object per device and line
+}
+
+/// One config range an output device reports
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FormatRange {
+    pub channels: u16,
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub sample_format: String, // cpal name, e.g. "i32"
+    pub usable: bool,          // The player can write this sample format
+}
+
+/// Everything an output device reports it can play
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceFormats {
+    pub host: String,
+    pub device: String,
+    pub default: bool,
+    pub formats: Vec<FormatRange>,
+    pub advertised_rates: Vec<u32>, // Candidate rates hello would offer for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why the formats couldn't be read
+}
+
+impl DeviceFormats {
+    pub fn new(
+        host: &str,
+        device: &str,
+        default: bool,
+        ranges: &[SupportedStreamConfigRange],
+    ) -> Self {
+        let formats: Vec<FormatRange> = ranges
+            .iter()
+            .map(|range| FormatRange {
+                channels: range.channels(),
+                min_rate: range.min_sample_rate().0,
+                max_rate: range.max_sample_rate().0,
+                sample_format: format!("{:?}", range.sample_format()).to_lowercase(),
+                usable: format_preference(range.sample_format()) > 0,
+            })
+            .collect();
+        // Same filter as probe_rates
+        let rates = DeviceRates {
+            ranges: formats
+                .iter()
+                .filter(|f| f.usable && f.channels == negotiate::CHANNELS as u16)
+                .map(|f| (f.min_rate, f.max_rate))
+                .collect(),
+        };
+        DeviceFormats {
+            host: host.to_string(),
+            device: device.to_string(),
+            default,
+            advertised_rates: negotiate::CANDIDATE_RATES
+                .into_iter()
+                .filter(|&rate| rates.supports(rate))
+                .collect(),
+            formats,
+            error: None,
+        }
+    }
+
+    /// A device whose formats couldn't be read
+    pub fn failed(host: &str, device: &str, default: bool, error: String) -> Self {
+        DeviceFormats {
+            error: Some(error),
+            ..Self::new(host, device, default, &[])
+        }
+    }
+
+    /// Single-line JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for DeviceFormats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = if self.default { " (default)" } else { "" };
+        writeln!(f, "{}: {}{}", self.host, self.device, marker)?;
+        if let Some(error) = &self.error {
+            return writeln!(f, "  (cannot read formats: {})", error);
+        }
+        for range in &self.formats {
+            let rates = if range.min_rate == range.max_rate {
+                format!("{} Hz", range.min_rate)
+            } else {
+                format!("{}-{} Hz", range.min_rate, range.max_rate)
+            };
+            let usable = if range.usable { "" } else { "  (not usable)" };
+            writeln!(
+                f,
+                "  {} ch  {:<15} {}{}",
+                range.channels, rates, range.sample_format, usable
+            )?;
+        }
+        if self.advertised_rates.is_empty() {
+            writeln!(
+                f,
+                "  advertised rates: none ({} ch in i16, i32 or f32 needed)",
+                negotiate::CHANNELS
+            )
+        } else {
+            let rates: Vec<String> = self.advertised_rates.iter().map(u32::to_string).collect();
+            writeln!(f, "  advertised rates: {} Hz", rates.join(", "))
+        }
+    }
+}
+
+/// Print what each output device supports, on `host` or on every host;
+/// with `device`, only the devices of that name
+pub fn list_formats(
+    host: Option<&str>,
+    device: Option<&str>,
+    style: ListStyle,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut listed = 0;
+    let ids = match host {
+        Some(name) => vec![open_host(name)?.id()],
+        None => cpal::available_hosts(),
+    };
+    for id in ids {
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(e) => {
+                warn!("Audio host {} unavailable: {}", id.name(), e);
+                continue;
+            }
+        };
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!("Cannot list devices of {}: {}", id.name(), e);
+                continue;
+            }
+        };
+        for output in devices {
+            let name = output.name().unwrap_or_else(|_| "<unknown>".to_string());
+            if device.is_some_and(|wanted| wanted != name) {
+                continue;
+            }
+            listed += 1;
+            let default = Some(&name) == default_name.as_ref();
+            let formats = match output.supported_output_configs() {
+                Ok(ranges) => {
+                    let ranges: Vec<_> = ranges.collect();
+                    DeviceFormats::new(id.name(), &name, default, &ranges)
+                }
+                Err(e) => DeviceFormats::failed(id.name(), &name, default, e.to_string()),
+            };
+            match style {
+                ListStyle::Text => println!("{}", formats),
+                ListStyle::Json => println!("{}", formats.to_json()),
+            }
+        }
+    }
+    match device {
+        Some(name) if listed == 0 => Err(format!("no output device named '{}'", name).into()),
+        _ => Ok(()),
+    }
+}
+
+/// Samples waiting for the stream callback, in the stream's sample domain
+enum Pending {
+    Int(Arc<Mutex<VecDeque<Sample>>>),
+    Float(Arc<Mutex<VecDeque<f32>>>), // f32 device: the player hands over f32 directly
+}
+
+/// cpal output stream on an explicitly chosen host
+pub struct HostOutput {
+    _stream: cpal::Stream,
+    pending: Pending,
+    capacity: usize,
+    failed: Arc<AtomicBool>, // Set by the stream error callback
+    latency: Duration,       // Fixed device buffer, zero when the device default is used
+    format: DeviceFormat,    // Sample format the stream was built with
+}
+
+impl HostOutput {
+    pub fn open(
+        host: &cpal::Host,
+        format: &AudioFormat,
+        device_buffer: Option<DeviceBuffer>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = host
+            .default_output_device()
+            .ok_or("no default output device on this host")?;
+        let device_name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+
+        // The device's own default format wins when it can carry this stream
+        let preferred = device
+            .default_output_config()
+            .ok()
+            .map(|config| config.sample_format());
+        let ranges: Vec<_> = device.supported_output_configs()?.collect();
+        let supported = select_config(&ranges, format, preferred)
+            .map_err(|e| format!("{}: {}", device_name, e))?;
+
+        let sample_format = supported.sample_format();
+        let device_format = DeviceFormat::from_cpal(sample_format)
+            .ok_or_else(|| format!("unsupported device sample format {:?}", sample_format))?;
+        let mut config: StreamConfig = supported.config();
+        let mut buffer_frames = None;
+        if let Some(requested) = device_buffer {
+            let frames = requested.frames(format.sample_rate);
+            match fixed_buffer_size(frames, supported.buffer_size()) {
+                Some(size) => {
+                    config.buffer_size = size;
+                    buffer_frames = Some(frames);
+                }
+                None => warn!(
+                    "{} doesn't support a {}-frame buffer ({:?}), using the device default",
+                    device_name,
+                    frames,
+                    supported.buffer_size()
+                ),
+            }
+        }
+        let channels = format.channels as usize;
+        let capacity =
+            (format.sample_rate as u128 * BUFFER_AHEAD.as_millis() / 1000) as usize * channels;
+        let pending = if device_format == DeviceFormat::F32 {
+            Pending::Float(Arc::new(Mutex::new(VecDeque::with_capacity(capacity))))
+        } else {
+            Pending::Int(Arc::new(Mutex::new(VecDeque::with_capacity(capacity))))
+        };
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let build = |config: &StreamConfig| -> Result<cpal::Stream, Box<dyn std::error::Error>> {
+            Ok(match (&pending, sample_format) {
+                (Pending::Float(buffer), _) => {
+                    build_stream(&device, config, buffer, &failed, |s: f32| s)?
+                }
+                (Pending::Int(buffer), SampleFormat::I16) => {
+                    build_stream(&device, config, buffer, &failed, |s| (s.0 >> 8) as i16)?
+                }
+                (Pending::Int(buffer), SampleFormat::I32) => {
+                    build_stream(&device, config, buffer, &failed, |s| s.0 << 8)?
+                }
+                (_, other) => {
+                    return Err(format!("unsupported device sample format {:?}", other).into())
+                }
+            })
+        };
+        let stream = match build(&config) {
+            Err(e) if buffer_frames.is_some() => {
+                warn!(
+                    "{} rejected a {}-frame buffer ({}), using the device default",
+                    device_name,
+                    buffer_frames.unwrap_or_default(),
+                    e
+                );
+                config.buffer_size = BufferSize::Default;
+                buffer_frames = None;
+                build(&config)?
+            }
+            result => result?,
+        };
+        stream.play()?;
+
+        let latency = buffer_frames.map_or(Duration::ZERO, |frames| {
+            frames_to_duration(frames, format.sample_rate)
+        });
+        info!(
+            "cpal output on '{}': {} Hz, {} ch, {:?}, buffer {}",
+            device_name,
+            format.sample_rate,
+            format.channels,
+            sample_format,
+            match buffer_frames {
+                Some(frames) => format!(
+                    "{} frames ({:.1} ms)",
+                    frames,
+                    latency.as_secs_f64() * 1000.0
+                ),
+                None => "device default".to_string(),
+            }
+        );
+
+        Ok(HostOutput {
+            _stream: stream,
+            pending,
+            capacity,
+            failed,
+            latency,
+            format: device_format,
+        })
+    }
+
+    /// Queue samples for the callback, blocking while the buffer is full
+    fn push<S: Copy>(
+        &self,
+        buffer: &Mutex<VecDeque<S>>,
+        samples: &[S],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.failed.load(Ordering::Relaxed) {
+            return Err("audio stream failed".into());
+        }
+        let mut offset = 0;
+        while offset < samples.len() {
+            let written = {
+                let mut buffer = buffer.lock().unwrap();
+                let room = self.capacity.saturating_sub(buffer.len());
+                let n = room.min(samples.len() - offset);
+                buffer.extend(samples[offset..offset + n].iter().copied());
+                n
+            };
+            offset += written;
+            if offset < samples.len() {
+                if self.failed.load(Ordering::Relaxed) {
+                    return Err("audio stream failed".into());
+                }
+                // Full: give the callback time to drain some
+                std::thread::sleep(Duration::from_millis(2));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pick the stream config for a format from the device's supported ranges:
+/// the device's default sample format (what its mixer runs at) first, then
+/// resolution
+pub fn select_config(
+    ranges: &[SupportedStreamConfigRange],
+    format: &AudioFormat,
+    preferred: Option<SampleFormat>,
+) -> Result<SupportedStreamConfig, String> {
+    let rate = cpal::SampleRate(format.sample_rate);
+    ranges
+        .iter()
+        .filter(|range| {
+            range.channels() == format.channels as u16
+                && range.min_sample_rate() <= rate
+                && rate <= range.max_sample_rate()
+                && format_preference(range.sample_format()) > 0
+        })
+        .max_by_key(|range| {
+            let default = Some(range.sample_format()) == preferred;
+            (default, format_preference(range.sample_format()))
+        })
+        .map(|range| range.with_sample_rate(rate))
+        .ok_or_else(|| {
+            let available: Vec<String> = ranges
+                .iter()
+                .map(|r| {
+                    format!(
+                        "{} ch {}-{} Hz {:?}",
+                        r.channels(),
+                        r.min_sample_rate().0,
+                        r.max_sample_rate().0,
+                        r.sample_format()
+                    )
+                })
+                .collect();
+            format!(
+                "doesn't support {} Hz / {} ch / {}-bit (supported: {})",
+                format.sample_rate,
+                format.channels,
+                format.bit_depth,
+                available.join(", ")
+            )
+        })
+}
+
+/// Higher is better: prefer formats that keep the full 24-bit resolution
+fn format_preference(format: SampleFormat) -> u8 {
+    match format {
+        SampleFormat::I32 => 3,
+        SampleFormat::F32 => 2,
+        SampleFormat::I16 => 1,
+        _ => 0,
+    }
+}
+
+fn build_stream<S, T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    buffer: &Arc<Mutex<VecDeque<S>>>,
+    failed: &Arc<AtomicBool>,
+    convert: fn(S) -> T,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    S: Copy + Send + 'static,
+    T: SizedSample + Send + 'static,
+{
+    let buffer = Arc::clone(buffer);
+    let failed = Arc::clone(failed);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut buffer = buffer.lock().unwrap();
+            for out in data.iter_mut() {
+                // Underrun: play silence rather than stale data
+                *out = buffer.pop_front().map_or(T::EQUILIBRIUM, convert);
+            }
+        },
+        move |e| {
+            error!("Audio stream error: {}", e);
+            failed.store(true, Ordering::Relaxed);
+        },
+        None,
+    )
+}
+
+impl OutputBackend for HostOutput {
+    fn name(&self) -> &'static str {
+        "cpal"
+    }
+
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn sample_format(&self) -> DeviceFormat {
+        self.format
+    }
+
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+        match self.pending {
+            Pending::Int(ref buffer) => self.push(buffer, samples),
+            Pending::Float(ref buffer) => self.push(buffer, &float::to_f32(samples)),
+        }
+    }
+
+    fn write_f32(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+        match self.pending {
+            Pending::Int(ref buffer) => self.push(buffer, &float::from_f32(samples)),
+            Pending::Float(ref buffer) => self.push(buffer, samples),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_host_case_insensitive() {
+        let available = ["ALSA", "JACK"];
+        assert_eq!(match_host("jack", &available), Ok("JACK"));
+        assert_eq!(match_host("ALSA", &available), Ok("ALSA"));
+    }
+
+    #[test]
+    fn test_match_host_lists_valid_names() {
+        let err = match_host("asio", &["WASAPI"]).unwrap_err();
+        assert!(err.contains("'asio'"));
+        assert!(err.contains("valid hosts: WASAPI"));
+    }
+
+    #[test]
+    fn test_host_names_not_empty() {
+        // Every platform build has at least its default host
+        assert!(!host_names().is_empty());
+    }
+
+    #[test]
+    fn test_parse_device_buffer() {
+        assert_eq!("1024".parse(), Ok(DeviceBuffer::Frames(1024)));
+        assert_eq!("20ms".parse(), Ok(DeviceBuffer::Millis(20)));
+        assert_eq!(" 15 ms ".parse(), Ok(DeviceBuffer::Millis(15)));
+        assert!("0".parse::<DeviceBuffer>().is_err());
+        assert!("fast".parse::<DeviceBuffer>().is_err());
+        assert!("-5ms".parse::<DeviceBuffer>().is_err());
+    }
+
+    #[test]
+    fn test_device_buffer_frames_and_ms() {
+        assert_eq!(DeviceBuffer::Millis(20).frames(48000), 960);
+        assert_eq!(DeviceBuffer::Millis(10).frames(44100), 441);
+        assert_eq!(DeviceBuffer::Frames(512).frames(48000), 512);
+        assert_eq!(frames_to_duration(960, 48000), Duration::from_millis(20));
+        assert_eq!(frames_to_duration(441, 44100), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_fixed_buffer_size_against_supported_range() {
+        let range = SupportedBufferSize::Range { min: 64, max: 4096 };
+        assert_eq!(
+            fixed_buffer_size(1024, &range),
+            Some(BufferSize::Fixed(1024))
+        );
+        assert_eq!(fixed_buffer_size(64, &range), Some(BufferSize::Fixed(64)));
+        assert_eq!(fixed_buffer_size(32, &range), None);
+        assert_eq!(fixed_buffer_size(8192, &range), None);
+        // Unknown range: try it and fall back if building the stream fails
+        assert_eq!(
+            fixed_buffer_size(8192, &SupportedBufferSize::Unknown),
+            Some(BufferSize::Fixed(8192))
+        );
+    }
+
+    fn range(rate: (u32, u32), sample_format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            2,
+            cpal::SampleRate(rate.0),
+            cpal::SampleRate(rate.1),
+            SupportedBufferSize::Unknown,
+            sample_format,
+        )
+    }
+
+    fn stream(sample_rate: u32, bit_depth: u8) -> AudioFormat {
+        AudioFormat {
+            codec: sendspin::audio::Codec::Pcm,
+            sample_rate,
+            channels: 2,
+            bit_depth,
+            codec_header: None,
+        }
+    }
+
+    #[test]
+    fn test_select_config_shared_prefers_device_default() {
+        let ranges = [
+            range((44100, 48000), SampleFormat::I32),
+            range((44100, 48000), SampleFormat::F32),
+        ];
+        let config = select_config(&ranges, &stream(48000, 24), Some(SampleFormat::F32));
+        assert_eq!(config.unwrap().sample_format(), SampleFormat::F32);
+
+        let config = select_config(&ranges, &stream(44100, 24), None).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I32);
+        assert_eq!(config.sample_rate().0, 44100);
+    }
+
+    #[test]
+    fn test_select_config_lists_supported_formats() {
+        let ranges = [
+            range((48000, 48000), SampleFormat::F32),
+            range((44100, 44100), SampleFormat::I16),
+        ];
+        let err = select_config(&ranges, &stream(96000, 24), None).unwrap_err();
+        assert!(err.contains("96000 Hz / 2 ch / 24-bit"), "{}", err);
+        assert!(err.contains("2 ch 44100-44100 Hz I16"), "{}", err);
+    }
+
+    #[test]
+    fn test_device_formats_listing() {
+        let ranges = [
+            range((44100, 192000), SampleFormat::I32),
+            range((48000, 48000), SampleFormat::U8),
+            SupportedStreamConfigRange::new(
+                6,
+                cpal::SampleRate(48000),
+                cpal::SampleRate(48000),
+                SupportedBufferSize::Unknown,
+                SampleFormat::F32,
+            ),
+        ];
+        let formats = DeviceFormats::new("ALSA", "USB DAC", true, &ranges);
+        assert_eq!(formats.advertised_rates, vec![48000, 44100, 96000, 88200]);
+        assert_eq!(
+            formats.to_string(),
+            "ALSA: USB DAC (default)\n\
+             \x20 2 ch  44100-192000 Hz i32\n\
+             \x20 2 ch  48000 Hz        u8  (not usable)\n\
+             \x20 6 ch  48000 Hz        f32\n\
+             \x20 advertised rates: 48000, 44100, 96000, 88200 Hz\n"
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&formats.to_json()).unwrap();
+        assert_eq!(value["device"], "USB DAC");
+        assert_eq!(value["formats"][1]["sample_format"], "u8");
+        assert_eq!(value["formats"][1]["usable"], false);
+        assert!(value.get("error").is_none());
+
+        // Only a multichannel or unusable format: nothing to advertise
+        let formats = DeviceFormats::new("ALSA", "hdmi", false, &ranges[1..]);
+        assert!(formats.advertised_rates.is_empty());
+        assert!(formats.to_string().contains("advertised rates: none"));
+        let failed = DeviceFormats::failed("ALSA", "busy", false, "device busy".to_string());
+        assert_eq!(
+            failed.to_string(),
+            "ALSA: busy\n  (cannot read formats: device busy)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_preference() {
+        assert!(format_preference(SampleFormat::I32) > format_preference(SampleFormat::F32));
+        assert!(format_preference(SampleFormat::F32) > format_preference(SampleFormat::I16));
+        assert_eq!(format_preference(SampleFormat::U8), 0);
+    }
+}
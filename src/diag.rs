@@ -0,0 +1,89 @@
+// Audio Diagnostics
+//
+// `--debug-audio-crc` logs a CRC32 of every decoded buffer next to its server
+// timestamp. The same stream played on two clients (or before and after a
+// reconnect) should produce identical lines, so crackling can be traced to
+// corrupted bytes or, if the checksums match, to timing.
+//
+// `--format-report` prints one JSON line per stream on stdout with the
+// negotiated format and how it reaches the device, for bug reports and for
+// tools that drive the CLI.
+
+use sendspin::audio::Sample;
+use serde::Serialize;
+
+/// CRC32 of decoded samples, each hashed as little-endian i32
+pub fn buffer_crc(samples: &[Sample]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for sample in samples {
+        hasher.update(&sample.0.to_le_bytes());
+    }
+    hasher.finalize()
+}
+
+/// Negotiated stream format and its route to the device
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FormatReport {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bit_depth: u8,
+    pub endianness: &'static str,
+    pub output_rate: u32, // Rate the device is opened at
+    pub resampling: bool,
+    pub downmix: bool,
+    pub backend: String,
+    pub device: String,
+}
+
+impl FormatReport {
+    /// Single-line JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_report_json() {
+        let report = FormatReport {
+            codec: "pcm".to_string(),
+            sample_rate: 44100,
+            channels: 2,
+            bit_depth: 24,
+            endianness: "little",
+            output_rate: 48000,
+            resampling: true,
+            downmix: false,
+            backend: "cpal".to_string(),
+            device: "USB DAC".to_string(),
+        };
+        let json = report.to_json();
+        assert!(!json.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["sample_rate"], 44100);
+        assert_eq!(value["endianness"], "little");
+        assert_eq!(value["resampling"], true);
+        assert_eq!(value["device"], "USB DAC");
+    }
+
+    #[test]
+    fn test_buffer_crc_known_value() {
+        // CRC32 of four zero bytes
+        assert_eq!(buffer_crc(&[Sample(0)]), 0x2144_DF1C);
+        assert_eq!(buffer_crc(&[]), 0);
+    }
+
+    #[test]
+    fn test_buffer_crc_detects_changes() {
+        let a = [Sample(1), Sample(-2), Sample(3)];
+        let b = [Sample(1), Sample(-2), Sample(4)];
+        let swapped = [Sample(-2), Sample(1), Sample(3)];
+        assert_eq!(buffer_crc(&a), buffer_crc(&a.clone()));
+        assert_ne!(buffer_crc(&a), buffer_crc(&b));
+        assert_ne!(buffer_crc(&a), buffer_crc(&swapped));
+    }
+}
@@ -0,0 +1,184 @@
+// Dither
+//
+// Samples are processed with 24 bits of resolution, which is plenty for
+// volume and EQ. On a device that keeps fewer bits (e.g. 16-bit only), the
+// conversion used to just drop the low bits, and that truncation turns into
+// audible distortion on quiet passages. The last stage before the write now
+// re-quantizes to the device's depth with TPDF dither (two uniform random
+// values, ±1 LSB), which trades the distortion for a constant, very low
+// noise floor. Optional first-order noise shaping feeds each channel's
+// quantization error back into the next sample, moving that noise towards
+// high frequencies where the ear is least sensitive.
+//
+// Dither only runs when there is something to lose: the device is narrower
+// than 24 bits and either the stream is deeper than the device or the
+// samples were processed (gain, EQ, fades...). A 16-bit stream played
+// untouched on a 16-bit device stays bit-perfect.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use crate::rng::Rng;
+use sendspin::audio::Sample;
+use std::sync::Arc;
+
+/// Bits a Sample carries
+pub const SAMPLE_BITS: u8 = 24;
+
+/// Whether samples must be dithered on their way to a `device_bits` output
+pub fn needed(device_bits: u8, source_bits: u8, processed: bool) -> bool {
+    device_bits < SAMPLE_BITS && (source_bits > device_bits || processed)
+}
+
+/// TPDF dither and re-quantization, state kept per channel
+#[derive(Debug)]
+pub struct Dither {
+    noise_shaping: bool,
+    errors: Vec<f64>, // Last quantization error per channel (noise shaping)
+    rng: Rng,
+}
+
+impl Dither {
+    pub fn new(noise_shaping: bool) -> Self {
+        Dither {
+            noise_shaping,
+            errors: Vec::new(),
+            rng: Rng::new(),
+        }
+    }
+
+    /// Dither with a fixed seed (reproducible noise)
+    pub fn with_seed(noise_shaping: bool, seed: u64) -> Self {
+        Dither {
+            noise_shaping,
+            errors: Vec::new(),
+            rng: Rng::with_seed(seed),
+        }
+    }
+
+    /// Forget the noise shaping error, e.g. on stream change
+    pub fn reset(&mut self) {
+        self.errors.clear();
+    }
+
+    /// Re-quantize interleaved samples to `bits`; the result is a multiple
+    /// of the target LSB, so narrowing it afterwards is exact
+    pub fn process(&mut self, samples: &[Sample], channels: usize, bits: u8) -> Arc<[Sample]> {
+        let mut out = samples.to_vec();
+        self.process_in_place(&mut out, channels, bits);
+        Arc::from(out)
+    }
+
+    /// Same as `process`, overwriting `samples`
+    pub fn process_in_place(&mut self, samples: &mut [Sample], channels: usize, bits: u8) {
+        let channels = channels.max(1);
+        if self.errors.len() != channels {
+            self.errors = vec![0.0; channels];
+        }
+        let step = (1i64 << (SAMPLE_BITS - bits.clamp(1, SAMPLE_BITS))) as f64;
+        // Largest multiple of the step that still fits
+        let max = (SAMPLE_MAX as f64 / step).floor() * step;
+        let min = SAMPLE_MIN as f64;
+
+        for frame in samples.chunks_mut(channels) {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let wanted = if self.noise_shaping {
+                    sample.0 as f64 - self.errors[ch]
+                } else {
+                    sample.0 as f64
+                };
+                let tpdf = (self.rng.next_unit() + self.rng.next_unit() - 1.0) * step;
+                let quantized = (((wanted + tpdf) / step).round() * step).clamp(min, max);
+                if self.noise_shaping {
+                    // Clipped samples would feed back a huge error: cap it
+                    self.errors[ch] = (quantized - wanted).clamp(-step, step);
+                }
+                *sample = Sample(quantized as i32);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LSB16: i32 = 256; // One 16-bit step in Sample units
+
+    #[test]
+    fn test_needed() {
+        assert!(needed(16, 24, false));
+        assert!(needed(16, 16, true));
+        // Untouched 16-bit audio on a 16-bit device stays bit-perfect
+        assert!(!needed(16, 16, false));
+        // 24-bit devices keep everything
+        assert!(!needed(24, 24, true));
+    }
+
+    #[test]
+    fn test_dither_amplitude_statistics() {
+        let mut dither = Dither::with_seed(false, 7);
+        let out = dither.process(&vec![Sample(0); 100_000], 1, 16);
+
+        // Silence becomes 0 or ±1 LSB: TPDF of ±1 LSB, then rounding
+        assert!(out.iter().all(|s| [-LSB16, 0, LSB16].contains(&s.0)));
+        let n = out.len() as f64;
+        let mean = out.iter().map(|s| s.0 as f64).sum::<f64>() / n;
+        let variance = out.iter().map(|s| (s.0 as f64).powi(2)).sum::<f64>() / n;
+        let lsb = LSB16 as f64;
+        assert!(mean.abs() < 0.01 * lsb, "mean {}", mean);
+        // 1/8 of samples at each of ±1 LSB
+        assert!((variance / (lsb * lsb) - 0.25).abs() < 0.01, "{}", variance);
+    }
+
+    #[test]
+    fn test_dither_keeps_sub_lsb_detail() {
+        // A level of 0.3 LSB truncates to nothing but survives dithering on average
+        let mut dither = Dither::with_seed(false, 11);
+        let level = (0.3 * LSB16 as f64) as i32;
+        let out = dither.process(&vec![Sample(level); 100_000], 1, 16);
+        let mean = out.iter().map(|s| s.0 as f64).sum::<f64>() / out.len() as f64;
+        assert!(
+            (mean - level as f64).abs() < 0.02 * LSB16 as f64,
+            "{}",
+            mean
+        );
+        assert!(out.iter().all(|s| s.0 % LSB16 == 0));
+    }
+
+    #[test]
+    fn test_full_scale_does_not_clip() {
+        for shaping in [false, true] {
+            let mut dither = Dither::with_seed(shaping, 3);
+            let input: Vec<Sample> = (0..20_000)
+                .map(|i| Sample(if i % 2 == 0 { SAMPLE_MAX } else { SAMPLE_MIN }))
+                .collect();
+            let out = dither.process(&input, 2, 16);
+            for (a, b) in input.iter().zip(out.iter()) {
+                // Stays in range, never wraps to the other polarity
+                assert!((SAMPLE_MIN..=SAMPLE_MAX).contains(&b.0));
+                assert_eq!(a.0.signum(), b.0.signum());
+                assert!((a.0 - b.0).abs() <= 2 * LSB16);
+                // Exactly representable in 16 bits
+                assert_eq!(b.0 % LSB16, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_shaping_moves_noise_up() {
+        // Error of plain dither is white; shaped error alternates (high-pass)
+        let input: Vec<Sample> = (0..50_000).map(|i| Sample((i * 37) % 5000)).collect();
+        let lag1 = |shaping: bool| {
+            let mut dither = Dither::with_seed(shaping, 5);
+            let out = dither.process(&input, 1, 16);
+            let error: Vec<f64> = out
+                .iter()
+                .zip(&input)
+                .map(|(o, i)| (o.0 - i.0) as f64)
+                .collect();
+            let power: f64 = error.iter().map(|e| e * e).sum();
+            error.windows(2).map(|w| w[0] * w[1]).sum::<f64>() / power
+        };
+        assert!(lag1(false).abs() < 0.05, "{}", lag1(false));
+        assert!(lag1(true) < -0.3, "{}", lag1(true));
+    }
+}
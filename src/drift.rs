@@ -0,0 +1,265 @@
+// Drift Correction
+//
+// The server timestamps audio on its clock; the device plays it on its own.
+// Over an hour a 50 ppm difference adds up to 180 ms, so the lead built by
+// --buffer shrinks until chunks go late (or grows until the room echoes).
+//
+// The player keeps a model of when the audio written so far will have played
+// (the device consuming frames at its nominal rate, restarting after an
+// underrun), and compares the start of each buffer with its play_at. Once the
+// smoothed error leaves half of --drift-band-ms, a PI loop nudges the rate
+// converter's ratio by at most a few ppm per second of audio, so the local
+// playback rate follows the server clock without audible pitch steps. When
+// the stream needs no rate conversion, the player's linear resampler (one
+// frame of latency) is engaged only while a correction is applied.
+//
+// Errors beyond 100 ms aren't drift (a late chunk, a gap in the stream) and
+// are left to the normal scheduling.
+//
+// The model only sees the device through the latency its backend reports,
+// and cpal reports none, so the correction is opt-in (--drift-correction).
+
+use std::time::{Duration, Instant};
+
+/// Default for --drift-band-ms
+pub const DEFAULT_BAND_MS: u64 = 5;
+
+/// Largest correction, well below what anyone could hear as pitch
+pub const MAX_PPM: f64 = 300.0;
+
+/// Largest change of the correction per adjustment
+const STEP_PPM: f64 = 5.0;
+
+/// Audio played between adjustments
+const ADJUST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time constant of the error smoothing (per-buffer timing is noisy)
+const SMOOTHING: Duration = Duration::from_secs(1);
+
+/// Loop gains: ppm per ms of error change, and ppm per ms of error
+const KP: f64 = 50.0;
+const KI: f64 = 2.0;
+
+/// Errors larger than this aren't drift
+const IGNORE_ABOVE_US: f64 = 100_000.0;
+
+/// Microseconds from `scheduled` to `actual` (positive = late)
+pub fn error_us(actual: Instant, scheduled: Instant) -> f64 {
+    if actual >= scheduled {
+        (actual - scheduled).as_secs_f64() * 1e6
+    } else {
+        -(scheduled - actual).as_secs_f64() * 1e6
+    }
+}
+
+/// When the audio written so far will have played, the device consuming it
+/// at its nominal rate
+#[derive(Debug, Default)]
+pub struct PlayoutClock {
+    end: Option<Instant>,
+}
+
+impl PlayoutClock {
+    /// Forget the written audio (output reopened)
+    pub fn reset(&mut self) {
+        self.end = None;
+    }
+
+    /// When a buffer written now starts playing; after an underrun the
+    /// device starts over from the write
+    pub fn next_start(&self, now: Instant, latency: Duration) -> Instant {
+        let earliest = now + latency;
+        self.end.map_or(earliest, |end| end.max(earliest))
+    }
+
+    /// When everything written so far will have played, None before any write
+    pub fn end(&self) -> Option<Instant> {
+        self.end
+    }
+
+    /// Account for `frames` written at `rate`, starting at `start`
+    pub fn advance(&mut self, start: Instant, frames: usize, rate: u32) {
+        self.end = Some(start + Duration::from_secs_f64(frames as f64 / rate.max(1) as f64));
+    }
+}
+
+/// Steers the playback rate so the scheduling error stays inside a band
+#[derive(Debug)]
+pub struct DriftCorrector {
+    band_us: f64,
+    smoothed_us: Option<f64>,
+    last_us: Option<f64>, // Smoothed error at the previous adjustment
+    since_adjust: Duration,
+    engaged: bool,
+    ppm: f64,
+}
+
+impl DriftCorrector {
+    pub fn new(band: Duration) -> Self {
+        DriftCorrector {
+            band_us: band.as_secs_f64() * 1e6,
+            smoothed_us: None,
+            last_us: None,
+            since_adjust: Duration::ZERO,
+            engaged: false,
+            ppm: 0.0,
+        }
+    }
+
+    /// Start over, e.g. for a new stream
+    pub fn reset(&mut self) {
+        *self = DriftCorrector::new(Duration::from_secs_f64(self.band_us / 1e6));
+    }
+
+    /// Current correction in ppm (positive = play faster)
+    pub fn ppm(&self) -> f64 {
+        self.ppm
+    }
+
+    /// Factor for the rate converter's ratio
+    pub fn ratio(&self) -> f64 {
+        1.0 + self.ppm * 1e-6
+    }
+
+    /// Smoothed scheduling error in microseconds (positive = late)
+    pub fn error_us(&self) -> f64 {
+        self.smoothed_us.unwrap_or(0.0)
+    }
+
+    /// Whether the error has left the band since the last reset
+    pub fn is_engaged(&self) -> bool {
+        self.engaged
+    }
+
+    /// Account for a buffer of `audio` that starts `error_us` after its
+    /// play_at; returns the new correction when it was adjusted
+    pub fn update(&mut self, error_us: f64, audio: Duration) -> Option<f64> {
+        if error_us.abs() > IGNORE_ABOVE_US {
+            return None;
+        }
+        let alpha = (audio.as_secs_f64() / SMOOTHING.as_secs_f64()).min(1.0);
+        let smoothed = match self.smoothed_us {
+            Some(smoothed) => smoothed + alpha * (error_us - smoothed),
+            None => error_us,
+        };
+        self.smoothed_us = Some(smoothed);
+
+        self.since_adjust += audio;
+        if self.since_adjust < ADJUST_INTERVAL {
+            return None;
+        }
+        self.since_adjust = Duration::ZERO;
+        if !self.engaged && smoothed.abs() <= self.band_us / 2.0 {
+            return None;
+        }
+        self.engaged = true;
+
+        // PI in velocity form: slewing the output can't wind the integral up
+        let error_ms = smoothed / 1000.0;
+        let change = self.last_us.map_or(0.0, |last| error_ms - last / 1000.0);
+        let step = (KI * error_ms + KP * change).clamp(-STEP_PPM, STEP_PPM);
+        self.ppm = (self.ppm + step).clamp(-MAX_PPM, MAX_PPM);
+        self.last_us = Some(smoothed);
+        Some(self.ppm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUFFER: Duration = Duration::from_millis(20);
+
+    /// Play `seconds` of audio with the device `drift_ppm` slower than the
+    /// server, measuring each buffer with up to ±1 ms of timing noise;
+    /// returns the largest error in ms
+    fn simulate(corrector: &mut DriftCorrector, drift_ppm: f64, seconds: u64) -> f64 {
+        let mut error_us = 0.0;
+        let mut worst: f64 = 0.0;
+        let mut noise: u32 = 12345;
+        for _ in 0..seconds * 1000 / BUFFER.as_millis() as u64 {
+            // Each buffer plays (drift - correction) ppm longer than scheduled
+            error_us += (drift_ppm - corrector.ppm()) * BUFFER.as_secs_f64();
+            noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let jitter = ((noise >> 16) % 2001) as f64 - 1000.0;
+            corrector.update(error_us + jitter, BUFFER);
+            worst = worst.max(error_us.abs());
+        }
+        worst / 1000.0
+    }
+
+    #[test]
+    fn test_50ppm_drift_stays_in_band_for_an_hour() {
+        for drift in [50.0, -50.0] {
+            let mut corrector = DriftCorrector::new(Duration::from_millis(5));
+            let worst = simulate(&mut corrector, drift, 3600);
+            assert!(worst < 5.0, "{} ppm: {} ms", drift, worst);
+            assert!(corrector.is_engaged());
+            // Locked on: the correction matches the drift
+            assert!(
+                (corrector.ppm() - drift).abs() < 10.0,
+                "{}",
+                corrector.ppm()
+            );
+            assert!(corrector.error_us().abs() < 2000.0);
+        }
+    }
+
+    #[test]
+    fn test_no_correction_inside_band() {
+        // Timing noise alone leaves the rate alone
+        let mut corrector = DriftCorrector::new(Duration::from_millis(5));
+        simulate(&mut corrector, 0.0, 600);
+        assert!(!corrector.is_engaged());
+        assert_eq!(corrector.ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_corrections_are_slewed_and_bounded() {
+        let mut corrector = DriftCorrector::new(Duration::from_millis(5));
+        let mut last = 0.0;
+        for _ in 0..2000 {
+            // Persistently 50 ms late
+            if let Some(ppm) = corrector.update(50_000.0, ADJUST_INTERVAL) {
+                assert!((ppm - last).abs() <= STEP_PPM + 1e-9);
+                last = ppm;
+            }
+        }
+        assert_eq!(corrector.ppm(), MAX_PPM);
+        assert!(corrector.ratio() > 1.0);
+    }
+
+    #[test]
+    fn test_ignores_glitches_and_resets() {
+        let mut corrector = DriftCorrector::new(Duration::from_millis(5));
+        // A chunk 2 s late isn't drift
+        for _ in 0..100 {
+            assert_eq!(corrector.update(2_000_000.0, ADJUST_INTERVAL), None);
+        }
+        simulate(&mut corrector, 200.0, 60);
+        assert!(corrector.ppm() > 0.0);
+        corrector.reset();
+        assert_eq!(corrector.ppm(), 0.0);
+        assert!(!corrector.is_engaged());
+    }
+
+    #[test]
+    fn test_playout_clock() {
+        let now = Instant::now();
+        let latency = Duration::from_millis(50);
+        let mut clock = PlayoutClock::default();
+        let start = clock.next_start(now, latency);
+        assert_eq!(start, now + latency);
+        clock.advance(start, 4800, 48000);
+        // Written ahead: the next buffer follows the previous one
+        assert_eq!(
+            clock.next_start(now, latency),
+            now + latency + Duration::from_millis(100)
+        );
+        // Written after the device ran dry: starts over from the write
+        let later = now + Duration::from_secs(1);
+        assert_eq!(clock.next_start(later, latency), later + latency);
+        clock.reset();
+        assert_eq!(clock.next_start(now, latency), now + latency);
+    }
+}
@@ -0,0 +1,164 @@
+// Gain Envelopes
+//
+// Stop and pause fades run from the moment the command arrives, whatever
+// buffer happens to be playing. When it's known in advance which buffers
+// should fade, e.g. the last seconds before `--max-session` runs out, the
+// session attaches an envelope to those buffers instead: a gain that moves
+// in a straight line from `start` on the buffer's first frame to `end` on
+// its last. `fade_out_before` works out a buffer's part of such a fade from
+// its play time.
+//
+// The envelope multiplies the combined volume, mute, ReplayGain and loudness
+// gain, so a muted player stays silent and a faded buffer follows volume
+// changes. Buffers without one play exactly as before.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use sendspin::audio::Sample;
+use std::time::{Duration, Instant};
+
+/// Linear gain across one buffer, from its first frame to its last
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Envelope {
+    pub fn new(start: f32, end: f32) -> Self {
+        Envelope { start, end }
+    }
+
+    /// Gain of frame `frame` out of `frames`
+    pub fn gain_at(&self, frame: usize, frames: usize) -> f32 {
+        if frames < 2 {
+            return self.start;
+        }
+        let progress = frame.min(frames - 1) as f32 / (frames - 1) as f32;
+        self.start + (self.end - self.start) * progress
+    }
+
+    /// The part left after the first `skipped` of `frames` frames were cut
+    /// off, e.g. by a seek or a resume after pause
+    pub fn after(&self, skipped: usize, frames: usize) -> Self {
+        Envelope {
+            start: self.gain_at(skipped, frames),
+            end: self.end,
+        }
+    }
+
+    /// Scale each frame by its envelope gain times `gain`, clamped to the
+    /// sample range
+    pub fn apply(&self, samples: &mut [Sample], channels: usize, gain: f32) {
+        let channels = channels.max(1);
+        let frames = samples.len() / channels;
+        for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let gain = gain * self.gain_at(i, frames);
+            for sample in frame.iter_mut() {
+                let scaled = sample.0 as f32 * gain;
+                *sample = Sample((scaled as i32).clamp(SAMPLE_MIN, SAMPLE_MAX));
+            }
+        }
+    }
+
+    /// Same for the f32 pipeline, which clamps at the end
+    pub fn apply_f32(&self, samples: &mut [f32], channels: usize, gain: f32) {
+        let channels = channels.max(1);
+        let frames = samples.len() / channels;
+        for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let gain = gain * self.gain_at(i, frames);
+            frame.iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+/// Envelope for a buffer playing from `start` for `duration` under a fade
+/// that falls linearly over `window` to silence at `stop`; None when the
+/// buffer is over before the fade begins
+pub fn fade_out_before(
+    start: Instant,
+    duration: Duration,
+    stop: Instant,
+    window: Duration,
+) -> Option<Envelope> {
+    let end = start + duration;
+    if end + window <= stop {
+        return None;
+    }
+    let gain = |at: Instant| {
+        let left = stop.saturating_duration_since(at).as_secs_f32();
+        (left / window.as_secs_f32().max(f32::EPSILON)).min(1.0)
+    };
+    Some(Envelope::new(gain(start), gain(end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_endpoints() {
+        let fade = Envelope::new(1.0, 0.0);
+        assert_eq!(fade.gain_at(0, 5), 1.0);
+        assert_eq!(fade.gain_at(2, 5), 0.5);
+        assert_eq!(fade.gain_at(4, 5), 0.0);
+        // A single frame takes the start gain
+        assert_eq!(fade.gain_at(0, 1), 1.0);
+
+        let mut samples = vec![Sample(1000); 5 * 2];
+        fade.apply(&mut samples, 2, 1.0);
+        let firsts: Vec<i32> = samples.chunks(2).map(|frame| frame[0].0).collect();
+        assert_eq!(firsts, vec![1000, 750, 500, 250, 0]);
+        assert!(samples.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn test_envelope_composes_with_volume() {
+        let rise = Envelope::new(0.0, 1.0);
+        let mut samples = vec![Sample(1000); 3];
+        rise.apply(&mut samples, 1, 0.5);
+        assert_eq!(samples, vec![Sample(0), Sample(250), Sample(500)]);
+
+        let mut pcm = vec![0.8f32; 3];
+        rise.apply_f32(&mut pcm, 1, 0.5);
+        assert_eq!(pcm, vec![0.0, 0.2, 0.4]);
+
+        // Gain above one still can't leave the sample range
+        let mut loud = vec![Sample(SAMPLE_MAX); 2];
+        Envelope::new(1.0, 1.0).apply(&mut loud, 1, 2.0);
+        assert_eq!(loud, vec![Sample(SAMPLE_MAX); 2]);
+    }
+
+    #[test]
+    fn test_fade_out_before_stop() {
+        let stop = Instant::now() + Duration::from_secs(10);
+        let window = Duration::from_secs(2);
+        let chunk = Duration::from_millis(500);
+        let at = |ms: u64| stop - Duration::from_millis(ms);
+
+        // Over before the fade: played as is
+        assert_eq!(fade_out_before(at(3000), chunk, stop, window), None);
+        assert_eq!(fade_out_before(at(2500), chunk, stop, window), None);
+        // Inside it: its share of the fall
+        assert_eq!(
+            fade_out_before(at(2000), chunk, stop, window),
+            Some(Envelope::new(1.0, 0.75))
+        );
+        assert_eq!(
+            fade_out_before(at(500), chunk, stop, window),
+            Some(Envelope::new(0.25, 0.0))
+        );
+        // Past the stop: silent
+        assert_eq!(
+            fade_out_before(stop, chunk, stop, window),
+            Some(Envelope::new(0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn test_envelope_after_trim() {
+        let fade = Envelope::new(1.0, 0.0);
+        // 2 of 5 frames cut: the rest starts where the fade had got to
+        assert_eq!(fade.after(2, 5), Envelope::new(0.5, 0.0));
+        assert_eq!(fade.after(0, 5), fade);
+    }
+}
@@ -0,0 +1,441 @@
+// Parametric Equalizer
+//
+// Cascade of biquad filters (RBJ Audio EQ Cookbook) applied per channel in
+// the playback thread, before volume. Configured from a CLI spec such as:
+//
+//   lowshelf:100:-4,peak:2500:2:+3,highshelf:9000:-2
+//
+// Shelves take freq:gain (with an optional Q before the gain), peaks take
+// freq:Q:gain. Coefficients are recomputed whenever the stream format changes.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use sendspin::audio::{AudioFormat, Sample};
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Q giving a maximally steep shelf without overshoot (slope S = 1)
+const SHELF_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Filter shape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+/// A single filter from the EQ spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterSpec {
+    pub kind: FilterKind,
+    pub freq_hz: f64,
+    pub q: f64,
+    pub gain_db: f64,
+}
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl FilterSpec {
+    /// Compute RBJ cookbook coefficients for the given sample rate
+    pub fn coefficients(&self, sample_rate: u32) -> Coefficients {
+        let a = 10f64.powf(self.gain_db / 40.0);
+        let w0 = 2.0 * PI * self.freq_hz / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q);
+
+        let (b0, b1, b2, a0, a1, a2) = match self.kind {
+            FilterKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => {
+                let s = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + s),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - s),
+                    (a + 1.0) + (a - 1.0) * cos + s,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - s,
+                )
+            }
+            FilterKind::HighShelf => {
+                let s = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + s),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - s),
+                    (a + 1.0) - (a - 1.0) * cos + s,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - s,
+                )
+            }
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// Parsed `--eq` option
+#[derive(Debug, Clone, PartialEq)]
+pub struct EqConfig {
+    pub filters: Vec<FilterSpec>,
+}
+
+impl FromStr for EqConfig {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let filters = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(parse_filter)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if filters.is_empty() {
+            return Err("EQ spec contains no filters".to_string());
+        }
+        Ok(EqConfig { filters })
+    }
+}
+
+fn parse_filter(part: &str) -> Result<FilterSpec, String> {
+    let fields: Vec<&str> = part.split(':').map(str::trim).collect();
+
+    let kind = match fields[0].to_ascii_lowercase().as_str() {
+        "lowshelf" => FilterKind::LowShelf,
+        "peak" => FilterKind::Peak,
+        "highshelf" => FilterKind::HighShelf,
+        other => {
+            return Err(format!(
+                "unknown filter type '{}' in '{}' (expected lowshelf, peak or highshelf)",
+                other, part
+            ))
+        }
+    };
+
+    // Shelves: freq:gain or freq:q:gain, peaks: freq:q:gain
+    let (freq, q, gain) = match (kind, fields.len()) {
+        (FilterKind::Peak, 4) => (fields[1], Some(fields[2]), fields[3]),
+        (FilterKind::Peak, _) => {
+            return Err(format!("peak filter '{}' must be peak:freq:q:gain", part));
+        }
+        (_, 3) => (fields[1], None, fields[2]),
+        (_, 4) => (fields[1], Some(fields[2]), fields[3]),
+        _ => {
+            return Err(format!(
+                "shelf filter '{}' must be {}:freq:gain or {}:freq:q:gain",
+                part, fields[0], fields[0]
+            ));
+        }
+    };
+
+    let freq_hz = parse_number("frequency", freq, part)?;
+    if freq_hz <= 0.0 {
+        return Err(format!("frequency must be positive in '{}'", part));
+    }
+    let q = match q {
+        Some(q) => parse_number("Q", q, part)?,
+        None => SHELF_Q,
+    };
+    if q <= 0.0 {
+        return Err(format!("Q must be positive in '{}'", part));
+    }
+    let gain_db = parse_number("gain", gain, part)?;
+
+    Ok(FilterSpec {
+        kind,
+        freq_hz,
+        q,
+        gain_db,
+    })
+}
+
+fn parse_number(what: &str, value: &str, part: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("invalid {} '{}' in '{}'", what, value, part))
+}
+
+/// One biquad stage with per-channel state (transposed direct form II)
+struct Stage {
+    coeffs: Coefficients,
+    state: Vec<[f64; 2]>,
+}
+
+/// Runtime EQ: the filter cascade designed for the current stream format
+pub struct Equalizer {
+    config: EqConfig,
+    sample_rate: u32,
+    channels: usize,
+    stages: Vec<Stage>,
+}
+
+impl Equalizer {
+    pub fn new(config: EqConfig) -> Self {
+        Equalizer {
+            config,
+            sample_rate: 0,
+            channels: 0,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Redesign the filters for a new format
+    fn configure(&mut self, sample_rate: u32, channels: usize) {
+        let nyquist = sample_rate as f64 / 2.0;
+        self.stages = self
+            .config
+            .filters
+            .iter()
+            .filter(|filter| {
+                if filter.freq_hz >= nyquist {
+                    warn!(
+                        "EQ: skipping {:?} at {} Hz (above Nyquist for {} Hz)",
+                        filter.kind, filter.freq_hz, sample_rate
+                    );
+                    return false;
+                }
+                true
+            })
+            .map(|filter| Stage {
+                coeffs: filter.coefficients(sample_rate),
+                state: vec![[0.0; 2]; channels],
+            })
+            .collect();
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        debug!(
+            "EQ: {} filter(s) designed for {} Hz, {} channel(s)",
+            self.stages.len(),
+            sample_rate,
+            channels
+        );
+    }
+
+    /// Clear filter history (e.g. between streams)
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.state.iter_mut().for_each(|s| *s = [0.0; 2]);
+        }
+    }
+
+    /// Redesign the cascade if the format changed; returns the channel count
+    fn prepare(&mut self, format: &AudioFormat) -> usize {
+        let channels = (format.channels as usize).max(1);
+        if format.sample_rate != self.sample_rate || channels != self.channels {
+            self.configure(format.sample_rate, channels);
+        }
+        channels
+    }
+
+    /// Run one sample of channel `ch` through every stage
+    fn filter(&mut self, ch: usize, mut x: f64) -> f64 {
+        for stage in &mut self.stages {
+            let c = stage.coeffs;
+            let s = &mut stage.state[ch];
+            let y = c.b0 * x + s[0];
+            s[0] = c.b1 * x - c.a1 * y + s[1];
+            s[1] = c.b2 * x - c.a2 * y;
+            x = y;
+        }
+        x
+    }
+
+    /// Filter interleaved samples, redesigning the cascade if the format changed
+    pub fn process(&mut self, samples: &[Sample], format: &AudioFormat) -> Arc<[Sample]> {
+        let channels = self.prepare(format);
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                let y = self.filter(i % channels, sample.0 as f64);
+                Sample((y.round() as i32).clamp(SAMPLE_MIN, SAMPLE_MAX))
+            })
+            .collect()
+    }
+
+    /// Filter interleaved f32 samples in place (the filters are scale-independent)
+    pub fn process_f32(&mut self, samples: &mut [f32], format: &AudioFormat) {
+        let channels = self.prepare(format);
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = self.filter(i % channels, *sample as f64) as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    /// Magnitude response at DC (z = 1) and Nyquist (z = -1)
+    fn dc_gain(c: &Coefficients) -> f64 {
+        (c.b0 + c.b1 + c.b2) / (1.0 + c.a1 + c.a2)
+    }
+
+    fn nyquist_gain(c: &Coefficients) -> f64 {
+        (c.b0 - c.b1 + c.b2) / (1.0 - c.a1 + c.a2)
+    }
+
+    #[test]
+    fn test_peak_coefficients() {
+        let filter = FilterSpec {
+            kind: FilterKind::Peak,
+            freq_hz: 1000.0,
+            q: 1.0,
+            gain_db: 6.0,
+        };
+        let c = filter.coefficients(48000);
+        assert_close(c.b0, 1.043953086990335);
+        assert_close(c.b1, -1.8953207239365961);
+        assert_close(c.b2, 0.8677222847598566);
+        assert_close(c.a1, -1.8953207239365961);
+        assert_close(c.a2, 0.9116753717501915);
+    }
+
+    #[test]
+    fn test_lowshelf_coefficients() {
+        let filter = FilterSpec {
+            kind: FilterKind::LowShelf,
+            freq_hz: 100.0,
+            q: SHELF_Q,
+            gain_db: -4.0,
+        };
+        let c = filter.coefficients(48000);
+        assert_close(c.b0, 0.9978663556755394);
+        assert_close(c.b1, -1.9792693862815813);
+        assert_close(c.b2, 0.9815377342531888);
+        assert_close(c.a1, -1.9792299926584733);
+        assert_close(c.a2, 0.9794434835518361);
+
+        // Shelf gain applies at DC, unity at Nyquist
+        assert!((dc_gain(&c) - 10f64.powf(-4.0 / 20.0)).abs() < 1e-6);
+        assert!((nyquist_gain(&c) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_highshelf_gain() {
+        let filter = FilterSpec {
+            kind: FilterKind::HighShelf,
+            freq_hz: 9000.0,
+            q: SHELF_Q,
+            gain_db: -2.0,
+        };
+        let c = filter.coefficients(44100);
+        assert!((dc_gain(&c) - 1.0).abs() < 1e-6);
+        assert!((nyquist_gain(&c) - 10f64.powf(-2.0 / 20.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let config: EqConfig = "lowshelf:100:-4,peak:2500:2:+3,highshelf:9000:-2"
+            .parse()
+            .unwrap();
+        assert_eq!(config.filters.len(), 3);
+        assert_eq!(config.filters[0].kind, FilterKind::LowShelf);
+        assert_eq!(config.filters[0].q, SHELF_Q);
+        assert_eq!(config.filters[1].kind, FilterKind::Peak);
+        assert_eq!(config.filters[1].freq_hz, 2500.0);
+        assert_eq!(config.filters[1].q, 2.0);
+        assert_eq!(config.filters[1].gain_db, 3.0);
+        assert_eq!(config.filters[2].gain_db, -2.0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = "notch:100:-4".parse::<EqConfig>().unwrap_err();
+        assert!(err.contains("unknown filter type 'notch'"), "{}", err);
+
+        let err = "peak:2500:3".parse::<EqConfig>().unwrap_err();
+        assert!(err.contains("must be peak:freq:q:gain"), "{}", err);
+
+        let err = "lowshelf:abc:-4".parse::<EqConfig>().unwrap_err();
+        assert!(err.contains("invalid frequency 'abc'"), "{}", err);
+
+        let err = "peak:2500:0:3".parse::<EqConfig>().unwrap_err();
+        assert!(err.contains("Q must be positive"), "{}", err);
+
+        let err = "highshelf:9000".parse::<EqConfig>().unwrap_err();
+        assert!(err.contains("highshelf:freq:gain"), "{}", err);
+
+        let err = " , ".parse::<EqConfig>().unwrap_err();
+        assert!(err.contains("no filters"), "{}", err);
+    }
+
+    #[test]
+    fn test_redesign_on_sample_rate_change() {
+        let mut eq = Equalizer::new("peak:1000:1:6".parse().unwrap());
+        let mut format = AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 16,
+            codec_header: None,
+        };
+
+        eq.process(&[Sample(0); 4], &format);
+        let at_48k = eq.stages[0].coeffs;
+
+        format.sample_rate = 44100;
+        eq.process(&[Sample(0); 4], &format);
+        assert_eq!(eq.sample_rate, 44100);
+        assert_ne!(eq.stages[0].coeffs, at_48k);
+    }
+
+    #[test]
+    fn test_f32_matches_integer_path() {
+        let format = AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 24,
+            codec_header: None,
+        };
+        let config: EqConfig = "lowshelf:100:-4,peak:2500:2:3".parse().unwrap();
+        let samples: Vec<Sample> = (0..200)
+            .map(|i| Sample((i * 37_331) % 4_000_000 - 2_000_000))
+            .collect();
+
+        let ints = Equalizer::new(config.clone()).process(&samples, &format);
+        let mut floats = crate::float::to_f32(&samples);
+        Equalizer::new(config).process_f32(&mut floats, &format);
+
+        // Same filter, only the final rounding differs
+        for (i, f) in ints.iter().zip(crate::float::from_f32(&floats)) {
+            assert!((i.0 - f.0).abs() <= 1, "{} vs {}", i.0, f.0);
+        }
+    }
+}
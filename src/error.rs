@@ -0,0 +1,137 @@
+// Connection Errors
+//
+// Connecting to a server can fail in ways that call for different reactions:
+// a server that isn't up yet, a wrong path during a server update or a
+// dropped network is worth retrying, while an upgrade refused for lack of
+// authorization (401, 403) or a server that closes the connection with a
+// protocol or policy code will refuse again. Discovery and the handshake
+// return this enum instead of a boxed error so the reconnect loop can tell
+// them apart; `is_transient` is what it asks. A failed playback thread ends
+// the session as an `Audio` error, which a new player may get past.
+
+use crate::compat::Disconnect;
+use crate::recovery::PlaybackFailed;
+use hickory_resolver::error::ResolveError;
+use std::time::Duration;
+use thiserror::Error;
+use tokio_tungstenite::tungstenite;
+use tungstenite::http::StatusCode;
+
+#[derive(Debug, Error)]
+pub enum SendspinCliError {
+    /// The WebSocket couldn't be opened: name lookup, TCP or the HTTP upgrade
+    #[error("can't connect: {0}")]
+    Connect(#[source] tungstenite::Error),
+    /// Connected, but the hello exchange didn't complete
+    #[error("handshake failed: {0}")]
+    Handshake(String),
+    /// The server closed the connection before its hello
+    #[error("server closed the connection during the handshake: {0}")]
+    Rejected(Disconnect),
+    /// A message couldn't be encoded
+    #[error("protocol error: {0}")]
+    Protocol(#[from] serde_json::Error),
+    /// The audio output failed and the player stopped playing
+    #[error("audio error: {0}")]
+    Audio(#[from] PlaybackFailed),
+    /// mDNS couldn't be started or browsed
+    #[error("mDNS discovery failed: {0}")]
+    Discovery(#[from] mdns_sd::Error),
+    /// mDNS ran, but no server answered
+    #[error("No Sendspin server found via mDNS after {0:?}")]
+    NoServerFound(Duration),
+    /// The unicast DNS-SD fallback couldn't query the resolver
+    #[error("DNS-SD lookup failed: {0}")]
+    Dns(#[from] ResolveError),
+    /// The resolver answered, but lists no server under the domain
+    #[error("No Sendspin server found via DNS-SD under {0}")]
+    NoDnsServer(String),
+}
+
+impl SendspinCliError {
+    /// Whether trying again later can help
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SendspinCliError::Connect(e) => match e {
+                // Refused by the HTTP server: only missing authorization
+                // stays refused, a 404 or 5xx may be a server mid-update
+                tungstenite::Error::Http(response) => !matches!(
+                    response.status(),
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+                ),
+                tungstenite::Error::Url(_) => false,
+                _ => true,
+            },
+            SendspinCliError::Rejected(disconnect) => disconnect.is_transient(),
+            SendspinCliError::Protocol(_) => false,
+            SendspinCliError::Handshake(_)
+            | SendspinCliError::Audio(_)
+            | SendspinCliError::Discovery(_)
+            | SendspinCliError::NoServerFound(_)
+            | SendspinCliError::Dns(_)
+            | SendspinCliError::NoDnsServer(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tungstenite::http::Response;
+
+    fn http(status: StatusCode) -> SendspinCliError {
+        let response = Response::builder().status(status).body(None).unwrap();
+        SendspinCliError::Connect(tungstenite::Error::Http(response.into()))
+    }
+
+    #[test]
+    fn test_retry_or_abort() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert!(SendspinCliError::Connect(tungstenite::Error::Io(refused)).is_transient());
+        assert!(http(StatusCode::SERVICE_UNAVAILABLE).is_transient());
+        assert!(http(StatusCode::NOT_FOUND).is_transient());
+        assert!(!http(StatusCode::UNAUTHORIZED).is_transient());
+        assert!(!http(StatusCode::FORBIDDEN).is_transient());
+        assert!(SendspinCliError::Handshake("timed out".to_string()).is_transient());
+
+        let policy = Disconnect::Closed {
+            code: Some(1008),
+            reason: "unauthorized".to_string(),
+        };
+        assert!(!SendspinCliError::Rejected(policy).is_transient());
+        let restart = Disconnect::Closed {
+            code: Some(1012),
+            reason: String::new(),
+        };
+        assert!(SendspinCliError::Rejected(restart).is_transient());
+    }
+
+    #[test]
+    fn test_messages() {
+        let e = SendspinCliError::NoServerFound(Duration::from_secs(5));
+        assert_eq!(e.to_string(), "No Sendspin server found via mDNS after 5s");
+        let e = SendspinCliError::NoDnsServer("_sendspin-server._tcp.example.com.".to_string());
+        assert_eq!(
+            e.to_string(),
+            "No Sendspin server found via DNS-SD under _sendspin-server._tcp.example.com."
+        );
+        let e = SendspinCliError::Rejected(Disconnect::Ended);
+        assert_eq!(
+            e.to_string(),
+            "server closed the connection during the handshake: connection ended without a close frame"
+        );
+        let e = SendspinCliError::Handshake("sending hello: broken pipe".to_string());
+        assert_eq!(
+            e.to_string(),
+            "handshake failed: sending hello: broken pipe"
+        );
+        let e = SendspinCliError::from(PlaybackFailed {
+            reason: "device unplugged".to_string(),
+        });
+        assert_eq!(
+            e.to_string(),
+            "audio error: playback stopped: device unplugged"
+        );
+        assert!(e.is_transient());
+    }
+}
@@ -0,0 +1,162 @@
+// Player Events
+//
+// An app embedding the client (a GUI wrapper showing whether the player is
+// connected, say) shouldn't have to scrape the log for it. The session emits
+// a `PlayerEvent` at each transition into an `EventSender`: the connection
+// coming and going, streams starting and ending, playback state, volume and
+// track metadata changing, underruns and errors. Events go either to a
+// channel, whose receiving end sees them in order, or to a callback run on
+// the spot (how `--json-events` prints them). Sending never blocks and never
+// fails: with no receiver, or one that was dropped, events are discarded.
+
+use sendspin::audio::AudioFormat;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// A transition in the connection to the server or in playback
+#[derive(Debug, Clone)]
+pub enum PlayerEvent {
+    /// Opening a connection to `url`
+    Connecting { url: String },
+    /// Handshake done (server/hello received)
+    Connected { server_id: String },
+    /// The connection ended or couldn't be made
+    Disconnected { reason: String },
+    /// Reconnect attempt `attempt` (from 1) starts after `delay`
+    Reconnecting { attempt: u32, delay: Duration },
+    /// stream/start set up playback in `format`
+    StreamStarted { format: AudioFormat },
+    /// stream/end: what's queued plays out
+    StreamEnded,
+    /// Playing, paused or stopped by a server command or a new stream
+    StateChanged { state: PlaybackState },
+    /// Volume or mute changed, by the server or on connecting
+    VolumeChanged { volume: u8, muted: bool },
+    /// server/state carried metadata for another track
+    MetadataChanged {
+        title: Option<String>,
+        artist: Option<String>,
+        album: Option<String>,
+    },
+    /// The output ran out of audio that was due
+    Underrun,
+    /// Something went wrong that the log reports as an error
+    Error { message: String },
+}
+
+/// What the player is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl PlaybackState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaybackState::Playing => "playing",
+            PlaybackState::Paused => "paused",
+            PlaybackState::Stopped => "stopped",
+        }
+    }
+}
+
+type Callback = Arc<dyn Fn(&PlayerEvent) + Send + Sync>;
+
+/// Where the session emits its events
+#[derive(Clone, Default)]
+pub struct EventSender {
+    sink: Option<Sink>,
+}
+
+#[derive(Clone)]
+enum Sink {
+    Channel(mpsc::UnboundedSender<PlayerEvent>),
+    Callback(Callback),
+}
+
+/// A sender and the receiver its events arrive at
+pub fn channel() -> (EventSender, mpsc::UnboundedReceiver<PlayerEvent>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let events = EventSender {
+        sink: Some(Sink::Channel(tx)),
+    };
+    (events, rx)
+}
+
+impl EventSender {
+    /// A sender that discards everything
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// A sender that runs `callback` on each event as it's emitted; it runs
+    /// on the session's task, so it should be quick
+    pub fn callback(callback: impl Fn(&PlayerEvent) + Send + Sync + 'static) -> Self {
+        EventSender {
+            sink: Some(Sink::Callback(Arc::new(callback))),
+        }
+    }
+
+    pub fn emit(&self, event: PlayerEvent) {
+        match &self.sink {
+            // A receiver that went away just stops listening
+            Some(Sink::Channel(tx)) => {
+                let _ = tx.send(event);
+            }
+            Some(Sink::Callback(callback)) => callback(&event),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_arrive_in_order() {
+        let (events, mut rx) = channel();
+        events.emit(PlayerEvent::Connecting {
+            url: "ws://speaker:8927/sendspin".to_string(),
+        });
+        events.emit(PlayerEvent::Connected {
+            server_id: "ma".to_string(),
+        });
+        assert!(matches!(rx.try_recv(), Ok(PlayerEvent::Connecting { .. })));
+        match rx.try_recv() {
+            Ok(PlayerEvent::Connected { server_id }) => assert_eq!(server_id, "ma"),
+            other => panic!("expected Connected, got {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+
+        // Nobody listening: still fine
+        drop(rx);
+        events.emit(PlayerEvent::Disconnected {
+            reason: "closed".to_string(),
+        });
+        EventSender::none().emit(PlayerEvent::Reconnecting {
+            attempt: 1,
+            delay: Duration::from_secs(1),
+        });
+    }
+
+    #[test]
+    fn test_callback_runs_on_emit() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let events = EventSender::callback(move |event| {
+            log.lock().unwrap().push(format!("{:?}", event));
+        });
+        events.emit(PlayerEvent::StreamEnded);
+        events.clone().emit(PlayerEvent::StateChanged {
+            state: PlaybackState::Paused,
+        });
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["StreamEnded", "StateChanged { state: Paused }"]
+        );
+    }
+}
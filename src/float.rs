@@ -0,0 +1,101 @@
+// Float Sample Path
+//
+// Samples travel as 24-bit integers. When the output device takes f32
+// natively, the player converts each buffer to f32 once, runs EQ, balance,
+// gain and fades in float and writes f32 straight to the device, instead of
+// rounding back to integers after every stage. Every 24-bit value fits in
+// an f32 mantissa, so the conversion itself is exact in both directions.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use sendspin::audio::Sample;
+
+/// Integer value of full scale (1.0)
+const SCALE: f32 = 8_388_608.0; // 2^23
+
+/// Convert 24-bit samples to f32 in -1.0..1.0
+pub fn to_f32(samples: &[Sample]) -> Vec<f32> {
+    samples.iter().map(|s| s.0 as f32 / SCALE).collect()
+}
+
+/// Convert into a reused buffer, replacing its contents
+pub fn to_f32_into(samples: &[Sample], out: &mut Vec<f32>) {
+    out.clear();
+    out.extend(samples.iter().map(|s| s.0 as f32 / SCALE));
+}
+
+/// Convert f32 back to 24-bit samples, rounding and clamping
+pub fn from_f32(samples: &[f32]) -> Vec<Sample> {
+    samples
+        .iter()
+        .map(|&s| Sample(((s * SCALE).round() as i32).clamp(SAMPLE_MIN, SAMPLE_MAX)))
+        .collect()
+}
+
+/// Scale in place by a linear gain
+pub fn apply_gain(samples: &mut [f32], gain: f32) {
+    samples.iter_mut().for_each(|s| *s *= gain);
+}
+
+/// Limit to the range the integer path can represent
+pub fn clamp(samples: &mut [f32]) {
+    let (min, max) = (SAMPLE_MIN as f32 / SCALE, SAMPLE_MAX as f32 / SCALE);
+    samples.iter_mut().for_each(|s| *s = s.clamp(min, max));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion_is_exact_for_24_bit() {
+        let samples = [
+            Sample(SAMPLE_MAX),
+            Sample(SAMPLE_MIN),
+            Sample(1),
+            Sample(-1),
+            Sample(0),
+            Sample(4_194_304),
+        ];
+        let float = to_f32(&samples);
+        assert_eq!(float[1], -1.0);
+        assert_eq!(float[5], 0.5);
+        let back: Vec<i32> = from_f32(&float).iter().map(|s| s.0).collect();
+        assert_eq!(back, vec![SAMPLE_MAX, SAMPLE_MIN, 1, -1, 0, 4_194_304]);
+    }
+
+    #[test]
+    fn test_16_bit_pass_through_is_bit_transparent() {
+        // 16-bit content is carried shifted up into 24 bits
+        let samples: Vec<Sample> = (i16::MIN..=i16::MAX)
+            .map(|v| Sample((v as i32) << 8))
+            .collect();
+        let mut float = to_f32(&samples);
+        // 0 dB: unity gain and clamping must not change a single value
+        apply_gain(&mut float, 1.0);
+        clamp(&mut float);
+        for (v, f) in (i16::MIN..=i16::MAX).zip(&float) {
+            assert_eq!(*f, v as f32 / 32768.0);
+        }
+        let back = from_f32(&float);
+        assert!(samples.iter().zip(&back).all(|(a, b)| a.0 == b.0));
+    }
+
+    #[test]
+    fn test_from_f32_rounds_and_clamps() {
+        let back: Vec<i32> = from_f32(&[1.5, -1.5, 0.6 / SCALE, -0.6 / SCALE])
+            .iter()
+            .map(|s| s.0)
+            .collect();
+        assert_eq!(back, vec![SAMPLE_MAX, SAMPLE_MIN, 1, -1]);
+    }
+
+    #[test]
+    fn test_gain_and_clamp() {
+        let mut samples = [0.5, -0.5, 0.9];
+        apply_gain(&mut samples, 2.0);
+        clamp(&mut samples);
+        assert_eq!(samples[0], SAMPLE_MAX as f32 / SCALE);
+        assert_eq!(samples[1], -1.0);
+        assert_eq!(samples[2], SAMPLE_MAX as f32 / SCALE);
+    }
+}
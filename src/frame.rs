@@ -0,0 +1,102 @@
+// Zero-Copy Audio Frames
+//
+// Audio arrives as binary WebSocket frames: one type byte, an 8-byte
+// big-endian server timestamp, then the encoded audio. The library parser
+// copies the payload into a fresh buffer for every chunk, and decoding reads
+// it once more, so each byte of audio was copied twice before it became a
+// sample. Audio frames are parsed here instead: the socket's payload moves
+// into a shared `Bytes` once and the chunk is a view past the header, so the
+// decoder reads straight from the bytes the socket received. Artwork,
+// visualizer and unknown frames are rare and still go through the library.
+//
+// `cargo bench --bench frame_parse` compares both paths on a stream of
+// multi-megabyte synthetic frames.
+
+use bytes::Bytes;
+
+/// Binary message type of a player audio chunk
+pub const AUDIO_CHUNK: u8 = 4;
+
+/// Type byte plus the 8-byte timestamp
+pub const HEADER_LEN: usize = 9;
+
+/// Audio chunk viewing the frame it arrived in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioFrame {
+    pub timestamp: i64, // Server clock, microseconds
+    pub data: Bytes,    // Encoded audio, shares the frame's buffer
+}
+
+/// Parse an audio frame without copying its payload; any other frame (or
+/// one too short to hold a header) is handed back for the library parser
+pub fn parse_audio(frame: Vec<u8>) -> Result<AudioFrame, Vec<u8>> {
+    if frame.len() < HEADER_LEN || frame[0] != AUDIO_CHUNK {
+        return Err(frame);
+    }
+    let mut timestamp = [0u8; 8];
+    timestamp.copy_from_slice(&frame[1..HEADER_LEN]);
+    Ok(AudioFrame {
+        timestamp: i64::from_be_bytes(timestamp),
+        data: Bytes::from(frame).slice(HEADER_LEN..),
+    })
+}
+
+impl From<sendspin::protocol::client::AudioChunk> for AudioFrame {
+    /// Chunks the library parsed (legacy layouts) keep working, at one copy
+    fn from(chunk: sendspin::protocol::client::AudioChunk) -> Self {
+        AudioFrame {
+            timestamp: chunk.timestamp,
+            data: Bytes::copy_from_slice(&chunk.data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp: i64, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![AUDIO_CHUNK];
+        frame.extend_from_slice(&timestamp.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_parses_timestamp_and_payload() {
+        let parsed = parse_audio(frame(-1_234_567, &[1, 2, 3, 4, 5, 6])).unwrap();
+        assert_eq!(parsed.timestamp, -1_234_567);
+        assert_eq!(&parsed.data[..], &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_payload_is_a_view_of_the_frame() {
+        let frame = frame(42, &[7; 4096]);
+        let start = frame.as_ptr();
+        let parsed = parse_audio(frame).unwrap();
+        assert_eq!(parsed.data.as_ptr(), start.wrapping_add(HEADER_LEN));
+    }
+
+    #[test]
+    fn test_header_only_frame_is_an_empty_chunk() {
+        let header = frame(i64::MAX, &[]);
+        assert_eq!(header.len(), HEADER_LEN);
+        let parsed = parse_audio(header).unwrap();
+        assert_eq!(parsed.timestamp, i64::MAX);
+        assert!(parsed.data.is_empty());
+
+        let one = parse_audio(frame(0, &[0xAB])).unwrap();
+        assert_eq!(&one.data[..], &[0xAB]);
+    }
+
+    #[test]
+    fn test_short_and_other_frames_are_handed_back() {
+        let short = frame(5, &[])[..HEADER_LEN - 1].to_vec();
+        assert_eq!(parse_audio(short.clone()), Err(short));
+        assert_eq!(parse_audio(Vec::new()), Err(Vec::new()));
+
+        let mut artwork = frame(5, &[1, 2, 3]);
+        artwork[0] = 8;
+        assert_eq!(parse_audio(artwork.clone()), Err(artwork));
+    }
+}
@@ -0,0 +1,139 @@
+// Player Identity
+//
+// Builds the name shown in Music Assistant. With `--name-suffix auto` the
+// machine hostname (and the output device, when one is named) is appended,
+// so one instance per room shows up as "Living Room (pi-kitchen)" without
+// hand-crafting a unique name for every box.
+//
+// With `--stable-id` the client_id is derived from hostname + output device,
+// so Music Assistant recognises the same player (and keeps its settings)
+// across restarts instead of seeing a new random id each run.
+
+use std::convert::Infallible;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Suffix appended to the player name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameSuffix {
+    Auto,         // Hostname, plus the output device if known
+    Text(String), // Used verbatim
+}
+
+impl FromStr for NameSuffix {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(if s == "auto" {
+            NameSuffix::Auto
+        } else {
+            NameSuffix::Text(s.to_string())
+        })
+    }
+}
+
+/// This machine's hostname, if it can be read
+pub fn hostname() -> Option<String> {
+    hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .filter(|name| !name.is_empty())
+}
+
+/// Player name with the optional suffix in parentheses
+pub fn player_name(
+    name: &str,
+    suffix: Option<&NameSuffix>,
+    host: Option<&str>,
+    device: Option<&str>,
+) -> String {
+    let suffix = match suffix {
+        None => return name.to_string(),
+        Some(NameSuffix::Text(text)) => text.clone(),
+        Some(NameSuffix::Auto) => host
+            .into_iter()
+            .chain(device)
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    if suffix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} ({})", name, suffix)
+    }
+}
+
+/// Deterministic client_id for a hostname and output device
+pub fn stable_client_id(host: &str, device: &str) -> String {
+    let key = format!("sendspin-rs-cli\0{}\0{}", host, device);
+    format!(
+        "sendspin-rs-{}",
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes())
+    )
+}
+
+/// Fresh random client_id (the default)
+pub fn random_client_id() -> String {
+    format!("sendspin-rs-{}", Uuid::new_v4())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_suffix() {
+        assert_eq!("auto".parse::<NameSuffix>().unwrap(), NameSuffix::Auto);
+        assert_eq!(
+            "attic".parse::<NameSuffix>().unwrap(),
+            NameSuffix::Text("attic".to_string())
+        );
+    }
+
+    #[test]
+    fn test_no_suffix_keeps_name() {
+        assert_eq!(
+            player_name("Living Room", None, Some("pi"), None),
+            "Living Room"
+        );
+    }
+
+    #[test]
+    fn test_auto_suffix() {
+        let auto = NameSuffix::Auto;
+        assert_eq!(
+            player_name("Living Room", Some(&auto), Some("pi"), None),
+            "Living Room (pi)"
+        );
+        assert_eq!(
+            player_name("Living Room", Some(&auto), Some("pi"), Some("hw:CARD=DAC")),
+            "Living Room (pi, hw:CARD=DAC)"
+        );
+        // Nothing known about the machine: plain name rather than "()"
+        assert_eq!(
+            player_name("Living Room", Some(&auto), None, None),
+            "Living Room"
+        );
+    }
+
+    #[test]
+    fn test_stable_client_id() {
+        let id = stable_client_id("pi-kitchen", "default");
+        assert_eq!(id, stable_client_id("pi-kitchen", "default"));
+        assert!(id.starts_with("sendspin-rs-"));
+
+        // Different box or different device: different player
+        assert_ne!(id, stable_client_id("pi-attic", "default"));
+        assert_ne!(id, stable_client_id("pi-kitchen", "hw:CARD=DAC,DEV=0"));
+        assert_ne!(random_client_id(), random_client_id());
+    }
+
+    #[test]
+    fn test_text_suffix() {
+        let text = NameSuffix::Text("upstairs".to_string());
+        assert_eq!(
+            player_name("Speaker", Some(&text), Some("pi"), None),
+            "Speaker (upstairs)"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+// Idle Release
+//
+// Many amplifiers go to standby on their own once their input stops, but a
+// server that keeps streaming digital silence (a long queue, a gap between
+// tracks) keeps the output busy and the amplifier awake. With
+// `--idle-release-secs N` the player closes the output after N seconds of
+// audio below -70 dBFS and keeps taking buffers off the queue on schedule
+// without playing them. The first buffer that isn't silent, or a new
+// stream, opens the output again the same way the start of a stream does.
+
+use sendspin::audio::{AudioFormat, Sample};
+use std::time::Duration;
+
+/// Peak level below which a buffer counts as silent
+pub const SILENCE_THRESHOLD_DBFS: f64 = -70.0;
+
+/// What a buffer changed about the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleEvent {
+    None,
+    Release,   // Silent long enough: close the output
+    Reacquire, // Audio is back: open it again
+}
+
+/// Whether every sample of a buffer is below the silence threshold
+pub fn is_silent(samples: &[Sample]) -> bool {
+    let threshold = 10f64.powf(SILENCE_THRESHOLD_DBFS / 20.0) * (1u32 << 23) as f64;
+    samples
+        .iter()
+        .all(|sample| (sample.0.unsigned_abs() as f64) < threshold)
+}
+
+/// Tracks how long the audio has been silent
+#[derive(Debug)]
+pub struct SilenceDetector {
+    release_after: Duration,
+    silent_for: Duration,
+    released: bool,
+}
+
+impl SilenceDetector {
+    pub fn new(release_after: Duration) -> Self {
+        SilenceDetector {
+            release_after,
+            silent_for: Duration::ZERO,
+            released: false,
+        }
+    }
+
+    /// Whether the output is released (buffers are consumed, not played)
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    /// Start over, e.g. for a new stream (which opens the output anyway)
+    pub fn reset(&mut self) {
+        self.silent_for = Duration::ZERO;
+        self.released = false;
+    }
+
+    /// Account for one buffer about to play
+    pub fn update(&mut self, samples: &[Sample], format: &AudioFormat) -> IdleEvent {
+        if !is_silent(samples) {
+            self.silent_for = Duration::ZERO;
+            return if std::mem::take(&mut self.released) {
+                IdleEvent::Reacquire
+            } else {
+                IdleEvent::None
+            };
+        }
+        let frames = samples.len() / (format.channels as usize).max(1);
+        self.silent_for +=
+            Duration::from_secs_f64(frames as f64 / format.sample_rate.max(1) as f64);
+        if !self.released && self.silent_for >= self.release_after {
+            self.released = true;
+            return IdleEvent::Release;
+        }
+        IdleEvent::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    const FORMAT: AudioFormat = AudioFormat {
+        codec: Codec::Pcm,
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+    };
+
+    /// 100 ms of stereo at a peak level in dBFS (None = digital silence)
+    fn buffer(level_dbfs: Option<f64>) -> Vec<Sample> {
+        let peak = level_dbfs.map_or(0.0, |db| 10f64.powf(db / 20.0) * (1u32 << 23) as f64);
+        (0..9600)
+            .map(|i| {
+                let phase = (i / 2) as f64 * 2.0 * std::f64::consts::PI * 440.0 / 48000.0;
+                Sample((peak * phase.sin()).round() as i32)
+            })
+            .collect()
+    }
+
+    /// Feed `count` buffers, collecting the events that weren't None
+    fn feed(detector: &mut SilenceDetector, samples: &[Sample], count: usize) -> Vec<IdleEvent> {
+        (0..count)
+            .map(|_| detector.update(samples, &FORMAT))
+            .filter(|event| *event != IdleEvent::None)
+            .collect()
+    }
+
+    #[test]
+    fn test_silence_threshold() {
+        assert!(is_silent(&buffer(None)));
+        assert!(is_silent(&buffer(Some(-80.0))));
+        assert!(!is_silent(&buffer(Some(-60.0))));
+        // One loud sample is enough
+        let mut samples = buffer(None);
+        samples[500] = Sample(100_000);
+        assert!(!is_silent(&samples));
+    }
+
+    #[test]
+    fn test_releases_after_silence() {
+        let mut detector = SilenceDetector::new(Duration::from_secs(2));
+        // 1.9 s of silence: still open
+        assert!(feed(&mut detector, &buffer(None), 19).is_empty());
+        assert!(!detector.is_released());
+        // 2 s: released, once
+        assert_eq!(
+            feed(&mut detector, &buffer(None), 10),
+            vec![IdleEvent::Release]
+        );
+        assert!(detector.is_released());
+
+        // Audio again: reopen right away
+        assert_eq!(
+            feed(&mut detector, &buffer(Some(-20.0)), 1),
+            vec![IdleEvent::Reacquire]
+        );
+        assert!(!detector.is_released());
+    }
+
+    #[test]
+    fn test_quiet_audio_keeps_device() {
+        // A quiet passage (-60 dBFS) isn't silence, and resets the count
+        let mut detector = SilenceDetector::new(Duration::from_secs(2));
+        for _ in 0..10 {
+            assert!(feed(&mut detector, &buffer(None), 15).is_empty());
+            assert!(feed(&mut detector, &buffer(Some(-60.0)), 1).is_empty());
+        }
+        assert!(!detector.is_released());
+
+        // Below the threshold counts as silence
+        assert_eq!(
+            feed(&mut detector, &buffer(Some(-75.0)), 20),
+            vec![IdleEvent::Release]
+        );
+    }
+
+    #[test]
+    fn test_new_stream_starts_over() {
+        let mut detector = SilenceDetector::new(Duration::from_secs(1));
+        feed(&mut detector, &buffer(None), 10);
+        assert!(detector.is_released());
+        detector.reset();
+        assert!(!detector.is_released());
+        // Counting starts over
+        assert!(feed(&mut detector, &buffer(None), 9).is_empty());
+    }
+}
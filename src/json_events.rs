@@ -0,0 +1,219 @@
+// JSON Events
+//
+// `--json-events` prints each player event as one JSON object per line on
+// stdout, for home-automation glue that would otherwise parse the log (which
+// stays on stderr, so stdout carries nothing else). Every line has an
+// `event` name, a `seq` number that goes up by one per event from 1, and a
+// Unix `timestamp_ms`, followed by the event's own fields.
+//
+// Scripts depend on these names: the tests pin the exact lines, so a change
+// to the schema shows up as a failing test rather than a broken script.
+
+use crate::events::PlayerEvent;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One event as printed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent {
+    Connecting {
+        url: String,
+    },
+    Connected {
+        server_id: String,
+    },
+    Disconnected {
+        reason: String,
+    },
+    Reconnecting {
+        attempt: u32,
+        delay_ms: u64,
+    },
+    StreamStarted {
+        codec: String,
+        sample_rate: u32,
+        channels: u8,
+        bit_depth: u8,
+    },
+    StreamEnded,
+    StateChanged {
+        state: &'static str,
+    },
+    VolumeChanged {
+        volume: u8,
+        muted: bool,
+    },
+    MetadataChanged {
+        title: Option<String>,
+        artist: Option<String>,
+        album: Option<String>,
+    },
+    Underrun,
+    Error {
+        message: String,
+    },
+}
+
+impl From<&PlayerEvent> for JsonEvent {
+    fn from(event: &PlayerEvent) -> Self {
+        match event.clone() {
+            PlayerEvent::Connecting { url } => JsonEvent::Connecting { url },
+            PlayerEvent::Connected { server_id } => JsonEvent::Connected { server_id },
+            PlayerEvent::Disconnected { reason } => JsonEvent::Disconnected { reason },
+            PlayerEvent::Reconnecting { attempt, delay } => JsonEvent::Reconnecting {
+                attempt,
+                delay_ms: delay.as_millis() as u64,
+            },
+            PlayerEvent::StreamStarted { format } => JsonEvent::StreamStarted {
+                codec: format!("{:?}", format.codec).to_lowercase(),
+                sample_rate: format.sample_rate,
+                channels: format.channels,
+                bit_depth: format.bit_depth,
+            },
+            PlayerEvent::StreamEnded => JsonEvent::StreamEnded,
+            PlayerEvent::StateChanged { state } => JsonEvent::StateChanged {
+                state: state.as_str(),
+            },
+            PlayerEvent::VolumeChanged { volume, muted } => {
+                JsonEvent::VolumeChanged { volume, muted }
+            }
+            PlayerEvent::MetadataChanged {
+                title,
+                artist,
+                album,
+            } => JsonEvent::MetadataChanged {
+                title,
+                artist,
+                album,
+            },
+            PlayerEvent::Underrun => JsonEvent::Underrun,
+            PlayerEvent::Error { message } => JsonEvent::Error { message },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    event: &'a JsonEvent,
+    seq: u64,
+    timestamp_ms: u64,
+}
+
+/// Numbers events and renders them as lines
+#[derive(Debug, Default)]
+pub struct JsonEventWriter {
+    seq: u64,
+}
+
+impl JsonEventWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next line, for an event that happened at `at`
+    pub fn line(&mut self, event: &PlayerEvent, at: SystemTime) -> String {
+        self.seq += 1;
+        let line = Line {
+            event: &JsonEvent::from(event),
+            seq: self.seq,
+            timestamp_ms: at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+        };
+        serde_json::to_string(&line).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::PlaybackState;
+    use sendspin::audio::{AudioFormat, Codec};
+    use std::time::Duration;
+
+    fn at(ms: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_event_schema() {
+        let mut writer = JsonEventWriter::new();
+        let lines: Vec<String> = [
+            PlayerEvent::Connected {
+                server_id: "ma".to_string(),
+            },
+            PlayerEvent::StreamStarted {
+                format: AudioFormat {
+                    codec: Codec::Pcm,
+                    sample_rate: 48000,
+                    channels: 2,
+                    bit_depth: 24,
+                    codec_header: None,
+                },
+            },
+            PlayerEvent::StateChanged {
+                state: PlaybackState::Paused,
+            },
+            PlayerEvent::VolumeChanged {
+                volume: 40,
+                muted: false,
+            },
+            PlayerEvent::MetadataChanged {
+                title: Some("Song".to_string()),
+                artist: None,
+                album: None,
+            },
+            PlayerEvent::Underrun,
+            PlayerEvent::StreamEnded,
+            PlayerEvent::Error {
+                message: "Audio device lost".to_string(),
+            },
+            PlayerEvent::Reconnecting {
+                attempt: 2,
+                delay: Duration::from_millis(1500),
+            },
+            PlayerEvent::Disconnected {
+                reason: "connection closed".to_string(),
+            },
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, event)| writer.line(event, at(1_700_000_000_000 + i as u64)))
+        .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"connected","server_id":"ma","seq":1,"timestamp_ms":1700000000000}"#,
+                r#"{"event":"stream_started","codec":"pcm","sample_rate":48000,"channels":2,"bit_depth":24,"seq":2,"timestamp_ms":1700000000001}"#,
+                r#"{"event":"state_changed","state":"paused","seq":3,"timestamp_ms":1700000000002}"#,
+                r#"{"event":"volume_changed","volume":40,"muted":false,"seq":4,"timestamp_ms":1700000000003}"#,
+                r#"{"event":"metadata_changed","title":"Song","artist":null,"album":null,"seq":5,"timestamp_ms":1700000000004}"#,
+                r#"{"event":"underrun","seq":6,"timestamp_ms":1700000000005}"#,
+                r#"{"event":"stream_ended","seq":7,"timestamp_ms":1700000000006}"#,
+                r#"{"event":"error","message":"Audio device lost","seq":8,"timestamp_ms":1700000000007}"#,
+                r#"{"event":"reconnecting","attempt":2,"delay_ms":1500,"seq":9,"timestamp_ms":1700000000008}"#,
+                r#"{"event":"disconnected","reason":"connection closed","seq":10,"timestamp_ms":1700000000009}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lines_parse_back() {
+        let mut writer = JsonEventWriter::new();
+        let line = writer.line(
+            &PlayerEvent::Connecting {
+                url: "ws://speaker:8927/sendspin".to_string(),
+            },
+            SystemTime::now(),
+        );
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "connecting");
+        assert_eq!(value["url"], "ws://speaker:8927/sendspin");
+        assert_eq!(value["seq"], 1);
+        assert!(value["timestamp_ms"].as_u64().unwrap() > 1_700_000_000_000);
+    }
+}
@@ -0,0 +1,161 @@
+// Keep-Open Output
+//
+// Some receivers take seconds to lock onto a new signal, so closing the
+// output between tracks cuts off the start of each one. With
+// `--keep-device-open[=SECS]` the output stays open after a stream ends or
+// playback stops, fed with silence, and the next stream reuses it when its
+// sample rate and channel count match. Silence is written in short chunks,
+// paced against the clock so the device never holds more than a couple.
+//
+// The server's `stop` command still closes the output right away, so the
+// device (and its "signal" light) goes off when the user stops playback;
+// pause, stream end and stream clear keep it open.
+
+use sendspin::audio::Sample;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Length of each silence write while idle
+pub const SILENCE_CHUNK: Duration = Duration::from_millis(10);
+
+/// How long to hold the output open after playback stops
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeepOpen {
+    #[default]
+    Off, // Close as soon as playback stops
+    For(Duration),
+    Forever,
+}
+
+impl FromStr for KeepOpen {
+    type Err = String;
+
+    /// Seconds ("30"), with "0" meaning off, or "forever"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("forever") {
+            return Ok(KeepOpen::Forever);
+        }
+        match s.parse::<u64>() {
+            Ok(0) => Ok(KeepOpen::Off),
+            Ok(secs) => Ok(KeepOpen::For(Duration::from_secs(secs))),
+            Err(_) => Err(format!(
+                "invalid keep-open time '{}' (expected seconds or \"forever\")",
+                s
+            )),
+        }
+    }
+}
+
+/// An output held open between streams
+pub struct IdleOutput {
+    until: Option<Instant>, // None = forever
+    fed_until: Instant,
+    silence: Arc<[Sample]>,
+}
+
+impl IdleOutput {
+    /// Start idling after a stop, unless the output should close instead
+    ///
+    /// `close_requested` is set by the server's `stop` command, which always
+    /// closes the output.
+    pub fn start(
+        keep: KeepOpen,
+        close_requested: bool,
+        channels: u8,
+        output_rate: u32,
+        now: Instant,
+    ) -> Option<Self> {
+        let until = match keep {
+            _ if close_requested => return None,
+            KeepOpen::Off => return None,
+            KeepOpen::For(duration) => Some(now + duration),
+            KeepOpen::Forever => None,
+        };
+        let frames = output_rate as u128 * SILENCE_CHUNK.as_millis() / 1000;
+        let samples = frames as usize * channels.max(1) as usize;
+        Some(IdleOutput {
+            until,
+            fed_until: now,
+            silence: vec![Sample(0); samples].into(),
+        })
+    }
+
+    /// Whether the keep-open time has run out
+    pub fn expired(&self, now: Instant) -> bool {
+        self.until.is_some_and(|until| now >= until)
+    }
+
+    /// The next chunk of silence to write, or None while the device still
+    /// has enough queued (the caller sleeps briefly instead)
+    pub fn next_silence(&mut self, now: Instant) -> Option<Arc<[Sample]>> {
+        if self.fed_until > now + SILENCE_CHUNK {
+            return None;
+        }
+        self.fed_until = self.fed_until.max(now) + SILENCE_CHUNK;
+        Some(Arc::clone(&self.silence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keep_open() {
+        assert_eq!("0".parse::<KeepOpen>(), Ok(KeepOpen::Off));
+        assert_eq!(
+            "30".parse::<KeepOpen>(),
+            Ok(KeepOpen::For(Duration::from_secs(30)))
+        );
+        assert_eq!("forever".parse::<KeepOpen>(), Ok(KeepOpen::Forever));
+        assert!("-1".parse::<KeepOpen>().is_err());
+        assert!("5m".parse::<KeepOpen>().is_err());
+    }
+
+    #[test]
+    fn test_off_closes_immediately() {
+        let now = Instant::now();
+        assert!(IdleOutput::start(KeepOpen::Off, false, 2, 48000, now).is_none());
+    }
+
+    #[test]
+    fn test_stop_command_closes_device() {
+        // The user pressed stop: the device light should go off even when
+        // the output would otherwise be kept open
+        let now = Instant::now();
+        for keep in [KeepOpen::For(Duration::from_secs(30)), KeepOpen::Forever] {
+            assert!(IdleOutput::start(keep, true, 2, 48000, now).is_none());
+            assert!(IdleOutput::start(keep, false, 2, 48000, now).is_some());
+        }
+    }
+
+    #[test]
+    fn test_keep_open_expires() {
+        let now = Instant::now();
+        let keep = KeepOpen::For(Duration::from_secs(5));
+        let idle = IdleOutput::start(keep, false, 2, 48000, now).unwrap();
+        assert!(!idle.expired(now + Duration::from_secs(4)));
+        assert!(idle.expired(now + Duration::from_secs(5)));
+
+        let idle = IdleOutput::start(KeepOpen::Forever, false, 2, 48000, now).unwrap();
+        assert!(!idle.expired(now + Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn test_silence_is_paced() {
+        let now = Instant::now();
+        let mut idle = IdleOutput::start(KeepOpen::Forever, false, 2, 48000, now).unwrap();
+
+        // 10 ms at the output rate, stereo
+        let chunk = idle.next_silence(now).unwrap();
+        assert_eq!(chunk.len(), 480 * 2);
+        assert!(chunk.iter().all(|s| s.0 == 0));
+
+        // Two chunks ahead at most, then wait for the clock
+        assert!(idle.next_silence(now).is_some());
+        assert!(idle.next_silence(now).is_none());
+        assert!(idle.next_silence(now + SILENCE_CHUNK).is_some());
+    }
+}
@@ -1,4 +1,57 @@
-// Library exports for testing
+// Library exports (shared by the CLI binary and tests)
 
+pub mod artwork;
+pub mod balance;
+pub mod callback;
+pub mod channel_guard;
+pub mod coalesce;
+pub mod compat;
+pub mod conceal;
+pub mod continuity;
+pub mod crossfade;
+pub mod dcblock;
+pub mod device;
+pub mod diag;
+pub mod dither;
+pub mod drift;
+pub mod envelope;
+pub mod eq;
+pub mod error;
+pub mod events;
+pub mod float;
+pub mod frame;
+pub mod identity;
+pub mod idle_release;
+pub mod json_events;
+pub mod keep_open;
+pub mod log_file;
+pub mod loudness;
 pub mod mdns;
+pub mod mono;
+pub mod negotiate;
+pub mod output;
+pub mod pcm_layout;
 pub mod player;
+pub mod pool;
+pub mod position;
+pub mod reconnect;
+pub mod recovery;
+pub mod replaygain;
+pub mod resample;
+pub mod ring;
+pub mod rng;
+pub mod rt_priority;
+pub mod seek;
+pub mod selftest;
+pub mod server_error;
+pub mod server_volume;
+pub mod session_limit;
+pub mod speed;
+pub mod state_report;
+pub mod stats;
+pub mod stream_start;
+pub mod telemetry;
+pub mod timing_trace;
+pub mod volume;
+pub mod wake;
+pub mod warmup;
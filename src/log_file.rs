@@ -0,0 +1,250 @@
+// Log File
+//
+// A headless player left running for months either loses its log to the
+// journal's scrollback or, with debug lines left on, fills the SD card.
+// `--log-file <path>` writes the log to a file as well as the console, and
+// rotates it by size: once the next line would take it past
+// `--log-max-size`, `sendspin.log` becomes `sendspin.log.1`, the older ones
+// move up by one (`.1` to `.2`, ...), and a new `sendspin.log` is started.
+// `--log-keep` rotated files are kept; the oldest is replaced. With
+// `--log-keep 0` the file is truncated and starts over instead.
+//
+// Rotation is a series of renames, oldest first, each of which either
+// happened or didn't: a process killed halfway leaves every line in one of
+// the files, at worst with a gap in the numbering that the next rotation
+// closes. The file is closed while it's renamed, as Windows requires, and
+// renames replace their target on every platform.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Default `--log-max-size`
+pub const DEFAULT_MAX_SIZE: &str = "10M";
+
+/// Default `--log-keep`
+pub const DEFAULT_KEEP: u32 = 3;
+
+/// Parse a file size: bytes, or a number with a K, M or G suffix (powers
+/// of 1024, an optional trailing B ignored), e.g. `512K` or `10MB`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let upper = trimmed.to_ascii_uppercase();
+    let upper = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, unit) = match upper.char_indices().last() {
+        Some((at, 'K')) => (&upper[..at], 1 << 10),
+        Some((at, 'M')) => (&upper[..at], 1 << 20),
+        Some((at, 'G')) => (&upper[..at], 1 << 30),
+        _ => (upper, 1),
+    };
+    let size = number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("invalid size '{}' (use e.g. 512K, 10M or 1G)", trimmed))?;
+    if size == 0 {
+        return Err(format!("size '{}' must be more than zero", trimmed));
+    }
+    Ok(size)
+}
+
+/// `path` with `.n` appended, e.g. `sendspin.log.2`
+pub fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// An append-only file that rotates itself by size
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32,
+    file: Option<File>, // None after a failed reopen, retried on the next write
+    size: u64,          // Bytes in the current file
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, max_size: u64, keep: u32) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            file: Some(file),
+            size,
+        })
+    }
+
+    /// Open the current file again after a failed rotation
+    fn reopen(&mut self) -> io::Result<()> {
+        let file = open_append(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Move the current file out of the way and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        // Closed first: Windows can't rename an open file
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        if self.keep == 0 {
+            let file = OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .create(true)
+                .open(&self.path)?;
+            drop(file);
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.size = 0;
+        self.file = Some(open_append(&self.path)?);
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for RotatingFile {
+    /// Writes `buf` whole: a line is never split across two files
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() {
+            self.reopen()?;
+        }
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let file = self.file.as_mut().ok_or(io::ErrorKind::NotFound)?;
+        file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system's temp dir
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sendspin-log-file-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("10mb"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn test_rotated_path_appends_the_number() {
+        let path = Path::new("logs").join("sendspin.log");
+        assert_eq!(
+            rotated_path(&path, 2),
+            Path::new("logs").join("sendspin.log.2")
+        );
+    }
+
+    #[test]
+    fn test_rotates_past_the_size_limit() {
+        let dir = scratch("rotate");
+        let path = dir.join("sendspin.log");
+        let mut log = RotatingFile::open(&path, 20, 2).unwrap();
+        for line in ["line 1 .......\n", "line 2 .......\n", "line 3 .......\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(&path), "line 3 .......\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "line 2 .......\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "line 1 .......\n");
+
+        // Only `keep` rotated files: the oldest is replaced
+        log.write_all(b"line 4 .......\n").unwrap();
+        assert_eq!(read(&rotated_path(&path, 2)), "line 2 .......\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        // Reopened, it carries on from the current file's size
+        drop(log);
+        let mut log = RotatingFile::open(&path, 20, 2).unwrap();
+        log.write_all(b"line 5\n").unwrap();
+        assert_eq!(read(&path), "line 5\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "line 4 .......\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "line 3 .......\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keep_zero_truncates() {
+        let dir = scratch("truncate");
+        let path = dir.join("sendspin.log");
+        let mut log = RotatingFile::open(&path, 10, 0).unwrap();
+        log.write_all(b"first....\n").unwrap();
+        log.write_all(b"second...\n").unwrap();
+        assert_eq!(read(&path), "second...\n");
+        assert!(!rotated_path(&path, 1).exists());
+
+        // A line longer than the limit still goes in whole
+        log.write_all(b"a much longer line\n").unwrap();
+        assert_eq!(read(&path), "a much longer line\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_rotation_loses_nothing() {
+        // Killed after moving .1 to .2 but before the current file became .1
+        let dir = scratch("interrupted");
+        let path = dir.join("sendspin.log");
+        fs::write(rotated_path(&path, 2), "older\n").unwrap();
+        fs::write(&path, "current..\n").unwrap();
+        let mut log = RotatingFile::open(&path, 10, 3).unwrap();
+        log.write_all(b"new......\n").unwrap();
+        assert_eq!(read(&path), "new......\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "current..\n");
+        assert_eq!(read(&rotated_path(&path, 3)), "older\n");
+        assert!(!rotated_path(&path, 2).exists());
+
+        // The next rotation closes the gap
+        log.write_all(b"newer....\n").unwrap();
+        assert_eq!(read(&rotated_path(&path, 1)), "new......\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "current..\n");
+        assert_eq!(read(&rotated_path(&path, 3)), "older\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
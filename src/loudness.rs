@@ -0,0 +1,405 @@
+// Loudness Normalization
+//
+// `--loudness-target <LUFS>` measures the stream's loudness as it plays and
+// slowly steers a gain towards the target, independently of ReplayGain tags
+// (it measures after ReplayGain, so it only corrects what is left). The
+// measurement is a K-weighted RMS (the BS.1770 pre-filter and high-pass,
+// built as biquads, channel powers summed), smoothed over a 400 ms window
+// like momentary loudness.
+//
+// To keep it from pumping on dynamic material:
+// - the gain moves slowly: 5 s time constant when turning down, 10 s when
+//   turning back up, so a drum hit or a short loud scene barely moves it
+// - audio below -70 LUFS (silence, fade tails) or more than 20 LU under the
+//   target (a quiet passage) doesn't move the gain at all, so pauses and
+//   quiet intros aren't pulled up to full level
+// - the gain stays within ±12 dB
+//
+// A boost never pushes peaks past full scale: the sample peak is held and
+// decays with the release time constant, and the gain applied is capped at
+// what that peak leaves room for. A transient louder than anything before
+// it lowers the cap on the buffer it arrives in, before it is heard, so
+// quiet but peaky material is turned up as far as it goes without clipping.
+// The steered gain itself is left alone, and the cap lifts as the peak
+// decays.
+//
+// The gain is combined with volume and ReplayGain into the single gain the
+// player applies.
+
+use crate::eq::{Coefficients, FilterKind, FilterSpec};
+use sendspin::audio::{AudioFormat, Sample};
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// Loudness window for the momentary estimate
+pub const WINDOW: Duration = Duration::from_millis(400);
+
+/// Time constant for turning the gain down (level rose)
+pub const ATTACK: Duration = Duration::from_secs(5);
+
+/// Time constant for turning the gain back up (level fell)
+pub const RELEASE: Duration = Duration::from_secs(10);
+
+/// Largest correction either way, in dB
+pub const MAX_GAIN_DB: f64 = 12.0;
+
+/// Audio quieter than this never moves the gain (BS.1770 absolute gate)
+pub const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// Audio this far below the target never moves the gain
+pub const RELATIVE_GATE_LU: f64 = 20.0;
+
+/// Parse a target loudness in LUFS, e.g. "-23"
+pub fn parse_target(s: &str) -> Result<f32, String> {
+    let target: f32 = s
+        .trim()
+        .trim_end_matches("LUFS")
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid loudness target '{}'", s))?;
+    if (-50.0..=0.0).contains(&target) {
+        Ok(target)
+    } else {
+        Err(format!(
+            "loudness target must be between -50 and 0 LUFS, got {}",
+            target
+        ))
+    }
+}
+
+/// BS.1770 K-weighting for a sample rate: the head-effect shelf, then the
+/// low-frequency roll-off
+fn k_weighting(sample_rate: u32) -> [Coefficients; 2] {
+    let shelf = FilterSpec {
+        kind: FilterKind::HighShelf,
+        freq_hz: 1681.97,
+        q: 0.7072,
+        gain_db: 4.0,
+    }
+    .coefficients(sample_rate);
+
+    let w0 = 2.0 * PI * 38.135 / sample_rate as f64;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * 0.5003);
+    let a0 = 1.0 + alpha;
+    let highpass = Coefficients {
+        b0: (1.0 + cos) / 2.0 / a0,
+        b1: -(1.0 + cos) / a0,
+        b2: (1.0 + cos) / 2.0 / a0,
+        a1: -2.0 * cos / a0,
+        a2: (1.0 - alpha) / a0,
+    };
+    [shelf, highpass]
+}
+
+/// One-pole smoothing factor for a step of `dt` with time constant `tau`
+fn smoothing(dt: f64, tau: Duration) -> f64 {
+    1.0 - (-dt / tau.as_secs_f64()).exp()
+}
+
+/// Measures loudness and steers a slowly varying gain towards a target
+#[derive(Debug)]
+pub struct LoudnessNormalizer {
+    target_lufs: f64,
+    sample_rate: u32,
+    filters: [Coefficients; 2],
+    state: Vec<[[f64; 2]; 2]>, // Per channel, per filter stage
+    power: Option<f64>,        // Smoothed K-weighted power, None until audio arrives
+    peak: f64,                 // Held sample peak after pre_gain, full scale = 1
+    gain_db: f64,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(target_lufs: f32) -> Self {
+        LoudnessNormalizer {
+            target_lufs: target_lufs as f64,
+            sample_rate: 0,
+            filters: k_weighting(48000),
+            state: Vec::new(),
+            power: None,
+            peak: 0.0,
+            gain_db: 0.0,
+        }
+    }
+
+    /// Current correction in dB
+    pub fn gain_db(&self) -> f64 {
+        self.gain_db
+    }
+
+    /// Held sample peak (full scale = 1); the gain applied never takes it
+    /// past full scale
+    pub fn peak(&self) -> f64 {
+        self.peak
+    }
+
+    /// Current momentary loudness estimate, if any audio was measured
+    pub fn loudness_lufs(&self) -> Option<f64> {
+        self.power.map(|p| -0.691 + 10.0 * p.max(1e-20).log10())
+    }
+
+    /// Forget the filter history, the loudness window and the held peak
+    /// (e.g. new stream); the gain itself carries over so the next track
+    /// doesn't jump
+    pub fn reset(&mut self) {
+        self.state.iter_mut().for_each(|s| *s = [[0.0; 2]; 2]);
+        self.power = None;
+        self.peak = 0.0;
+    }
+
+    /// Measure one buffer (heard at `pre_gain`, e.g. ReplayGain) and return
+    /// the linear gain to apply to it
+    pub fn process(&mut self, samples: &[Sample], format: &AudioFormat, pre_gain: f32) -> f32 {
+        let channels = (format.channels as usize).max(1);
+        if format.sample_rate != self.sample_rate {
+            self.sample_rate = format.sample_rate;
+            self.filters = k_weighting(format.sample_rate);
+            self.state.clear();
+        }
+        if self.state.len() != channels {
+            self.state = vec![[[0.0; 2]; 2]; channels];
+        }
+        let frames = samples.len() / channels;
+        if frames == 0 || format.sample_rate == 0 {
+            return self.linear_gain();
+        }
+
+        // Sum of the channels' mean square, full scale = 0 dB
+        let scale = pre_gain as f64 / (1u32 << 23) as f64;
+        let mut sum = 0.0;
+        let mut block_peak: f64 = 0.0;
+        for (i, sample) in samples.iter().enumerate() {
+            let ch = i % channels;
+            let mut x = sample.0 as f64 * scale;
+            block_peak = block_peak.max(x.abs());
+            for (c, s) in self.filters.iter().zip(self.state[ch].iter_mut()) {
+                let y = c.b0 * x + s[0];
+                s[0] = c.b1 * x - c.a1 * y + s[1];
+                s[1] = c.b2 * x - c.a2 * y;
+                x = y;
+            }
+            sum += x * x;
+        }
+        let block_power = sum / frames as f64;
+
+        let dt = frames as f64 / format.sample_rate as f64;
+        self.peak = (self.peak * (1.0 - smoothing(dt, RELEASE))).max(block_peak);
+        let power = match self.power {
+            Some(p) => p + (block_power - p) * smoothing(dt, WINDOW),
+            None => block_power,
+        };
+        self.power = Some(power);
+
+        // Gate on the buffer itself as well, so the window's decay into a
+        // pause doesn't count as a quiet passage to boost
+        let gate = ABSOLUTE_GATE_LUFS.max(self.target_lufs - RELATIVE_GATE_LU);
+        let loudness = -0.691 + 10.0 * power.max(1e-20).log10();
+        let block_loudness = -0.691 + 10.0 * block_power.max(1e-20).log10();
+        let gated = loudness < gate || block_loudness < gate;
+        if !gated {
+            let wanted = (self.target_lufs - loudness).clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+            let tau = if wanted < self.gain_db {
+                ATTACK
+            } else {
+                RELEASE
+            };
+            self.gain_db += (wanted - self.gain_db) * smoothing(dt, tau);
+        }
+        self.linear_gain()
+    }
+
+    /// The steered gain, capped by the held peak's headroom
+    fn linear_gain(&self) -> f32 {
+        let gain = 10f64.powf(self.gain_db / 20.0);
+        if self.peak > 0.0 {
+            gain.min(1.0 / self.peak) as f32
+        } else {
+            gain as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    const RATE: u32 = 48000;
+    const CHUNK: usize = 960; // 20 ms
+
+    fn format() -> AudioFormat {
+        AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate: RATE,
+            channels: 2,
+            bit_depth: 24,
+            codec_header: None,
+        }
+    }
+
+    /// Stereo 1 kHz tone at `lufs` (a full-scale sine in both channels is 0 LUFS)
+    fn tone(lufs: f64, seconds: f64, phase: &mut usize) -> Vec<Sample> {
+        let amplitude = 10f64.powf(lufs / 20.0) * (1u32 << 23) as f64;
+        let frames = (seconds * RATE as f64) as usize;
+        let mut out = Vec::with_capacity(frames * 2);
+        for _ in 0..frames {
+            let t = *phase as f64 / RATE as f64;
+            let v = Sample((amplitude * (2.0 * PI * 1000.0 * t).sin()) as i32);
+            out.extend([v, v]);
+            *phase += 1;
+        }
+        out
+    }
+
+    /// Play `samples` in 20 ms chunks, returning the gain (dB) after each
+    fn play(norm: &mut LoudnessNormalizer, samples: &[Sample]) -> Vec<f64> {
+        samples
+            .chunks(CHUNK * 2)
+            .map(|chunk| {
+                norm.process(chunk, &format(), 1.0);
+                norm.gain_db()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(parse_target("-23"), Ok(-23.0));
+        assert_eq!(parse_target("-16 LUFS"), Ok(-16.0));
+        assert!(parse_target("3").is_err());
+        assert!(parse_target("-80").is_err());
+        assert!(parse_target("loud").is_err());
+    }
+
+    #[test]
+    fn test_measures_tone_loudness() {
+        let mut norm = LoudnessNormalizer::new(-23.0);
+        let mut phase = 0;
+        play(&mut norm, &tone(-20.0, 2.0, &mut phase));
+        let lufs = norm.loudness_lufs().unwrap();
+        assert!((lufs - -20.0).abs() < 0.5, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn test_converges_to_target() {
+        let mut norm = LoudnessNormalizer::new(-20.0);
+        let mut phase = 0;
+        // Too loud: turned down within a few attack time constants
+        play(&mut norm, &tone(-14.0, 30.0, &mut phase));
+        assert!((norm.gain_db() - -6.0).abs() < 0.3, "{}", norm.gain_db());
+        // Too quiet: turned up, more slowly
+        play(&mut norm, &tone(-26.0, 60.0, &mut phase));
+        assert!((norm.gain_db() - 6.0).abs() < 0.3, "{}", norm.gain_db());
+    }
+
+    #[test]
+    fn test_step_follows_time_constants() {
+        let mut norm = LoudnessNormalizer::new(-20.0);
+        let mut phase = 0;
+        play(&mut norm, &tone(-20.0, 5.0, &mut phase));
+        assert!(norm.gain_db().abs() < 0.1);
+
+        // Level jumps by 10 dB: after one attack time constant (plus the
+        // window's delay) roughly 63% of the correction is applied, not all
+        let gains = play(&mut norm, &tone(-10.0, 10.0, &mut phase));
+        let after = |secs: f64| gains[(secs * 50.0) as usize - 1];
+        assert!(after(0.2) > -0.5, "{}", after(0.2));
+        let at_attack = after(ATTACK.as_secs_f64() + WINDOW.as_secs_f64());
+        assert!((-7.5..=-5.0).contains(&at_attack), "{}", at_attack);
+
+        // And back down by 10 dB: recovery is slower (release)
+        let start = norm.gain_db();
+        let gains = play(&mut norm, &tone(-20.0, 30.0, &mut phase));
+        let after = |secs: f64| gains[(secs * 50.0) as usize - 1] / start;
+        assert!(after(ATTACK.as_secs_f64()) > 0.55, "{}", after(5.0));
+        let at_release = after(RELEASE.as_secs_f64() + WINDOW.as_secs_f64());
+        assert!((0.25..=0.5).contains(&at_release), "{}", at_release);
+    }
+
+    #[test]
+    fn test_short_burst_does_not_pump() {
+        // A 200 ms hit 10 dB over the programme level
+        let mut norm = LoudnessNormalizer::new(-20.0);
+        let mut phase = 0;
+        play(&mut norm, &tone(-20.0, 5.0, &mut phase));
+        let before = norm.gain_db();
+        let mut gains = play(&mut norm, &tone(-10.0, 0.2, &mut phase));
+        gains.extend(play(&mut norm, &tone(-20.0, 2.0, &mut phase)));
+        let dip = gains.iter().fold(f64::MAX, |a, &b| a.min(b)) - before;
+        assert!(dip > -1.0, "gain dipped {} dB", dip);
+    }
+
+    #[test]
+    fn test_dynamic_material_does_not_pump() {
+        // Loud and soft phrases alternating every 500 ms, 12 dB apart
+        let mut norm = LoudnessNormalizer::new(-20.0);
+        let mut phase = 0;
+        let mut gains = Vec::new();
+        for _ in 0..60 {
+            gains.extend(play(&mut norm, &tone(-14.0, 0.5, &mut phase)));
+            gains.extend(play(&mut norm, &tone(-26.0, 0.5, &mut phase)));
+        }
+        // Once settled, the gain wobbles by well under a dB per phrase
+        let settled = &gains[gains.len() / 2..];
+        let min = settled.iter().fold(f64::MAX, |a, &b| a.min(b));
+        let max = settled.iter().fold(f64::MIN, |a, &b| a.max(b));
+        assert!(max - min < 0.5, "gain swings {:.2}..{:.2} dB", min, max);
+    }
+
+    #[test]
+    fn test_silence_and_quiet_passages_hold_gain() {
+        let mut norm = LoudnessNormalizer::new(-20.0);
+        let mut phase = 0;
+        play(&mut norm, &tone(-20.0, 5.0, &mut phase));
+        let before = norm.gain_db();
+
+        // Ten seconds of silence, then a passage 30 LU down: no boost
+        play(&mut norm, &vec![Sample(0); RATE as usize * 2 * 10]);
+        play(&mut norm, &tone(-50.0, 10.0, &mut phase));
+        assert!((norm.gain_db() - before).abs() < 0.1, "{}", norm.gain_db());
+    }
+
+    #[test]
+    fn test_gain_is_bounded() {
+        let mut norm = LoudnessNormalizer::new(-10.0);
+        let mut phase = 0;
+        play(&mut norm, &tone(-29.0, 120.0, &mut phase));
+        assert!(norm.gain_db() <= MAX_GAIN_DB + 1e-9);
+        assert!(norm.gain_db() > MAX_GAIN_DB - 0.2, "{}", norm.gain_db());
+    }
+
+    #[test]
+    fn test_boost_leaves_peaks_below_full_scale() {
+        // A quiet tone with a click at -6 dBFS in every other chunk: the
+        // loudness asks for the full +12 dB, the clicks leave room for 6
+        let mut norm = LoudnessNormalizer::new(-14.0);
+        let mut phase = 0;
+        let quiet = tone(-26.0, 60.0, &mut phase);
+        for (i, chunk) in quiet.chunks(CHUNK * 2).enumerate() {
+            let mut chunk = chunk.to_vec();
+            if i % 2 == 1 {
+                chunk[CHUNK] = Sample(1 << 22);
+            }
+            let peak = chunk.iter().map(|s| s.0.abs()).max().unwrap();
+            let gain = norm.process(&chunk, &format(), 1.0);
+            let loudest = peak as f64 * gain as f64 / (1u32 << 23) as f64;
+            assert!(loudest <= 1.0 + 1e-6, "chunk {} peaks at {}", i, loudest);
+        }
+        assert!(norm.gain_db() > 10.0, "{}", norm.gain_db());
+        let applied = 20.0 * (norm.process(&[], &format(), 1.0) as f64).log10();
+        assert!((5.0..=6.03).contains(&applied), "{} dB", applied);
+    }
+
+    #[test]
+    fn test_pre_gain_is_measured() {
+        // ReplayGain already took 6 dB off: only the rest is corrected
+        let mut norm = LoudnessNormalizer::new(-20.0);
+        let mut phase = 0;
+        let loud = tone(-14.0, 2.0, &mut phase);
+        for chunk in loud.chunks(CHUNK * 2) {
+            norm.process(chunk, &format(), 0.5);
+        }
+        let lufs = norm.loudness_lufs().unwrap();
+        assert!((lufs - -20.0).abs() < 0.5, "{} LUFS", lufs);
+    }
+}
@@ -3,63 +3,830 @@
 // Design principles:
 // 1. Audio IN → Decode → Simple Queue (VecDeque)
 // 2. Audio OUT → Time-synced playback from queue
-// 3. Stop → Clear queue + drop output (instant)
+// 3. Stop → 50 ms fade-out, then clear queue + drop output
 // 4. Skip → Stop old + Start new (clean transition)
 // 5. All output is time-synced to play_at timestamps
 
-mod compat;
-mod mdns;
-mod player;
-
-use clap::Parser;
-use log::{debug, error, info};
-use player::Player;
-use sendspin::audio::decode::{Decoder, PcmDecoder, PcmEndian};
-use sendspin::audio::{AudioBuffer, AudioFormat, Codec};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use sendspin::audio::decode::PcmEndian;
+use sendspin::audio::{AudioBuffer, AudioFormat, Codec, Sample};
 use sendspin::protocol::messages::{
     AudioFormatSpec, ClientHello, ClientState, ClientTime, DeviceInfo, Message, PlayerState,
     PlayerSyncState, PlayerV1Support,
 };
+use sendspin_rs_cli::continuity::{self, Continuity, ContinuityTracker, StreamStats};
+use sendspin_rs_cli::error::SendspinCliError;
+use sendspin_rs_cli::events::{EventSender, PlaybackState, PlayerEvent};
+use sendspin_rs_cli::json_events::JsonEventWriter;
+use sendspin_rs_cli::log_file::{self, RotatingFile};
+use sendspin_rs_cli::negotiate::{self, CapabilitiesChanged, DeviceRates};
+use sendspin_rs_cli::output::{self, AlsaAccess, OutputBackendKind, OutputConfig, Scheduling};
+use sendspin_rs_cli::pcm_layout::{self, PcmLayout};
+use sendspin_rs_cli::player::{Drained, Player, PlayerConfig, LOOKAHEAD};
+use sendspin_rs_cli::position::PositionTracker;
+use sendspin_rs_cli::reconnect::ReconnectOn;
+use sendspin_rs_cli::recovery::{RetriesExhausted, RetryExhausted};
+use sendspin_rs_cli::resample::ResampleQuality;
+use sendspin_rs_cli::state_report::{RecentMessages, ReportSignal, SharedRecent, StateReport};
+use sendspin_rs_cli::stats::{self, StatsLine, StatsSnapshot, SyncSamples};
+use sendspin_rs_cli::timing_trace::{Enqueued, TimingRecord, TimingTrace};
+use sendspin_rs_cli::volume::VolumeBackendKind;
+use sendspin_rs_cli::warmup::WarmupSlew;
+use sendspin_rs_cli::{
+    coalesce, compat, device, diag, drift, envelope, eq, identity, keep_open, loudness, mdns,
+    reconnect, replaygain, seek, selftest, server_error, server_volume, session_limit, speed,
+    stream_start, telemetry, timing_trace, wake,
+};
+use std::io::Write;
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, info_span, trace, trace_span, warn, Instrument, Span};
+
+/// How long --probe waits for the server to answer the hello
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Volume change of one volume_up/volume_down command
+const VOLUME_STEP: i8 = 5;
+
+/// New players started after the playback thread ends on an error, before
+/// the client gives up
+const PLAYER_RESTARTS: u32 = 3;
 
 #[derive(Parser, Debug)]
 #[command(name = "sendspin-rs-cli")]
 #[command(about = "Connect to Music Assistant and play audio", long_about = None)]
 #[command(version)]
 struct Args {
-    #[arg(short, long)]
+    #[arg(short, long, env = "SENDSPIN_SERVER")]
     server: Option<String>,
-    #[arg(short, long, default_value = "Sendspin-RS Player")]
+    /// Don't look for a server via mDNS (where it's blocked); needs --server
+    /// or --dns-domain
+    #[arg(long)]
+    disable_mdns: bool,
+    /// When mDNS finds no server, look it up by unicast DNS-SD
+    /// (_sendspin-server._tcp.<DOMAIN>)
+    #[arg(long, value_name = "DOMAIN")]
+    dns_domain: Option<String>,
+    #[arg(
+        short,
+        long,
+        env = "SENDSPIN_NAME",
+        default_value = "Sendspin-RS Player"
+    )]
     name: String,
+    /// Append "auto" (hostname and output device) or any text to the name
+    #[arg(long)]
+    name_suffix: Option<identity::NameSuffix>,
     #[arg(long)]
     client_id: Option<String>,
-    #[arg(short, long, default_value = "30")]
+    /// Derive the client ID from hostname and output device so it survives restarts
+    #[arg(long)]
+    stable_id: bool,
+    /// Randomize each reconnect delay by up to this fraction (0.2 = ±20%),
+    /// so players that lost the same server don't all retry at once
+    #[arg(long, value_name = "FRACTION", default_value = "0.2",
+          value_parser = reconnect::parse_jitter)]
+    reconnect_jitter: f64,
+    /// Which disconnects to reconnect after: transient ones only, exiting
+    /// when the server rejects the client (policy or auth close code, 4xx
+    /// on the upgrade), or always
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ReconnectOn::Transient)]
+    reconnect_on: ReconnectOn,
+    /// Stop, disconnect and exit after this long from startup, whatever is
+    /// playing, e.g. 30m or 1h30m (for demos and kiosks)
+    #[arg(long, value_name = "DURATION", value_parser = session_limit::parse_length)]
+    max_session: Option<Duration>,
+    /// Initial volume (0-100); when not given, the server's volume is used
+    /// if it sends one on connect
+    #[arg(short, long, env = "SENDSPIN_VOLUME", default_value = "30")]
     volume: u8,
     #[arg(short, long, default_value = "20")]
     buffer: u64,
+    /// Ignore ReplayGain / loudness metadata sent by the server
+    #[arg(long)]
+    no_replaygain: bool,
+    /// Fixed offset in dB added to the server's ReplayGain
+    #[arg(long, default_value = "0", allow_hyphen_values = true)]
+    replaygain_preamp: f32,
+    /// Slowly steer the level towards this loudness, e.g. -23 (off by default)
+    #[arg(long, value_name = "LUFS", allow_hyphen_values = true,
+          value_parser = loudness::parse_target)]
+    loudness_target: Option<f32>,
+    /// EQ filters, e.g. "lowshelf:100:-4,peak:2500:2:+3,highshelf:9000:-2"
+    #[arg(long, allow_hyphen_values = true)]
+    eq: Option<eq::EqConfig>,
+    /// Remove DC offset with a 2 Hz high-pass before volume
+    #[arg(long)]
+    dc_block: bool,
+    /// Where volume is applied (alsa uses the hardware mixer, Linux only)
+    #[arg(long, value_enum, default_value_t = VolumeBackendKind::Software)]
+    volume_backend: VolumeBackendKind,
+    /// Left/right balance (-100 = left only, 100 = right only)
+    #[arg(long, default_value = "0", allow_hyphen_values = true,
+          value_parser = clap::value_parser!(i8).range(-100..=100))]
+    balance: i8,
+    /// Exchange left and right channels
+    #[arg(long)]
+    swap_channels: bool,
+    /// Downmix every stream to mono and play it on all output channels
+    #[arg(long)]
+    mono: bool,
+    /// Gain applied to the left + right sum with --mono
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value = "-6",
+        allow_hyphen_values = true
+    )]
+    mono_gain_db: f32,
+    /// Overlap consecutive streams by this many milliseconds (0 = off)
+    #[arg(long, default_value = "0")]
+    crossfade_ms: u64,
+    /// Hold a stream/clear back this many milliseconds and drop it if the
+    /// stream carries on meanwhile (0 = clear at once)
+    #[arg(long, value_name = "MS", default_value = "0")]
+    clear_grace_ms: u64,
+    /// Shift playback by a fixed amount to line up with other devices,
+    /// e.g. a TV (negative = earlier, positive = later)
+    #[arg(long, default_value = "0", allow_hyphen_values = true)]
+    playback_offset_ms: i32,
+    /// Play this many milliseconds of silence before the first buffer of each
+    /// stream, delaying it by that much with the device already playing
+    /// (0 = off)
+    #[arg(long, value_name = "MS", default_value = "0")]
+    preroll_silence_ms: u64,
+    /// Schedule this many chunks after connecting the way they are before the
+    /// clock syncs, back to back from arrival, while the clock estimate
+    /// settles; synced timing takes over after them (0 = off)
+    #[arg(long, value_name = "CHUNKS", default_value = "0")]
+    clock_warmup_chunks: u32,
+    /// Report the playback position to the server in client/state this
+    /// often, for progress bars that follow the player [default: off]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    report_position_secs: Option<u64>,
+    /// Log one line of playback statistics this often (10 s without a
+    /// value): buffer level, chunks, underruns, drops, clock offset and
+    /// round trip, decode time [default: off]
+    #[arg(
+        long,
+        value_name = "SECS",
+        num_args = 0..=1,
+        default_missing_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    stats: Option<u64>,
+    /// Log the player's internal state this often (10 s without a value):
+    /// stopped or playing, volume, queue, output, frames played, last
+    /// underrun, for bug reports about playback that stopped [default: off]
+    #[arg(
+        long,
+        value_name = "SECS",
+        num_args = 0..=1,
+        default_missing_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    debug_player: Option<u64>,
+    /// Run the playback thread with real-time scheduling (SCHED_FIFO) at
+    /// this priority, 1-99 (10 without a value), falling back to a raised
+    /// nice level when that isn't allowed; Linux only [default: off]
+    #[arg(
+        long,
+        value_name = "PRIO",
+        num_args = 0..=1,
+        default_missing_value = "10",
+        value_parser = clap::value_parser!(u8).range(1..=99)
+    )]
+    rt_priority: Option<u8>,
+    /// Fade in over this many milliseconds whenever the output opens (0 = off)
+    #[arg(long, value_name = "MS", default_value = "10")]
+    fade_in_ms: u64,
+    /// Audio output backend (alsa needs a Linux build with the alsa-backend
+    /// feature; null discards audio in real time; file writes raw PCM)
+    #[arg(long, value_enum, default_value_t = OutputBackendKind::Cpal)]
+    backend: OutputBackendKind,
+    /// Where the file backend writes raw little-endian PCM
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<std::path::PathBuf>,
+    /// cpal audio host to use, e.g. "ALSA" or "JACK" (default host if not set)
+    #[arg(long)]
+    audio_host: Option<String>,
+    /// List output devices grouped by audio host, then exit
+    #[arg(long)]
+    list_devices: bool,
+    /// List the channels, rates and sample formats each output device
+    /// supports (of --audio-host if set, only --alsa-device if set), then exit
+    #[arg(long, value_name = "STYLE", value_enum, num_args = 0..=1,
+          default_missing_value = "text")]
+    list_formats: Option<device::ListStyle>,
+    /// Exit with an error instead of falling back to the null backend when
+    /// no audio device is available
+    #[arg(long)]
+    require_audio: bool,
+    /// Device buffer size for the cpal backend, in frames ("1024") or
+    /// milliseconds ("20ms") [default: device default]
+    #[arg(long, value_name = "FRAMES|MS")]
+    device_buffer: Option<device::DeviceBuffer>,
+    /// How buffers are timed: "write" sleeps until each is due, "callback"
+    /// lets the device callback pull them and places them by silence (cpal
+    /// and file backends; experimental)
+    #[arg(long, value_enum, default_value_t = Scheduling::Write)]
+    scheduling: Scheduling,
+    /// Use WASAPI exclusive mode: the device is opened at the stream's rate
+    /// and must take it as-is, bypassing the Windows mixer (Windows only)
+    #[arg(long)]
+    exclusive: bool,
+    /// Open the device only at the rate it runs at natively and convert other
+    /// rates here with --resample-quality, instead of leaving it to the OS
+    /// mixer (cpal backend)
+    #[arg(long)]
+    native_format_only: bool,
+    /// Keep the output open, playing silence, for this many seconds after a
+    /// stream ends or playback pauses; alone it means forever [default: 0]
+    #[arg(long, value_name = "SECS", num_args = 0..=1, default_value = "0",
+          default_missing_value = "forever", hide_default_value = true)]
+    keep_device_open: keep_open::KeepOpen,
+    /// Close the output after this many seconds of silence (amplifier standby)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    idle_release_secs: Option<u64>,
+    /// Don't dither when reducing to a device with fewer bits (e.g. 16-bit)
+    #[arg(long)]
+    no_dither: bool,
+    /// Shape the dither noise towards high frequencies, where it is less audible
+    #[arg(long)]
+    noise_shaping: bool,
+    /// Send decoded samples to the device untouched: no volume, EQ, fades,
+    /// resampling or dither; streams the device can't play natively are refused
+    #[arg(long)]
+    bit_perfect: bool,
+    /// With --drift-correction, keep playback within this many milliseconds
+    /// of the server clock by adjusting the rate a few ppm at a time
+    #[arg(long, value_name = "MS", default_value_t = drift::DEFAULT_BAND_MS,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    drift_band_ms: u64,
+    /// Correct clock drift between the server and the audio device (best on
+    /// the alsa backend, which reports its output latency)
+    #[arg(long)]
+    drift_correction: bool,
+    /// Sleep until this many microseconds before a buffer is due, then spin
+    /// so it's written on time
+    #[arg(long, value_name = "US", default_value_t = wake::DEFAULT_SPIN_US)]
+    wake_spin_us: u64,
+    /// Take each buffer off the queue this many milliseconds before it's
+    /// due; until then the playback thread sleeps in a single wait
+    #[arg(long, value_name = "MS", default_value_t = LOOKAHEAD.as_millis() as u64)]
+    schedule_margin_ms: u64,
+    /// Only sleep before writing a buffer, never spin (saves power on
+    /// battery-powered devices at the cost of sync accuracy)
+    #[arg(long)]
+    no_wake_spin: bool,
+    /// Sample rate conversion quality when the device can't play the stream's
+    /// rate: fast (linear), medium (polyphase sinc) or high/best (sinc)
+    #[arg(long, value_enum, value_name = "QUALITY", default_value_t = ResampleQuality::High)]
+    resample_quality: ResampleQuality,
+    /// After the output device disappears, retry it this many times before
+    /// switching to the default device [default: keep retrying it]
+    #[arg(long, value_name = "ATTEMPTS")]
+    device_fallback: Option<u32>,
+    /// After the output device disappears, give up on the stream after this
+    /// many reopen attempts in all [default: keep retrying]
+    #[arg(long, value_name = "ATTEMPTS", value_parser = clap::value_parser!(u32).range(1..))]
+    device_retry_attempts: Option<u32>,
+    /// What to do once --device-retry-attempts are used up: exit with an
+    /// error, or wait and try the device again on the next stream
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = RetryExhausted::Wait)]
+    device_retry_exhausted: RetryExhausted,
+    /// ALSA device string, e.g. "hw:CARD=DAC,DEV=0" [default: default]
+    #[arg(long)]
+    alsa_device: Option<String>,
+    /// ALSA access type
+    #[arg(long, value_enum, default_value_t = AlsaAccess::Rw)]
+    alsa_access: AlsaAccess,
+    /// ALSA period size in frames (device default if not set)
+    #[arg(long)]
+    alsa_period: Option<usize>,
+    /// ALSA buffer size in frames (device default if not set)
+    #[arg(long)]
+    alsa_buffer: Option<usize>,
+    /// Audio chunks buffered between the socket and the decoder [default:
+    /// enough 20 ms chunks for the buffer capacity]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    audio_channel_capacity: Option<u64>,
+    /// What to do when the audio channel is full
+    #[arg(long, value_enum, default_value_t = compat::AudioOverflow::DropOldest)]
+    audio_overflow: compat::AudioOverflow,
+    /// How 24-bit samples are packed: s24le (3 bytes), s24_4le (low 3 of 4)
+    /// or s32le (high 3 of 4) [default: from the stream, else detected]
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    pcm_layout: Option<PcmLayout>,
+    /// Advertise only this codec, to test the server's fallback negotiation
+    #[arg(long, value_enum, value_name = "CODEC")]
+    only_codec: Option<negotiate::CodecName>,
+    /// Bytes of audio the server may send ahead, advertised and enforced
+    /// [default: 1 MiB, more if --buffer needs it]
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(1..))]
+    buffer_capacity: Option<u32>,
+    /// Merge consecutive small audio chunks into buffers of at least this
+    /// many milliseconds before queueing them (0 = off)
+    #[arg(long, value_name = "MS", default_value_t = coalesce::DEFAULT_TARGET_MS)]
+    coalesce_ms: u64,
+    /// Send a merged buffer on short once its first chunk has waited this
+    /// many milliseconds for the rest [default: no limit]
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    coalesce_window_ms: Option<u64>,
+    /// Beep each output channel in turn (channel N beeps N times), then exit
+    #[arg(long, value_name = "CHANNELS", num_args = 0..=1, default_missing_value = "2",
+          value_parser = clap::value_parser!(u8).range(1..=8))]
+    channel_test: Option<u8>,
+    /// Connect, complete the handshake and exit 0, or non-zero with the
+    /// reason; plays no audio (readiness/liveness checks)
+    #[arg(long)]
+    probe: bool,
+    /// Print the negotiated format of each stream as one JSON line on stdout
+    #[arg(long)]
+    format_report: bool,
+    /// Print player events (connection, streams, state, volume, metadata,
+    /// underruns, errors) as JSON lines on stdout; the log stays on stderr
+    #[arg(long, conflicts_with = "format_report")]
+    json_events: bool,
+    /// Play a short, quiet beep each time the connection to the server is
+    /// made, to confirm a headless player is live and its output works
+    #[arg(long)]
+    connect_tone: bool,
+    /// Log a CRC32 of every decoded audio buffer with its timestamp
+    #[arg(long)]
+    debug_audio_crc: bool,
+    /// Append one CSV row of timing per audio chunk to this file, for
+    /// telling network, clock and device sync errors apart
+    ///
+    /// Columns: chunk_ts_us, arrival_ms, play_at_ms, write_ms, lateness_ms,
+    /// duration_ms, queue_depth, offset_us; times are ms since the trace
+    /// started. play_at_ms - arrival_ms is how much lead a chunk arrived
+    /// with: if it shrinks before lateness grows, the network is late. If
+    /// play_at_ms jumps between consecutive chunks together with
+    /// offset_us, the clock estimate moved. If lateness_ms drifts or jumps
+    /// while the lead and offset are steady, the output device is the
+    /// cause. Empty write columns mean the chunk was never played.
+    #[arg(long, value_name = "FILE")]
+    timing_trace: Option<std::path::PathBuf>,
+    /// Export connection, stream and chunk spans to this OpenTelemetry
+    /// collector over OTLP/gRPC, e.g. http://localhost:4317 (needs the otlp
+    /// feature)
+    #[arg(long, value_name = "ENDPOINT")]
+    otlp: Option<String>,
+    /// Also write the log to this file (at info level unless RUST_LOG is
+    /// set), rotating it by size
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+    /// Rotate the log file before it grows past this size, e.g. 512K or 10M
+    #[arg(long, value_name = "SIZE", default_value = log_file::DEFAULT_MAX_SIZE,
+          value_parser = log_file::parse_size)]
+    log_max_size: u64,
+    /// Rotated log files to keep (0 = truncate the log file instead)
+    #[arg(long, value_name = "FILES", default_value_t = log_file::DEFAULT_KEEP)]
+    log_keep: u32,
+    /// Play a sine tone at this frequency (Hz) without a server, then exit
+    #[arg(long, hide = true)]
+    self_test: Option<f32>,
+}
+
+/// Supported rates of the output device, when the backend lets us ask
+fn probe_device_rates(args: &Args) -> Option<DeviceRates> {
+    if args.backend != OutputBackendKind::Cpal {
+        return None;
+    }
+    if args.exclusive {
+        return match output::exclusive_rates(negotiate::CHANNELS) {
+            Ok(rates) if !rates.ranges.is_empty() => {
+                info!("Exclusive-mode sample rates: {:?}", rates.ranges);
+                Some(rates)
+            }
+            Ok(_) => {
+                warn!("The output device takes none of the candidate formats in exclusive mode");
+                None
+            }
+            Err(e) => {
+                debug!(
+                    "Can't probe exclusive mode ({}), advertising all formats",
+                    e
+                );
+                None
+            }
+        };
+    }
+    if args.native_format_only {
+        return probe_native_rate(args);
+    }
+    match device::probe_rates(args.audio_host.as_deref(), negotiate::CHANNELS) {
+        Ok(rates) if !rates.ranges.is_empty() => {
+            info!("Output device sample rates: {:?}", rates.ranges);
+            Some(rates)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            debug!(
+                "Can't probe the output device ({}), advertising all formats",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// The device's native rate as its only rate (--native-format-only)
+fn probe_native_rate(args: &Args) -> Option<DeviceRates> {
+    let native = match device::native_rate(args.audio_host.as_deref()) {
+        Ok(rate) => rate,
+        Err(e) => {
+            warn!(
+                "Can't find the output device's native rate ({}); the OS may resample",
+                e
+            );
+            return None;
+        }
+    };
+    let accepted =
+        device::probe_rates(args.audio_host.as_deref(), negotiate::CHANNELS).unwrap_or_default();
+    let resampled = negotiate::os_resampled(&accepted, native);
+    info!(
+        "Native format only: the output device runs at {} Hz",
+        native
+    );
+    if !resampled.is_empty() {
+        info!(
+            "{:?} Hz would otherwise be resampled by the OS; converting here ({:?} quality)",
+            resampled, args.resample_quality
+        );
+    }
+    Some(DeviceRates::only(native))
+}
+
+/// PCM formats for the hello, narrowed to the device's rates when known
+fn advertised_formats(
+    args: &Args,
+    device_rates: Option<&DeviceRates>,
+) -> Result<Vec<AudioFormatSpec>, String> {
+    let formats = negotiate::supported_formats(device_rates);
+    match args.only_codec {
+        Some(codec) => negotiate::only_codec(formats, codec),
+        None => Ok(formats),
+    }
+}
+
+/// Buffer capacity to advertise, which the player's queue also holds to
+fn buffer_capacity(args: &Args) -> u32 {
+    negotiate::buffer_capacity(args.buffer_capacity, Duration::from_millis(args.buffer))
+}
+
+/// Audio channel between the socket and the decoder, by default as long as
+/// the player's queue can get
+fn audio_channel_config(args: &Args) -> compat::AudioChannelConfig {
+    compat::AudioChannelConfig {
+        capacity: match args.audio_channel_capacity {
+            Some(capacity) => capacity as usize,
+            None => {
+                compat::audio_channel_capacity(negotiate::max_queue_duration(buffer_capacity(args)))
+            }
+        },
+        overflow: args.audio_overflow,
+    }
+}
+
+/// Player settings taken from the command line
+fn player_config(
+    args: &Args,
+    device_rates: Option<DeviceRates>,
+    timing_trace: Option<TimingTrace>,
+) -> PlayerConfig {
+    PlayerConfig {
+        initial_volume: if volume_fixed(args) { 100 } else { args.volume },
+        eq: args.eq.clone(),
+        dc_block: args.dc_block,
+        loudness_target: args.loudness_target,
+        volume_backend: args.volume_backend,
+        balance: args.balance,
+        swap_channels: args.swap_channels,
+        mono: args.mono.then_some(args.mono_gain_db),
+        crossfade_ms: args.crossfade_ms,
+        output: OutputConfig {
+            backend: args.backend,
+            host: args.audio_host.clone(),
+            device: args.alsa_device.clone(),
+            access: args.alsa_access,
+            period_frames: args.alsa_period,
+            buffer_frames: args.alsa_buffer,
+            require_audio: args.require_audio,
+            device_buffer: args.device_buffer,
+            file: args.output_file.clone(),
+            scheduling: args.scheduling,
+            exclusive: args.exclusive,
+            native_only: args.native_format_only,
+            recorder: None,
+        },
+        device_fallback: args.device_fallback,
+        device_retry_attempts: args.device_retry_attempts,
+        fade_in_ms: if args.bit_perfect { 0 } else { args.fade_in_ms },
+        device_rates,
+        prebuffer_ms: args.buffer,
+        buffer_capacity: buffer_capacity(args) as usize,
+        keep_device_open: args.keep_device_open,
+        idle_release: args.idle_release_secs.map(Duration::from_secs),
+        resample_quality: args.resample_quality,
+        dither: !args.no_dither && !args.bit_perfect,
+        noise_shaping: args.noise_shaping,
+        bit_perfect: args.bit_perfect,
+        drift_band: args
+            .drift_correction
+            .then(|| Duration::from_millis(args.drift_band_ms)),
+        coalesce: Duration::from_millis(args.coalesce_ms),
+        coalesce_window: args.coalesce_window_ms.map(Duration::from_millis),
+        wake_spin: if args.no_wake_spin {
+            Duration::ZERO
+        } else {
+            Duration::from_micros(args.wake_spin_us)
+        },
+        schedule_margin: Some(Duration::from_millis(args.schedule_margin_ms)),
+        timing_trace,
+        rt_priority: args.rt_priority,
+    }
+}
+
+/// Bit-perfect with software volume: the samples can't be scaled, so the
+/// volume stays at 100 and the server's changes are refused
+fn volume_fixed(args: &Args) -> bool {
+    args.bit_perfect && args.volume_backend == VolumeBackendKind::Software
+}
+
+/// Options that would change the samples, given together with --bit-perfect
+fn bit_perfect_conflicts(args: &Args, matches: &clap::ArgMatches) -> Vec<&'static str> {
+    let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    [
+        ("--eq", args.eq.is_some()),
+        ("--dc-block", args.dc_block),
+        ("--loudness-target", args.loudness_target.is_some()),
+        ("--balance", args.balance != 0),
+        ("--swap-channels", args.swap_channels),
+        ("--mono", args.mono),
+        ("--crossfade-ms", args.crossfade_ms > 0),
+        (
+            "--fade-in-ms",
+            explicit("fade_in_ms") && args.fade_in_ms > 0,
+        ),
+        ("--replaygain-preamp", args.replaygain_preamp != 0.0),
+        ("--noise-shaping", args.noise_shaping),
+        ("--resample-quality", explicit("resample_quality")),
+        ("--drift-correction", args.drift_correction),
+    ]
+    .into_iter()
+    .filter_map(|(flag, set)| set.then_some(flag))
+    .collect()
+}
+
+/// Sample layout of a stream: --pcm-layout, else what its bit depth implies,
+/// else a guess from the first chunk that isn't silent
+fn pcm_layout(args: &Args, format: &AudioFormat, data: &[u8]) -> Option<PcmLayout> {
+    let (layout, source) = if let Some(layout) = args.pcm_layout {
+        (layout, "--pcm-layout")
+    } else if let Some(layout) = PcmLayout::for_bit_depth(format.bit_depth) {
+        (layout, "stream/start")
+    } else {
+        let layout = pcm_layout::detect(data, format.channels as usize)?;
+        (layout, "detected from the first audio")
+    };
+    info!("PCM layout: {} ({})", layout.as_str(), source);
+    Some(layout)
+}
+
+/// Negotiated format of a stream and where it is played, for --format-report
+fn format_report(
+    args: &Args,
+    format: &AudioFormat,
+    endian: PcmEndian,
+    device_rates: Option<&DeviceRates>,
+) -> diag::FormatReport {
+    let output_rate = match args.backend {
+        OutputBackendKind::Cpal => {
+            device_rates.map_or(format.sample_rate, |d| d.output_rate(format.sample_rate))
+        }
+        _ => format.sample_rate,
+    };
+    let device = match args.backend {
+        OutputBackendKind::Alsa => args.alsa_device.as_deref().unwrap_or("default").to_string(),
+        OutputBackendKind::Null => "null".to_string(),
+        OutputBackendKind::File => args
+            .output_file
+            .as_ref()
+            .map_or_else(|| "file".to_string(), |path| path.display().to_string()),
+        OutputBackendKind::Cpal => device::default_output_name(args.audio_host.as_deref())
+            .unwrap_or_else(|| "default".to_string()),
+    };
+    diag::FormatReport {
+        codec: format!("{:?}", format.codec).to_lowercase(),
+        sample_rate: format.sample_rate,
+        channels: format.channels,
+        bit_depth: format.bit_depth,
+        endianness: match endian {
+            PcmEndian::Little => "little",
+            PcmEndian::Big => "big",
+        },
+        output_rate,
+        resampling: output_rate != format.sample_rate,
+        downmix: args.mono && format.channels > 1,
+        backend: format!("{:?}", args.backend).to_lowercase(),
+        device,
+    }
+}
+
+/// Build a synchronized client/state message reporting the current volume
+fn client_state(volume: u8, muted: bool) -> Message {
+    player_state(PlayerSyncState::Synchronized, volume, muted)
+}
+
+/// client/state with the playback position added when known, which the
+/// library's message has no field for
+fn client_state_with_position(
+    volume: u8,
+    muted: bool,
+    position: Option<Duration>,
+) -> serde_json::Value {
+    let mut state = serde_json::to_value(client_state(volume, muted)).unwrap_or_default();
+    if let (Some(position), Some(player)) = (
+        position,
+        state
+            .pointer_mut("/payload/player")
+            .and_then(|player| player.as_object_mut()),
+    ) {
+        player.insert(
+            "position_ms".to_string(),
+            (position.as_millis() as u64).into(),
+        );
+    }
+    state
+}
+
+/// client/state reporting the given player state
+fn player_state(state: PlayerSyncState, volume: u8, muted: bool) -> Message {
+    Message::ClientState(ClientState {
+        player: Some(PlayerState {
+            state,
+            volume: Some(volume),
+            muted: Some(muted),
+        }),
+    })
+}
+
+/// Full JSON of a server message, for logs users can attach to bug reports
+fn payload<T: serde::Serialize + std::fmt::Debug>(msg: &T) -> String {
+    serde_json::to_string(msg).unwrap_or_else(|_| format!("{:?}", msg))
+}
+
+/// Length of `ms` milliseconds of pre-roll, in whole frames at `sample_rate`
+fn preroll_frames(ms: u64, sample_rate: u32) -> usize {
+    (ms * sample_rate as u64 / 1000) as usize
+}
+
+/// Resolves at `at`, never when it's None
+async fn deadline(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Shift a play time by a signed millisecond offset
+fn apply_playback_offset(play_at: Instant, offset_ms: i32) -> Instant {
+    let offset = Duration::from_millis(offset_ms.unsigned_abs() as u64);
+    if offset_ms >= 0 {
+        play_at + offset
+    } else {
+        play_at.checked_sub(offset).unwrap_or(play_at)
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let log_file = match args.log_file {
+        Some(ref path) => Some(
+            RotatingFile::open(path, args.log_max_size, args.log_keep)
+                .map_err(|e| format!("--log-file {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+    let telemetry = telemetry::init(args.otlp.as_deref(), log_file)?;
+    telemetry.toggle_debug_on_sigusr2();
+    for (id, var) in [
+        ("server", "SENDSPIN_SERVER"),
+        ("name", "SENDSPIN_NAME"),
+        ("volume", "SENDSPIN_VOLUME"),
+    ] {
+        if matches.value_source(id) == Some(ValueSource::EnvVariable) {
+            debug!("--{} taken from {}", id, var);
+        }
+    }
+
+    // Nothing to discover with: say so now rather than after setting up
+    let needs_server = !args.list_devices
+        && args.list_formats.is_none()
+        && args.channel_test.is_none()
+        && args.self_test.is_none();
+    if args.disable_mdns && args.server.is_none() && args.dns_domain.is_none() && needs_server {
+        return Err(
+            "--disable-mdns needs a server: pass --server <host:port>, set SENDSPIN_SERVER or look it up with --dns-domain"
+                .into(),
+        );
+    }
+
+    if args.exclusive && !cfg!(windows) {
+        return Err("--exclusive is only supported on Windows (WASAPI)".into());
+    }
+    if args.exclusive && (args.backend != OutputBackendKind::Cpal || args.native_format_only) {
+        return Err(
+            "--exclusive replaces the cpal backend and can't be combined with --native-format-only"
+                .into(),
+        );
+    }
+
+    if args.native_format_only && args.backend != OutputBackendKind::Cpal {
+        return Err("--native-format-only needs the cpal backend".into());
+    }
+
+    if args.bit_perfect {
+        let conflicts = bit_perfect_conflicts(&args, &matches);
+        if !conflicts.is_empty() {
+            return Err(format!(
+                "--bit-perfect can't be combined with {}",
+                conflicts.join(", ")
+            )
+            .into());
+        }
+        if args.volume_backend == VolumeBackendKind::Software {
+            info!(
+                "Bit-perfect: software volume is off (reported as 100), use the amplifier or --volume-backend alsa"
+            );
+        }
+    }
 
-    let client_id = args
-        .client_id
-        .clone()
-        .unwrap_or_else(|| format!("sendspin-rs-{}", uuid::Uuid::new_v4()));
+    if args.mono && args.swap_channels {
+        warn!("--swap-channels has no effect with --mono");
+    }
+
+    if args.list_devices {
+        device::list_devices();
+        return Ok(());
+    }
+
+    if let Some(style) = args.list_formats {
+        return device::list_formats(
+            args.audio_host.as_deref(),
+            args.alsa_device.as_deref(),
+            style,
+        );
+    }
+
+    if let Some(channels) = args.channel_test {
+        return selftest::channel_test(&player_config(&args, None, None).output, channels);
+    }
+
+    if let Some(freq) = args.self_test {
+        let player = Player::with_config(player_config(&args, probe_device_rates(&args), None));
+        return selftest::run(&player, freq, selftest::DURATION);
+    }
+
+    let output_device = match args.backend {
+        OutputBackendKind::Alsa => args.alsa_device.as_deref(),
+        _ => None,
+    };
+    let host = identity::hostname();
+
+    let client_id = match (&args.client_id, &host) {
+        (Some(id), _) => id.clone(),
+        (None, Some(host)) if args.stable_id => {
+            identity::stable_client_id(host, output_device.unwrap_or("default"))
+        }
+        (None, None) if args.stable_id => {
+            warn!("Hostname unavailable, using a random client ID instead of --stable-id");
+            identity::random_client_id()
+        }
+        (None, _) => identity::random_client_id(),
+    };
 
     info!("Client ID: {}", client_id);
 
     // Determine server address (either from args or mDNS discovery)
-    let server_addr = match args.server {
+    let server_addr = match args.server.clone() {
         Some(addr) => {
             info!("Using specified server: {}", addr);
             addr
         }
         None => {
-            info!("No server specified, attempting mDNS discovery...");
-            match mdns::discover_sendspin_server() {
+            if args.disable_mdns {
+                info!("No server specified, looking it up by DNS-SD...");
+            } else {
+                info!("No server specified, attempting mDNS discovery...");
+            }
+            match mdns::discover_sendspin_server(args.dns_domain.as_deref(), !args.disable_mdns) {
                 Ok(addr) => addr,
                 Err(e) => {
                     error!("Failed to discover Sendspin server: {}", e);
@@ -74,9 +841,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let ws_url = format!("ws://{}/sendspin", server_addr);
     info!("Connecting to {}...", ws_url);
 
-    let hello = ClientHello {
+    let name = identity::player_name(
+        &args.name,
+        args.name_suffix.as_ref(),
+        host.as_deref(),
+        output_device,
+    );
+    info!("Player name: {}", name);
+
+    // Advertise what the output device plays natively (--probe never opens it)
+    let device_rates = if args.probe {
+        None
+    } else {
+        probe_device_rates(&args)
+    };
+    let supported_formats = advertised_formats(&args, device_rates.as_ref())?;
+    if let Some(codec) = args.only_codec {
+        info!("Advertising {} only", codec.as_str());
+    }
+
+    if let Some(capacity) = args.buffer_capacity {
+        let needed = negotiate::prebuffer_bytes(Duration::from_millis(args.buffer));
+        if (capacity as u64) < needed {
+            warn!(
+                "--buffer-capacity {} bytes can't hold --buffer {} ms of 96 kHz/24-bit audio ({} bytes)",
+                capacity, args.buffer, needed
+            );
+        }
+    }
+
+    let mut hello = ClientHello {
         client_id: client_id.clone(),
-        name: args.name.clone(),
+        name,
         version: 1,
         supported_roles: vec!["player@v1".to_string()],
         device_info: Some(DeviceInfo {
@@ -85,40 +881,354 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             software_version: Some(env!("CARGO_PKG_VERSION").to_string()),
         }),
         player_v1_support: Some(PlayerV1Support {
-            supported_formats: vec![
-                AudioFormatSpec {
-                    codec: "pcm".to_string(),
-                    channels: 2,
-                    sample_rate: 48000,
-                    bit_depth: 24,
-                },
-                AudioFormatSpec {
-                    codec: "pcm".to_string(),
-                    channels: 2,
-                    sample_rate: 48000,
-                    bit_depth: 16,
-                },
-            ],
-            buffer_capacity: 1048576,
+            supported_formats,
+            buffer_capacity: buffer_capacity(&args),
             supported_commands: vec!["volume".to_string(), "mute".to_string()],
         }),
         artwork_v1_support: None,
         visualizer_v1_support: None,
     };
 
+    if args.probe {
+        return probe(&args, &ws_url, hello).await;
+    }
+
+    // Create player with initial volume and output processing; it outlives
+    // reconnects so the output isn't torn down with the connection
+    let timing_trace = match args.timing_trace {
+        Some(ref path) => Some(
+            timing_trace::start(path)
+                .map_err(|e| format!("--timing-trace {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+    let mut player = Player::with_config(player_config(
+        &args,
+        device_rates.clone(),
+        timing_trace.clone(),
+    ));
+    let mut player_restarts = 0;
+    let mut status = SessionStatus {
+        volume: if volume_fixed(&args) {
+            100
+        } else {
+            args.volume
+        },
+        muted: false,
+        server_volume: matches.value_source("volume") == Some(ValueSource::DefaultValue)
+            && !volume_fixed(&args),
+        ends_at: args.max_session.map(|limit| Instant::now() + limit),
+        events: if args.json_events {
+            json_event_printer()
+        } else {
+            EventSender::none()
+        },
+        state: None,
+        server_id: None,
+        counted: StatsSnapshot::default(),
+        stats_baseline: StatsSnapshot::default(),
+        recent: RecentMessages::shared(),
+        report: ReportSignal::new(),
+        timing_trace,
+    };
+    let mut backoff = reconnect::ReconnectBackoff::new(args.reconnect_jitter);
+    let mut device_rates = device_rates;
+
+    loop {
+        // Advertise the device as it is now, it may have changed since the last hello
+        let current_rates = player.device_rates();
+        if current_rates != device_rates {
+            if let Some(support) = hello.player_v1_support.as_mut() {
+                support.supported_formats = advertised_formats(&args, current_rates.as_ref())?;
+            }
+            device_rates = current_rates;
+        }
+
+        let connection = info_span!(
+            "connection",
+            server = %server_addr,
+            server_id = tracing::field::Empty
+        );
+        let result = run_session(
+            &args,
+            &ws_url,
+            hello.clone(),
+            device_rates.clone(),
+            &player,
+            &mut status,
+            &mut backoff,
+        )
+        .instrument(connection)
+        .await;
+        // Whatever was playing came from the lost connection
+        player.stop();
+        let reason = match &result {
+            Ok(()) => "connection closed".to_string(),
+            Err(e) => e.to_string(),
+        };
+        status.events.emit(PlayerEvent::Disconnected { reason });
+        set_state(&mut status, PlaybackState::Stopped);
+        match result {
+            Err(e) if e.is::<session_limit::SessionLimitReached>() => {
+                info!("{}, exiting", e);
+                return Ok(());
+            }
+            Err(e) if e.is::<CapabilitiesChanged>() => {
+                info!("{}", e);
+                continue;
+            }
+            Err(e) if e.is::<RetriesExhausted>() => {
+                error!("Audio device retries used up, exiting");
+                return Err(e);
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(SendspinCliError::Audio(_))) => {
+                player_restarts += 1;
+                if player_restarts > PLAYER_RESTARTS {
+                    error!("{} (after {} new players), exiting", e, PLAYER_RESTARTS);
+                    return Err(e);
+                }
+                warn!(
+                    "{}, starting a new player ({} of {})",
+                    e, player_restarts, PLAYER_RESTARTS
+                );
+                status.counted.add(&player.stats().snapshot());
+                status.stats_baseline = StatsSnapshot::default();
+                player = Player::with_config(player_config(
+                    &args,
+                    device_rates.clone(),
+                    status.timing_trace.clone(),
+                ));
+            }
+            Err(e)
+                if e.downcast_ref::<SendspinCliError>()
+                    .is_some_and(|err| !args.reconnect_on.retries(err.is_transient())) =>
+            {
+                error!("Server refused the connection ({}), not retrying", e);
+                return Err(e);
+            }
+            Err(e) => match e.downcast_ref::<compat::Disconnect>() {
+                Some(disconnect) if !args.reconnect_on.retries(disconnect.is_transient()) => {
+                    error!("Server rejected the connection, not reconnecting");
+                    return Err(e);
+                }
+                Some(_) => warn!("Disconnected from {}: {}", server_addr, e),
+                None => warn!("Connection to {} lost: {}", server_addr, e),
+            },
+            Ok(()) => warn!("Connection to {} closed", server_addr),
+        }
+        let delay = backoff.next_delay();
+        if status
+            .ends_at
+            .is_some_and(|end| Instant::now() + delay >= end)
+        {
+            info!("Session limit runs out before the next reconnect, exiting");
+            return Ok(());
+        }
+        info!("Reconnecting in {:.1}s...", delay.as_secs_f32());
+        status.events.emit(PlayerEvent::Reconnecting {
+            attempt: backoff.attempts(),
+            delay,
+        });
+        let reconnect_at = tokio::time::sleep(delay);
+        tokio::pin!(reconnect_at);
+        let mut stats_tick = tokio::time::interval(Duration::from_secs(
+            args.stats.unwrap_or(stats::DEFAULT_INTERVAL_SECS),
+        ));
+        stats_tick.reset();
+        loop {
+            tokio::select! {
+                _ = &mut reconnect_at => break,
+                _ = status.report.recv() => {
+                    info!("{}", state_report(&args, &ws_url, false, &status, &player, None, None));
+                }
+                _ = stats_tick.tick(), if args.stats.is_some() => {
+                    let line = StatsLine {
+                        connected: false,
+                        buffered: player.queued_duration(),
+                        playback: stats_interval(&mut status, &player),
+                        replay_gain_db: replaygain::linear_to_db(player.replay_gain()),
+                        ..Default::default()
+                    };
+                    info!("{}", line);
+                }
+            }
+        }
+    }
+}
+
+/// --probe: one handshake with the server, no player
+async fn probe(
+    args: &Args,
+    ws_url: &str,
+    hello: ClientHello,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connect = compat::connect_with_compat(
+        ws_url,
+        hello,
+        audio_channel_config(args),
+        RecentMessages::shared(),
+    );
+    let connection = tokio::time::timeout(PROBE_TIMEOUT, connect)
+        .await
+        .map_err(|_| format!("no server/hello from {} within {:?}", ws_url, PROBE_TIMEOUT))?
+        .map_err(|e| format!("probe of {} failed: {}", ws_url, e))?;
+    connection.sender.close().await;
+    info!("Probe of {} succeeded", ws_url);
+    Ok(())
+}
+
+/// Player state carried across reconnects
+struct SessionStatus {
+    volume: u8, // Volume/mute as last reported to the server
+    muted: bool,
+    server_volume: bool,      // No --volume: adopt the server's volume on connect
+    ends_at: Option<Instant>, // --max-session runs out, across reconnects
+    events: EventSender,      // Player events (--json-events, or an embedding app)
+    state: Option<PlaybackState>, // As last told to the events' listener
+    server_id: Option<String>, // Of the last server reached
+    counted: StatsSnapshot,   // Playback counts of lost players
+    stats_baseline: StatsSnapshot, // The player's counts at the last --stats line
+    recent: SharedRecent,     // Message types received lately, across connections
+    report: ReportSignal,     // SIGUSR1
+    timing_trace: Option<TimingTrace>, // --timing-trace
+}
+
+/// Playback counts since the last --stats line, which this one becomes
+fn stats_interval(status: &mut SessionStatus, player: &Player) -> StatsSnapshot {
+    let now = player.stats().snapshot();
+    let interval = now.since(&status.stats_baseline);
+    status.stats_baseline = now;
+    interval
+}
+
+/// What SIGUSR1 logs; `format` and `sync` are the session's, None between
+/// connections
+fn state_report(
+    args: &Args,
+    url: &str,
+    connected: bool,
+    status: &SessionStatus,
+    player: &Player,
+    format: Option<&AudioFormat>,
+    sync: Option<&SyncSamples>,
+) -> StateReport {
+    let mut totals = status.counted;
+    totals.add(&player.stats().snapshot());
+    StateReport {
+        url: url.to_string(),
+        connected,
+        server_id: status.server_id.clone(),
+        format: format.cloned(),
+        playing: player.is_playing(),
+        output_open: player.output_open(),
+        queued_buffers: player.queued_buffers(),
+        buffered: player.queued_duration(),
+        offset_us: sync.and_then(SyncSamples::offset_us),
+        rtt_us: sync.and_then(SyncSamples::rtt_us),
+        drift_ppm: args.drift_correction.then(|| player.stats().drift_ppm()),
+        volume: status.volume,
+        muted: status.muted,
+        replay_gain_db: replaygain::linear_to_db(player.replay_gain()),
+        totals,
+        recent: status.recent.lock().unwrap().recent(Instant::now()),
+    }
+}
+
+/// --json-events: each event as one JSON line on stdout
+fn json_event_printer() -> EventSender {
+    let writer = std::sync::Mutex::new(JsonEventWriter::new());
+    EventSender::callback(move |event| {
+        let line = writer.lock().unwrap().line(event, SystemTime::now());
+        // A reader that went away (e.g. `| head`) mustn't take the player down
+        let mut stdout = std::io::stdout().lock();
+        if let Err(e) = writeln!(stdout, "{}", line).and_then(|()| stdout.flush()) {
+            debug!("Can't write an event to stdout: {}", e);
+        }
+    })
+}
+
+/// Tell the events' listener about a playback state it hasn't seen yet
+fn set_state(status: &mut SessionStatus, state: PlaybackState) {
+    if status.state != Some(state) {
+        status.state = Some(state);
+        status.events.emit(PlayerEvent::StateChanged { state });
+    }
+}
+
+fn volume_changed(status: &SessionStatus) {
+    status.events.emit(PlayerEvent::VolumeChanged {
+        volume: status.volume,
+        muted: status.muted,
+    });
+}
+
+/// Switch to the volume the server announced when we connected
+fn adopt_server_volume(player: &Player, status: &mut SessionStatus, volume: u8, source: &str) {
+    info!(
+        "Using the server's volume {} (from {}) instead of {}",
+        volume, source, status.volume
+    );
+    player.set_volume(volume);
+    status.volume = volume;
+    volume_changed(status);
+}
+
+/// One connection to the server: handshake, then handle messages until it closes
+async fn run_session(
+    args: &Args,
+    ws_url: &str,
+    hello: ClientHello,
+    mut device_rates: Option<DeviceRates>,
+    player: &Player,
+    status: &mut SessionStatus,
+    backoff: &mut reconnect::ReconnectBackoff,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advertised_rates = device_rates.clone(); // What this connection's hello offered
+    status.events.emit(PlayerEvent::Connecting {
+        url: ws_url.to_string(),
+    });
+
     // Use compatibility shim to fix field names for Music Assistant
-    let (mut message_rx, mut audio_rx, clock_sync, ws_tx) =
-        compat::connect_with_compat(&ws_url, hello).await?;
+    let compat::CompatConnection {
+        messages: mut message_rx,
+        raw_messages: mut raw_rx,
+        audio: mut audio_rx,
+        artwork: mut artwork_rx,
+        clock_sync,
+        sender: ws_tx,
+        disconnect,
+        server_hello,
+    } = compat::connect_with_compat(
+        ws_url,
+        hello,
+        audio_channel_config(args),
+        status.recent.clone(),
+    )
+    .await?;
     info!("Connected!");
+    let server_id = server_hello.get("server_id").and_then(|id| id.as_str());
+    Span::current().record("server_id", server_id);
+    status.server_id = server_id.map(str::to_string);
+    status.events.emit(PlayerEvent::Connected {
+        server_id: server_id.unwrap_or_default().to_string(),
+    });
+    backoff.reset();
+
+    // Until a stream starts, a volume from the server replaces --volume's default
+    let mut volume_pending = status.server_volume;
+    if !volume_pending {
+        info!("Using volume {} (--volume given)", status.volume);
+    } else if let Some(volume) = server_volume::volume_from_payload(&server_hello) {
+        adopt_server_volume(player, status, volume, "server/hello");
+        volume_pending = false;
+    }
+
+    if args.connect_tone {
+        selftest::connect_tone(player);
+    }
 
     // Send initial state
-    let initial_state = Message::ClientState(ClientState {
-        player: Some(PlayerState {
-            state: PlayerSyncState::Synchronized,
-            volume: Some(args.volume),
-            muted: Some(false),
-        }),
-    });
+    let initial_state = client_state(status.volume, status.muted);
     ws_tx.send_message(initial_state).await?;
     info!("Sent initial client/state");
 
@@ -137,15 +1247,59 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Waiting for stream to start...");
 
-    // Create player with initial volume
-    let player = Player::new(args.volume);
-
     // Message handling
-    let mut decoder: Option<PcmDecoder> = None;
+    let mut layout: Option<PcmLayout> = None; // Decided on the first audible chunk
     let mut audio_format: Option<AudioFormat> = None;
+    let mut stream_span = Span::none(); // Parent of the stream's chunk spans
     let mut endian_locked: Option<PcmEndian> = None;
     let mut next_play_time: Option<Instant> = None;
+    let mut continuity = ContinuityTracker::new(); // Gaps and duplicates by timestamp
+    let mut continuity_counted = StreamStats::default(); // Its counts at the last --stats line
     let buffer_ms = args.buffer;
+    let mut first_chunk = true;
+    let mut stream_start: Option<i64> = None; // First timestamp of the stream, for seeks
+    let mut preroll = Duration::ZERO; // Silence ahead of the current stream
+    let mut progress = PositionTracker::new(); // Position reported with --report-position-secs
+    let mut position_tick =
+        tokio::time::interval(Duration::from_secs(args.report_position_secs.unwrap_or(1)));
+    position_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut stats_tick = tokio::time::interval(Duration::from_secs(
+        args.stats.unwrap_or(stats::DEFAULT_INTERVAL_SECS),
+    ));
+    stats_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    stats_tick.reset(); // The first line after one interval, not at once
+    let mut debug_player_tick = tokio::time::interval(Duration::from_secs(
+        args.debug_player.unwrap_or(stats::DEFAULT_INTERVAL_SECS),
+    ));
+    debug_player_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut sync_samples = SyncSamples::new(); // Clock offset and round trip for --stats
+    let mut chunks_received: u64 = 0; // Counted for --stats, per interval
+    let mut chunks_decoded: u64 = 0;
+    let mut decode_time = Duration::ZERO;
+    let mut warmup_left = args.clock_warmup_chunks; // Chunks still paced while the clock converges
+    let mut warmup_paced = false; // A chunk was paced although the clock was ready
+    let mut warmup_slew: Option<WarmupSlew> = None; // Gliding from the paced schedule to the clock's
+    let mut clear_at: Option<Instant> = None; // stream/clear held back by --clear-grace-ms
+    let mut clear_due = false; // Clear the stream after this message
+
+    // Title, artist and album last announced with --json-events
+    let mut last_track: Option<(Option<String>, Option<String>, Option<String>)> = None;
+
+    if args.playback_offset_ms != 0 {
+        info!("Playback offset: {:+} ms", args.playback_offset_ms);
+    }
+    if args.preroll_silence_ms > 0 {
+        info!(
+            "Pre-roll: {} ms of silence before each stream",
+            args.preroll_silence_ms
+        );
+    }
+    if args.clock_warmup_chunks > 0 {
+        info!(
+            "Clock warm-up: first {} chunks paced by arrival",
+            args.clock_warmup_chunks
+        );
+    }
 
     loop {
         tokio::select! {
@@ -160,121 +1314,253 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 match msg {
                     Message::StreamStart(stream_start) => {
+                        if clear_at.take().is_some() {
+                            info!("stream/start within the clear grace period, skipping the clear");
+                        }
+                        if std::mem::take(&mut volume_pending) {
+                            info!("No volume from the server, using {}", status.volume);
+                        }
                         if let Some(player_config) = &stream_start.player {
                             let codec = &player_config.codec;
                             let sample_rate = player_config.sample_rate;
                             let channels = player_config.channels;
                             let bit_depth = player_config.bit_depth;
 
-                            if codec != "pcm" || (bit_depth != 16 && bit_depth != 24) {
+                            if codec != "pcm" || ![16, 24, 32].contains(&bit_depth) {
                                 error!("Unsupported format: {} {}bit", codec, bit_depth);
                                 continue;
                             }
 
-                            // New stream: Stop old, setup new, Resume
-                            player.stop();
-                            std::thread::sleep(Duration::from_millis(5)); // Give time to clear
-                            player.resume();
-
-                            audio_format = Some(AudioFormat {
+                            let format = AudioFormat {
                                 codec: Codec::Pcm,
                                 sample_rate,
                                 channels,
                                 bit_depth,
                                 codec_header: None,
+                            };
+                            if stream_start::is_repeat(
+                                audio_format.as_ref(),
+                                &format,
+                                player.is_playing(),
+                            ) {
+                                info!(
+                                    "stream/start repeats the stream playing ({}Hz {}ch {}bit), carrying on",
+                                    sample_rate, channels, bit_depth
+                                );
+                                let state = client_state(status.volume, status.muted);
+                                let _ = ws_tx.send_message(state).await;
+                                continue;
+                            }
+                            if let Some(rates) =
+                                device_rates.as_ref().filter(|d| !d.supports(sample_rate))
+                            {
+                                if args.native_format_only {
+                                    info!(
+                                        "{} Hz isn't the device's native rate: converting to {} Hz here ({:?} quality) instead of in the OS",
+                                        sample_rate,
+                                        rates.output_rate(sample_rate),
+                                        args.resample_quality
+                                    );
+                                } else {
+                                    info!(
+                                        "{} Hz isn't native to the output device, it will be converted to {} Hz",
+                                        sample_rate,
+                                        rates.output_rate(sample_rate)
+                                    );
+                                }
+                            }
+
+                            // New stream: Stop old (or fade it out), setup new, Resume
+                            if args.crossfade_ms > 0 {
+                                player.crossfade();
+                            } else {
+                                player.stop();
+                                std::thread::sleep(Duration::from_millis(5)); // Give time to clear
+                                player.resume();
+                            }
+
+                            // Chunk lengths change with the format: size the pool afresh
+                            player.sample_pool().prepare(&format);
+                            status.events.emit(PlayerEvent::StreamStarted {
+                                format: format.clone(),
                             });
+                            set_state(status, PlaybackState::Playing);
+                            audio_format = Some(format);
+                            stream_span = info_span!(
+                                "stream",
+                                format = %format!("{}/{}/{}", sample_rate, channels, bit_depth)
+                            );
 
-                            decoder = None;
+                            layout = None;
                             endian_locked = None;
                             next_play_time = None;
+                            continuity.new_stream();
+                            first_chunk = true;
+                            stream_start = None;
+                            progress.reset();
 
                             info!("Stream: {}Hz {}ch {}bit", sample_rate, channels, bit_depth);
 
                             // Send playing state to server
-                            let state = Message::ClientState(ClientState {
-                                player: Some(PlayerState {
-                                    state: PlayerSyncState::Synchronized,
-                                    volume: Some(args.volume),
-                                    muted: Some(false),
-                                }),
-                            });
+                            let state = client_state(status.volume, status.muted);
                             let _ = ws_tx.send_message(state).await;
                         }
                     }
                     Message::StreamEnd(_end_data) => {
-                        info!("← stream/end");
-
-                        // Stop playback on stream/end
-                        player.stop();
-                        next_play_time = None;
+                        info!(
+                            "← stream/end ({} ms still queued)",
+                            player.queued_duration().as_millis()
+                        );
+                        status.events.emit(PlayerEvent::StreamEnded);
 
-                        // Send synchronized state to server (not playing but ready)
-                        let state = Message::ClientState(ClientState {
-                            player: Some(PlayerState {
-                                state: PlayerSyncState::Synchronized,
-                                volume: Some(args.volume),
-                                muted: Some(false),
-                            }),
+                        // Let the buffered tail play out, then the player stops itself
+                        let drain = player.drain();
+                        tokio::spawn(async move {
+                            if drain.await == Drained::Played {
+                                debug!("Stream played out to its end");
+                            }
                         });
-                        let _ = ws_tx.send_message(state).await;
-                    }
-                    Message::StreamClear(_) => {
-                        player.stop();
-                        decoder = None;
+                        layout = None;
                         audio_format = None;
+                        stream_span = Span::none();
                         endian_locked = None;
                         next_play_time = None;
+                        continuity.reset();
+                        let stats = continuity.stream_stats();
+                        if stats.gaps > 0 || stats.duplicates > 0 {
+                            info!(
+                                "Stream continuity: {} gaps ({} ms of silence inserted), {} duplicates",
+                                stats.gaps,
+                                stats.silence_inserted_us / 1000,
+                                stats.duplicates
+                            );
+                        }
 
-                        // Send synchronized state to server
-                        let state = Message::ClientState(ClientState {
-                            player: Some(PlayerState {
-                                state: PlayerSyncState::Synchronized,
-                                volume: Some(args.volume),
-                                muted: Some(false),
-                            }),
-                        });
+                        // Send synchronized state to server (not playing but ready)
+                        let state = client_state(status.volume, status.muted);
+                        let _ = ws_tx.send_message(state).await;
+                    }
+                    Message::StreamClear(_) if args.clear_grace_ms > 0 => {
+                        // Some servers clear and carry on with the same stream:
+                        // keep playing unless nothing follows
+                        let grace = Duration::from_millis(args.clear_grace_ms);
+                        clear_at.get_or_insert(Instant::now() + grace);
+                        let state = client_state(status.volume, status.muted);
                         let _ = ws_tx.send_message(state).await;
                     }
+                    Message::StreamClear(_) => clear_due = true,
                     Message::ServerCommand(command) => {
                         // Check if this is a player command
                         if let Some(player_cmd) = &command.player {
                             match player_cmd.command.as_str() {
                                 "pause" | "stop" => {
                                     info!("→ Handling pause/stop command");
-                                    player.stop();
-                                    // Send synchronized state to server
-                                    let state = Message::ClientState(ClientState {
-                                        player: Some(PlayerState {
-                                            state: PlayerSyncState::Synchronized,
-                                            volume: Some(args.volume),
-                                            muted: Some(false),
-                                        }),
-                                    });
-                                    let _ = ws_tx.send_message(state).await;
+                                    progress.pause(player.last_played_timestamp());
+                                    if player_cmd.command == "stop" {
+                                        player.fade_out();
+                                        // Stop turns the device off even with --keep-device-open
+                                        player.close_output();
+                                    } else {
+                                        // Remember the position so resume doesn't replay it
+                                        player.pause();
+                                    }
+                                    let state = if player_cmd.command == "stop" {
+                                        PlaybackState::Stopped
+                                    } else {
+                                        PlaybackState::Paused
+                                    };
+                                    set_state(status, state);
+                                    continuity.reset();
+                                    // Send synchronized state to server, with where
+                                    // playback stopped so its progress bar holds there
+                                    let state = client_state_with_position(
+                                        status.volume,
+                                        status.muted,
+                                        progress.position(None),
+                                    );
+                                    let _ = ws_tx.send_json(&state).await;
                                 }
                                 "play" => {
                                     info!("→ Handling play command");
+                                    // Playback carries on from where it paused
+                                    let resumed_at = progress.position(None);
+                                    progress.resume();
+                                    if let Some(position) = player.pause_position() {
+                                        info!("Resuming after ts={}", position);
+                                    }
                                     player.resume();
+                                    set_state(status, PlaybackState::Playing);
                                     // Send playing state to server
-                                    let state = Message::ClientState(ClientState {
-                                        player: Some(PlayerState {
-                                            state: PlayerSyncState::Synchronized,
-                                            volume: Some(args.volume),
-                                            muted: Some(false),
-                                        }),
-                                    });
-                                    let _ = ws_tx.send_message(state).await;
+                                    let state = client_state_with_position(
+                                        status.volume,
+                                        status.muted,
+                                        resumed_at,
+                                    );
+                                    let _ = ws_tx.send_json(&state).await;
                                 }
                                 "volume" => {
-                                    if let Some(vol) = player_cmd.volume {
+                                    volume_pending = false;
+                                    if volume_fixed(args) {
+                                        warn!("Bit-perfect: ignoring volume change, use the amplifier or --volume-backend alsa");
+                                    } else if let Some(vol) = player_cmd.volume {
                                         info!("← Setting volume to {}", vol);
                                         player.set_volume(vol);
+                                        status.volume = vol;
+                                        volume_changed(status);
                                     }
+                                    let state = client_state(status.volume, status.muted);
+                                    let _ = ws_tx.send_message(state).await;
                                 }
-                                _ => {
-                                    debug!("Unknown command: {}", player_cmd.command);
+                                "volume_up" | "volume_down" if volume_fixed(args) => {
+                                    warn!("Bit-perfect: ignoring {}, use the amplifier or --volume-backend alsa", player_cmd.command);
+                                    let state = client_state(status.volume, status.muted);
+                                    let _ = ws_tx.send_message(state).await;
+                                }
+                                "volume_up" | "volume_down" => {
+                                    volume_pending = false;
+                                    let step = if player_cmd.command == "volume_up" {
+                                        VOLUME_STEP
+                                    } else {
+                                        -VOLUME_STEP
+                                    };
+                                    status.volume = player.adjust_volume(step);
+                                    info!("← Volume {:+} to {}", step, status.volume);
+                                    volume_changed(status);
+                                    let state = client_state(status.volume, status.muted);
+                                    let _ = ws_tx.send_message(state).await;
+                                }
+                                "mute" => {
+                                    // Read the flag from the serialized form so it doesn't
+                                    // depend on how the library names the field
+                                    let mute = serde_json::to_value(player_cmd)
+                                        .ok()
+                                        .and_then(|v| v.get("mute").and_then(|m| m.as_bool()));
+                                    if let Some(mute) = mute {
+                                        info!("→ Handling mute command: {}", mute);
+                                        player.set_muted(mute);
+                                        status.muted = mute;
+                                        volume_changed(status);
+                                    } else {
+                                        warn!("mute command without a mute flag: {}", payload(&command));
+                                    }
+                                    let state = client_state(status.volume, status.muted);
+                                    let _ = ws_tx.send_message(state).await;
+                                }
+                                "seek" | "next" | "previous" | "shuffle" | "unshuffle"
+                                | "repeat_off" | "repeat_one" | "repeat_all" => {
+                                    // Carried out by the server, which then clears or restarts
+                                    // the stream - acknowledge with our current state. A seek
+                                    // that carries a position is applied from the raw message
+                                    info!("→ Acknowledging {} command", player_cmd.command);
+                                    let state = client_state(status.volume, status.muted);
+                                    let _ = ws_tx.send_message(state).await;
+                                }
+                                other => {
+                                    warn!("Unhandled server command '{}': {}", other, payload(&command));
                                 }
                             }
+                        } else {
+                            warn!("Unhandled server command: {}", payload(&command));
                         }
                     }
                     Message::ServerTime(server_time) => {
@@ -288,31 +1574,347 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             server_time.server_transmitted,
                             t4
                         );
+                        sync_samples.add(
+                            server_time.client_transmitted,
+                            server_time.server_received,
+                            server_time.server_transmitted,
+                            t4,
+                        );
+                        trace!(
+                            offset_us = sync_samples.offset_us(),
+                            rtt_us = sync_samples.rtt_us(),
+                            "Clock sync sample"
+                        );
                     }
                     _ => {}
                 }
             }
 
+            Some(raw) = raw_rx.recv() => {
+                if let Some(err) = server_error::server_error_from(&raw.msg_type, &raw.payload) {
+                    error!("Server reported an error: {}", err);
+                    status.events.emit(PlayerEvent::Error {
+                        message: format!("Server reported an error: {}", err),
+                    });
+                    if err.rejects_state() {
+                        // Synchronized is all we can report, so sending it again wouldn't help
+                        warn!("Server refused our client/state, the server may show the player out of sync");
+                    }
+                }
+                if volume_pending && raw.msg_type == "server/state" {
+                    if let Some(volume) = server_volume::volume_from_payload(&raw.payload) {
+                        adopt_server_volume(player, status, volume, "server/state");
+                        volume_pending = false;
+                        let state = client_state(status.volume, status.muted);
+                        let _ = ws_tx.send_message(state).await;
+                    }
+                }
+                if raw.msg_type == "server/command" {
+                    if let Some(position) = seek::position_from_payload(&raw.payload) {
+                        match (stream_start, &audio_format) {
+                            (Some(start), Some(fmt)) => {
+                                let target = seek::target_timestamp(start, position, fmt.sample_rate);
+                                info!("→ Seeking to {:?} (ts={})", position, target);
+                                player.seek_to(target);
+                                progress.seek(target, position);
+                                // The stream jumps ahead: not a gap to fill
+                                continuity.reset();
+                            }
+                            _ => debug!("Seek to {:?} with no stream playing, ignored", position),
+                        }
+                    }
+                }
+                if raw.msg_type == "server/state" {
+                    if let Some(metadata) = raw.payload.get("metadata") {
+                        let field = |key: &str| {
+                            metadata.get(key).and_then(|v| v.as_str()).map(str::to_string)
+                        };
+                        let track = (field("title"), field("artist"), field("album"));
+                        if track != (None, None, None) && last_track.as_ref() != Some(&track) {
+                            let (title, artist, album) = track.clone();
+                            status.events.emit(PlayerEvent::MetadataChanged {
+                                title,
+                                artist,
+                                album,
+                            });
+                            last_track = Some(track);
+                        }
+                    }
+                }
+                if matches!(raw.msg_type.as_str(), "server/state" | "server/command") {
+                    if let Some(speed) = speed::playback_speed_from_payload(&raw.payload) {
+                        // The player goes back to x1 by itself on a stop or
+                        // crossfade, so it's asked rather than remembered here
+                        if speed != player.playback_speed() {
+                            info!("Playback speed x{:.3} (from {})", speed, raw.msg_type);
+                            player.set_playback_speed(speed);
+                        }
+                    }
+                }
+
+                // Bit-perfect playback applies no gain at all
+                if args.no_replaygain || args.bit_perfect {
+                    continue;
+                }
+                let gain_db = replaygain::gain_db_from_payload(&raw.payload);
+                let gain_db = match raw.msg_type.as_str() {
+                    // Every new stream starts from unity unless it carries its own gain
+                    "stream/start" => Some(gain_db.map_or(0.0, |db| db + args.replaygain_preamp)),
+                    "server/state" => gain_db.map(|db| db + args.replaygain_preamp),
+                    _ => None,
+                };
+                if let Some(db) = gain_db {
+                    let factor = replaygain::db_to_linear(db);
+                    debug!(
+                        "ReplayGain from {}: {:+.2} dB (preamp {:+.2} dB) → x{:.3}",
+                        raw.msg_type, db, args.replaygain_preamp, factor
+                    );
+                    player.set_replay_gain(factor);
+                }
+            }
+
+            Some(rates) = player.device_rates_changed() => {
+                device_rates = Some(rates.clone());
+                // The hello can't be updated in place: reconnect only when
+                // the stream playing now would otherwise be converted, and a
+                // new hello would offer something else
+                let stream_rate = audio_format.as_ref().map(|fmt| fmt.sample_rate);
+                if negotiate::renegotiate(advertised_rates.as_ref(), &rates, stream_rate) {
+                    warn!(
+                        "The new output device can't play {} Hz natively, reconnecting to advertise its rates",
+                        stream_rate.unwrap_or_default()
+                    );
+                    ws_tx.close().await;
+                    return Err(CapabilitiesChanged { rates }.into());
+                }
+                info!(
+                    "Output device rates now {:?}, advertised from the next connection",
+                    rates.ranges
+                );
+            }
+
+            _ = player.device_failed() => {
+                status.events.emit(PlayerEvent::Error {
+                    message: "Audio device unavailable".to_string(),
+                });
+                // Tell the server this player can't play, then exit or wait
+                let state = player_state(PlayerSyncState::Error, status.volume, status.muted);
+                let _ = ws_tx.send_message(state).await;
+                if args.device_retry_exhausted == RetryExhausted::Exit {
+                    return Err(RetriesExhausted {
+                        attempts: args.device_retry_attempts.unwrap_or_default(),
+                    }
+                    .into());
+                }
+                warn!("Audio device unavailable, waiting for the next stream");
+            }
+
+            failed = player.playback_failed() => {
+                status.events.emit(PlayerEvent::Error {
+                    message: failed.to_string(),
+                });
+                // Nothing plays through this player again: report it and let
+                // the reconnect loop replace it
+                let state = player_state(PlayerSyncState::Error, status.volume, status.muted);
+                let _ = ws_tx.send_message(state).await;
+                ws_tx.close().await;
+                return Err(SendspinCliError::Audio(failed).into());
+            }
+
+            _ = position_tick.tick(), if args.report_position_secs.is_some() => {
+                if let Some(at) = progress.position(player.last_played_timestamp()) {
+                    debug!("Reporting playback position {:.1}s", at.as_secs_f64());
+                    let state = client_state_with_position(status.volume, status.muted, Some(at));
+                    let _ = ws_tx.send_json(&state).await;
+                }
+            }
+
+            _ = player.stats().next_underrun(), if args.json_events => {
+                status.events.emit(PlayerEvent::Underrun);
+            }
+
+            _ = stats_tick.tick(), if args.stats.is_some() => {
+                let line = StatsLine {
+                    connected: true,
+                    format: audio_format.clone(),
+                    buffered: player.queued_duration(),
+                    chunks_received: std::mem::take(&mut chunks_received),
+                    chunks_decoded: std::mem::take(&mut chunks_decoded),
+                    decode_time: std::mem::take(&mut decode_time),
+                    playback: stats_interval(status, player),
+                    offset_us: sync_samples.offset_us(),
+                    rtt_us: sync_samples.rtt_us(),
+                    continuity: continuity.stats.since(&continuity_counted),
+                    replay_gain_db: replaygain::linear_to_db(player.replay_gain()),
+                };
+                continuity_counted = continuity.stats;
+                info!("{}", line);
+            }
+
+            _ = debug_player_tick.tick(), if args.debug_player.is_some() => {
+                info!("{}", player.debug_state());
+            }
+
+            _ = status.report.recv() => {
+                let report = state_report(
+                    args,
+                    ws_url,
+                    true,
+                    status,
+                    player,
+                    audio_format.as_ref(),
+                    Some(&sync_samples),
+                );
+                info!("{}", report);
+            }
+
+            _ = session_limit::expired(status.ends_at) => {
+                let limit = args.max_session.unwrap_or_default();
+                info!("Session limit of {:?} reached, disconnecting", limit);
+                player.stop();
+                let state = client_state(status.volume, status.muted);
+                let _ = ws_tx.send_message(state).await;
+                ws_tx.close().await;
+                return Err(session_limit::SessionLimitReached { limit }.into());
+            }
+
+            Some(image) = artwork_rx.recv() => {
+                info!(
+                    "Artwork received: channel {}, {}, {} bytes",
+                    image.channel,
+                    image.content_type.mime_type(),
+                    image.data.len()
+                );
+            }
+
+            _ = deadline(player.coalesce_deadline()), if args.coalesce_window_ms.is_some() => {
+                // No chunk came to send the ones held for merging on
+                player.flush_expired();
+            }
+
+            _ = deadline(clear_at), if clear_at.is_some() => {
+                info!(
+                    "Nothing followed stream/clear within {} ms, clearing",
+                    args.clear_grace_ms
+                );
+                clear_at = None;
+                clear_due = true;
+            }
+
             Some(chunk) = audio_rx.recv() => {
+                let arrival = Instant::now();
+                chunks_received += 1;
+                // Only audio that carries on from the same position cancels a
+                // held clear; anything else is the audio the clear was for
+                if clear_at.is_some() && continuity.continues(chunk.timestamp) {
+                    clear_at = None;
+                    info!("Audio carried on after stream/clear, skipping the clear");
+                }
                 if let Some(ref fmt) = audio_format {
+                    if layout.is_none() {
+                        layout = pcm_layout(args, fmt, &chunk.data);
+                    }
                     if endian_locked.is_none() {
                         endian_locked = Some(PcmEndian::Little);
-                        decoder = Some(PcmDecoder::with_endian(fmt.bit_depth, PcmEndian::Little));
+                        if args.format_report {
+                            let report =
+                                format_report(args, fmt, PcmEndian::Little, device_rates.as_ref());
+                            println!("{}", report.to_json());
+                        }
                     }
                 }
 
-                if let (Some(ref dec), Some(ref fmt)) = (&decoder, &audio_format) {
-                    if let Ok(samples) = dec.decode(&chunk.data) {
-                        let frames = samples.len() / fmt.channels as usize;
-                        let duration = Duration::from_micros(
-                            (frames as u64 * 1_000_000) / fmt.sample_rate as u64
+                if let Some(ref fmt) = audio_format {
+                    let chunk_span = trace_span!(
+                        parent: &stream_span,
+                        "chunk",
+                        ts = chunk.timestamp,
+                        frames = tracing::field::Empty,
+                        lead_ms = tracing::field::Empty
+                    );
+                    // Not held across the clock lock below, which may yield
+                    let in_chunk = chunk_span.enter();
+
+                    // Undecided means silence so far: packed is as good as any
+                    let decode_start = Instant::now();
+                    let samples = layout
+                        .unwrap_or(PcmLayout::S24le)
+                        .decode_pooled(&chunk.data, player.sample_pool());
+                    decode_time += decode_start.elapsed();
+                    chunks_decoded += 1;
+                    let frames = samples.len() / fmt.channels as usize;
+                    chunk_span.record("frames", frames);
+                    if args.debug_audio_crc {
+                        info!(
+                            "Audio CRC: ts={} frames={} crc={:08x}",
+                            chunk.timestamp,
+                            frames,
+                            diag::buffer_crc(&samples)
                         );
+                    }
 
-                        // Determine play time
-                        let sync = clock_sync.lock().await;
-                        let play_at = if let Some(instant) = sync.server_to_local_instant(chunk.timestamp) {
-                            instant
-                        } else {
+                    // Fill lost chunks with silence, skip what was received twice
+                    let channels = fmt.channels as usize;
+                    let check = continuity.check(chunk.timestamp, frames, fmt.sample_rate);
+                    match check {
+                        Continuity::Fill { frames } => warn!(
+                            "Gap of {} frames before ts={}, filling with silence",
+                            frames, chunk.timestamp
+                        ),
+                        Continuity::Trim { from } => {
+                            debug!(from, "Chunk overlaps the previous one, playing from its end")
+                        }
+                        Continuity::Drop => debug!("Dropping duplicate chunk"),
+                        Continuity::Play => {}
+                    }
+                    let Some((timestamp, samples)) =
+                        continuity::realign(check, chunk.timestamp, samples, channels, fmt.sample_rate)
+                    else {
+                        continue;
+                    };
+                    let frames = samples.len() / channels;
+                    let duration = Duration::from_micros(
+                        (frames as u64 * 1_000_000) / fmt.sample_rate as u64
+                    );
+
+                    drop(in_chunk);
+
+                    // Determine play time
+                    let sync = clock_sync.lock().await;
+                    let synced = sync.server_to_local_instant(timestamp);
+                    drop(sync);
+                    // The first estimates move by milliseconds between chunks,
+                    // which is audible at the start; pace by arrival until the
+                    // warm-up count is used up, ready or not
+                    let warming_up = warmup_left > 0;
+                    warmup_left = warmup_left.saturating_sub(1);
+                    let play_at = match synced {
+                        Some(instant) if !warming_up => {
+                            if std::mem::take(&mut warmup_paced) {
+                                if let Some(paced) = next_play_time {
+                                    let slew = WarmupSlew::new(paced, instant);
+                                    info!(
+                                        "Clock warm-up over, moving to synced timing ({:+.1} ms from the paced schedule, over {} chunks)",
+                                        slew.offset_us() as f64 / 1000.0,
+                                        slew.chunks_left()
+                                    );
+                                    warmup_slew = Some(slew);
+                                }
+                            }
+                            match warmup_slew.as_mut() {
+                                Some(slew) => {
+                                    let play_at = slew.apply(instant);
+                                    if slew.is_done() {
+                                        debug!("Clock warm-up handover done, synced timing from here");
+                                        warmup_slew = None;
+                                    }
+                                    play_at
+                                }
+                                None => instant,
+                            }
+                        }
+                        _ => {
+                            warmup_paced |= synced.is_some();
                             // Fallback timing
                             if next_play_time.is_none() {
                                 next_play_time = Some(Instant::now() + Duration::from_millis(buffer_ms));
@@ -320,25 +1922,156 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             let pt = next_play_time.unwrap();
                             next_play_time = Some(pt + duration);
                             pt
-                        };
-                        drop(sync);
+                        }
+                    };
+                    let play_at = apply_playback_offset(play_at, args.playback_offset_ms);
 
-                        let buffer = AudioBuffer {
-                            timestamp: chunk.timestamp,
-                            play_at,
-                            samples,
-                            format: fmt.clone(),
-                        };
+                    // The stream's own audio waits for its pre-roll silence
+                    if first_chunk {
+                        let frames = preroll_frames(args.preroll_silence_ms, fmt.sample_rate);
+                        preroll = Duration::from_micros(
+                            frames as u64 * 1_000_000 / fmt.sample_rate as u64
+                        );
+                        if frames > 0 {
+                            player.enqueue(AudioBuffer {
+                                timestamp: timestamp - preroll.as_micros() as i64,
+                                play_at,
+                                samples: Arc::from(vec![Sample(0); frames * channels]),
+                                format: fmt.clone(),
+                            });
+                        }
+                    }
+                    let play_at = play_at + preroll;
+                    let lead = play_at.saturating_duration_since(Instant::now());
+                    chunk_span.record("lead_ms", lead.as_millis() as u64);
 
-                        // Add to player queue
-                        player.enqueue(buffer);
+                    if first_chunk {
+                        debug!(
+                            parent: &chunk_span,
+                            "First chunk plays in {} ms (offset {:+} ms)",
+                            lead.as_millis(),
+                            args.playback_offset_ms
+                        );
+                        first_chunk = false;
+                        stream_start = Some(timestamp);
+                        progress.start(timestamp);
                     }
+
+                    let buffer = AudioBuffer {
+                        timestamp,
+                        play_at,
+                        samples,
+                        format: fmt.clone(),
+                    };
+
+                    if let Some(ref trace) = status.timing_trace {
+                        trace.send(TimingRecord::Enqueued(Enqueued {
+                            timestamp,
+                            arrival,
+                            play_at,
+                            duration,
+                            queue_depth: player.queued_buffers(),
+                            offset_us: sync_samples.offset_us(),
+                        }));
+                    }
+
+                    // Add to player queue, fading out ahead of --max-session
+                    let fade = status
+                        .ends_at
+                        .filter(|_| !args.bit_perfect)
+                        .and_then(|ends_at| {
+                            envelope::fade_out_before(play_at, duration, ends_at, session_limit::FADE)
+                        });
+                    match fade {
+                        Some(fade) => player.enqueue_with_envelope(buffer, fade),
+                        None => player.enqueue(buffer),
+                    };
                 }
             }
 
             else => break,
         }
+
+        if std::mem::take(&mut clear_due) {
+            player.fade_out();
+            set_state(status, PlaybackState::Stopped);
+            layout = None;
+            audio_format = None;
+            stream_span = Span::none();
+            endian_locked = None;
+            next_play_time = None;
+            continuity.reset();
+
+            // Send synchronized state to server
+            let state = client_state(status.volume, status.muted);
+            let _ = ws_tx.send_message(state).await;
+        }
     }
 
-    Ok(())
+    info!(
+        "Player at session end: playing={}, output open={}, {} buffers ({} ms) queued{}",
+        player.is_playing(),
+        player.output_open(),
+        player.queued_buffers(),
+        player.queued_duration().as_millis(),
+        player
+            .last_played_timestamp()
+            .map(|ts| format!(", played up to ts={}", ts))
+            .unwrap_or_default()
+    );
+
+    let wakes = player.wake_stats();
+    if let Some(p95) = wakes.percentile(95.0) {
+        info!(
+            "Write wake-up accuracy so far: p95 within {:?} over {} buffers ({})",
+            p95,
+            wakes.count(),
+            wakes
+        );
+    } else if wakes.count() > 0 {
+        warn!(
+            "Write wake-ups often more than 5 ms late ({}); try a larger --wake-spin-us",
+            wakes
+        );
+    }
+
+    // Totals since start, players replaced after an audio error included;
+    // --stats lines take their intervals from snapshots and leave these alone
+    let mut totals = status.counted;
+    totals.add(&player.stats().snapshot());
+    info!("Playback so far: {}", totals);
+
+    let device = player.device_stats();
+    if device.write_errors > 0 {
+        warn!(
+            "Output writes failed {} times so far ({} device disconnects)",
+            device.write_errors, device.disconnects
+        );
+    }
+
+    if player.seek_dropped() > 0 {
+        info!(
+            "Seeks dropped {} queued buffers so far",
+            player.seek_dropped()
+        );
+    }
+
+    let pool = player.sample_pool().stats();
+    if pool.hits + pool.misses > 0 {
+        info!("Sample buffer pool this session: {}", pool);
+    }
+
+    let overflow = audio_rx.stats();
+    if overflow.dropped_chunks > 0 || overflow.backpressure_waits > 0 {
+        warn!(
+            "Audio channel overflowed this session: {} chunks dropped, {} waits for room",
+            overflow.dropped_chunks, overflow.backpressure_waits
+        );
+    }
+
+    // Channels closed: report why the router stopped
+    match disconnect.await {
+        Ok(reason) => Err(reason.into()),
+        Err(_) => Ok(()),
+    }
 }
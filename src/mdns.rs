@@ -1,12 +1,180 @@
 // mDNS service discovery for Sendspin servers
+//
+// Where multicast is filtered but ordinary DNS works, `--dns-domain` adds a
+// unicast DNS-SD fallback: the server instances listed (PTR) under
+// `_sendspin-server._tcp.<domain>`, or failing that an SRV record on that name
+// itself. The record with the lowest priority (highest weight among equals)
+// wins, and the result comes back as "host:port" like a multicast one.
+//
+// Either way a server's IPv4 address is preferred, then a routable IPv6 one.
+// A link-local IPv6 address (fe80::) only works with the interface it was
+// seen on, which the lookup doesn't tell us, so it is used only when a single
+// interface has link-local addresses at all.
 
-use log::{debug, info};
+use crate::error::SendspinCliError;
+use hickory_resolver::error::ResolveError;
+use hickory_resolver::proto::rr::rdata::SRV;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::{Name, Resolver};
 use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::cmp::Reverse;
+use std::net::{IpAddr, SocketAddrV4, SocketAddrV6};
 use std::time::Duration;
+use tracing::{debug, info, warn};
 
-/// Discover Sendspin server via mDNS
+/// Service looked up under --dns-domain by unicast DNS-SD
+const DNS_SD_SERVICE: &str = "_sendspin-server._tcp";
+
+/// Format a discovered address as a usable "host:port"
+///
+/// IPv6 addresses are bracketed, and link-local ones carry the interface
+/// scope id (e.g. "[fe80::1%2]:8927") since they are ambiguous without it.
+pub fn format_server_address(addr: &IpAddr, port: u16, scope_id: Option<u32>) -> String {
+    match addr {
+        IpAddr::V4(v4) => SocketAddrV4::new(*v4, port).to_string(),
+        IpAddr::V6(v6) => {
+            let scope_id = if v6.is_unicast_link_local() {
+                scope_id.unwrap_or(0)
+            } else {
+                0
+            };
+            SocketAddrV6::new(*v6, port, 0, scope_id).to_string()
+        }
+    }
+}
+
+/// Indexes of the non-loopback interfaces with a link-local IPv6 address:
+/// the ones a link-local server address could be reached through
+fn link_local_interfaces() -> Vec<u32> {
+    let mut indexes: Vec<u32> = if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| {
+            !iface.is_loopback()
+                && matches!(iface.ip(), IpAddr::V6(v6) if v6.is_unicast_link_local())
+        })
+        .filter_map(|iface| iface.index)
+        .collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+    indexes
+}
+
+/// "host:port" for the best of a server's addresses: IPv4, then routable
+/// IPv6, then link-local IPv6 scoped to `interfaces` when that is a single
+/// one; None when only link-local addresses on an unknown interface remain
+fn select_address(addresses: &[IpAddr], port: u16, interfaces: &[u32]) -> Option<String> {
+    let link_local = |addr: &&IpAddr| matches!(addr, IpAddr::V6(v6) if v6.is_unicast_link_local());
+    if let Some(addr) = addresses
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or_else(|| addresses.iter().find(|a| !link_local(a)))
+    {
+        return Some(format_server_address(addr, port, None));
+    }
+    match (addresses.iter().find(link_local), interfaces) {
+        (Some(addr), &[scope_id]) => Some(format_server_address(addr, port, Some(scope_id))),
+        _ => None,
+    }
+}
+
+/// Discover Sendspin server via mDNS, then unicast DNS-SD under
+/// `dns_domain` if given and mDNS found nothing; with `multicast` false and
+/// a domain, only DNS-SD
 /// Returns server address in format "host:port"
-pub fn discover_sendspin_server() -> Result<String, Box<dyn std::error::Error>> {
+pub fn discover_sendspin_server(
+    dns_domain: Option<&str>,
+    multicast: bool,
+) -> Result<String, SendspinCliError> {
+    if let (false, Some(domain)) = (multicast, dns_domain) {
+        return discover_unicast(domain);
+    }
+    match (discover_multicast(), dns_domain) {
+        (Err(e), Some(domain)) => {
+            warn!("{}, trying unicast DNS-SD under {}", e, domain);
+            discover_unicast(domain)
+        }
+        (result, _) => result,
+    }
+}
+
+/// The preferred of a service's SRV records: lowest priority, then highest weight
+fn preferred_srv<'a>(records: impl IntoIterator<Item = &'a SRV>) -> Option<&'a SRV> {
+    records
+        .into_iter()
+        .min_by_key(|srv| (srv.priority(), Reverse(srv.weight())))
+}
+
+/// Unicast DNS-SD lookup of a server under `domain`
+fn discover_unicast(domain: &str) -> Result<String, SendspinCliError> {
+    let service = format!("{}.{}.", DNS_SD_SERVICE, domain.trim_end_matches('.'));
+    info!("Looking up {} by unicast DNS...", service);
+    // The blocking resolver starts a runtime of its own, which can't be
+    // nested in the caller's, so it gets a thread
+    let lookup = {
+        let service = service.clone();
+        std::thread::spawn(move || resolve_service(&service))
+    };
+    let found = lookup
+        .join()
+        .unwrap_or_else(|_| Err(ResolveError::from("DNS lookup thread panicked")))?;
+    let server = found.ok_or(SendspinCliError::NoDnsServer(service))?;
+    info!("Discovered Sendspin server by DNS-SD: {}", server);
+    Ok(server)
+}
+
+/// Resolve `service` to "host:port", None when nothing is listed under it
+fn resolve_service(service: &str) -> Result<Option<String>, ResolveError> {
+    let resolver = Resolver::from_system_conf()?;
+    let service_name = Name::from_ascii(service)?;
+
+    // DNS-SD instances first, then an SRV record on the service name itself
+    let mut names: Vec<Name> = match resolver.lookup(service_name.clone(), RecordType::PTR) {
+        Ok(lookup) => lookup
+            .iter()
+            .filter_map(|rdata| match rdata {
+                RData::PTR(ptr) => Some(ptr.0.clone()),
+                _ => None,
+            })
+            .collect(),
+        Err(e) => {
+            debug!("No DNS-SD instances under {}: {}", service, e);
+            Vec::new()
+        }
+    };
+    names.push(service_name);
+
+    for name in names {
+        let records = match resolver.srv_lookup(name.clone()) {
+            Ok(records) => records,
+            Err(e) => {
+                debug!("No SRV record for {}: {}", name, e);
+                continue;
+            }
+        };
+        let Some(srv) = preferred_srv(records.iter()) else {
+            continue;
+        };
+        let host = srv.target().to_utf8();
+        debug!("SRV {} → {}:{}", name, host, srv.port());
+        // Same preference as the multicast path; the host name if it has no
+        // usable address
+        let addresses: Vec<IpAddr> = match resolver.lookup_ip(srv.target().clone()) {
+            Ok(ips) => ips.iter().collect(),
+            Err(e) => {
+                debug!("No address for {}: {}", host, e);
+                Vec::new()
+            }
+        };
+        let server = select_address(&addresses, srv.port(), &link_local_interfaces())
+            .unwrap_or_else(|| format!("{}:{}", host.trim_end_matches('.'), srv.port()));
+        return Ok(Some(server));
+    }
+    Ok(None)
+}
+
+/// Browse for a server by multicast mDNS
+fn discover_multicast() -> Result<String, SendspinCliError> {
     info!("Starting mDNS discovery for Sendspin server...");
 
     // Create mDNS daemon
@@ -24,7 +192,7 @@ pub fn discover_sendspin_server() -> Result<String, Box<dyn std::error::Error>>
 
     let result = loop {
         if start.elapsed() >= timeout {
-            break Err("No Sendspin server found via mDNS after 5 seconds".into());
+            break Err(SendspinCliError::NoServerFound(timeout));
         }
 
         if let Ok(event) = receiver.recv_timeout(Duration::from_millis(100)) {
@@ -42,18 +210,17 @@ pub fn discover_sendspin_server() -> Result<String, Box<dyn std::error::Error>>
                     );
                     debug!("Addresses: {:?}", addresses);
 
-                    // Prefer IPv4 address
-                    if let Some(addr) = addresses.iter().find(|a| a.is_ipv4()) {
-                        let server = format!("{}:{}", addr, port);
-                        info!("Discovered Sendspin server: {}", server);
-                        break Ok(server);
-                    }
-
-                    // Fallback to any address
-                    if let Some(addr) = addresses.iter().next() {
-                        let server = format!("{}:{}", addr, port);
-                        info!("Discovered Sendspin server: {}", server);
-                        break Ok(server);
+                    let addresses: Vec<IpAddr> = addresses.iter().copied().collect();
+                    match select_address(&addresses, port, &link_local_interfaces()) {
+                        Some(server) => {
+                            info!("Discovered Sendspin server: {}", server);
+                            break Ok(server);
+                        }
+                        None if !addresses.is_empty() => warn!(
+                            "{} has only link-local addresses and more than one interface could reach them, skipping",
+                            info.get_fullname()
+                        ),
+                        None => {}
                     }
                 }
                 ServiceEvent::ServiceFound(type_name, fullname) => {
@@ -93,7 +260,7 @@ mod tests {
         // This test verifies mDNS discovery works
         // It may find a server (Ok) or timeout (Err) depending on network
         let start = std::time::Instant::now();
-        let result = discover_sendspin_server();
+        let result = discover_sendspin_server(None, true);
         let elapsed = start.elapsed();
 
         match result {
@@ -112,6 +279,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_format_ipv4_address() {
+        let addr: IpAddr = "192.168.1.100".parse().unwrap();
+        assert_eq!(
+            format_server_address(&addr, 8927, None),
+            "192.168.1.100:8927"
+        );
+        // Scope ids only apply to IPv6
+        assert_eq!(
+            format_server_address(&addr, 8927, Some(3)),
+            "192.168.1.100:8927"
+        );
+    }
+
+    #[test]
+    fn test_format_global_ipv6_address() {
+        let addr: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            format_server_address(&addr, 8927, None),
+            "[2001:db8::1]:8927"
+        );
+        // Global addresses never carry a scope
+        assert_eq!(
+            format_server_address(&addr, 8927, Some(3)),
+            "[2001:db8::1]:8927"
+        );
+    }
+
+    #[test]
+    fn test_format_link_local_ipv6_address() {
+        let addr: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(
+            format_server_address(&addr, 8927, Some(3)),
+            "[fe80::1%3]:8927"
+        );
+        assert_eq!(format_server_address(&addr, 8927, None), "[fe80::1]:8927");
+
+        // Result must parse back as a socket address
+        let parsed: std::net::SocketAddr =
+            format_server_address(&addr, 8927, Some(3)).parse().unwrap();
+        assert_eq!(parsed.port(), 8927);
+    }
+
+    #[test]
+    fn test_select_address() {
+        let ips =
+            |list: &[&str]| -> Vec<IpAddr> { list.iter().map(|a| a.parse().unwrap()).collect() };
+
+        // IPv4 first, then routable IPv6, whatever the interfaces
+        let all = ips(&["fe80::1", "2001:db8::1", "192.168.1.10"]);
+        assert_eq!(
+            select_address(&all, 8927, &[2, 3]).unwrap(),
+            "192.168.1.10:8927"
+        );
+        let v6 = ips(&["fe80::1", "fd00::1"]);
+        assert_eq!(
+            select_address(&v6, 8927, &[2, 3]).unwrap(),
+            "[fd00::1]:8927"
+        );
+
+        // Link-local only: scoped when one interface could have seen it
+        let link_local = ips(&["fe80::1"]);
+        assert_eq!(
+            select_address(&link_local, 8927, &[2]).unwrap(),
+            "[fe80::1%2]:8927"
+        );
+        // With docker0, veth or wlan0 as well there's no telling which
+        assert_eq!(select_address(&link_local, 8927, &[2, 5]), None);
+        assert_eq!(select_address(&link_local, 8927, &[]), None);
+        assert_eq!(select_address(&[], 8927, &[2]), None);
+    }
+
+    #[test]
+    fn test_preferred_srv_record() {
+        let host = Name::from_ascii("music.example.com.").unwrap();
+        let records = [
+            SRV::new(20, 100, 8927, host.clone()),
+            SRV::new(10, 5, 8928, host.clone()),
+            SRV::new(10, 50, 8929, host.clone()),
+        ];
+        assert_eq!(preferred_srv(&records).unwrap().port(), 8929);
+        assert!(preferred_srv(&[]).is_none());
+    }
+
     #[test]
     fn test_service_type_constant() {
         // Verify the service type format is correct
@@ -0,0 +1,160 @@
+// Mono Downmix
+//
+// A single speaker fed one channel of a stereo stream loses half the mix.
+// `--mono` sums every frame to one signal and writes it to all the channels
+// the output was opened with, so any of them can drive the speaker.
+//
+// Surround sources are first folded to stereo the usual way (centre and
+// surrounds at -3 dB into their side, LFE dropped, channel order as in WAV:
+// FL FR FC LFE BL BR SL SR), normalized so the fold alone can't exceed full
+// scale. Left and right are then summed at `--mono-gain-db` (-6 dB by
+// default), so a hard-panned full-scale source stays below full scale and
+// an identical left and right come out at the original level.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use sendspin::audio::Sample;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::sync::Arc;
+
+/// Gain applied to the left + right sum by default
+pub const DEFAULT_GAIN_DB: f32 = -6.0;
+
+/// Contribution of each channel to the left and right of the stereo fold
+pub fn stereo_fold(channels: usize) -> Vec<(f32, f32)> {
+    const L: (f32, f32) = (1.0, 0.0);
+    const R: (f32, f32) = (0.0, 1.0);
+    const C: (f32, f32) = (FRAC_1_SQRT_2, FRAC_1_SQRT_2);
+    const LFE: (f32, f32) = (0.0, 0.0);
+    const SL: (f32, f32) = (FRAC_1_SQRT_2, 0.0);
+    const SR: (f32, f32) = (0.0, FRAC_1_SQRT_2);
+    let fold = match channels {
+        1 => vec![C],
+        2 => vec![L, R],
+        3 => vec![L, R, C],
+        4 => vec![L, R, SL, SR],
+        5 => vec![L, R, C, SL, SR],
+        6 => vec![L, R, C, LFE, SL, SR],
+        8 => vec![L, R, C, LFE, SL, SR, SL, SR],
+        // Unknown layout: every channel counts the same on both sides
+        n => vec![(0.5, 0.5); n],
+    };
+    let norm = fold.iter().map(|(left, _)| left).sum::<f32>().max(1.0);
+    fold.into_iter()
+        .map(|(left, right)| (left / norm, right / norm))
+        .collect()
+}
+
+/// Weight of each channel in the mono signal
+pub fn channel_gains(channels: usize, gain_db: f32) -> Vec<f32> {
+    if channels <= 1 {
+        return vec![1.0; channels];
+    }
+    let gain = 10f32.powf(gain_db / 20.0);
+    stereo_fold(channels)
+        .into_iter()
+        .map(|(left, right)| (left + right) * gain)
+        .collect()
+}
+
+/// Downmix interleaved samples, repeating the result on every channel
+pub fn downmix(samples: &[Sample], channels: usize, gain_db: f32) -> Arc<[Sample]> {
+    if channels <= 1 {
+        return Arc::from(samples);
+    }
+    let gains = channel_gains(channels, gain_db);
+    let mut out = samples.to_vec();
+    for frame in out.chunks_exact_mut(channels) {
+        let mono: f32 = frame
+            .iter()
+            .zip(&gains)
+            .map(|(sample, gain)| sample.0 as f32 * gain)
+            .sum();
+        let mono = Sample((mono.round() as i32).clamp(SAMPLE_MIN, SAMPLE_MAX));
+        frame.fill(mono);
+    }
+    Arc::from(out)
+}
+
+/// Downmix for the f32 pipeline, in place
+pub fn downmix_f32(samples: &mut [f32], channels: usize, gain_db: f32) {
+    if channels <= 1 {
+        return;
+    }
+    let gains = channel_gains(channels, gain_db);
+    for frame in samples.chunks_exact_mut(channels) {
+        let mono: f32 = frame.iter().zip(&gains).map(|(x, gain)| x * gain).sum();
+        frame.fill(mono);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(samples: &[Sample]) -> Vec<i32> {
+        samples.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn test_gain_math() {
+        let half = 10f32.powf(-6.0 / 20.0);
+        assert_eq!(channel_gains(2, -6.0), vec![half, half]);
+        assert_eq!(channel_gains(2, 0.0), vec![1.0, 1.0]);
+        assert_eq!(channel_gains(1, -6.0), vec![1.0]);
+
+        // 5.1: centre counts on both sides, LFE not at all, and the fold is
+        // normalized by 1 + 2 × 0.707 per side
+        let gains = channel_gains(6, 0.0);
+        let norm = 1.0 + 2.0 * FRAC_1_SQRT_2;
+        assert!((gains[0] - 1.0 / norm).abs() < 1e-6);
+        assert!((gains[2] - 2.0 * FRAC_1_SQRT_2 / norm).abs() < 1e-6);
+        assert_eq!(gains[3], 0.0);
+        assert!((gains[4] - FRAC_1_SQRT_2 / norm).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_stereo_to_both_channels() {
+        let out = downmix(
+            &[Sample(1000), Sample(3000), Sample(-500), Sample(500)],
+            2,
+            0.0,
+        );
+        assert_eq!(values(&out), vec![4000, 4000, 0, 0]);
+        let out = downmix(&[Sample(1000), Sample(3000)], 2, -6.0206);
+        assert_eq!(values(&out), vec![2000, 2000]);
+    }
+
+    #[test]
+    fn test_hard_panned_full_scale_does_not_clip() {
+        let input = [Sample(SAMPLE_MAX), Sample(0), Sample(0), Sample(SAMPLE_MIN)];
+        let out = downmix(&input, 2, DEFAULT_GAIN_DB);
+        let expected = (SAMPLE_MAX as f32 * 10f32.powf(-0.3)).round() as i32;
+        assert_eq!(values(&out[..2]), vec![expected, expected]);
+        assert!(out[2].0 > SAMPLE_MIN && out[2].0 == out[3].0);
+
+        let mut pcm = [1.0, 0.0, 0.0, -1.0];
+        downmix_f32(&mut pcm, 2, DEFAULT_GAIN_DB);
+        assert!(pcm.iter().all(|x| x.abs() < 0.51));
+        assert_eq!(pcm[0], pcm[1]);
+    }
+
+    #[test]
+    fn test_surround_fold() {
+        // 5.1 frame with everything at full scale stays within range
+        let frame = [Sample(SAMPLE_MAX); 6];
+        let out = downmix(&frame, 6, 0.0);
+        assert!(out.iter().all(|s| s.0 == out[0].0 && s.0 <= SAMPLE_MAX));
+
+        // LFE alone is dropped
+        let mut lfe = [Sample(0); 6];
+        lfe[3] = Sample(1_000_000);
+        assert!(downmix(&lfe, 6, 0.0).iter().all(|s| s.0 == 0));
+
+        // Odd channel counts still fill every channel of every frame
+        let input: Vec<Sample> = (0..9).map(|i| Sample(i * 1000)).collect();
+        let out = downmix(&input, 3, 0.0);
+        for frame in out.chunks(3) {
+            assert!(frame.iter().all(|s| s.0 == frame[0].0));
+        }
+    }
+}
@@ -0,0 +1,369 @@
+// Format Negotiation
+//
+// The hello lists the PCM formats this client accepts and the server picks
+// one per stream. When the output device can be probed, only the rates it
+// plays natively are advertised, so the server sends something the device
+// can take as-is. If a stream still arrives at a rate the device lacks (or
+// none of our rates are native to it), the player opens the device at the
+// closest rate it does support and converts.
+//
+// The hello also advertises a buffer capacity in bytes, which the server
+// uses to decide how far ahead it sends. The player's queue is bounded by
+// that same number, counted the way the server counts it (PCM bytes as they
+// arrive on the wire), so we never claim room we don't have.
+//
+// The device can change under a running client (a USB DAC unplugged and
+// another one plugged in). The player probes the device again when it comes
+// back; the hello has no update message, so when the stream playing now
+// isn't native to the new device the client reconnects to advertise the new
+// rates, and otherwise the next connection does. A reconnect that would
+// advertise the same rates as before can't get a better stream, so the
+// client converts instead.
+//
+// Shared-mode hosts (CoreAudio, PulseAudio, PipeWire) report a wide range of
+// rates but mix everything at one rate and resample the rest themselves, with
+// a converter we don't choose. `--native-format-only` treats the device as
+// playing only the rate its mixer runs at: the hello advertises that rate
+// alone, and a stream at any other rate is converted here with
+// `--resample-quality` before it reaches the device.
+//
+// `--only-codec` narrows the list to one codec, to check how the server
+// falls back when a client accepts nothing else. Only codecs this build can
+// decode are allowed.
+
+use clap::ValueEnum;
+use sendspin::protocol::messages::AudioFormatSpec;
+use std::fmt;
+use std::time::Duration;
+
+/// Sample rates we decode, most preferred first
+pub const CANDIDATE_RATES: [u32; 4] = [48000, 44100, 96000, 88200];
+
+/// Bit depths we decode, most preferred first
+pub const CANDIDATE_BIT_DEPTHS: [u8; 2] = [24, 16];
+
+/// Channel count we advertise
+pub const CHANNELS: u8 = 2;
+
+/// Codec names accepted by `--only-codec`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CodecName {
+    Pcm,
+    Flac,
+    Opus,
+}
+
+impl CodecName {
+    /// Name used in the protocol's format specs
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CodecName::Pcm => "pcm",
+            CodecName::Flac => "flac",
+            CodecName::Opus => "opus",
+        }
+    }
+}
+
+/// Codecs this build has a decoder for
+pub const DECODABLE_CODECS: [CodecName; 1] = [CodecName::Pcm];
+
+/// Buffer capacity advertised (and held) when `--buffer-capacity` isn't set
+pub const DEFAULT_BUFFER_CAPACITY: u32 = 1024 * 1024;
+
+/// PCM bytes per second of the largest format we advertise
+pub fn max_byte_rate() -> u64 {
+    let rate = CANDIDATE_RATES.into_iter().max().unwrap_or(48000) as u64;
+    let depth = CANDIDATE_BIT_DEPTHS.into_iter().max().unwrap_or(16) as u64;
+    rate * CHANNELS as u64 * depth.div_ceil(8)
+}
+
+/// Longest audio a queue of `capacity` bytes can hold: the smallest format
+/// we advertise fills it slowest
+pub fn max_queue_duration(capacity: u32) -> Duration {
+    let rate = CANDIDATE_RATES.into_iter().min().unwrap_or(48000) as u64;
+    let depth = CANDIDATE_BIT_DEPTHS.into_iter().min().unwrap_or(16) as u64;
+    let byte_rate = rate * CHANNELS as u64 * depth.div_ceil(8);
+    Duration::from_millis(capacity as u64 * 1000 / byte_rate)
+}
+
+/// Bytes needed to hold `prebuffer` of audio in the largest format
+pub fn prebuffer_bytes(prebuffer: Duration) -> u64 {
+    (max_byte_rate() as u128 * prebuffer.as_millis() / 1000) as u64
+}
+
+/// Capacity to advertise and bound the queue with: the requested value, or
+/// the default grown to fit the prebuffer
+pub fn buffer_capacity(requested: Option<u32>, prebuffer: Duration) -> u32 {
+    requested.unwrap_or_else(|| {
+        let needed = prebuffer_bytes(prebuffer).min(u32::MAX as u64) as u32;
+        DEFAULT_BUFFER_CAPACITY.max(needed)
+    })
+}
+
+/// Sample rate ranges an output device supports for our channel count
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceRates {
+    pub ranges: Vec<(u32, u32)>, // Inclusive min/max
+}
+
+impl DeviceRates {
+    pub fn supports(&self, rate: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(min, max)| min <= rate && rate <= max)
+    }
+
+    /// A device that plays exactly one rate
+    pub fn only(rate: u32) -> Self {
+        DeviceRates {
+            ranges: vec![(rate, rate)],
+        }
+    }
+
+    /// Rate to open the device at for a stream: its own rate when supported,
+    /// otherwise the closest one the device has (higher wins a tie)
+    pub fn output_rate(&self, stream_rate: u32) -> u32 {
+        if self.ranges.is_empty() || self.supports(stream_rate) {
+            return stream_rate;
+        }
+        self.ranges
+            .iter()
+            .map(|&(min, max)| stream_rate.clamp(min, max))
+            .min_by_key(|&rate| (rate.abs_diff(stream_rate), u32::MAX - rate))
+            .unwrap_or(stream_rate)
+    }
+}
+
+/// Rates for the hello: the candidates the device plays natively, or all of
+/// them when it can't be probed or supports none (they get converted)
+fn advertised_rates(device: Option<&DeviceRates>) -> Vec<u32> {
+    let native: Vec<u32> = CANDIDATE_RATES
+        .into_iter()
+        .filter(|&rate| device.is_none_or(|d| d.supports(rate)))
+        .collect();
+    if native.is_empty() {
+        CANDIDATE_RATES.to_vec()
+    } else {
+        native
+    }
+}
+
+/// PCM formats for the hello, at `advertised_rates`
+pub fn supported_formats(device: Option<&DeviceRates>) -> Vec<AudioFormatSpec> {
+    advertised_rates(device)
+        .into_iter()
+        .flat_map(|sample_rate| {
+            CANDIDATE_BIT_DEPTHS
+                .into_iter()
+                .map(move |bit_depth| AudioFormatSpec {
+                    codec: "pcm".to_string(),
+                    channels: CHANNELS,
+                    sample_rate,
+                    bit_depth,
+                })
+        })
+        .collect()
+}
+
+/// Candidate rates a device accepts but doesn't run at, so the OS would
+/// resample them (what `--native-format-only` converts here instead)
+pub fn os_resampled(accepted: &DeviceRates, native: u32) -> Vec<u32> {
+    CANDIDATE_RATES
+        .into_iter()
+        .filter(|&rate| rate != native && accepted.supports(rate))
+        .collect()
+}
+
+/// Whether the device changing to `rates` is worth a reconnect while a
+/// stream at `stream_rate` plays: only when the new device can't play it
+/// natively, and a new hello would offer other rates than the one this
+/// connection made (with `advertised`)
+pub fn renegotiate(
+    advertised: Option<&DeviceRates>,
+    rates: &DeviceRates,
+    stream_rate: Option<u32>,
+) -> bool {
+    stream_rate.is_some_and(|rate| !rates.supports(rate))
+        && advertised_rates(Some(rates)) != advertised_rates(advertised)
+}
+
+/// The output device now plays rates the server wasn't told about; the
+/// connection ends so the next hello can advertise them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilitiesChanged {
+    pub rates: DeviceRates,
+}
+
+impl fmt::Display for CapabilitiesChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "output device changed (now {:?} Hz), renegotiating formats",
+            self.rates.ranges
+        )
+    }
+}
+
+impl std::error::Error for CapabilitiesChanged {}
+
+/// Keep only the formats of one codec (`--only-codec`)
+pub fn only_codec(
+    formats: Vec<AudioFormatSpec>,
+    codec: CodecName,
+) -> Result<Vec<AudioFormatSpec>, String> {
+    let name = codec.as_str();
+    if !DECODABLE_CODECS.contains(&codec) {
+        let available: Vec<&str> = DECODABLE_CODECS.iter().map(|c| c.as_str()).collect();
+        return Err(format!(
+            "--only-codec {}: this build has no {} decoder (available: {})",
+            name,
+            name,
+            available.join(", ")
+        ));
+    }
+    let formats: Vec<AudioFormatSpec> = formats
+        .into_iter()
+        .filter(|spec| spec.codec == name)
+        .collect();
+    if formats.is_empty() {
+        return Err(format!(
+            "--only-codec {}: no {} format to advertise",
+            name, name
+        ));
+    }
+    Ok(formats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertised(device: Option<&DeviceRates>) -> Vec<(u32, u8)> {
+        supported_formats(device)
+            .iter()
+            .map(|spec| (spec.sample_rate, spec.bit_depth))
+            .collect()
+    }
+
+    #[test]
+    fn test_unknown_device_advertises_everything() {
+        let formats = advertised(None);
+        assert_eq!(formats.len(), CANDIDATE_RATES.len() * 2);
+        assert_eq!(formats[0], (48000, 24));
+        assert_eq!(formats[1], (48000, 16));
+    }
+
+    #[test]
+    fn test_fixed_44k1_dac() {
+        let dac = DeviceRates {
+            ranges: vec![(44100, 44100)],
+        };
+        assert_eq!(advertised(Some(&dac)), vec![(44100, 24), (44100, 16)]);
+        assert_eq!(dac.output_rate(44100), 44100);
+        // A 48 kHz stream anyway: convert to what the DAC can do
+        assert_eq!(dac.output_rate(48000), 44100);
+    }
+
+    #[test]
+    fn test_range_device_keeps_order() {
+        let device = DeviceRates {
+            ranges: vec![(8000, 48000)],
+        };
+        assert_eq!(
+            advertised(Some(&device)),
+            vec![(48000, 24), (48000, 16), (44100, 24), (44100, 16)]
+        );
+        assert_eq!(device.output_rate(96000), 48000);
+        assert_eq!(device.output_rate(22050), 22050);
+    }
+
+    #[test]
+    fn test_device_without_candidate_rates() {
+        // Only 32 kHz: advertise the usual list and convert everything
+        let device = DeviceRates {
+            ranges: vec![(32000, 32000)],
+        };
+        assert_eq!(advertised(Some(&device)).len(), CANDIDATE_RATES.len() * 2);
+        assert_eq!(device.output_rate(48000), 32000);
+    }
+
+    #[test]
+    fn test_native_rate_only() {
+        // A PulseAudio sink: accepts anything, mixes at 44.1 kHz
+        let accepted = DeviceRates {
+            ranges: vec![(1, 384000)],
+        };
+        assert_eq!(os_resampled(&accepted, 44100), vec![48000, 96000, 88200]);
+
+        let native = DeviceRates::only(44100);
+        assert_eq!(advertised(Some(&native)), vec![(44100, 24), (44100, 16)]);
+        assert_eq!(native.output_rate(96000), 44100);
+        // Nothing the OS would touch
+        assert!(os_resampled(&native, 44100).is_empty());
+    }
+
+    #[test]
+    fn test_output_rate_prefers_closest_then_higher() {
+        let device = DeviceRates {
+            ranges: vec![(44100, 44100), (96000, 96000)],
+        };
+        assert_eq!(device.output_rate(48000), 44100);
+        assert_eq!(device.output_rate(88200), 96000);
+        assert_eq!(DeviceRates::default().output_rate(48000), 48000);
+    }
+
+    #[test]
+    fn test_renegotiate_only_when_it_helps() {
+        let any = DeviceRates {
+            ranges: vec![(8000, 192000)],
+        };
+        let dac_44k1 = DeviceRates::only(44100);
+        let dac_48k = DeviceRates::only(48000);
+
+        // Nothing playing, or the new device plays the stream as it is
+        assert!(!renegotiate(Some(&any), &dac_44k1, None));
+        assert!(!renegotiate(Some(&any), &dac_44k1, Some(44100)));
+        // It can't, and a new hello would offer only what it can
+        assert!(renegotiate(Some(&any), &dac_44k1, Some(48000)));
+        assert!(renegotiate(None, &dac_44k1, Some(96000)));
+        // It can't, but the hello already offered exactly its rates
+        assert!(!renegotiate(Some(&dac_44k1), &dac_44k1, Some(48000)));
+        assert!(!renegotiate(None, &DeviceRates::only(32000), Some(48000)));
+        // Back from a 48 kHz-only device to one without it
+        assert!(renegotiate(Some(&dac_48k), &dac_44k1, Some(48000)));
+    }
+
+    #[test]
+    fn test_buffer_capacity() {
+        // 96 kHz, stereo, 24-bit
+        assert_eq!(max_byte_rate(), 576_000);
+        assert_eq!(prebuffer_bytes(Duration::from_millis(500)), 288_000);
+
+        let small = Duration::from_millis(20);
+        assert_eq!(buffer_capacity(None, small), DEFAULT_BUFFER_CAPACITY);
+        // A long prebuffer must fit in what we advertise
+        let long = Duration::from_secs(3);
+        assert_eq!(buffer_capacity(None, long), 1_728_000);
+        // An explicit value is used as-is
+        assert_eq!(buffer_capacity(Some(65536), long), 65536);
+
+        // 44.1 kHz, stereo, 16-bit makes the same bytes last longest
+        assert_eq!(max_queue_duration(176_400), Duration::from_secs(1));
+        assert_eq!(
+            max_queue_duration(DEFAULT_BUFFER_CAPACITY),
+            Duration::from_millis(5944)
+        );
+    }
+
+    #[test]
+    fn test_only_codec() {
+        let pcm = only_codec(supported_formats(None), CodecName::Pcm).unwrap();
+        assert_eq!(pcm.len(), CANDIDATE_RATES.len() * 2);
+        assert!(pcm.iter().all(|spec| spec.codec == "pcm"));
+
+        // No decoder in this build: refuse rather than advertise it
+        let err = only_codec(supported_formats(None), CodecName::Flac).unwrap_err();
+        assert!(err.contains("no flac decoder"), "{}", err);
+        assert!(only_codec(supported_formats(None), CodecName::Opus).is_err());
+    }
+}
@@ -0,0 +1,1178 @@
+// Audio Output Backends
+//
+// The playback thread writes processed samples through the OutputBackend
+// trait, so it doesn't care which audio API sits behind it:
+// - cpal (default, all platforms); with `--scheduling callback` the device's
+//   callback pulls from a FIFO and writes are placed by play time (callback.rs)
+// - null: discards samples at the real-time rate, for headless machines and CI
+// - file: raw little-endian PCM at the stream's bit depth, for checking what
+//   the player hands to a device (e.g. that --bit-perfect leaves it untouched)
+// - ALSA opened directly (Linux, `alsa-backend` feature), for devices such as
+//   `hw:CARD=DAC,DEV=0` that need explicit access type, period and buffer sizes
+// - WASAPI exclusive mode opened directly (Windows, `--exclusive`), since
+//   cpal can only open WASAPI streams in shared mode
+//
+// A Recorder set in the config replaces whichever backend was chosen with an
+// in-memory one paced like null, so tests can drive the playback thread and
+// look at every write it made, and when.
+
+use crate::callback::{Aligner, CallbackOutput};
+use crate::device::{self, DeviceBuffer};
+use crate::float;
+use crate::negotiate::DeviceRates;
+use clap::ValueEnum;
+use sendspin::audio::{AudioFormat, Sample};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Output backend selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputBackendKind {
+    #[default]
+    Cpal,
+    Alsa,
+    Null,
+    File,
+}
+
+/// ALSA transfer method
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum AlsaAccess {
+    #[default]
+    Rw,
+    Mmap,
+}
+
+/// How buffers are timed onto the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Scheduling {
+    /// Sleep until each buffer is due, then write it
+    #[default]
+    Write,
+    /// The device callback pulls from a FIFO; buffers are queued early,
+    /// placed by silence (cpal and file backends)
+    Callback,
+}
+
+/// How to open the audio device
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfig {
+    pub backend: OutputBackendKind,
+    pub host: Option<String>,   // cpal host name, default host if unset
+    pub device: Option<String>, // ALSA device string, passed through verbatim
+    pub access: AlsaAccess,     // ALSA only
+    pub period_frames: Option<usize>, // ALSA only, device default if unset
+    pub buffer_frames: Option<usize>, // ALSA only, device default if unset
+    pub require_audio: bool,    // Fail instead of falling back to null
+    pub device_buffer: Option<DeviceBuffer>, // cpal only, device default if unset
+    pub exclusive: bool,        // WASAPI exclusive mode instead of cpal, Windows only
+    pub file: Option<PathBuf>,  // file backend only, rewritten each time it opens
+    pub scheduling: Scheduling,
+    pub native_only: bool, // cpal only: open at the device's native rate, convert the rest here
+    pub recorder: Option<Recorder>, // Record writes in memory instead of opening a device
+}
+
+/// Sample format an opened device takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeviceFormat {
+    I16,
+    #[default]
+    I24, // 24-bit in the low bits of an i32
+    I32,
+    F32, // Full scale = 1.0
+}
+
+impl DeviceFormat {
+    /// The format for a cpal sample format, None when the player can't feed it
+    pub fn from_cpal(format: cpal::SampleFormat) -> Option<Self> {
+        match format {
+            cpal::SampleFormat::I16 => Some(DeviceFormat::I16),
+            cpal::SampleFormat::I32 => Some(DeviceFormat::I32),
+            cpal::SampleFormat::F32 => Some(DeviceFormat::F32),
+            _ => None,
+        }
+    }
+
+    /// Bits of resolution the device keeps (f32 has a 24-bit mantissa)
+    pub fn bits(self) -> u8 {
+        match self {
+            DeviceFormat::I16 => 16,
+            DeviceFormat::I24 | DeviceFormat::I32 | DeviceFormat::F32 => 24,
+        }
+    }
+}
+
+/// Interleaved samples in a device's format
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceSamples {
+    I16(Vec<i16>),
+    I24(Vec<i32>),
+    I32(Vec<i32>),
+    F32(Vec<f32>),
+}
+
+/// Convert decoded (24-bit integer) samples to what the device takes
+pub fn to_device(samples: &[Sample], format: DeviceFormat) -> DeviceSamples {
+    match format {
+        DeviceFormat::I16 => DeviceSamples::I16(to_i16(samples)),
+        DeviceFormat::I24 => DeviceSamples::I24(samples.iter().map(|s| s.0).collect()),
+        DeviceFormat::I32 => DeviceSamples::I32(to_i32(samples)),
+        DeviceFormat::F32 => DeviceSamples::F32(float::to_f32(samples)),
+    }
+}
+
+/// Convert samples from the player's float path to what the device takes
+pub fn f32_to_device(samples: &[f32], format: DeviceFormat) -> DeviceSamples {
+    match format {
+        DeviceFormat::F32 => DeviceSamples::F32(samples.to_vec()),
+        other => to_device(&float::from_f32(samples), other),
+    }
+}
+
+/// Destination for processed audio
+pub trait OutputBackend {
+    fn name(&self) -> &'static str;
+
+    /// Delay between writing a sample and hearing it, when known
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Write interleaved samples, blocking until the device accepts them
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Sample format the device was opened with; on F32 the player
+    /// processes in float and writes with `write_f32`
+    fn sample_format(&self) -> DeviceFormat {
+        DeviceFormat::I24
+    }
+
+    /// Bits per sample the device keeps (the player dithers down to this)
+    fn bit_depth(&self) -> u8 {
+        self.sample_format().bits()
+    }
+
+    /// Whether writes are placed by `start_at` instead of by when they're
+    /// made, so the caller doesn't wait for a buffer's time
+    fn places_writes(&self) -> bool {
+        false
+    }
+
+    /// Make the next write start playing at `at`, padding with silence as
+    /// needed (backends that place writes)
+    fn start_at(&mut self, _at: Instant) {}
+
+    /// Write interleaved f32 samples (full scale = 1.0)
+    fn write_f32(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+        self.write(&Arc::from(float::from_f32(samples)))
+    }
+}
+
+/// Open the configured backend for a stream format
+pub fn open(
+    config: &OutputConfig,
+    format: AudioFormat,
+) -> Result<Box<dyn OutputBackend>, Box<dyn std::error::Error>> {
+    if let Some(ref recorder) = config.recorder {
+        return Ok(Box::new(RecordingOutput::new(recorder.clone(), &format)));
+    }
+    if config.device_buffer.is_some() && config.backend != OutputBackendKind::Cpal {
+        warn!(
+            "--device-buffer is ignored by the {:?} backend",
+            config.backend
+        );
+    }
+    if config.scheduling == Scheduling::Callback
+        && matches!(
+            config.backend,
+            OutputBackendKind::Alsa | OutputBackendKind::Null
+        )
+    {
+        warn!(
+            "--scheduling callback is ignored by the {:?} backend",
+            config.backend
+        );
+    }
+    match config.backend {
+        OutputBackendKind::Cpal => {
+            if config.device.is_some() {
+                warn!("--alsa-device is ignored by the cpal backend");
+            }
+            if config.exclusive {
+                return open_exclusive(config, &format);
+            }
+            if config.scheduling == Scheduling::Callback {
+                if config.device_buffer.is_some() {
+                    warn!("--device-buffer is ignored with --scheduling callback");
+                }
+                return Ok(Box::new(CallbackOutput::open(
+                    config.host.as_deref(),
+                    &format,
+                )?));
+            }
+            if let Some(ref host) = config.host {
+                let host = device::open_host(host)?;
+                return Ok(Box::new(device::HostOutput::open(
+                    &host,
+                    &format,
+                    config.device_buffer,
+                )?));
+            }
+            // Built here rather than by sendspin, so samples are converted to
+            // whatever format the device was actually opened with
+            match device::HostOutput::open(&cpal::default_host(), &format, config.device_buffer) {
+                Ok(out) => Ok(Box::new(out)),
+                Err(e) if !config.require_audio => {
+                    warn!("==============================================================");
+                    warn!("No usable audio device ({}).", e);
+                    warn!("Falling back to the null backend: audio will NOT be heard.");
+                    warn!("Pass --require-audio to fail instead.");
+                    warn!("==============================================================");
+                    Ok(Box::new(NullOutput::new(&format)))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        OutputBackendKind::Null => Ok(Box::new(NullOutput::new(&format))),
+        OutputBackendKind::File => {
+            let path = config
+                .file
+                .as_ref()
+                .ok_or("the file backend needs --output-file")?;
+            let out = FileOutput::create(path, &format)?;
+            Ok(Box::new(match config.scheduling {
+                Scheduling::Write => out,
+                Scheduling::Callback => out.scheduled(&format),
+            }))
+        }
+        #[cfg(all(target_os = "linux", feature = "alsa-backend"))]
+        OutputBackendKind::Alsa => Ok(Box::new(alsa_output::AlsaOutput::open(config, &format)?)),
+        #[cfg(not(all(target_os = "linux", feature = "alsa-backend")))]
+        OutputBackendKind::Alsa => {
+            Err("ALSA output needs Linux and a build with the alsa-backend feature".into())
+        }
+    }
+}
+
+/// The default WASAPI device opened in exclusive mode at the stream's rate
+#[cfg(windows)]
+fn open_exclusive(
+    config: &OutputConfig,
+    format: &AudioFormat,
+) -> Result<Box<dyn OutputBackend>, Box<dyn std::error::Error>> {
+    if config.host.is_some() || config.device_buffer.is_some() {
+        warn!("--audio-host and --device-buffer are ignored with --exclusive");
+    }
+    if config.scheduling == Scheduling::Callback {
+        warn!("--scheduling callback is ignored with --exclusive");
+    }
+    Ok(Box::new(wasapi_output::WasapiOutput::open(format)?))
+}
+
+#[cfg(not(windows))]
+fn open_exclusive(
+    _config: &OutputConfig,
+    _format: &AudioFormat,
+) -> Result<Box<dyn OutputBackend>, Box<dyn std::error::Error>> {
+    Err("--exclusive is only supported on Windows (WASAPI)".into())
+}
+
+/// Sample rates the default WASAPI device accepts in exclusive mode, for the
+/// hello (the shared-mode rates cpal reports don't apply)
+#[cfg(windows)]
+pub fn exclusive_rates(channels: u8) -> Result<DeviceRates, Box<dyn std::error::Error>> {
+    wasapi_output::probe_rates(channels)
+}
+
+#[cfg(not(windows))]
+pub fn exclusive_rates(_channels: u8) -> Result<DeviceRates, Box<dyn std::error::Error>> {
+    Err("--exclusive is only supported on Windows (WASAPI)".into())
+}
+
+/// Sink that consumes audio at the rate a real device would
+pub struct NullOutput {
+    sample_rate: u32,
+    channels: usize,
+    started: Instant,
+    frames_written: u64,
+}
+
+impl NullOutput {
+    pub fn new(format: &AudioFormat) -> Self {
+        NullOutput {
+            sample_rate: format.sample_rate.max(1),
+            channels: (format.channels as usize).max(1),
+            started: Instant::now(),
+            frames_written: 0,
+        }
+    }
+}
+
+impl OutputBackend for NullOutput {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Instant::now();
+        let played =
+            Duration::from_micros(self.frames_written * 1_000_000 / self.sample_rate as u64);
+        if now > self.started + played + Duration::from_millis(100) {
+            // Writer fell behind (underrun): restart the clock instead of catching up
+            self.started = now;
+            self.frames_written = 0;
+        }
+
+        self.frames_written += (samples.len() / self.channels) as u64;
+        let done_at = self.started
+            + Duration::from_micros(self.frames_written * 1_000_000 / self.sample_rate as u64);
+        let now = Instant::now();
+        if done_at > now {
+            std::thread::sleep(done_at - now);
+        }
+        Ok(())
+    }
+}
+
+/// One write an output took
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub at: Instant, // When the write was made
+    pub format: AudioFormat,
+    pub samples: Vec<Sample>,
+}
+
+/// Shared log of the writes made to recording outputs; clones see the same log
+#[derive(Debug, Clone, Default)]
+pub struct Recorder(Arc<Mutex<Vec<Recorded>>>);
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every write so far, oldest first
+    pub fn writes(&self) -> Vec<Recorded> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// All samples written so far, in order
+    pub fn samples(&self) -> Vec<Sample> {
+        let writes = self.0.lock().unwrap();
+        writes
+            .iter()
+            .flat_map(|w| w.samples.iter().copied())
+            .collect()
+    }
+
+    /// Frames written so far, over all writes
+    pub fn frames(&self) -> usize {
+        let writes = self.0.lock().unwrap();
+        writes
+            .iter()
+            .map(|w| w.samples.len() / (w.format.channels as usize).max(1))
+            .sum()
+    }
+}
+
+/// Output that keeps what it's given in a Recorder, paced like NullOutput
+pub struct RecordingOutput {
+    recorder: Recorder,
+    format: AudioFormat,
+    pace: NullOutput,
+}
+
+impl RecordingOutput {
+    pub fn new(recorder: Recorder, format: &AudioFormat) -> Self {
+        RecordingOutput {
+            recorder,
+            format: format.clone(),
+            pace: NullOutput::new(format),
+        }
+    }
+}
+
+impl OutputBackend for RecordingOutput {
+    fn name(&self) -> &'static str {
+        "recorder"
+    }
+
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+        self.recorder.0.lock().unwrap().push(Recorded {
+            at: Instant::now(),
+            format: self.format.clone(),
+            samples: samples.to_vec(),
+        });
+        self.pace.write(samples)
+    }
+}
+
+/// Raw PCM written to a file: little-endian, interleaved, at the stream's
+/// bit depth, so 16-bit audio comes out exactly as it was decoded
+pub struct FileOutput {
+    file: File,
+    format: DeviceFormat, // I16 or I24
+    timeline: Option<FileTimeline>,
+}
+
+/// Virtual clock of a scheduled file: the first sample plays at the first
+/// write's start time and every frame after it at the stream's rate
+struct FileTimeline {
+    aligner: Aligner,
+    start: Option<Instant>,
+    frames: u64,
+    channels: usize,
+    sample_rate: u32,
+}
+
+impl FileOutput {
+    pub fn create(path: &Path, format: &AudioFormat) -> std::io::Result<Self> {
+        Ok(FileOutput {
+            file: File::create(path)?,
+            format: if format.bit_depth <= 16 {
+                DeviceFormat::I16
+            } else {
+                DeviceFormat::I24
+            },
+            timeline: None,
+        })
+    }
+
+    /// Place writes by `start_at` on the file's virtual clock, padding gaps
+    /// with silence as `--scheduling callback` does on a device
+    pub fn scheduled(self, format: &AudioFormat) -> Self {
+        FileOutput {
+            timeline: Some(FileTimeline {
+                aligner: Aligner::new(format.sample_rate),
+                start: None,
+                frames: 0,
+                channels: (format.channels as usize).max(1),
+                sample_rate: format.sample_rate.max(1),
+            }),
+            ..self
+        }
+    }
+}
+
+impl FileTimeline {
+    /// Silence frames to write before `frames` more frames of audio
+    fn advance(&mut self, frames: usize) -> usize {
+        let silence = match self.start {
+            Some(start) => {
+                let written = self.frames as f64 / self.sample_rate as f64;
+                self.aligner
+                    .silence_frames(start + Duration::from_secs_f64(written))
+            }
+            // Nothing written yet: the file starts where the first write does
+            None => {
+                self.start = Some(self.aligner.take_target().unwrap_or_else(Instant::now));
+                0
+            }
+        };
+        self.frames += (silence + frames) as u64;
+        silence
+    }
+}
+
+impl OutputBackend for FileOutput {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn sample_format(&self) -> DeviceFormat {
+        self.format
+    }
+
+    fn places_writes(&self) -> bool {
+        self.timeline.is_some()
+    }
+
+    fn start_at(&mut self, at: Instant) {
+        if let Some(ref mut timeline) = self.timeline {
+            timeline.aligner.start_at(at);
+        }
+    }
+
+    fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(ref mut timeline) = self.timeline {
+            let silence = timeline.advance(samples.len() / timeline.channels) * timeline.channels;
+            let bytes_per_sample = if self.format == DeviceFormat::I16 {
+                2
+            } else {
+                3
+            };
+            self.file.write_all(&vec![0; silence * bytes_per_sample])?;
+        }
+        let bytes: Vec<u8> = if self.format == DeviceFormat::I16 {
+            to_i16(samples)
+                .iter()
+                .flat_map(|s| s.to_le_bytes())
+                .collect()
+        } else {
+            samples
+                .iter()
+                .flat_map(|s| {
+                    let [b0, b1, b2, _] = s.0.to_le_bytes();
+                    [b0, b1, b2]
+                })
+                .collect()
+        };
+        self.file.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Prebuffer above this is almost certainly a typo or wasted memory
+const PREBUFFER_MAX: Duration = Duration::from_secs(5);
+
+/// Feedback on `--buffer` compared with the device's own buffering
+///
+/// `device_latency` is zero when the backend can't report it, in which case
+/// only the upper bound is checked.
+pub fn prebuffer_warning(prebuffer: Duration, device_latency: Duration) -> Option<String> {
+    if prebuffer > PREBUFFER_MAX {
+        Some(format!(
+            "--buffer {} ms is very large (over {} s): it only delays playback and uses memory",
+            prebuffer.as_millis(),
+            PREBUFFER_MAX.as_secs()
+        ))
+    } else if !device_latency.is_zero() && prebuffer < device_latency {
+        Some(format!(
+            "--buffer {} ms is smaller than the device buffer ({} ms), so it adds no protection against underruns",
+            prebuffer.as_millis(),
+            device_latency.as_millis()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Convert 24-bit samples to 16-bit for devices that only take S16
+pub fn to_i16(samples: &[Sample]) -> Vec<i16> {
+    samples.iter().map(|s| (s.0 >> 8) as i16).collect()
+}
+
+/// Convert 24-bit samples to full-range 32-bit (S32)
+pub fn to_i32(samples: &[Sample]) -> Vec<i32> {
+    samples.iter().map(|s| s.0 << 8).collect()
+}
+
+#[cfg(all(target_os = "linux", feature = "alsa-backend"))]
+mod alsa_output {
+    use super::{AlsaAccess, DeviceFormat, DeviceSamples, OutputBackend, OutputConfig};
+    use alsa::pcm::{Access, Format, Frames, HwParams, IoFormat, State, PCM};
+    use alsa::{Direction, ValueOr};
+    use sendspin::audio::{AudioFormat, Sample};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tracing::{info, warn};
+
+    /// ALSA format for a device sample format (S24 is 24-bit in the low bits of 32)
+    fn alsa_format(format: DeviceFormat) -> Format {
+        match format {
+            DeviceFormat::I16 => Format::s16(),
+            DeviceFormat::I24 => Format::s24(),
+            DeviceFormat::I32 => Format::s32(),
+            DeviceFormat::F32 => Format::float(),
+        }
+    }
+
+    /// Direct ALSA PCM playback
+    pub struct AlsaOutput {
+        pcm: PCM,
+        access: AlsaAccess,
+        sample_format: DeviceFormat,
+        channels: usize,
+        latency: Duration, // Hardware buffer length
+    }
+
+    impl AlsaOutput {
+        pub fn open(
+            config: &OutputConfig,
+            format: &AudioFormat,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let device = config.device.as_deref().unwrap_or("default");
+            let pcm = PCM::new(device, Direction::Playback, false)?;
+
+            // Float last: plugins such as dmix may only offer FLOAT_LE
+            let preferred: &[DeviceFormat] = if format.bit_depth > 16 {
+                &[
+                    DeviceFormat::I32,
+                    DeviceFormat::I24,
+                    DeviceFormat::I16,
+                    DeviceFormat::F32,
+                ]
+            } else {
+                &[DeviceFormat::I16, DeviceFormat::I32, DeviceFormat::F32]
+            };
+
+            let sample_format = {
+                let hwp = HwParams::any(&pcm)?;
+                hwp.set_access(match config.access {
+                    AlsaAccess::Rw => Access::RWInterleaved,
+                    AlsaAccess::Mmap => Access::MMapInterleaved,
+                })?;
+                let sample_format = *preferred
+                    .iter()
+                    .find(|f| hwp.test_format(alsa_format(**f)).is_ok())
+                    .ok_or("device supports none of S16/S24/S32/FLOAT")?;
+                hwp.set_format(alsa_format(sample_format))?;
+                hwp.set_channels(format.channels as u32)?;
+                hwp.set_rate(format.sample_rate, ValueOr::Nearest)?;
+                if let Some(period) = config.period_frames {
+                    hwp.set_period_size_near(period as Frames, ValueOr::Nearest)?;
+                }
+                if let Some(buffer) = config.buffer_frames {
+                    hwp.set_buffer_size_near(buffer as Frames)?;
+                }
+                pcm.hw_params(&hwp)?;
+                sample_format
+            };
+
+            let hwp = pcm.hw_params_current()?;
+            let rate = hwp.get_rate()?;
+            if rate != format.sample_rate {
+                return Err(format!(
+                    "{} doesn't support {} Hz (nearest is {} Hz)",
+                    device, format.sample_rate, rate
+                )
+                .into());
+            }
+            let buffer_frames = hwp.get_buffer_size()?;
+            info!(
+                "ALSA output '{}': {:?}, {} Hz, period {} / buffer {} frames",
+                device,
+                sample_format,
+                rate,
+                hwp.get_period_size()?,
+                buffer_frames
+            );
+            drop(hwp);
+
+            Ok(AlsaOutput {
+                pcm,
+                access: config.access,
+                sample_format,
+                channels: format.channels as usize,
+                latency: Duration::from_micros(buffer_frames as u64 * 1_000_000 / rate as u64),
+            })
+        }
+
+        fn write_interleaved<S: IoFormat>(&self, data: &[S]) -> alsa::Result<()> {
+            let io = self.pcm.io_checked::<S>()?;
+            let mut offset = 0;
+            while offset < data.len() {
+                let remaining = &data[offset..];
+                let frames = match self.access {
+                    AlsaAccess::Rw => io.writei(remaining)?,
+                    AlsaAccess::Mmap => {
+                        if self.pcm.avail_update()? == 0 {
+                            self.pcm.wait(Some(100))?;
+                            continue;
+                        }
+                        let channels = self.channels;
+                        let written = io.mmap(remaining.len() / channels, |buf| {
+                            let len = buf.len().min(remaining.len());
+                            buf[..len].copy_from_slice(&remaining[..len]);
+                            len / channels
+                        })?;
+                        if self.pcm.state() == State::Prepared {
+                            self.pcm.start()?;
+                        }
+                        written
+                    }
+                };
+                offset += frames * self.channels;
+            }
+            Ok(())
+        }
+
+        fn write_samples(&self, samples: &DeviceSamples) -> alsa::Result<()> {
+            match samples {
+                DeviceSamples::I16(samples) => self.write_interleaved(samples),
+                DeviceSamples::I24(samples) | DeviceSamples::I32(samples) => {
+                    self.write_interleaved(samples)
+                }
+                DeviceSamples::F32(samples) => self.write_interleaved(samples),
+            }
+        }
+
+        fn write_device(
+            &mut self,
+            samples: DeviceSamples,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            match self.write_samples(&samples) {
+                Err(e) if is_xrun(&e) => {
+                    warn!("ALSA underrun, re-preparing device");
+                    self.pcm.prepare()?;
+                    self.write_samples(&samples).map_err(Into::into)
+                }
+                result => result.map_err(Into::into),
+            }
+        }
+    }
+
+    /// EPIPE from a write means the device ran dry (XRUN)
+    fn is_xrun(e: &alsa::Error) -> bool {
+        std::io::Error::from_raw_os_error(e.errno()).kind() == std::io::ErrorKind::BrokenPipe
+    }
+
+    impl OutputBackend for AlsaOutput {
+        fn name(&self) -> &'static str {
+            "alsa"
+        }
+
+        fn latency(&self) -> Duration {
+            self.latency
+        }
+
+        fn sample_format(&self) -> DeviceFormat {
+            self.sample_format
+        }
+
+        fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+            self.write_device(super::to_device(samples, self.sample_format))
+        }
+
+        fn write_f32(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+            self.write_device(super::f32_to_device(samples, self.sample_format))
+        }
+    }
+}
+
+#[cfg(windows)]
+mod wasapi_output {
+    use super::{DeviceFormat, DeviceSamples, OutputBackend};
+    use crate::negotiate::{DeviceRates, CANDIDATE_BIT_DEPTHS, CANDIDATE_RATES};
+    use sendspin::audio::{AudioFormat, Sample};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tracing::info;
+    use wasapi::{
+        AudioClient, AudioRenderClient, Direction, Handle, SampleType, ShareMode, WaveFormat,
+    };
+
+    /// A sample format to offer the device, with the bits it carries
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ExclusiveFormat {
+        pub sample_format: DeviceFormat,
+        pub valid_bits: u8, // Of the container; 24 in 32 is left-aligned
+    }
+
+    const fn exclusive(sample_format: DeviceFormat, valid_bits: u8) -> ExclusiveFormat {
+        ExclusiveFormat {
+            sample_format,
+            valid_bits,
+        }
+    }
+
+    /// Formats to try for a stream, best first. The mixer doesn't convert in
+    /// exclusive mode, so none is narrower than the stream's bit depth.
+    pub fn exclusive_formats(bit_depth: u8) -> Vec<ExclusiveFormat> {
+        [
+            exclusive(DeviceFormat::I16, 16),
+            exclusive(DeviceFormat::I32, 24),
+            exclusive(DeviceFormat::I32, 32),
+            exclusive(DeviceFormat::F32, 24),
+        ]
+        .into_iter()
+        .filter(|f| f.valid_bits >= bit_depth)
+        .collect()
+    }
+
+    /// WAVEFORMATEXTENSIBLE for a format at a rate
+    pub fn wave_format(format: ExclusiveFormat, rate: u32, channels: u8) -> WaveFormat {
+        let (store_bits, valid_bits, sample_type) = match format.sample_format {
+            DeviceFormat::I16 => (16, 16, SampleType::Int),
+            DeviceFormat::I24 | DeviceFormat::I32 => (32, format.valid_bits, SampleType::Int),
+            DeviceFormat::F32 => (32, 32, SampleType::Float),
+        };
+        WaveFormat::new(
+            store_bits,
+            valid_bits as usize,
+            &sample_type,
+            rate as usize,
+            channels as usize,
+            None,
+        )
+    }
+
+    /// The first format the device takes in exclusive mode, or its refusal
+    /// of the last one tried
+    fn select_format(
+        client: &AudioClient,
+        format: &AudioFormat,
+    ) -> Result<(ExclusiveFormat, WaveFormat), String> {
+        let mut refusal = String::from("no format wide enough");
+        for candidate in exclusive_formats(format.bit_depth) {
+            let wave = wave_format(candidate, format.sample_rate, format.channels);
+            match client.is_supported(&wave, &ShareMode::Exclusive) {
+                Ok(_) => return Ok((candidate, wave)),
+                Err(e) => refusal = e.to_string(),
+            }
+        }
+        Err(refusal)
+    }
+
+    /// Exclusive-mode rates of the default device, at any candidate depth
+    pub fn probe_rates(channels: u8) -> Result<DeviceRates, Box<dyn std::error::Error>> {
+        wasapi::initialize_mta().ok()?;
+        let device = wasapi::get_default_device(&Direction::Render)?;
+        let client = device.get_iaudioclient()?;
+        let ranges = CANDIDATE_RATES
+            .into_iter()
+            .filter(|&rate| {
+                CANDIDATE_BIT_DEPTHS.into_iter().any(|bit_depth| {
+                    exclusive_formats(bit_depth).into_iter().any(|f| {
+                        let wave = wave_format(f, rate, channels);
+                        client.is_supported(&wave, &ShareMode::Exclusive).is_ok()
+                    })
+                })
+            })
+            .map(|rate| (rate, rate))
+            .collect();
+        Ok(DeviceRates { ranges })
+    }
+
+    /// WASAPI exclusive-mode playback, event driven: each event asks for one
+    /// whole device buffer
+    pub struct WasapiOutput {
+        client: AudioClient,
+        render: AudioRenderClient,
+        event: Handle,
+        sample_format: DeviceFormat,
+        block_align: usize,
+        buffer_frames: usize,
+        pending: Vec<u8>, // Bytes short of a whole device buffer
+        started: bool,
+        latency: Duration, // Device buffer length
+    }
+
+    impl WasapiOutput {
+        pub fn open(format: &AudioFormat) -> Result<Self, Box<dyn std::error::Error>> {
+            wasapi::initialize_mta().ok()?;
+            let device = wasapi::get_default_device(&Direction::Render)?;
+            let name = device.get_friendlyname()?;
+            let mut client = device.get_iaudioclient()?;
+            let (selected, wave) = select_format(&client, format).map_err(|refusal| {
+                format!(
+                    "{} refuses {} Hz / {} ch / {}-bit in exclusive mode: {}",
+                    name, format.sample_rate, format.channels, format.bit_depth, refusal
+                )
+            })?;
+            let (default_period, _) = client.get_periods()?;
+            client.initialize_client(
+                &wave,
+                default_period,
+                &Direction::Render,
+                &ShareMode::Exclusive,
+                false,
+            )?;
+            let event = client.set_get_eventhandle()?;
+            let render = client.get_audiorenderclient()?;
+            let buffer_frames = client.get_bufferframecount()? as usize;
+            info!(
+                "WASAPI exclusive output '{}': {:?} ({}-bit), {} Hz, buffer {} frames",
+                name,
+                selected.sample_format,
+                selected.valid_bits,
+                format.sample_rate,
+                buffer_frames
+            );
+            Ok(WasapiOutput {
+                client,
+                render,
+                event,
+                sample_format: selected.sample_format,
+                block_align: wave.get_blockalign() as usize,
+                buffer_frames,
+                pending: Vec::new(),
+                started: false,
+                latency: Duration::from_micros(
+                    buffer_frames as u64 * 1_000_000 / format.sample_rate as u64,
+                ),
+            })
+        }
+
+        /// Queue samples and hand the device every whole buffer they fill
+        fn write_device(
+            &mut self,
+            samples: DeviceSamples,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            match samples {
+                DeviceSamples::I16(samples) => self
+                    .pending
+                    .extend(samples.iter().flat_map(|s| s.to_le_bytes())),
+                DeviceSamples::I24(samples) | DeviceSamples::I32(samples) => self
+                    .pending
+                    .extend(samples.iter().flat_map(|s| s.to_le_bytes())),
+                DeviceSamples::F32(samples) => self
+                    .pending
+                    .extend(samples.iter().flat_map(|s| s.to_le_bytes())),
+            }
+            let buffer_bytes = self.buffer_frames * self.block_align;
+            while self.pending.len() >= buffer_bytes {
+                // The first buffer is written before starting, as WASAPI asks
+                if self.started {
+                    self.event.wait_for_event(1000)?;
+                }
+                self.render.write_to_device(
+                    self.buffer_frames,
+                    self.block_align,
+                    &self.pending[..buffer_bytes],
+                    None,
+                )?;
+                self.pending.drain(..buffer_bytes);
+                if !self.started {
+                    self.client.start_stream()?;
+                    self.started = true;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for WasapiOutput {
+        fn drop(&mut self) {
+            if self.started {
+                let _ = self.client.stop_stream();
+            }
+        }
+    }
+
+    impl OutputBackend for WasapiOutput {
+        fn name(&self) -> &'static str {
+            "wasapi-exclusive"
+        }
+
+        fn latency(&self) -> Duration {
+            self.latency
+        }
+
+        fn sample_format(&self) -> DeviceFormat {
+            self.sample_format
+        }
+
+        fn write(&mut self, samples: &Arc<[Sample]>) -> Result<(), Box<dyn std::error::Error>> {
+            self.write_device(super::to_device(samples, self.sample_format))
+        }
+
+        fn write_f32(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+            self.write_device(super::f32_to_device(samples, self.sample_format))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_exclusive_formats_never_truncate() {
+            let formats = exclusive_formats(24);
+            assert!(formats.iter().all(|f| f.valid_bits >= 24));
+            assert_eq!(formats[0], exclusive(DeviceFormat::I32, 24));
+
+            // 16-bit streams go to the device as-is when it takes S16
+            let formats = exclusive_formats(16);
+            assert_eq!(formats[0], exclusive(DeviceFormat::I16, 16));
+            assert_eq!(formats.len(), 4);
+        }
+
+        #[test]
+        fn test_wave_format_matches_the_samples_written() {
+            let wave = wave_format(exclusive(DeviceFormat::I32, 24), 44100, 2);
+            assert_eq!(wave.get_bitspersample(), 32);
+            assert_eq!(wave.get_validbitspersample(), 24);
+            assert_eq!(wave.get_samplespersec(), 44100);
+            assert_eq!(wave.get_blockalign(), 8);
+
+            let wave = wave_format(exclusive(DeviceFormat::I16, 16), 48000, 2);
+            assert_eq!(wave.get_blockalign(), 4);
+            assert_eq!(wave.get_subformat().unwrap(), SampleType::Int);
+
+            let wave = wave_format(exclusive(DeviceFormat::F32, 24), 96000, 2);
+            assert_eq!(wave.get_validbitspersample(), 32);
+            assert_eq!(wave.get_subformat().unwrap(), SampleType::Float);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+
+    #[test]
+    fn test_sample_conversion() {
+        let samples = [
+            Sample(SAMPLE_MAX),
+            Sample(SAMPLE_MIN),
+            Sample(256),
+            Sample(0),
+        ];
+        assert_eq!(to_i16(&samples), vec![i16::MAX, i16::MIN, 1, 0]);
+        assert_eq!(to_i32(&samples), vec![i32::MAX - 255, i32::MIN, 65536, 0]);
+    }
+
+    #[test]
+    fn test_int_samples_to_each_device_format() {
+        let samples = [Sample(SAMPLE_MAX), Sample(SAMPLE_MIN), Sample(256)];
+        assert_eq!(
+            to_device(&samples, DeviceFormat::I16),
+            DeviceSamples::I16(vec![i16::MAX, i16::MIN, 1])
+        );
+        assert_eq!(
+            to_device(&samples, DeviceFormat::I24),
+            DeviceSamples::I24(vec![SAMPLE_MAX, SAMPLE_MIN, 256])
+        );
+        assert_eq!(
+            to_device(&samples, DeviceFormat::I32),
+            DeviceSamples::I32(vec![i32::MAX - 255, i32::MIN, 65536])
+        );
+        let DeviceSamples::F32(float) = to_device(&samples, DeviceFormat::F32) else {
+            panic!("expected f32 samples");
+        };
+        assert_eq!(float[1], -1.0);
+        assert_eq!(float[2], 256.0 / 8_388_608.0);
+        assert!(float[0] < 1.0 && float[0] > 0.9999);
+    }
+
+    #[test]
+    fn test_float_samples_to_each_device_format() {
+        let samples = [1.0, -1.0, 0.5, 2.0];
+        assert_eq!(
+            f32_to_device(&samples, DeviceFormat::F32),
+            DeviceSamples::F32(samples.to_vec())
+        );
+        // Integer devices get rounded, clamped values at their own width
+        assert_eq!(
+            f32_to_device(&samples, DeviceFormat::I16),
+            DeviceSamples::I16(vec![i16::MAX, i16::MIN, 16384, i16::MAX])
+        );
+        assert_eq!(
+            f32_to_device(&samples, DeviceFormat::I24),
+            DeviceSamples::I24(vec![SAMPLE_MAX, SAMPLE_MIN, 4_194_304, SAMPLE_MAX])
+        );
+        assert_eq!(
+            f32_to_device(&samples, DeviceFormat::I32),
+            DeviceSamples::I32(vec![i32::MAX - 255, i32::MIN, 1 << 30, i32::MAX - 255])
+        );
+    }
+
+    #[test]
+    fn test_device_format_from_cpal() {
+        assert_eq!(
+            DeviceFormat::from_cpal(cpal::SampleFormat::F32),
+            Some(DeviceFormat::F32)
+        );
+        assert_eq!(
+            DeviceFormat::from_cpal(cpal::SampleFormat::I16),
+            Some(DeviceFormat::I16)
+        );
+        assert_eq!(DeviceFormat::from_cpal(cpal::SampleFormat::U8), None);
+        assert_eq!(DeviceFormat::I16.bits(), 16);
+        assert_eq!(DeviceFormat::F32.bits(), 24);
+    }
+
+    fn format() -> AudioFormat {
+        AudioFormat {
+            codec: sendspin::audio::Codec::Pcm,
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 24,
+            codec_header: None,
+        }
+    }
+
+    #[test]
+    fn test_null_output_paces_in_real_time() {
+        let config = OutputConfig {
+            backend: OutputBackendKind::Null,
+            ..Default::default()
+        };
+        let mut out = open(&config, format()).unwrap();
+        assert_eq!(out.name(), "null");
+
+        // 5 x 20 ms of stereo audio must take about 100 ms to "play"
+        let chunk: Arc<[Sample]> = Arc::from(vec![Sample(0); 960 * 2]);
+        let start = Instant::now();
+        for _ in 0..5 {
+            out.write(&chunk).unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(95), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_file_output_writes_raw_pcm() {
+        let path = std::env::temp_dir().join(format!("sendspin-out-{}.pcm", std::process::id()));
+        let config = OutputConfig {
+            backend: OutputBackendKind::File,
+            file: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut out = open(&config, format()).unwrap();
+        assert_eq!(out.bit_depth(), 24);
+        out.write(&Arc::from(vec![Sample(0x123456), Sample(-2)]))
+            .unwrap();
+        drop(out);
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, vec![0x56, 0x34, 0x12, 0xfe, 0xff, 0xff]);
+
+        // No path given
+        let config = OutputConfig {
+            backend: OutputBackendKind::File,
+            ..Default::default()
+        };
+        assert!(open(&config, format()).is_err());
+    }
+
+    #[test]
+    fn test_prebuffer_warning() {
+        let ms = Duration::from_millis;
+        assert!(prebuffer_warning(ms(20), ms(10)).is_none());
+        assert!(prebuffer_warning(ms(20), Duration::ZERO).is_none());
+        let small = prebuffer_warning(ms(5), ms(85)).unwrap();
+        assert!(
+            small.contains("smaller than the device buffer (85 ms)"),
+            "{}",
+            small
+        );
+        let huge = prebuffer_warning(ms(60_000), Duration::ZERO).unwrap();
+        assert!(huge.contains("very large"), "{}", huge);
+    }
+
+    #[test]
+    fn test_default_backend_is_cpal() {
+        let config = OutputConfig::default();
+        assert_eq!(config.backend, OutputBackendKind::Cpal);
+        assert_eq!(config.access, AlsaAccess::Rw);
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "alsa-backend")))]
+    #[test]
+    fn test_alsa_unavailable_without_feature() {
+        let config = OutputConfig {
+            backend: OutputBackendKind::Alsa,
+            ..Default::default()
+        };
+        assert!(open(&config, format()).is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_exclusive_unavailable_off_windows() {
+        let config = OutputConfig {
+            exclusive: true,
+            ..Default::default()
+        };
+        assert!(open(&config, format()).is_err());
+        assert!(exclusive_rates(2).is_err());
+    }
+}
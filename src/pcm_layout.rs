@@ -0,0 +1,249 @@
+// PCM Sample Layouts
+//
+// 24-bit PCM reaches us in more than one container. Most servers pack each
+// sample in 3 bytes (s24le), some put it in the low 3 bytes of a 4-byte word
+// (s24_4le, ALSA's S24_LE), and 32-bit streams carry it in the high bytes
+// (s32le, truncated to the 24 bits a Sample keeps). Reading one layout as
+// another shifts every sample by a byte and sounds like loud crackling.
+//
+// The layout comes from the stream/start bit depth when it says enough
+// (16 or 32 bits), from --pcm-layout when given, and otherwise from the first
+// chunk that isn't silent: a chunk that only splits into whole frames one way
+// decides the container, and 4-byte words whose spare high byte always
+// repeats the sign point to s24_4le. Detection only runs for 24-bit streams,
+// so it picks between the two 24-bit containers; s32le is never guessed.
+
+use crate::pool::SamplePool;
+use clap::ValueEnum;
+use sendspin::audio::Sample;
+use std::sync::Arc;
+
+/// How samples are laid out in the stream's bytes (all little-endian)
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PcmLayout {
+    S16le,
+    S24le, // Packed, 3 bytes per sample
+    #[value(name = "s24_4le")]
+    S24_4le, // 24 bits in the low 3 bytes of 4 (LSB-justified)
+    S32le, // 32 bits, the low byte dropped
+}
+
+impl PcmLayout {
+    /// Layout implied by the stream/start bit depth, None when 24-bit
+    /// audio could be in either container
+    pub fn for_bit_depth(bit_depth: u8) -> Option<Self> {
+        match bit_depth {
+            16 => Some(PcmLayout::S16le),
+            32 => Some(PcmLayout::S32le),
+            _ => None,
+        }
+    }
+
+    /// Name as given to --pcm-layout
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PcmLayout::S16le => "s16le",
+            PcmLayout::S24le => "s24le",
+            PcmLayout::S24_4le => "s24_4le",
+            PcmLayout::S32le => "s32le",
+        }
+    }
+
+    /// Bytes per sample on the wire
+    pub fn sample_bytes(self) -> usize {
+        match self {
+            PcmLayout::S16le => 2,
+            PcmLayout::S24le => 3,
+            PcmLayout::S24_4le | PcmLayout::S32le => 4,
+        }
+    }
+
+    /// Decode to 24-bit Samples; a trailing partial sample is ignored
+    pub fn decode(self, data: &[u8]) -> Arc<[Sample]> {
+        data.chunks_exact(self.sample_bytes())
+            .map(|b| self.sample(b))
+            .collect()
+    }
+
+    /// Decode into a caller's (e.g. pooled) buffer, replacing its contents
+    pub fn decode_into(self, data: &[u8], out: &mut Vec<Sample>) {
+        out.clear();
+        out.extend(
+            data.chunks_exact(self.sample_bytes())
+                .map(|b| self.sample(b)),
+        );
+    }
+
+    /// Decode into a buffer taken from `pool` rather than a fresh allocation
+    pub fn decode_pooled(self, data: &[u8], pool: &SamplePool) -> Arc<[Sample]> {
+        let chunks = data.chunks_exact(self.sample_bytes());
+        let mut samples = pool.take(chunks.len());
+        let out = Arc::get_mut(&mut samples).expect("pooled buffers are unshared");
+        for (out, b) in out.iter_mut().zip(chunks) {
+            *out = self.sample(b);
+        }
+        samples
+    }
+
+    /// One sample from its `sample_bytes` bytes
+    fn sample(self, b: &[u8]) -> Sample {
+        Sample(match self {
+            PcmLayout::S16le => (i16::from_le_bytes([b[0], b[1]]) as i32) << 8,
+            PcmLayout::S24le => i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8,
+            // The spare high byte is ignored, bit 23 is the sign
+            PcmLayout::S24_4le => i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8,
+            PcmLayout::S32le => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) >> 8,
+        })
+    }
+}
+
+/// Guess the layout of 24-bit audio from a chunk, S24le or S24_4le; None
+/// when the chunk can't tell (e.g. digital silence)
+pub fn detect(data: &[u8], channels: usize) -> Option<PcmLayout> {
+    let channels = channels.max(1);
+    let packed = data.len().is_multiple_of(3 * channels);
+    let wide = data.len().is_multiple_of(4 * channels);
+    if !wide {
+        return packed.then_some(PcmLayout::S24le);
+    }
+
+    // Look at what 4-byte words would hold
+    let words: Vec<[u8; 4]> = data
+        .chunks_exact(4)
+        .map(|b| [b[0], b[1], b[2], b[3]])
+        .filter(|w| *w != [0; 4])
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let share = |test: fn(&[u8; 4]) -> bool| {
+        words.iter().filter(|w| test(w)).count() as f64 / words.len() as f64
+    };
+    if share(is_sign_extended) > 0.99 || !packed {
+        Some(PcmLayout::S24_4le)
+    } else {
+        Some(PcmLayout::S24le)
+    }
+}
+
+/// Whether the high byte of a little-endian word repeats the sign of bit 23
+fn is_sign_extended(word: &[u8; 4]) -> bool {
+    word[3] == if word[2] & 0x80 != 0 { 0xFF } else { 0x00 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stereo 24-bit ramp over most of the range, both polarities
+    fn ramp() -> Vec<i32> {
+        (0..4800)
+            .map(|i| (i * 3491) % 16_000_000 - 8_000_000)
+            .collect()
+    }
+
+    fn encode(layout: PcmLayout, values: &[i32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|&v| match layout {
+                PcmLayout::S16le => ((v >> 8) as i16).to_le_bytes().to_vec(),
+                PcmLayout::S24le => v.to_le_bytes()[..3].to_vec(),
+                PcmLayout::S24_4le => v.to_le_bytes().to_vec(),
+                PcmLayout::S32le => (v << 8).to_le_bytes().to_vec(),
+            })
+            .collect()
+    }
+
+    fn values(samples: &[Sample]) -> Vec<i32> {
+        samples.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn test_layouts_decode_identically() {
+        let ramp = ramp();
+        for layout in [PcmLayout::S24le, PcmLayout::S24_4le, PcmLayout::S32le] {
+            let decoded = layout.decode(&encode(layout, &ramp));
+            assert_eq!(values(&decoded), ramp, "{:?}", layout);
+        }
+        // 16-bit keeps the top 16 bits
+        let decoded = PcmLayout::S16le.decode(&encode(PcmLayout::S16le, &ramp));
+        let expected: Vec<i32> = ramp.iter().map(|v| v >> 8 << 8).collect();
+        assert_eq!(values(&decoded), expected);
+    }
+
+    #[test]
+    fn test_decode_into_reuses_the_buffer() {
+        let ramp = ramp();
+        let mut out = Vec::with_capacity(ramp.len());
+        let start = out.as_ptr();
+        for layout in [PcmLayout::S16le, PcmLayout::S24le, PcmLayout::S32le] {
+            let data = encode(layout, &ramp);
+            layout.decode_into(&data, &mut out);
+            assert_eq!(values(&out), values(&layout.decode(&data)), "{:?}", layout);
+        }
+        assert_eq!(out.as_ptr(), start);
+        // Left over bytes from a partial sample are dropped
+        PcmLayout::S24le.decode_into(&[0x00, 0x00, 0x01, 0xFF], &mut out);
+        assert_eq!(values(&out), vec![65_536]);
+    }
+
+    #[test]
+    fn test_decode_pooled_overwrites_a_reused_buffer() {
+        let ramp = ramp();
+        let pool = SamplePool::new();
+        let data = encode(PcmLayout::S24le, &ramp);
+        let first = PcmLayout::S24le.decode_pooled(&data, &pool);
+        pool.give(first);
+        let data = encode(
+            PcmLayout::S32le,
+            &ramp.iter().map(|v| -v).collect::<Vec<_>>(),
+        );
+        let second = PcmLayout::S32le.decode_pooled(&data, &pool);
+        assert_eq!(values(&second), values(&PcmLayout::S32le.decode(&data)));
+        assert_eq!(pool.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_s24_4le_ignores_spare_byte() {
+        // Some senders leave garbage rather than a sign extension up there
+        let decoded = PcmLayout::S24_4le.decode(&[0x01, 0x00, 0x80, 0x55, 0xFF, 0xFF, 0x7F, 0xAA]);
+        assert_eq!(values(&decoded), vec![-8_388_607, 8_388_607]);
+    }
+
+    #[test]
+    fn test_detects_layout_from_first_chunk() {
+        let ramp = ramp();
+        for layout in [PcmLayout::S24le, PcmLayout::S24_4le] {
+            // 4800 samples: whole frames in both 3- and 4-byte containers
+            assert_eq!(detect(&encode(layout, &ramp), 2), Some(layout));
+            // 439 frames: only the right container splits evenly
+            assert_eq!(detect(&encode(layout, &ramp[..878]), 2), Some(layout));
+        }
+        // Quiet audio still decides by the spare byte
+        let quiet: Vec<i32> = ramp.iter().map(|v| v / 1000).collect();
+        assert_eq!(
+            detect(&encode(PcmLayout::S24_4le, &quiet), 2),
+            Some(PcmLayout::S24_4le)
+        );
+        assert_eq!(
+            detect(&encode(PcmLayout::S24le, &quiet), 2),
+            Some(PcmLayout::S24le)
+        );
+        // A 24-bit stream is never taken for 32-bit samples
+        for data in [
+            encode(PcmLayout::S32le, &ramp),
+            encode(PcmLayout::S32le, &quiet),
+        ] {
+            assert_ne!(detect(&data, 2), Some(PcmLayout::S32le));
+        }
+    }
+
+    #[test]
+    fn test_silence_is_inconclusive() {
+        assert_eq!(detect(&[0; 4800], 2), None);
+        assert_eq!(detect(&[0; 7], 2), None);
+        assert_eq!(PcmLayout::for_bit_depth(24), None);
+        assert_eq!(PcmLayout::for_bit_depth(32), Some(PcmLayout::S32le));
+    }
+}
@@ -1,174 +1,2255 @@
 // Audio Player Module
 //
 // Handles all audio playback logic:
-// - Simple FIFO queue for incoming audio buffers
+// - Lock-free FIFO queue (single-producer ring) for incoming audio buffers,
+//   bounded by the advertised buffer capacity; stop and new streams discard
+//   what was queued before them by generation, without touching the queue
+// - Small chunks merged into longer buffers before they are queued
 // - Time-synced playback
-// - Volume control (software scaling)
-// - Stop/Resume commands
+// - Optional per-buffer gain envelopes set by the owner, on top of the volume
+// - Optional crossfade from the previous stream's tail into a new stream
+// - Playback speed adjustment (resampling, reset on stream change)
+// - Sample rate conversion when the device can't play the stream's rate
+// - Optional EQ (biquad cascade, bypassed when not configured)
+// - Balance and left/right channel swap, on the output channels (after any
+//   mono downmix)
+// - Volume control (software scaling or ALSA hardware mixer)
+// - f32 processing for devices that take float samples natively
+// - TPDF dither (optionally noise shaped) when the device keeps fewer bits
+// - ReplayGain (combined with volume, clamped to the sample range)
+// - Optional loudness normalization towards a target LUFS (slow gain, same path)
+// - Stop/Resume commands (stop can fade out briefly to avoid a click)
+// - Drain: play out what's queued, then stop; the caller can await the
+//   moment the last of it has been heard
+// - Pause remembers the stream position that was heard last; audio from before
+//   it is skipped on resume instead of being played twice
+// - Short fade-in whenever the output (re)opens, so playback doesn't pop
+// - A queue that runs dry mid-stream is bridged with silence, faded at both
+//   edges, instead of letting the device run out (underrun concealment)
+// - Device disconnect recovery (reopen with backoff, discard audio meanwhile);
+//   the device that comes back is probed again, and the owner is told when it
+//   plays different rates than the one advertised in the hello
+// - An error that ends the playback thread is handed to the owner, which can
+//   replace the player; failed writes are counted in the device stats
+// - Playback counters (frames and buffers played, underruns, drops, trims,
+//   device opens, output latency) the owner can read or reset at any time
+// - Optionally keeping the output open (fed silence) between streams
+// - Optionally releasing the output during long silence (amplifier standby)
+// - Bit-perfect mode: decoded samples go to the device untouched, streams the
+//   device can't play natively are refused
+// - Buffers are written on time to within tens of microseconds (sleep, then
+//   spin through the last moments before the deadline)
+// - No polling while idle: stopped, or with nothing due, the thread sleeps
+//   until a command or queued audio rings for it
+// - Dropping the player shuts the thread down: the output is released and the
+//   thread joined (bounded, so a wedged device can't hang the caller)
 
-use log::{error, info};
-use sendspin::audio::{AudioBuffer, AudioOutput, CpalOutput, Sample};
+use crate::balance;
+use crate::channel_guard::{self, Throttle};
+use crate::coalesce::Coalescer;
+use crate::conceal::{self, Concealer};
+use crate::crossfade::{self, Crossfade};
+use crate::dcblock::DcBlocker;
+use crate::device;
+use crate::dither::{self, Dither};
+use crate::drift::{self, DriftCorrector, PlayoutClock};
+use crate::envelope::Envelope;
+use crate::eq::{EqConfig, Equalizer};
+use crate::float;
+use crate::idle_release::{IdleEvent, SilenceDetector};
+use crate::keep_open::{IdleOutput, KeepOpen, SILENCE_CHUNK};
+use crate::loudness::LoudnessNormalizer;
+use crate::mono;
+use crate::negotiate::{self, DeviceRates};
+use crate::output::{self, DeviceFormat, OutputBackend, OutputBackendKind, OutputConfig};
+use crate::pool::{in_place, SamplePool};
+use crate::recovery::{DeviceRecovery, DeviceStats, PlaybackFailed};
+use crate::resample::{self, LinearResampler, ResampleQuality, Resampler};
+use crate::ring::{self, Consumer, Producer};
+use crate::rt_priority;
+use crate::stats::PlayerStats;
+use crate::timing_trace::{TimingRecord, TimingTrace, Written};
+use crate::volume::{self, apply_gain, VolumeBackendKind};
+use crate::wake::{self, WakeHistogram};
+use sendspin::audio::{AudioBuffer, AudioFormat, Sample};
 use std::collections::VecDeque;
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::Duration;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{
+    AtomicBool, AtomicI64, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Notify};
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Largest magnitude a Sample can carry (24-bit audio in an i32)
+pub(crate) const SAMPLE_MAX: i32 = (1 << 23) - 1;
+pub(crate) const SAMPLE_MIN: i32 = -(1 << 23);
+
+/// How long a fade-out on stop/pause takes
+pub const FADE_OUT: Duration = Duration::from_millis(50);
+
+/// How a drain ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drained {
+    Played,      // Everything queued has played
+    Interrupted, // A stop, new stream or resume came first, or the player closed
+}
+
+/// Where the playback thread reports a drain's end: when its last audio
+/// will have played, or None if it was interrupted
+pub type DrainDone = oneshot::Sender<Option<Instant>>;
+
+/// Resolves when a drain has finished; see `Player::drain`. The drain runs
+/// whether or not this is awaited.
+pub struct Drain(Pin<Box<dyn Future<Output = Drained> + Send>>);
+
+impl Future for Drain {
+    type Output = Drained;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Drained> {
+        self.0.as_mut().poll(cx)
+    }
+}
 
 /// Player control commands
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum PlaybackControl {
-    Stop,          // Clear queue and close output immediately
-    Resume,        // Allow playback to continue
-    SetVolume(u8), // Set volume 0-100
+    Stop,                  // Clear queue and close output (or keep it open, idle)
+    FadeOut,               // Fade queued audio out over FADE_OUT, then stop
+    Pause,                 // Fade out and stop, remembering the position
+    Resume,                // Allow playback to continue
+    Drain(DrainDone),      // Play out queued audio, then stop and close output
+    Crossfade,             // New stream: fade the queued tail out under it
+    CloseOutput,           // Close the output at the next stop, even with keep-open
+    SetVolume(u8),         // Set volume 0-100
+    AdjustVolume(i8),      // Change volume by a step, clamped to 0-100
+    SetReplayGain(f32),    // Linear track gain applied on top of volume
+    SetMuted(bool),        // Silence output without forgetting the volume
+    SetBalance(i8),        // Left/right balance -100..100
+    SetSwapChannels(bool), // Exchange left and right channels
+    SetPlaybackSpeed(f32), // Speed factor for the current stream (1.0 = normal)
+    SeekTo(i64),           // Drop audio before this stream timestamp, trim the buffer across it
+    Shutdown,              // Release the output and end the playback thread
+}
+
+/// Playback settings fixed for the lifetime of the player
+#[derive(Debug, Clone, Default)]
+pub struct PlayerConfig {
+    pub initial_volume: u8,
+    pub eq: Option<EqConfig>,
+    pub dc_block: bool,               // High-pass out any DC offset before volume
+    pub loudness_target: Option<f32>, // Normalize towards this LUFS, None = off
+    pub volume_backend: VolumeBackendKind,
+    pub balance: i8,
+    pub swap_channels: bool,
+    pub mono: Option<f32>, // Downmix to mono at this L+R gain in dB, None = off
+    pub crossfade_ms: u64, // 0 = hard cut between streams
+    pub output: OutputConfig,
+    pub device_fallback: Option<u32>, // Reopen attempts before trying the default device
+    pub device_retry_attempts: Option<u32>, // Reopen attempts before giving up on the stream
+    pub fade_in_ms: u64,              // Ramp up the first audio after the output opens, 0 = off
+    pub device_rates: Option<DeviceRates>, // Probed device rates, None = open at the stream's rate
+    pub prebuffer_ms: u64,            // --buffer, checked against the device's buffering
+    pub keep_device_open: KeepOpen,   // Hold the output open after playback stops
+    pub resample_quality: ResampleQuality, // Converter used when the device lacks the stream's rate
+    pub dither: bool,                 // Dither when reducing to a narrower device
+    pub noise_shaping: bool,          // Shape the dither noise towards high frequencies
+    pub buffer_capacity: usize,       // Queue bound in wire bytes, 0 = unbounded
+    pub idle_release: Option<Duration>, // Close the output after this much silence
+    pub bit_perfect: bool,            // No processing at all; refuse streams that would need it
+    pub drift_band: Option<Duration>, // Steer the rate to keep timing errors inside this band
+    pub coalesce: Duration,           // Merge small chunks into buffers this long, zero = off
+    pub coalesce_window: Option<Duration>, // Longest a chunk is held for merging, None = no limit
+    pub wake_spin: Duration,          // Spin this close to a write deadline, zero = sleep only
+    pub schedule_margin: Option<Duration>, // Take buffers off the queue this early, None = LOOKAHEAD
+    pub timing_trace: Option<TimingTrace>, // Record each buffer's write (--timing-trace)
+    pub rt_priority: Option<u8>,           // Run the playback thread at this SCHED_FIFO priority
+}
+
+/// How far ahead of its write time a buffer is taken off the queue, unless
+/// configured otherwise; until then the thread sleeps in one wait, which
+/// commands and newly queued audio cut short
+pub const LOOKAHEAD: Duration = Duration::from_millis(100);
+
+/// Longest the playback thread sleeps with nothing to do; commands and
+/// queued audio wake it sooner
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+/// How much later than its play time a buffer can start, and how long the
+/// device can sit without audio, before it counts as an underrun
+const UNDERRUN_SLACK: Duration = Duration::from_millis(5);
+
+/// How long dropping the player waits for the playback thread to finish
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(2);
+
+/// Buffers the queue holds at most, whatever their size (~80 s of 20 ms chunks)
+const QUEUE_SLOTS: usize = 4096;
+
+/// A queued buffer and the generation it was queued in
+struct Queued {
+    buffer: AudioBuffer,
+    envelope: Option<Envelope>,
+    epoch: u64,
+}
+
+/// State shared by both ends of the audio queue
+struct QueueShared {
+    epoch: AtomicU64,        // Bumped by stop and new streams: older buffers are stale
+    bytes: AtomicUsize,      // Wire bytes queued, for the capacity check
+    micros: AtomicU64,       // Play time queued
+    doorbell: Doorbell,      // Rung by enqueues and commands, so an idle thread can sleep
+    wakeups: AtomicU64,      // Passes of the playback loop
+    seek_dropped: AtomicU64, // Buffers dropped because a seek skipped past them
+    stats: PlayerStats,      // Counted by the playback thread, read by the owner
+    // Published by the playback thread once per pass, for the owner to query
+    playing: AtomicBool,     // Not stopped
+    output_open: AtomicBool, // The output device is open (or held open)
+    played_until: AtomicI64, // End timestamp of the last written buffer, NOT_PLAYED = none
+    volume: AtomicU8,        // Volume the playback thread applies, 0-100
+    speed: AtomicU32,        // Playback speed the playback thread applies, f32 bits
+    replay_gain: AtomicU32,  // Linear ReplayGain the playback thread applies, f32 bits
+}
+
+/// `played_until` before anything was written since the last stop
+const NOT_PLAYED: i64 = i64::MIN;
+
+impl QueueShared {
+    fn new() -> Self {
+        QueueShared {
+            epoch: AtomicU64::new(0),
+            bytes: AtomicUsize::new(0),
+            micros: AtomicU64::new(0),
+            doorbell: Doorbell::default(),
+            wakeups: AtomicU64::new(0),
+            seek_dropped: AtomicU64::new(0),
+            stats: PlayerStats::new(),
+            playing: AtomicBool::new(false),
+            output_open: AtomicBool::new(false),
+            played_until: AtomicI64::new(NOT_PLAYED),
+            volume: AtomicU8::new(0),
+            speed: AtomicU32::new(1.0f32.to_bits()),
+            replay_gain: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+
+    /// Make the playback speed applied visible to `Player::playback_speed`
+    fn publish_speed(&self, speed: f32) {
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Make the playback thread's state visible to `Player`'s queries
+    fn publish(&self, playing: bool, output_open: bool, played_until: Option<i64>) {
+        self.playing.store(playing, Ordering::Relaxed);
+        self.output_open.store(output_open, Ordering::Relaxed);
+        self.played_until
+            .store(played_until.unwrap_or(NOT_PLAYED), Ordering::Relaxed);
+    }
+}
+
+/// Wakes the playback thread when there's something new for it
+#[derive(Default)]
+struct Doorbell {
+    rung: Mutex<bool>,
+    ring: Condvar,
+}
+
+impl Doorbell {
+    fn ring(&self) {
+        *self.rung.lock().unwrap() = true;
+        self.ring.notify_one();
+    }
+
+    /// Wait until rung or `timeout` passes; a ring since the last wait
+    /// returns at once
+    fn wait(&self, timeout: Duration) {
+        let rung = self.rung.lock().unwrap();
+        let (mut rung, _) = self
+            .ring
+            .wait_timeout_while(rung, timeout, |rung| !*rung)
+            .unwrap();
+        *rung = false;
+    }
+}
+
+/// Network side's end of the audio queue
+struct QueueWriter {
+    tx: Producer<Queued>,
+    coalescer: Coalescer, // Chunks held back until they make a long enough buffer
+    shared: Arc<QueueShared>,
+    overflowing: bool, // Warned about a full queue, until it has room again
+}
+
+impl QueueWriter {
+    /// Put a (possibly merged) buffer on the queue in the current generation
+    fn push(&mut self, buffer: AudioBuffer, envelope: Option<Envelope>) -> bool {
+        let bytes = wire_bytes(&buffer);
+        let micros = play_micros(&buffer);
+        // Counted before the playback thread can take it off again
+        self.shared.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.shared.micros.fetch_add(micros, Ordering::Relaxed);
+        let epoch = self.shared.epoch.load(Ordering::Acquire);
+        let queued = Queued {
+            buffer,
+            envelope,
+            epoch,
+        };
+        if self.tx.push(queued).is_err() {
+            self.shared.bytes.fetch_sub(bytes, Ordering::Relaxed);
+            self.shared.micros.fetch_sub(micros, Ordering::Relaxed);
+            if !std::mem::replace(&mut self.overflowing, true) {
+                warn!(
+                    "Audio queue full ({} buffers), dropping audio until it drains",
+                    QUEUE_SLOTS
+                );
+            }
+            return false;
+        }
+        self.shared.doorbell.ring();
+        true
+    }
+
+    /// Queue the chunks held back for merging as they are
+    fn flush(&mut self) {
+        if let Some(buffer) = self.coalescer.flush() {
+            self.push(buffer, None);
+        }
+    }
+}
+
+/// Playback thread's end of the audio queue
+struct QueueReader {
+    rx: Consumer<Queued>,
+    shared: Arc<QueueShared>,
+    pool: SamplePool, // Played buffers go back for the next decode or merge
+}
+
+impl QueueReader {
+    /// The next buffer, left in the queue
+    fn front(&self) -> Option<&AudioBuffer> {
+        self.rx.peek().map(|queued| &queued.buffer)
+    }
+
+    /// Whether the next buffer was queued before the latest stop or new stream
+    fn front_is_stale(&self) -> bool {
+        let epoch = self.shared.epoch.load(Ordering::Acquire);
+        self.rx.peek().is_some_and(|queued| queued.epoch < epoch)
+    }
+
+    fn pop(&mut self) -> Option<(AudioBuffer, Option<Envelope>)> {
+        let queued = self.rx.pop()?;
+        self.shared
+            .bytes
+            .fetch_sub(wire_bytes(&queued.buffer), Ordering::Relaxed);
+        self.shared
+            .micros
+            .fetch_sub(play_micros(&queued.buffer), Ordering::Relaxed);
+        Some((queued.buffer, queued.envelope))
+    }
+
+    /// Take everything queued before the latest stop or new stream; what the
+    /// network side queued since stays
+    fn take_stale(&mut self) -> VecDeque<AudioBuffer> {
+        let mut stale = VecDeque::new();
+        while self.front_is_stale() {
+            stale.extend(self.pop().map(|(buffer, _)| buffer));
+        }
+        stale
+    }
 }
 
 /// Audio Player
 pub struct Player {
-    audio_queue: Arc<Mutex<VecDeque<AudioBuffer>>>,
+    audio_queue: Mutex<QueueWriter>, // Only the network side locks this
+    queue_shared: Arc<QueueShared>,
     control_tx: mpsc::Sender<PlaybackControl>,
+    device_stats: Arc<Mutex<DeviceStats>>,
+    wake_stats: Arc<Mutex<WakeHistogram>>,
+    pause_position: Arc<Mutex<Option<i64>>>,
+    buffer_capacity: usize,
+    volume: AtomicU8,             // Volume as last set or adjusted, 0-100
+    device_failed: Arc<Notify>,   // Device retries used up (--device-retry-attempts)
+    playback_failed: Arc<Notify>, // The playback thread ended on an error
+    failure: Arc<Mutex<Option<PlaybackFailed>>>, // Why it did
+    device_rates: Arc<Mutex<Option<DeviceRates>>>, // As last probed
+    rates_changed: Arc<Notify>,   // A reopened device plays different rates
+    pool: SamplePool,
+    playback: Option<JoinHandle<()>>,
+    playback_exited: mpsc::Receiver<()>, // Hangs up when the playback thread ends
+}
+
+/// Snapshot of the player from `Player::debug_state`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerDebugState {
+    pub stopped: bool,                    // As of the playback thread's last pass
+    pub current_volume: u8,               // What the playback thread applies
+    pub requested_volume: u8,             // As last set or adjusted
+    pub queue_len: usize,                 // Buffers waiting in the queue
+    pub queued: Duration,                 // Their play time
+    pub output_open: bool,                // As of the playback thread's last pass
+    pub frames_played: u64,               // Since the player started
+    pub since_underrun: Option<Duration>, // Since the last underrun, None before any
+    pub wakeups: u64,                     // Passes of the playback loop
+    pub failure: Option<String>,          // Why the playback thread ended, None while it runs
+}
+
+impl fmt::Display for PlayerDebugState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "player: {}, volume {}",
+            if self.stopped { "stopped" } else { "playing" },
+            self.current_volume
+        )?;
+        if self.requested_volume != self.current_volume {
+            write!(f, " ({} requested)", self.requested_volume)?;
+        }
+        write!(
+            f,
+            ", queue {} buffers ({} ms), output {}, {} frames played",
+            self.queue_len,
+            self.queued.as_millis(),
+            if self.output_open { "open" } else { "closed" },
+            self.frames_played
+        )?;
+        match self.since_underrun {
+            Some(ago) => write!(f, ", last underrun {:.1} s ago", ago.as_secs_f64())?,
+            None => write!(f, ", no underruns")?,
+        }
+        write!(f, ", {} wakeups", self.wakeups)?;
+        match self.failure {
+            Some(ref reason) => write!(f, ", thread ended: {}", reason),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Volume after a relative change, clamped to 0-100
+pub fn adjusted_volume(volume: u8, delta: i8) -> u8 {
+    (volume as i16 + delta as i16).clamp(0, 100) as u8
+}
+
+/// Size of a buffer as the server sent it (PCM bytes on the wire)
+fn wire_bytes(buffer: &AudioBuffer) -> usize {
+    buffer.samples.len() * (buffer.format.bit_depth as usize).div_ceil(8)
+}
+
+/// Play time of a buffer in microseconds
+fn play_micros(buffer: &AudioBuffer) -> u64 {
+    (end_timestamp(buffer) - buffer.timestamp) as u64
 }
 
 impl Player {
     /// Create a new player and spawn the playback thread
     pub fn new(initial_volume: u8) -> Self {
-        let audio_queue: Arc<Mutex<VecDeque<AudioBuffer>>> = Arc::new(Mutex::new(VecDeque::new()));
-        let queue_clone = Arc::clone(&audio_queue);
+        Self::with_config(PlayerConfig {
+            initial_volume,
+            ..Default::default()
+        })
+    }
+
+    /// Create a new player with explicit settings and spawn the playback thread
+    pub fn with_config(config: PlayerConfig) -> Self {
+        let (audio_queue, rx) = ring::ring(QUEUE_SLOTS);
+        let queue_shared = Arc::new(QueueShared::new());
+        let pool = SamplePool::new();
+        let coalescer = Coalescer::new(config.coalesce)
+            .with_window(config.coalesce_window)
+            .with_pool(pool.clone());
+        let writer = Mutex::new(QueueWriter {
+            tx: audio_queue,
+            coalescer,
+            shared: Arc::clone(&queue_shared),
+            overflowing: false,
+        });
+        let reader = QueueReader {
+            rx,
+            shared: Arc::clone(&queue_shared),
+            pool: pool.clone(),
+        };
 
+        let buffer_capacity = config.buffer_capacity;
+        let initial_volume = config.initial_volume;
         let (control_tx, control_rx) = mpsc::channel::<PlaybackControl>();
+        let device_stats = Arc::new(Mutex::new(DeviceStats::default()));
+        let stats_clone = Arc::clone(&device_stats);
+        let wake_stats = Arc::new(Mutex::new(WakeHistogram::default()));
+        let wake_clone = Arc::clone(&wake_stats);
+        let pause_position = Arc::new(Mutex::new(None));
+        let position_clone = Arc::clone(&pause_position);
+        let device_failed = Arc::new(Notify::new());
+        let failed_clone = Arc::clone(&device_failed);
+        let device_rates = Arc::new(Mutex::new(config.device_rates.clone()));
+        let rates_clone = Arc::clone(&device_rates);
+        let rates_changed = Arc::new(Notify::new());
+        let changed_clone = Arc::clone(&rates_changed);
+        let playback_failed = Arc::new(Notify::new());
+        let playback_failed_clone = Arc::clone(&playback_failed);
+        let failure = Arc::new(Mutex::new(None));
+        let failure_clone = Arc::clone(&failure);
+        let (exited_tx, playback_exited) = mpsc::channel::<()>();
 
         // Spawn playback thread
-        std::thread::spawn(move || {
-            if let Err(e) = Self::playback_thread(queue_clone, control_rx, initial_volume) {
+        let span = info_span!("playback", backend = ?config.output.backend);
+        let playback = std::thread::spawn(move || {
+            let _exited = exited_tx;
+            let _playback = span.entered();
+            let mut config = config;
+            if let Some(priority) = config.rt_priority {
+                let granted = rt_priority::raise_current_thread(priority);
+                if matches!(granted, rt_priority::Granted::RealTime(_))
+                    && !config.wake_spin.is_zero()
+                {
+                    // yield_now only gives way to equal priorities here
+                    debug!("Wake spin off under {}", granted);
+                    config.wake_spin = Duration::ZERO;
+                }
+            }
+            if let Err(e) = Self::playback_thread(
+                reader,
+                control_rx,
+                config,
+                stats_clone,
+                wake_clone,
+                position_clone,
+                failed_clone,
+                rates_clone,
+                changed_clone,
+            ) {
                 error!("Playback thread error: {}", e);
+                *failure_clone.lock().unwrap() = Some(PlaybackFailed {
+                    reason: e.to_string(),
+                });
+                playback_failed_clone.notify_one();
             }
         });
 
         Player {
-            audio_queue,
+            audio_queue: writer,
+            queue_shared,
             control_tx,
+            device_stats,
+            wake_stats,
+            pause_position,
+            buffer_capacity,
+            volume: AtomicU8::new(initial_volume),
+            device_failed,
+            playback_failed,
+            failure,
+            device_rates,
+            rates_changed,
+            pool,
+            playback: Some(playback),
+            playback_exited,
         }
     }
 
     /// Add an audio buffer to the playback queue
-    pub fn enqueue(&self, buffer: AudioBuffer) {
-        self.audio_queue.lock().unwrap().push_back(buffer);
+    ///
+    /// Returns false when the buffer was dropped because the queue already
+    /// holds the advertised capacity (the server sent more than we asked for).
+    pub fn enqueue(&self, buffer: AudioBuffer) -> bool {
+        self.enqueue_with(buffer, None)
+    }
+
+    /// Add an audio buffer whose gain follows `envelope` across it, on top of
+    /// volume, mute and the other gains
+    ///
+    /// The buffer isn't merged with its neighbours, so the envelope covers
+    /// exactly its samples. A seek or resume that cuts the buffer short trims
+    /// the envelope with it; a buffer that ends up in a crossfade tail plays
+    /// without it, under the crossfade's own fade.
+    pub fn enqueue_with_envelope(&self, buffer: AudioBuffer, envelope: Envelope) -> bool {
+        self.enqueue_with(buffer, Some(envelope))
+    }
+
+    fn enqueue_with(&self, buffer: AudioBuffer, envelope: Option<Envelope>) -> bool {
+        let mut writer = self.audio_queue.lock().unwrap();
+        let bytes = wire_bytes(&buffer);
+        if self.buffer_capacity > 0 {
+            let queued =
+                self.queue_shared.bytes.load(Ordering::Relaxed) + writer.coalescer.pending_bytes();
+            if queued + bytes > self.buffer_capacity {
+                if !std::mem::replace(&mut writer.overflowing, true) {
+                    warn!(
+                        "Audio queue full ({} of {} bytes), dropping audio until it drains",
+                        queued, self.buffer_capacity
+                    );
+                }
+                return false;
+            }
+            writer.overflowing = false;
+        }
+        if envelope.is_some() {
+            // Chunks held for merging play first, unmerged with this one
+            writer.flush();
+            return writer.push(buffer, envelope);
+        }
+        let mut queued = true;
+        for buffer in writer.coalescer.push(buffer) {
+            queued &= writer.push(buffer, None);
+        }
+        queued
+    }
+
+    /// Queue the chunks still held back for merging, e.g. at the end of a stream
+    fn flush_pending(&self) {
+        self.audio_queue.lock().unwrap().flush();
+    }
+
+    /// When chunks held back for merging will have waited out
+    /// `--coalesce-window-ms`, for the network side's timer; None when
+    /// nothing is held or there is no window
+    pub fn coalesce_deadline(&self) -> Option<Instant> {
+        self.audio_queue.lock().unwrap().coalescer.deadline()
+    }
+
+    /// Queue the chunks held back for merging once they've waited out
+    /// `--coalesce-window-ms`, when no further chunk came to do it
+    pub fn flush_expired(&self) {
+        let mut writer = self.audio_queue.lock().unwrap();
+        if let Some(buffer) = writer.coalescer.flush_expired(Instant::now()) {
+            writer.push(buffer, None);
+        }
+    }
+
+    /// Buffers waiting in the queue
+    pub fn queued_buffers(&self) -> usize {
+        self.audio_queue.lock().unwrap().tx.len()
+    }
+
+    /// Play time waiting in the queue; chunks still held back for merging
+    /// and what the device holds are not counted
+    pub fn queued_duration(&self) -> Duration {
+        Duration::from_micros(self.queue_shared.micros.load(Ordering::Relaxed))
+    }
+
+    /// Whether the playback thread is playing (resumed and not stopped
+    /// since), as of its last pass
+    pub fn is_playing(&self) -> bool {
+        self.queue_shared.playing.load(Ordering::Relaxed)
+    }
+
+    /// Whether the output device is open, as of the playback thread's last pass
+    pub fn output_open(&self) -> bool {
+        self.queue_shared.output_open.load(Ordering::Relaxed)
+    }
+
+    /// Stream timestamp (µs) the audio written to the output reaches, None
+    /// when nothing was written since the last stop
+    pub fn last_played_timestamp(&self) -> Option<i64> {
+        let until = self.queue_shared.played_until.load(Ordering::Relaxed);
+        (until != NOT_PLAYED).then_some(until)
+    }
+
+    /// Mark everything queued so far as belonging to the previous stream
+    fn new_epoch(&self) {
+        // The held-back chunks too, so they're treated like the rest of it
+        let mut writer = self.audio_queue.lock().unwrap();
+        writer.flush();
+        self.queue_shared.epoch.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Hand a command to the playback thread and wake it
+    fn send(&self, command: PlaybackControl) {
+        let _ = self.control_tx.send(command);
+        self.queue_shared.doorbell.ring();
     }
 
     /// Stop playback and clear the queue
     pub fn stop(&self) {
-        let _ = self.control_tx.send(PlaybackControl::Stop);
+        self.new_epoch();
+        self.send(PlaybackControl::Stop);
+    }
+
+    /// Fade the queued audio out quickly, then stop and clear the queue
+    pub fn fade_out(&self) {
+        self.new_epoch();
+        self.send(PlaybackControl::FadeOut);
+    }
+
+    /// Fade out and stop like `fade_out`, remembering where playback was
+    pub fn pause(&self) {
+        self.new_epoch();
+        self.send(PlaybackControl::Pause);
+    }
+
+    /// Let the queued audio play out, then stop and close the output
+    ///
+    /// The returned future resolves once the last queued buffer has been
+    /// played (not just written), at once when nothing is queued, or with
+    /// `Drained::Interrupted` when a stop, new stream or resume comes first.
+    pub fn drain(&self) -> Drain {
+        self.flush_pending();
+        let (done, ended) = oneshot::channel();
+        self.send(PlaybackControl::Drain(done));
+        Drain(Box::pin(async move {
+            match ended.await {
+                Ok(Some(played_by)) => {
+                    tokio::time::sleep_until(played_by.into()).await;
+                    Drained::Played
+                }
+                Ok(None) | Err(_) => Drained::Interrupted,
+            }
+        }))
+    }
+
+    /// Skip to stream timestamp `timestamp` within the current stream: what's
+    /// queued before it is dropped, and so is anything before it still arriving
+    pub fn seek_to(&self, timestamp: i64) {
+        self.flush_pending();
+        self.send(PlaybackControl::SeekTo(timestamp));
+    }
+
+    /// Start a new stream, crossfading from the queued tail when enabled
+    ///
+    /// Falls back to a clean stop/resume when crossfade is disabled, playback
+    /// is paused or nothing of the previous stream is left.
+    pub fn crossfade(&self) {
+        self.new_epoch();
+        self.send(PlaybackControl::Crossfade);
+    }
+
+    /// Make the next stop close the output even when it would be kept open
+    /// (the user stopped playback and expects the device to turn off)
+    pub fn close_output(&self) {
+        self.send(PlaybackControl::CloseOutput);
     }
 
     /// Resume playback
     pub fn resume(&self) {
-        let _ = self.control_tx.send(PlaybackControl::Resume);
+        self.send(PlaybackControl::Resume);
     }
 
     /// Set volume (0-100)
     pub fn set_volume(&self, volume: u8) {
-        let _ = self.control_tx.send(PlaybackControl::SetVolume(volume));
+        self.volume.store(volume, Ordering::Relaxed);
+        self.send(PlaybackControl::SetVolume(volume));
+    }
+
+    /// Change the volume by `delta` (clamped to 0-100) and return the new
+    /// volume, for reporting back to the server
+    pub fn adjust_volume(&self, delta: i8) -> u8 {
+        let previous = self
+            .volume
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |volume| {
+                Some(adjusted_volume(volume, delta))
+            })
+            .unwrap_or_default();
+        self.send(PlaybackControl::AdjustVolume(delta));
+        adjusted_volume(previous, delta)
+    }
+
+    /// Volume as last set or adjusted (0-100)
+    pub fn volume(&self) -> u8 {
+        self.volume.load(Ordering::Relaxed)
+    }
+
+    /// Mute or unmute output, keeping the current volume
+    pub fn set_muted(&self, muted: bool) {
+        self.send(PlaybackControl::SetMuted(muted));
+    }
+
+    /// Set the linear ReplayGain factor for the current track (1.0 = none)
+    pub fn set_replay_gain(&self, factor: f32) {
+        self.send(PlaybackControl::SetReplayGain(factor));
+    }
+
+    /// Set left/right balance (-100 = left only, 100 = right only)
+    pub fn set_balance(&self, balance: i8) {
+        self.send(PlaybackControl::SetBalance(balance.clamp(-100, 100)));
+    }
+
+    /// Exchange left and right channels
+    pub fn set_swap_channels(&self, swap: bool) {
+        self.send(PlaybackControl::SetSwapChannels(swap));
+    }
+
+    /// Play the current stream faster or slower (1.0 = normal, reset on stream change)
+    pub fn set_playback_speed(&self, speed: f32) {
+        self.send(PlaybackControl::SetPlaybackSpeed(speed));
+    }
+
+    /// Playback speed the playback thread applies, back to 1.0 after a stop
+    /// or crossfade
+    pub fn playback_speed(&self) -> f32 {
+        f32::from_bits(self.queue_shared.speed.load(Ordering::Relaxed))
+    }
+
+    /// Linear ReplayGain factor (preamp included) the playback thread applies
+    pub fn replay_gain(&self) -> f32 {
+        f32::from_bits(self.queue_shared.replay_gain.load(Ordering::Relaxed))
+    }
+
+    /// Times the playback thread has woken, for checking that it sleeps
+    /// while there's nothing to play
+    pub fn playback_wakeups(&self) -> u64 {
+        self.queue_shared.wakeups.load(Ordering::Relaxed)
+    }
+
+    /// Output device disconnect/reconnect counters
+    pub fn device_stats(&self) -> DeviceStats {
+        *self.device_stats.lock().unwrap()
+    }
+
+    /// Buffers shared between decoding and playback; decode chunks into
+    /// these so played buffers are reused instead of reallocated
+    pub fn sample_pool(&self) -> &SamplePool {
+        &self.pool
+    }
+
+    /// How late the playback thread woke for each buffer's write deadline
+    pub fn wake_stats(&self) -> WakeHistogram {
+        *self.wake_stats.lock().unwrap()
+    }
+
+    /// Completes when the output device couldn't be reopened within
+    /// --device-retry-attempts; the rest of the stream is discarded and the
+    /// next one tries the device again
+    pub async fn device_failed(&self) {
+        self.device_failed.notified().await
+    }
+
+    /// Completes when the playback thread has ended on an error (e.g. the
+    /// output couldn't be opened at all); the player plays nothing after it
+    pub async fn playback_failed(&self) -> PlaybackFailed {
+        self.playback_failed.notified().await;
+        self.failure().unwrap_or_else(|| PlaybackFailed {
+            reason: "unknown error".to_string(),
+        })
+    }
+
+    /// Why the playback thread ended, None while it runs
+    pub fn failure(&self) -> Option<PlaybackFailed> {
+        self.failure.lock().unwrap().clone()
+    }
+
+    /// Playback counters since the player started or was last reset
+    pub fn stats(&self) -> &PlayerStats {
+        &self.queue_shared.stats
+    }
+
+    /// Buffers dropped by seeks since the player started
+    pub fn seek_dropped(&self) -> u64 {
+        self.queue_shared.seek_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sample rates of the output device as last probed, None when it can't be
+    pub fn device_rates(&self) -> Option<DeviceRates> {
+        self.device_rates.lock().unwrap().clone()
+    }
+
+    /// Completes with the new rates when the device reopened after a
+    /// disconnect plays different ones than before (hotplug, another DAC)
+    pub async fn device_rates_changed(&self) -> Option<DeviceRates> {
+        self.rates_changed.notified().await;
+        self.device_rates()
+    }
+
+    /// What the player and its playback thread are up to, for telling where
+    /// playback that stopped responding is stuck; each value is read on its
+    /// own, without stopping the thread
+    pub fn debug_state(&self) -> PlayerDebugState {
+        let stats = self.stats();
+        PlayerDebugState {
+            stopped: !self.is_playing(),
+            current_volume: self.queue_shared.volume.load(Ordering::Relaxed),
+            requested_volume: self.volume(),
+            queue_len: self.queued_buffers(),
+            queued: self.queued_duration(),
+            output_open: self.output_open(),
+            frames_played: stats.frames_total(),
+            since_underrun: stats.last_underrun().map(|at| at.elapsed()),
+            wakeups: self.playback_wakeups(),
+            failure: self.failure().map(|failed| failed.reason),
+        }
+    }
+
+    /// Stream timestamp (server µs) of the last audio heard before the most
+    /// recent pause, None if playback was never paused
+    ///
+    /// Set once the pause's fade-out has finished, so it accounts for the
+    /// audio still in the device when the output stopped.
+    pub fn pause_position(&self) -> Option<i64> {
+        *self.pause_position.lock().unwrap()
     }
 
     /// Playback thread - handles audio output
     fn playback_thread(
-        queue: Arc<Mutex<VecDeque<AudioBuffer>>>,
+        mut queue: QueueReader,
         control_rx: mpsc::Receiver<PlaybackControl>,
-        initial_volume: u8,
+        config: PlayerConfig,
+        device_stats: Arc<Mutex<DeviceStats>>,
+        wake_stats: Arc<Mutex<WakeHistogram>>,
+        pause_position: Arc<Mutex<Option<i64>>>,
+        device_failed: Arc<Notify>,
+        shared_rates: Arc<Mutex<Option<DeviceRates>>>,
+        rates_changed: Arc<Notify>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut output: Option<CpalOutput> = None;
+        let mut output: Option<Box<dyn OutputBackend>> = None;
         let mut stopped = true; // Start stopped
-        let mut current_volume: u8 = initial_volume;
+        let mut draining = false;
+        let mut current_volume: u8 = config.initial_volume;
+        // The mixer of the card the output plays on; cpal always plays on the default one
+        let mixer_card = match config.output.backend {
+            OutputBackendKind::Alsa => config.output.device.as_deref(),
+            _ => None,
+        };
+        let mut volume_backend = volume::open_backend(config.volume_backend, mixer_card);
+        let mut volume_gain = volume::set_volume_with_fallback(&mut volume_backend, current_volume);
+        queue.shared.volume.store(current_volume, Ordering::Relaxed);
+        let mut eq = config.eq.map(Equalizer::new);
+        let mut dc_block = config.dc_block.then(DcBlocker::new);
+        let mut loudness = config.loudness_target.map(LoudnessNormalizer::new);
+        let mut silence = config.idle_release.map(SilenceDetector::new);
+        let mut replay_gain: f32 = 1.0;
+        let mut muted = false;
+        let mut balance = config.balance;
+        let mut swap_channels = config.swap_channels;
+        let mut warned_mono = false;
+        let mut outgoing: VecDeque<AudioBuffer> = VecDeque::new(); // Previous stream's tail
+        let mut pending: Option<(AudioBuffer, Option<Envelope>)> = None; // Taken off the queue, not played yet
+        let mut fade: Option<Crossfade> = None;
+        let mut fade_out: Option<Ramp> = None;
+        let mut fade_in: Option<Ramp> = None;
+        let mut fade_out_deadline: Option<Instant> = None;
+        let mut playback_speed: f32 = 1.0;
+        let mut resampler: Box<dyn Resampler> = Box::new(LinearResampler::new());
+        let mut output_rate: u32 = 0; // Rate the output was opened at
+        let mut device_rates = config.device_rates.clone(); // Re-probed after a disconnect
+        let mut output_format: Option<AudioFormat> = None; // Stream format it was opened for
+        let mut idle: Option<IdleOutput> = None; // Output kept open while stopped
+        let mut close_requested = false;
+        let mut converting = false; // Stream rate differs from output_rate
+        let mut tail_flushed = false; // Converter's held-back frames queued at stream end
+        let mut checked_prebuffer = false;
+        let mut recovery = DeviceRecovery::new(config.device_fallback)
+            .with_max_attempts(config.device_retry_attempts);
+        let mut ditherer = Dither::new(config.noise_shaping);
+        let mut pcm: Vec<f32> = Vec::new(); // Float path's working buffer, reused
+        let mut pausing = false; // The running fade-out is a pause
+        let mut heard_until: Option<i64> = None; // End timestamp of the last written buffer
+        let mut resume_from: Option<i64> = None; // Skip audio before this after a pause
+        let mut seek_from: Option<i64> = None; // Drop arriving audio before this after a seek
+        let mut refused = false; // Bit-perfect: the device can't take this stream as-is
+        let mut announced = false; // Bit-perfect: passthrough confirmed for this stream
+        let mut drift = config
+            .drift_band
+            .filter(|_| !config.bit_perfect)
+            .map(DriftCorrector::new);
+        let mut playout = PlayoutClock::default(); // When the written audio will have played
+        let mut concealer = Concealer::new(); // Silence over a queue that ran dry
+        let mut stream_channels: Option<u8> = None; // Channels the stream started with
+        let mut remap_warning = Throttle::new(channel_guard::WARN_EVERY);
+        let mut ragged_warning = Throttle::new(channel_guard::WARN_EVERY);
+        let mut drains: Vec<DrainDone> = Vec::new(); // Waiting for the queue to play out
+        let margin = config.schedule_margin.unwrap_or(LOOKAHEAD);
 
         loop {
-            // Check for control commands
-            while let Ok(cmd) = control_rx.try_recv() {
+            queue.shared.wakeups.fetch_add(1, Ordering::Relaxed);
+
+            // A finished fade-out completes as a regular stop
+            let fade_out_finished = fade_out_deadline.is_some_and(|deadline| {
+                Instant::now() >= deadline || fade_out.as_ref().is_some_and(Ramp::is_done)
+            });
+            let mut commands: Vec<PlaybackControl> = fade_out_finished
+                .then_some(PlaybackControl::Stop)
+                .into_iter()
+                .collect();
+
+            // Check for control commands; the player being dropped ends the thread
+            loop {
+                match control_rx.try_recv() {
+                    Ok(cmd) => commands.push(cmd),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        info!("→ Playback: player closed, releasing output");
+                        drop((output.take(), idle.take()));
+                        queue.shared.publish(false, false, None);
+                        return Ok(());
+                    }
+                }
+            }
+
+            for cmd in commands {
                 match cmd {
                     PlaybackControl::Stop => {
                         info!("→ Playback: STOP");
-                        // Clear everything instantly
-                        queue.lock().unwrap().clear();
-                        output = None; // Drops output, stops audio immediately
+                        if std::mem::take(&mut pausing) {
+                            // What the device still held when it stopped wasn't heard
+                            let latency =
+                                output.as_ref().map_or(0, |out| out.latency().as_micros());
+                            let position = heard_until.map(|end| end - latency as i64);
+                            if let Some(position) = position {
+                                info!("→ Playback: paused at ts={}", position);
+                            }
+                            *pause_position.lock().unwrap() = position;
+                            resume_from = position;
+                        } else {
+                            // Whatever comes next isn't the paused stream resuming
+                            resume_from = None;
+                        }
+                        heard_until = None;
+                        // Clear everything queued before the stop instantly
+                        let cleared = queue.take_stale().len() + usize::from(pending.is_some());
+                        queue.shared.stats.dropped(cleared);
+                        pending = None;
+                        // Drops output, stops audio immediately (unless kept open)
+                        idle = park_output(
+                            &mut output,
+                            output_format.as_ref(),
+                            output_rate,
+                            config.keep_device_open,
+                            close_requested,
+                        );
+                        close_requested = false;
                         stopped = true;
+                        draining = false;
+                        finish_drains(&mut drains, None);
+                        if let Some(ref mut eq) = eq {
+                            eq.reset();
+                        }
+                        if let Some(ref mut dc_block) = dc_block {
+                            dc_block.reset();
+                        }
+                        if let Some(ref mut loudness) = loudness {
+                            loudness.reset();
+                        }
+                        if let Some(ref mut detector) = silence {
+                            detector.reset();
+                        }
+                        warned_mono = false;
+                        outgoing.clear();
+                        fade = None;
+                        fade_out = None;
+                        fade_out_deadline = None;
+                        playback_speed = 1.0;
+                        queue.shared.publish_speed(1.0);
+                        resampler.reset();
+                        if let Some(ref mut drift) = drift {
+                            drift.reset();
+                        }
+                        queue.shared.stats.set_drift(0.0);
+                        refused = false;
+                        seek_from = None;
+                        announced = false;
+                        recovery.retry_again();
+                        ditherer.reset();
+                        concealer.reset();
+                        stream_channels = None;
+                    }
+                    PlaybackControl::FadeOut | PlaybackControl::Pause => {
+                        if matches!(cmd, PlaybackControl::Pause) {
+                            pausing = true;
+                        }
+                        if fade_out_deadline.is_none() {
+                            info!("→ Playback: FADE OUT");
+                            // Nothing audible to fade: the next pass stops right away
+                            let window = if stopped || output.is_none() {
+                                Duration::ZERO
+                            } else {
+                                FADE_OUT
+                            };
+                            fade_out_deadline = Some(Instant::now() + window);
+                        }
+                    }
+                    PlaybackControl::Crossfade => {
+                        let mut tail = queue.take_stale();
+                        if let Some((buffer, _)) = pending.take() {
+                            tail.push_front(buffer);
+                        }
+                        if config.crossfade_ms == 0 || stopped || tail.is_empty() {
+                            info!("→ Playback: NEW STREAM (no crossfade)");
+                            queue.shared.stats.dropped(tail.len());
+                            // A kept-open output is reused if the format matches
+                            if config.keep_device_open == KeepOpen::Off {
+                                output = None;
+                            } else if output.is_some() {
+                                fade_in = (config.fade_in_ms > 0).then(|| {
+                                    Ramp::up(output_rate, Duration::from_millis(config.fade_in_ms))
+                                });
+                            }
+                            if let Some(ref mut eq) = eq {
+                                eq.reset();
+                            }
+                            if let Some(ref mut dc_block) = dc_block {
+                                dc_block.reset();
+                            }
+                            if let Some(ref mut loudness) = loudness {
+                                loudness.reset();
+                            }
+                            if let Some(ref mut detector) = silence {
+                                detector.reset();
+                            }
+                            warned_mono = false;
+                            outgoing.clear();
+                        } else {
+                            info!("→ Playback: CROSSFADE from {} queued buffers", tail.len());
+                            outgoing = tail;
+                        }
+                        fade = None;
+                        fade_out = None;
+                        fade_out_deadline = None;
+                        pausing = false;
+                        playback_speed = 1.0;
+                        queue.shared.publish_speed(1.0);
+                        resampler.reset();
+                        if let Some(ref mut drift) = drift {
+                            drift.reset();
+                        }
+                        queue.shared.stats.set_drift(0.0);
+                        refused = false;
+                        seek_from = None;
+                        announced = false;
+                        recovery.retry_again();
+                        concealer.reset();
+                        stream_channels = None;
+                        stopped = false;
+                        draining = false;
+                        resume_from = None; // A new stream, not the paused one
+                        finish_drains(&mut drains, None);
+                        idle = None;
+                        close_requested = false;
+                    }
+                    PlaybackControl::CloseOutput => {
+                        info!("→ Playback: CLOSE OUTPUT");
+                        if stopped && fade_out_deadline.is_none() {
+                            idle = None;
+                            output = None;
+                        } else {
+                            // Still fading out or draining: close when that stops
+                            close_requested = true;
+                        }
                     }
                     PlaybackControl::Resume => {
                         info!("→ Playback: RESUME");
+                        // Coming out of held silence: fade in as on a fresh open
+                        if idle.take().is_some() && config.fade_in_ms > 0 {
+                            fade_in = Some(Ramp::up(
+                                output_rate,
+                                Duration::from_millis(config.fade_in_ms),
+                            ));
+                        }
                         stopped = false;
+                        draining = false;
+                        finish_drains(&mut drains, None);
+                        close_requested = false;
+                        fade_out = None;
+                        fade_out_deadline = None;
+                        pausing = false; // Played again before the fade ended
+                    }
+                    PlaybackControl::Drain(done) => {
+                        info!("→ Playback: DRAIN");
+                        draining = true;
+                        if stopped {
+                            // Nothing plays until a resume, which ends the drain
+                            let empty = queue.front().is_none() && pending.is_none();
+                            let _ = done.send(empty.then(Instant::now));
+                        } else {
+                            drains.push(done);
+                        }
                     }
                     PlaybackControl::SetVolume(vol) => {
                         info!("→ Playback: SET VOLUME {}", vol);
                         current_volume = vol;
+                        volume_gain = volume::set_volume_with_fallback(&mut volume_backend, vol);
+                        queue.shared.volume.store(current_volume, Ordering::Relaxed);
+                    }
+                    PlaybackControl::AdjustVolume(delta) => {
+                        current_volume = adjusted_volume(current_volume, delta);
+                        info!(
+                            "→ Playback: ADJUST VOLUME {:+} to {}",
+                            delta, current_volume
+                        );
+                        volume_gain =
+                            volume::set_volume_with_fallback(&mut volume_backend, current_volume);
+                        queue.shared.volume.store(current_volume, Ordering::Relaxed);
+                    }
+                    PlaybackControl::SetReplayGain(factor) => {
+                        info!("→ Playback: SET REPLAYGAIN x{:.3}", factor);
+                        replay_gain = factor;
+                        queue
+                            .shared
+                            .replay_gain
+                            .store(factor.to_bits(), Ordering::Relaxed);
+                    }
+                    PlaybackControl::SetMuted(mute) => {
+                        info!("→ Playback: SET MUTED {}", mute);
+                        muted = mute;
+                    }
+                    PlaybackControl::SetBalance(value) => {
+                        info!("→ Playback: SET BALANCE {}", value);
+                        balance = value;
+                    }
+                    PlaybackControl::SetSwapChannels(swap) => {
+                        info!("→ Playback: SWAP CHANNELS {}", swap);
+                        swap_channels = swap;
+                    }
+                    PlaybackControl::SetPlaybackSpeed(_) if config.bit_perfect => {
+                        warn!("Bit-perfect: ignoring playback speed change");
+                    }
+                    PlaybackControl::SetPlaybackSpeed(speed) => {
+                        info!("→ Playback: SET SPEED x{:.3}", speed);
+                        let correcting = drift.as_ref().is_some_and(|d| d.ppm() != 0.0);
+                        if speed == 1.0 && !converting && !correcting {
+                            resampler.reset();
+                        }
+                        playback_speed = speed;
+                        queue.shared.publish_speed(speed);
+                    }
+                    PlaybackControl::SeekTo(target) => {
+                        let mut dropped = 0;
+                        // What was taken off the queue plays before the rest
+                        if let Some((buffer, envelope)) = pending.take() {
+                            pending = skip_enveloped(buffer, envelope, target, &queue.shared.stats);
+                            dropped += u64::from(pending.is_none());
+                        }
+                        while pending.is_none() && !queue.front_is_stale() {
+                            match queue.front() {
+                                Some(next) if next.timestamp < target => {}
+                                _ => break,
+                            }
+                            let (buffer, envelope) = queue.pop().expect("front was checked");
+                            pending = skip_enveloped(buffer, envelope, target, &queue.shared.stats);
+                            dropped += u64::from(pending.is_none());
+                        }
+                        info!(
+                            "→ Playback: SEEK to ts={} ({} buffers dropped)",
+                            target, dropped
+                        );
+                        queue
+                            .shared
+                            .seek_dropped
+                            .fetch_add(dropped, Ordering::Relaxed);
+                        // Found the target in the queue, or wait for it to arrive
+                        seek_from = pending.is_none().then_some(target);
+                        if let Some(ref mut drift) = drift {
+                            drift.reset();
+                        }
+                        queue.shared.stats.set_drift(0.0);
+                    }
+                    PlaybackControl::Shutdown => {
+                        info!("→ Playback: SHUTDOWN, releasing output");
+                        drop((output.take(), idle.take()));
+                        queue.shared.publish(false, false, None);
+                        return Ok(());
                     }
                 }
             }
+            queue
+                .shared
+                .publish(!stopped, output.is_some(), heard_until);
 
-            // If stopped, don't play anything
+            // If stopped, don't play anything (but keep a held output fed)
             if stopped {
-                std::thread::sleep(Duration::from_millis(10));
+                let now = Instant::now();
+                match (idle.as_mut(), output.as_mut()) {
+                    (Some(held), Some(out)) if !held.expired(now) => match held.next_silence(now) {
+                        Some(silence) => {
+                            if let Err(e) = out.write(&silence) {
+                                warn!("Idle output failed ({}), closing it", e);
+                                idle = None;
+                                output = None;
+                            }
+                        }
+                        None => queue.shared.doorbell.wait(SILENCE_CHUNK / 2),
+                    },
+                    (Some(_), _) => {
+                        info!("→ Playback: keep-open time over, closing output");
+                        idle = None;
+                        output = None;
+                    }
+                    // Nothing to do until a command comes in
+                    _ => queue.shared.doorbell.wait(IDLE_WAIT),
+                }
                 continue;
             }
 
-            // Get next buffer
-            let buffer = queue.lock().unwrap().pop_front();
+            // Get next buffer, playing the previous stream's tail until the new one is due
+            let now = Instant::now();
+            let incoming_due = queue.front().is_some_and(|next| next.play_at <= now);
+            let incoming_at = queue.front().map(|next| next.play_at);
+            let lead = output.as_ref().map_or(Duration::ZERO, |out| out.latency());
+            let (buffer, from_tail) = if pending.is_some() {
+                (pending.take(), false)
+            } else if !outgoing.is_empty() && !incoming_due {
+                (outgoing.pop_front().map(|buffer| (buffer, None)), true)
+            } else if fade_out_deadline.is_some() && !queue.front_is_stale() {
+                // Fading out: only the stopped stream's audio is left to fade
+                (None, false)
+            } else if let Some(look_at) = queue
+                .front()
+                .and_then(|next| next.play_at.checked_sub(lead + margin))
+                .filter(|&at| at > now)
+            {
+                // Too far in the future: leave it queued until it's nearly due,
+                // bridging a gap that's being concealed until then
+                let bridge = match (output.as_mut(), output_format.as_ref()) {
+                    (Some(out), Some(format)) if concealer.is_active() => conceal_gap(
+                        out.as_mut(),
+                        &mut concealer,
+                        &mut playout,
+                        (format.channels as usize, output_rate),
+                        incoming_at,
+                        &queue.shared.stats,
+                    ),
+                    _ => None,
+                };
+                let wake = bridge.map_or(look_at, |at| at.min(look_at));
+                queue
+                    .shared
+                    .doorbell
+                    .wait(wake.saturating_duration_since(Instant::now()));
+                continue;
+            } else {
+                (queue.pop(), false)
+            };
+
+            if let Some((buffer, envelope)) = buffer {
+                // Not whole frames: nothing tells where its channels start
+                let channels = buffer.format.channels as usize;
+                if !channel_guard::whole_frames(buffer.samples.len(), channels) {
+                    if let Some(held_back) = ragged_warning.hit(Instant::now()) {
+                        warn!(
+                            "Dropping a buffer of {} samples, not whole {}-channel frames ({} more since the last warning)",
+                            buffer.samples.len(),
+                            channels,
+                            held_back
+                        );
+                    }
+                    queue.shared.stats.dropped(1);
+                    continue;
+                }
+
+                // Back from a pause: don't play what was already heard
+                let (buffer, envelope) = match resume_from {
+                    Some(from) if !from_tail && !buffer.samples.is_empty() => {
+                        match skip_enveloped(buffer, envelope, from, &queue.shared.stats) {
+                            Some(trimmed) => {
+                                resume_from = None;
+                                trimmed
+                            }
+                            None => {
+                                debug!("Skipping a buffer heard before the pause (ts<{})", from);
+                                continue;
+                            }
+                        }
+                    }
+                    _ => (buffer, envelope),
+                };
+                // After a seek: what was still on its way from before the target
+                let (buffer, envelope) = match seek_from {
+                    Some(target) if !from_tail => {
+                        match skip_enveloped(buffer, envelope, target, &queue.shared.stats) {
+                            Some(trimmed) => {
+                                seek_from = None;
+                                trimmed
+                            }
+                            None => {
+                                queue.shared.seek_dropped.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                        }
+                    }
+                    _ => (buffer, envelope),
+                };
+                let buffer_end =
+                    (!from_tail && !buffer.samples.is_empty()).then(|| end_timestamp(&buffer));
 
-            if let Some(buffer) = buffer {
-                // Time-sync: wait until play_at time
-                let now = std::time::Instant::now();
-                if buffer.play_at > now {
-                    let wait = buffer.play_at - now;
-                    if wait < Duration::from_millis(100) {
-                        std::thread::sleep(wait);
+                // Time-sync: wait until play_at time, less the device's own buffering
+                // and the frames the rate converter holds back (so they aren't late)
+                let held_back = if converting {
+                    Duration::from_secs_f64(
+                        resampler.latency_frames() as f64 / buffer.format.sample_rate as f64,
+                    )
+                } else {
+                    Duration::ZERO
+                };
+                let latency = output
+                    .as_ref()
+                    .map_or(Duration::ZERO, |out| out.latency() + held_back);
+                let write_at = buffer
+                    .play_at
+                    .checked_sub(latency)
+                    .unwrap_or(buffer.play_at);
+                // A backend that places writes itself (--scheduling callback)
+                // takes the buffer early and pads up to its time
+                let places_writes = output.as_ref().is_some_and(|out| out.places_writes());
+                // Audio back while a gap is being concealed: bridge it up to
+                // this buffer's start, a chunk at a time
+                if write_at > Instant::now() && concealer.is_active() {
+                    let bridge = match (output.as_mut(), output_format.as_ref()) {
+                        (Some(out), Some(format)) => conceal_gap(
+                            out.as_mut(),
+                            &mut concealer,
+                            &mut playout,
+                            (format.channels as usize, output_rate),
+                            Some(write_at + (latency - held_back)),
+                            &queue.shared.stats,
+                        ),
+                        _ => None,
+                    };
+                    if let Some(at) = bridge {
+                        pending = Some((buffer, envelope));
+                        queue
+                            .shared
+                            .doorbell
+                            .wait(at.min(write_at).saturating_duration_since(Instant::now()));
+                        continue;
+                    }
+                }
+                let now = Instant::now();
+                if write_at > now {
+                    let wait = write_at - now;
+                    if wait < margin {
+                        if !places_writes {
+                            let late = wake::sleep_until(write_at, config.wake_spin);
+                            wake_stats.lock().unwrap().record(late);
+                        }
                     } else {
-                        // Too far in future, put back and wait
-                        queue.lock().unwrap().push_front(buffer);
-                        std::thread::sleep(Duration::from_millis(1));
+                        // Too far in future, hold on to it and wait
+                        if from_tail {
+                            outgoing.push_front(buffer);
+                        } else {
+                            pending = Some((buffer, envelope));
+                        }
+                        queue.shared.doorbell.wait(wait - margin);
                         continue;
                     }
                 }
 
-                // Initialize output if needed
-                if output.is_none() {
-                    match CpalOutput::new(buffer.format.clone()) {
-                        Ok(out) => {
-                            info!("Audio output initialized with volume {}", current_volume);
-                            output = Some(out);
+                // First buffer of the new stream: mix the remaining tail into it
+                if !from_tail && !outgoing.is_empty() {
+                    let tail = std::mem::take(&mut outgoing);
+                    if crossfade::formats_compatible(&tail[0].format, &buffer.format) {
+                        let frames = config.crossfade_ms * buffer.format.sample_rate as u64 / 1000;
+                        let channels = buffer.format.channels as usize;
+                        let started = Crossfade::new(tail, channels, frames as usize);
+                        info!("Crossfading over {} frames", started.fade_frames());
+                        fade = Some(started);
+                    } else {
+                        // Can't mix different formats - cut over to a fresh output
+                        info!("Stream format changed, skipping crossfade");
+                        queue.shared.stats.dropped(tail.len());
+                        output = None;
+                        if let Some(ref mut eq) = eq {
+                            eq.reset();
                         }
-                        Err(e) => {
-                            error!("Failed to create output: {}", e);
-                            return Err(e.into());
+                        if let Some(ref mut dc_block) = dc_block {
+                            dc_block.reset();
+                        }
+                        if let Some(ref mut loudness) = loudness {
+                            loudness.reset();
                         }
                     }
                 }
 
-                // Apply volume scaling to samples
-                let samples = if current_volume < 100 {
-                    let volume_factor = current_volume as f32 / 100.0;
-                    let scaled_samples: Vec<_> = buffer
-                        .samples
-                        .iter()
-                        .map(|sample| Sample((sample.0 as f32 * volume_factor) as i32))
-                        .collect();
-                    std::sync::Arc::from(scaled_samples.into_boxed_slice())
+                // The channel count changing mid-stream is a malformed stream,
+                // not a new one: its buffers are fitted to the output below
+                let stream_layout = if from_tail {
+                    buffer.format.channels
                 } else {
-                    buffer.samples
+                    *stream_channels.get_or_insert(buffer.format.channels)
                 };
+                let layout_quirk = stream_layout != buffer.format.channels;
 
-                // Write audio
-                if let Some(ref mut out) = output {
-                    if let Err(e) = out.write(&samples) {
-                        error!("Output error: {}", e);
+                // A kept-open output can't take a stream of another rate or layout
+                if output.is_some()
+                    && output_format.as_ref().is_some_and(|opened| {
+                        !crossfade::formats_compatible(opened, &buffer.format)
+                            && !(layout_quirk && opened.sample_rate == buffer.format.sample_rate)
+                    })
+                {
+                    info!("Stream format changed, reopening output");
+                    output = None;
+                }
+
+                // Long silence: release the output, consume buffers until audio returns
+                if let Some(ref mut detector) = silence {
+                    match detector.update(&buffer.samples, &buffer.format) {
+                        IdleEvent::Release => {
+                            info!(
+                                "Silent for {:?}, releasing the audio device",
+                                config.idle_release.unwrap_or_default()
+                            );
+                            output = None;
+                            recovery.stats.idle_releases += 1;
+                            *device_stats.lock().unwrap() = recovery.stats;
+                        }
+                        IdleEvent::Reacquire => {
+                            info!("Audio resumed, reopening the audio device");
+                            recovery.stats.idle_reacquires += 1;
+                            *device_stats.lock().unwrap() = recovery.stats;
+                        }
+                        IdleEvent::None => {}
+                    }
+                    if detector.is_released() {
+                        continue;
                     }
                 }
-            } else {
-                // Queue empty
-                std::thread::sleep(Duration::from_micros(500));
-            }
-        }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use sendspin::audio::{AudioFormat, Codec, Sample};
-    use std::time::Instant;
+                // Refused in bit-perfect mode: consume the stream without playing it
+                if refused {
+                    continue;
+                }
+
+                // Initialize output if needed (after a disconnect, only once the backoff allows)
+                if output.is_none() && recovery.retry_due(Instant::now()) {
+                    let output_config = recovery.output_config(&config.output);
+                    let fallback = recovery.using_fallback();
+                    // What comes back may be another device: ask it again
+                    if recovery.is_lost() && device_rates.is_some() {
+                        if let Some(rates) = probe_rates(&output_config) {
+                            if device_rates.as_ref() != Some(&rates) {
+                                info!(
+                                    "Output device sample rates changed: {:?} -> {:?}",
+                                    device_rates.as_ref().map(|d| &d.ranges),
+                                    rates.ranges
+                                );
+                                *shared_rates.lock().unwrap() = Some(rates.clone());
+                                device_rates = Some(rates);
+                                rates_changed.notify_one();
+                            }
+                        }
+                    }
+                    let stream_rate = buffer.format.sample_rate;
+                    let rate = device_rates
+                        .as_ref()
+                        .map_or(stream_rate, |device| device.output_rate(stream_rate));
+                    if config.bit_perfect && rate != stream_rate {
+                        error!(
+                            "Bit-perfect: the device can't play {} Hz natively, refusing the stream",
+                            stream_rate
+                        );
+                        refused = true;
+                        continue;
+                    }
+                    let format = AudioFormat {
+                        sample_rate: rate,
+                        ..buffer.format.clone()
+                    };
+                    match output::open(&output_config, format) {
+                        Ok(out)
+                            if config.bit_perfect && out.bit_depth() < buffer.format.bit_depth =>
+                        {
+                            error!(
+                                "Bit-perfect: {} keeps {} bits, refusing the {}-bit stream",
+                                out.name(),
+                                out.bit_depth(),
+                                buffer.format.bit_depth
+                            );
+                            refused = true;
+                            continue;
+                        }
+                        Ok(out) => {
+                            info!(
+                                "Audio output ({}) initialized with volume {}{}",
+                                out.name(),
+                                current_volume,
+                                if out.sample_format() == DeviceFormat::F32 {
+                                    ", f32 pipeline"
+                                } else {
+                                    ""
+                                }
+                            );
+                            if let Some(gone) = recovery.reconnected(Instant::now()) {
+                                info!(
+                                    "Audio device reconnected{} after {:.1}s ({} buffers discarded)",
+                                    if fallback { " (default device)" } else { "" },
+                                    gone.as_secs_f32(),
+                                    recovery.stats.discarded_buffers
+                                );
+                                *device_stats.lock().unwrap() = recovery.stats;
+                            }
+                            converting = rate != stream_rate;
+                            resampler = if converting {
+                                info!(
+                                    "Converting {} Hz to {} Hz for the output device ({:?} quality)",
+                                    stream_rate, rate, config.resample_quality
+                                );
+                                resample::for_quality(config.resample_quality)
+                            } else {
+                                Box::new(LinearResampler::new())
+                            };
+                            output_rate = rate;
+                            output_format = Some(buffer.format.clone());
+                            if !checked_prebuffer {
+                                let prebuffer = Duration::from_millis(config.prebuffer_ms);
+                                if let Some(warning) =
+                                    output::prebuffer_warning(prebuffer, out.latency())
+                                {
+                                    warn!("{}", warning);
+                                }
+                                checked_prebuffer = true;
+                            }
+                            fade_in = (config.fade_in_ms > 0)
+                                .then(|| Ramp::up(rate, Duration::from_millis(config.fade_in_ms)));
+                            playout.reset();
+                            concealer.reset();
+                            queue.shared.stats.device_opened();
+                            output = Some(out);
+                        }
+                        Err(e) if recovery.is_lost() => {
+                            recovery.retry_failed(Instant::now());
+                            if recovery.exhausted() {
+                                error!(
+                                    "Audio device still unavailable after {} attempts ({}), discarding the stream",
+                                    recovery.attempts(),
+                                    e
+                                );
+                                device_failed.notify_one();
+                            } else {
+                                warn!(
+                                    "Audio device still unavailable ({}), retrying in {:?}{}",
+                                    e,
+                                    recovery.backoff(),
+                                    if recovery.using_fallback() {
+                                        " with the default device"
+                                    } else {
+                                        ""
+                                    }
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to create output: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+
+                // Device gone: consume the buffer on schedule without playing it
+                let Some(out) = output.as_mut() else {
+                    recovery.discard();
+                    *device_stats.lock().unwrap() = recovery.stats;
+                    queue.shared.stats.dropped(1);
+                    continue;
+                };
+                out.start_at(write_at);
+
+                // Fit a buffer of another channel count to the output
+                let mut buffer = buffer;
+                let opened_channels = output_format.as_ref().map(|format| format.channels);
+                if let Some(to) = opened_channels.filter(|&to| to != buffer.format.channels) {
+                    if let Some(held_back) = remap_warning.hit(Instant::now()) {
+                        warn!(
+                            "Stream switched from {} to {} channels mid-stream, remapping to the output's {} ({} more since the last warning)",
+                            stream_layout,
+                            buffer.format.channels,
+                            to,
+                            held_back
+                        );
+                    }
+                    let remapped = channel_guard::remap(
+                        &buffer.samples,
+                        buffer.format.channels as usize,
+                        to as usize,
+                    );
+                    queue.pool.give(buffer.samples);
+                    buffer.samples = Arc::from(remapped);
+                    buffer.format.channels = to;
+                }
+
+                // First audio after a concealed gap fades in
+                if concealer.resume() && fade_in.is_none() {
+                    fade_in = Some(Ramp::up(output_rate, conceal::EDGE));
+                }
+
+                let mixed = fade.is_some();
+                let samples = match fade {
+                    Some(ref mut fade) => {
+                        let mixed = fade.mix(&buffer.samples);
+                        queue.pool.give(buffer.samples);
+                        mixed
+                    }
+                    None => buffer.samples,
+                };
+                if fade.as_ref().is_some_and(Crossfade::is_done) {
+                    fade = None;
+                }
+
+                // Speed change and device rate conversion in one pass; play_at
+                // stays put, only the number of frames covering it changes
+                let channels = buffer.format.channels as usize;
+                let ratio = playback_speed as f64 * buffer.format.sample_rate as f64
+                    / output_rate as f64
+                    * drift.as_ref().map_or(1.0, DriftCorrector::ratio);
+                let samples = if samples.is_empty() && converting {
+                    // Empty buffer queued at stream end: play out what the converter held back
+                    resampler.flush(channels, ratio)
+                } else if ratio != 1.0 {
+                    tail_flushed = false;
+                    let started = Instant::now();
+                    let resampled = resampler.process(&samples, channels, ratio);
+                    trace!(
+                        frames = samples.len() / channels.max(1),
+                        elapsed_us = started.elapsed().as_micros() as u64,
+                        "Resampled"
+                    );
+                    queue.pool.give(samples);
+                    resampled
+                } else {
+                    samples
+                };
+                let format = AudioFormat {
+                    sample_rate: output_rate,
+                    ..buffer.format
+                };
+
+                // After a mono downmix both channels carry the same signal:
+                // balance still moves it between the speakers, a swap can't
+                let apply_balance = if balance == 0 && !swap_channels {
+                    false
+                } else if config.mono.is_some() && balance == 0 {
+                    if !warned_mono {
+                        warn!("Channel swap has no effect in mono mode");
+                        warned_mono = true;
+                    }
+                    false
+                } else if balance::applies_to(channels) {
+                    true
+                } else {
+                    if !warned_mono {
+                        warn!("Balance/channel swap ignored for mono stream");
+                        warned_mono = true;
+                    }
+                    false
+                };
+
+                // Measured on the decoded stream as heard after ReplayGain
+                let loudness_gain = match loudness {
+                    Some(ref mut loudness) => loudness.process(&samples, &format, replay_gain),
+                    None => 1.0,
+                };
+
+                // Volume, mute, ReplayGain and loudness combined into one linear gain
+                let gain = if muted {
+                    0.0
+                } else {
+                    volume_gain * replay_gain * loudness_gain
+                };
+
+                // Stop/pause in progress: ramp the written audio down to silence
+                let ramp = fade_out_deadline.map(|_| {
+                    fade_out.get_or_insert_with(|| Ramp::down(format.sample_rate, FADE_OUT))
+                });
+
+                // When this buffer starts on the device, against when the server wants it
+                let frames = samples.len() / channels.max(1);
+                let written_at = if out.places_writes() {
+                    write_at
+                } else {
+                    Instant::now()
+                };
+                let start = playout.next_start(written_at, out.latency());
+                // The device ran dry while this buffer was already due
+                let starved = playout
+                    .end()
+                    .is_some_and(|end| start > end + UNDERRUN_SLACK);
+                if starved
+                    && !samples.is_empty()
+                    && start + held_back > buffer.play_at + UNDERRUN_SLACK
+                {
+                    queue.shared.stats.underrun();
+                }
+                if let Some(ref mut drift) = drift {
+                    if !from_tail && !samples.is_empty() {
+                        let engaged = drift.is_engaged();
+                        let error = drift::error_us(start + held_back, buffer.play_at);
+                        let audio = Duration::from_secs_f64(frames as f64 / output_rate as f64);
+                        if let Some(ppm) = drift.update(error, audio) {
+                            queue.shared.stats.set_drift(ppm);
+                            if !engaged {
+                                info!(
+                                    "Playback is drifting from the server clock ({:+.1} ms), correcting the rate",
+                                    drift.error_us() / 1000.0
+                                );
+                            }
+                            debug!(
+                                error_ms = drift.error_us() / 1000.0,
+                                ppm, "Drift correction"
+                            );
+                        }
+                    }
+                }
+
+                // DC blocking and EQ run before volume so filter headroom isn't affected by it
+                let written = if config.bit_perfect {
+                    if !announced {
+                        info!(
+                            "Bit-perfect: {} Hz {}-bit {}ch, decoder → {} untouched (no volume, EQ, resampling or dither)",
+                            buffer.format.sample_rate,
+                            buffer.format.bit_depth,
+                            channels,
+                            out.name()
+                        );
+                        announced = true;
+                    }
+                    // Mute is honoured as digital silence, which is still bit-exact
+                    let samples = if muted {
+                        let mut silent = queue.pool.take(samples.len());
+                        Arc::get_mut(&mut silent)
+                            .expect("pooled buffers are unshared")
+                            .fill(Sample(0));
+                        queue.pool.give(samples);
+                        silent
+                    } else {
+                        samples
+                    };
+                    concealer.wrote(&samples, channels);
+                    let written = out.write(&samples);
+                    queue.pool.give(samples);
+                    written
+                } else if out.sample_format() == DeviceFormat::F32 {
+                    // Float device: convert once, process in f32, no integer round trips
+                    float::to_f32_into(&samples, &mut pcm);
+                    queue.pool.give(samples);
+                    if let Some(ref mut dc_block) = dc_block {
+                        dc_block.process_f32(&mut pcm, &format);
+                    }
+                    if let Some(ref mut eq) = eq {
+                        eq.process_f32(&mut pcm, &format);
+                    }
+                    if let Some(gain_db) = config.mono {
+                        mono::downmix_f32(&mut pcm, channels, gain_db);
+                    }
+                    if apply_balance {
+                        balance::apply_f32(&mut pcm, channels, balance, swap_channels);
+                    }
+                    match envelope {
+                        Some(envelope) => envelope.apply_f32(&mut pcm, channels, gain),
+                        None if gain != 1.0 => float::apply_gain(&mut pcm, gain),
+                        None => {}
+                    }
+                    if let Some(ramp) = ramp {
+                        ramp.apply_f32(&mut pcm, channels);
+                    }
+                    if let Some(ref mut ramp) = fade_in {
+                        ramp.apply_f32(&mut pcm, channels);
+                    }
+                    float::clamp(&mut pcm);
+                    concealer.wrote_f32(&pcm, channels);
+                    out.write_f32(&pcm)
+                } else {
+                    let samples = match dc_block {
+                        Some(ref mut dc_block) => dc_block.process(&samples, &format),
+                        None => samples,
+                    };
+                    let samples = match eq {
+                        Some(ref mut eq) => eq.process(&samples, &format),
+                        None => samples,
+                    };
+                    let samples = match config.mono {
+                        Some(gain_db) if channels > 1 => mono::downmix(&samples, channels, gain_db),
+                        _ => samples,
+                    };
+                    let samples = if apply_balance {
+                        balance::apply(&samples, channels, balance, swap_channels)
+                    } else {
+                        samples
+                    };
+                    let samples = match envelope {
+                        Some(envelope) => {
+                            in_place(samples, &queue.pool, |s| envelope.apply(s, channels, gain))
+                        }
+                        None if gain != 1.0 => {
+                            in_place(samples, &queue.pool, |s| apply_gain(s, gain))
+                        }
+                        None => samples,
+                    };
+                    let samples = match ramp {
+                        Some(ramp) => in_place(samples, &queue.pool, |s| ramp.apply(s, channels)),
+                        None => samples,
+                    };
+                    let samples = match fade_in {
+                        Some(ref mut ramp) => {
+                            in_place(samples, &queue.pool, |s| ramp.apply(s, channels))
+                        }
+                        None => samples,
+                    };
+                    // Last stage: re-quantize for a device narrower than 24 bits
+                    let processed = mixed
+                        || ratio != 1.0
+                        || eq.is_some()
+                        || dc_block.is_some()
+                        || apply_balance
+                        || (config.mono.is_some() && channels > 1)
+                        || gain != 1.0
+                        || envelope.is_some()
+                        || fade_out_deadline.is_some()
+                        || fade_in.is_some();
+                    let device_bits = out.bit_depth();
+                    let samples = if config.dither
+                        && dither::needed(device_bits, buffer.format.bit_depth, processed)
+                    {
+                        in_place(samples, &queue.pool, |s| {
+                            ditherer.process_in_place(s, channels, device_bits)
+                        })
+                    } else {
+                        samples
+                    };
+                    concealer.wrote(&samples, channels);
+                    let written = out.write(&samples);
+                    queue.pool.give(samples);
+                    written
+                };
+
+                if fade_in.as_ref().is_some_and(Ramp::is_done) {
+                    fade_in = None;
+                }
+
+                match written {
+                    Ok(()) => {
+                        recovery.write_ok();
+                        queue.shared.stats.played(frames);
+                        queue.shared.stats.set_latency(out.latency());
+                        if let (Some(trace), Some(end)) = (config.timing_trace.as_ref(), buffer_end)
+                        {
+                            trace.send(TimingRecord::Written(Written {
+                                timestamp: buffer.timestamp,
+                                end,
+                                written_at,
+                                lateness_us: drift::error_us(start + held_back, buffer.play_at)
+                                    as i64,
+                            }));
+                        }
+                        heard_until = buffer_end.or(heard_until);
+                        playout.advance(start, frames, output_rate);
+                    }
+                    Err(e) if recovery.write_failed() => {
+                        warn!("Audio device lost ({}), closing output and retrying", e);
+                        output = None;
+                        recovery.disconnected(Instant::now());
+                        *device_stats.lock().unwrap() = recovery.stats;
+                    }
+                    Err(e) => {
+                        error!("Output error: {}", e);
+                        *device_stats.lock().unwrap() = recovery.stats;
+                    }
+                }
+            } else if fade_out_deadline.is_some() {
+                // Nothing left to fade - finish the stop on the next pass
+                fade_out_deadline = Some(Instant::now());
+            } else if draining && converting && !tail_flushed && output_format.is_some() {
+                // Push one empty buffer through so the converter's last frames play
+                let flush = AudioBuffer {
+                    timestamp: 0,
+                    play_at: Instant::now(),
+                    samples: Arc::from(Vec::new()),
+                    format: output_format.clone().unwrap(),
+                };
+                pending = Some((flush, None));
+                tail_flushed = true;
+            } else if draining {
+                // Queue drained after stream end - close output until next stream
+                info!("→ Playback: drained, stopping");
+                let now = Instant::now();
+                let played_by = playout.end().map_or(now, |end| end.max(now));
+                finish_drains(&mut drains, Some(played_by));
+                concealer.reset(); // The stream ended, there's no gap to fill
+                stream_channels = None;
+                idle = park_output(
+                    &mut output,
+                    output_format.as_ref(),
+                    output_rate,
+                    config.keep_device_open,
+                    close_requested,
+                );
+                close_requested = false;
+                stopped = true;
+                draining = false;
+                queue.shared.publish(false, output.is_some(), heard_until);
+            } else {
+                // Queue empty: keep a playing device fed with silence, then
+                // sleep until something is queued
+                let bridge = match (output.as_mut(), output_format.as_ref()) {
+                    (Some(out), Some(format)) => conceal_gap(
+                        out.as_mut(),
+                        &mut concealer,
+                        &mut playout,
+                        (format.channels as usize, output_rate),
+                        None,
+                        &queue.shared.stats,
+                    ),
+                    _ => None,
+                };
+                let wait = bridge.map_or(IDLE_WAIT, |at| {
+                    at.saturating_duration_since(Instant::now()).min(IDLE_WAIT)
+                });
+                queue.shared.doorbell.wait(wait);
+            }
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.send(PlaybackControl::Shutdown);
+        let Some(playback) = self.playback.take() else {
+            return;
+        };
+        // A write blocked on a wedged device can outlast the wait; the thread
+        // is left to finish on its own then rather than hanging the caller
+        match self.playback_exited.recv_timeout(SHUTDOWN_WAIT) {
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                warn!(
+                    "Playback thread didn't stop within {:?}, leaving it behind",
+                    SHUTDOWN_WAIT
+                );
+            }
+            _ => {
+                let _ = playback.join();
+            }
+        }
+    }
+}
+
+/// Tell everyone waiting on a drain how it ended
+fn finish_drains(drains: &mut Vec<DrainDone>, played_by: Option<Instant>) {
+    for done in drains.drain(..) {
+        let _ = done.send(played_by);
+    }
+}
+
+/// Bridge a gap in the audio with silence, up to `until` and a chunk at a
+/// time, once the written audio is about to run out. Returns when to look
+/// again (now, after a write), or None when there's nothing to bridge: the
+/// device already ran dry, or the gap is filled up to `until`.
+fn conceal_gap(
+    out: &mut dyn OutputBackend,
+    concealer: &mut Concealer,
+    playout: &mut PlayoutClock,
+    (channels, rate): (usize, u32),
+    until: Option<Instant>,
+    stats: &PlayerStats,
+) -> Option<Instant> {
+    if !concealer.follows_audio() {
+        return None;
+    }
+    let now = Instant::now();
+    let latency = out.latency();
+    let end = playout.end()?;
+    if now + latency > end + UNDERRUN_SLACK {
+        return None;
+    }
+    if end > now + latency + conceal::LEAD {
+        return Some(end - latency - conceal::LEAD);
+    }
+    let start = playout.next_start(now, latency);
+    let stop = until.map_or(start + conceal::CHUNK, |until| {
+        until.min(start + conceal::CHUNK)
+    });
+    let frames = conceal::frames(stop.saturating_duration_since(start), rate);
+    if frames == 0 {
+        return None;
+    }
+    if !concealer.is_active() {
+        debug!("Queue ran dry, concealing the gap with silence");
+        stats.underrun();
+    }
+    let silence = concealer.silence(frames, channels, conceal::frames(conceal::EDGE, rate));
+    match out.write(&Arc::from(silence)) {
+        Ok(()) => {
+            playout.advance(start, frames, rate);
+            stats.concealed(stop - start);
+            Some(now)
+        }
+        Err(e) => {
+            // The next buffer's write goes through device recovery
+            debug!("Writing silence failed ({}), leaving the gap", e);
+            playout.reset();
+            None
+        }
+    }
+}
+
+/// Close the output after playback stops, or hand it over to be kept open
+fn park_output(
+    output: &mut Option<Box<dyn OutputBackend>>,
+    format: Option<&AudioFormat>,
+    output_rate: u32,
+    keep: KeepOpen,
+    close_requested: bool,
+) -> Option<IdleOutput> {
+    let idle = match (output.as_ref(), format) {
+        (Some(_), Some(format)) => IdleOutput::start(
+            keep,
+            close_requested,
+            format.channels,
+            output_rate,
+            Instant::now(),
+        ),
+        _ => None,
+    };
+    match idle {
+        Some(_) => info!("→ Playback: keeping output open"),
+        None => *output = None,
+    }
+    idle
+}
+
+/// One-shot linear gain ramp, continued across buffers
+struct Ramp {
+    total_frames: usize,
+    position: usize,
+    rising: bool,
+}
+
+impl Ramp {
+    /// Ramp to silence (stop/pause)
+    fn down(sample_rate: u32, length: Duration) -> Self {
+        Self::new(sample_rate, length, false)
+    }
+
+    /// Ramp from silence (output just opened)
+    fn up(sample_rate: u32, length: Duration) -> Self {
+        Self::new(sample_rate, length, true)
+    }
+
+    fn new(sample_rate: u32, length: Duration, rising: bool) -> Self {
+        let total_frames = (sample_rate as u128 * length.as_millis() / 1000) as usize;
+        Ramp {
+            total_frames: total_frames.max(1),
+            position: 0,
+            rising,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.position >= self.total_frames
+    }
+
+    /// Gain for the next frame
+    fn next_gain(&mut self) -> f32 {
+        self.position = (self.position + 1).min(self.total_frames);
+        let progress = self.position as f32 / self.total_frames as f32;
+        if self.rising {
+            progress
+        } else {
+            1.0 - progress
+        }
+    }
+
+    /// Apply the next part of the envelope; past the end a fade-out stays
+    /// silent and a fade-in stays at full level
+    fn apply(&mut self, samples: &mut [Sample], channels: usize) {
+        for frame in samples.chunks_exact_mut(channels.max(1)) {
+            let gain = self.next_gain();
+            for sample in frame.iter_mut() {
+                *sample = Sample((sample.0 as f32 * gain) as i32);
+            }
+        }
+    }
+
+    /// Same envelope for the f32 pipeline
+    fn apply_f32(&mut self, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_exact_mut(channels.max(1)) {
+            let gain = self.next_gain();
+            frame.iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+/// Rates the device an output config opens plays, None when it can't be asked
+fn probe_rates(config: &OutputConfig) -> Option<DeviceRates> {
+    if config.backend != OutputBackendKind::Cpal {
+        return None;
+    }
+    if config.exclusive {
+        return output::exclusive_rates(negotiate::CHANNELS)
+            .ok()
+            .filter(|rates| !rates.ranges.is_empty());
+    }
+    if config.native_only {
+        return device::native_rate(config.host.as_deref())
+            .ok()
+            .map(DeviceRates::only);
+    }
+    device::probe_rates(config.host.as_deref(), negotiate::CHANNELS)
+        .ok()
+        .filter(|rates| !rates.ranges.is_empty())
+}
+
+/// Stream timestamp (server µs) just past the end of a buffer
+fn end_timestamp(buffer: &AudioBuffer) -> i64 {
+    let channels = buffer.format.channels.max(1) as i64;
+    let frames = buffer.samples.len() as i64 / channels;
+    buffer.timestamp + frames * 1_000_000 / buffer.format.sample_rate.max(1) as i64
+}
+
+/// Drop the part of a buffer that plays before stream timestamp `from`;
+/// None when all of it does
+fn skip_heard(buffer: AudioBuffer, from: i64) -> Option<AudioBuffer> {
+    if end_timestamp(&buffer) <= from {
+        return None;
+    }
+    if buffer.timestamp >= from {
+        return Some(buffer);
+    }
+    let rate = buffer.format.sample_rate.max(1) as i64;
+    let channels = buffer.format.channels.max(1) as usize;
+    let frames = ((from - buffer.timestamp) * rate / 1_000_000) as usize;
+    let skipped = Duration::from_micros(frames as u64 * 1_000_000 / rate as u64);
+    Some(AudioBuffer {
+        timestamp: buffer.timestamp + skipped.as_micros() as i64,
+        play_at: buffer.play_at + skipped,
+        samples: Arc::from(&buffer.samples[frames * channels..]),
+        format: buffer.format,
+    })
+}
+
+/// `skip_heard` for a buffer with its envelope, which is trimmed to match
+fn skip_enveloped(
+    buffer: AudioBuffer,
+    envelope: Option<Envelope>,
+    from: i64,
+    stats: &PlayerStats,
+) -> Option<(AudioBuffer, Option<Envelope>)> {
+    let channels = buffer.format.channels.max(1) as usize;
+    let frames = buffer.samples.len() / channels;
+    let Some(trimmed) = skip_heard(buffer, from) else {
+        stats.dropped(1);
+        return None;
+    };
+    let skipped = frames - trimmed.samples.len() / channels;
+    stats.trimmed(skipped);
+    Some((trimmed, envelope.map(|env| env.after(skipped, frames))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Recorder;
+    use sendspin::audio::{AudioFormat, Codec, Sample};
+    use std::time::Instant;
+
+    /// 16-bit stereo PCM at 48 kHz
+    fn format() -> AudioFormat {
+        AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: 16,
+            codec_header: None,
+        }
+    }
+
+    /// Poll until the playback thread has got as far as `done` shows, for
+    /// up to 2 s; returns whether it did
+    fn wait_for(done: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !done() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
 
     #[test]
     fn test_player_creation() {
@@ -180,18 +2261,10 @@ mod tests {
     fn test_enqueue_buffer() {
         let player = Player::new(50);
 
-        let format = AudioFormat {
-            codec: Codec::Pcm,
-            sample_rate: 44100,
-            channels: 2,
-            bit_depth: 16,
-            codec_header: None,
-        };
-
         let samples = vec![Sample(0); 1024];
         let buffer = AudioBuffer {
             timestamp: 0,
-            format,
+            format: format(),
             samples: Arc::from(samples.into_boxed_slice()),
             play_at: Instant::now(),
         };
@@ -199,28 +2272,336 @@ mod tests {
         player.enqueue(buffer);
 
         // Verify buffer was added to queue
-        let queue_size = player.audio_queue.lock().unwrap().len();
+        let queue_size = player.queued_buffers();
         assert_eq!(queue_size, 1);
     }
 
     #[test]
-    fn test_stop_clears_queue() {
+    fn test_queue_and_state_queries() {
         let player = Player::new(50);
+        let start = Instant::now() + Duration::from_secs(60);
+        // 20 ms of 48 kHz stereo each
+        let buffer = |i: u32| AudioBuffer {
+            timestamp: i as i64 * 20_000,
+            format: format(),
+            samples: Arc::from(vec![Sample(0); 960 * 2].into_boxed_slice()),
+            play_at: start + Duration::from_millis(20) * i,
+        };
+        assert_eq!(player.queued_duration(), Duration::ZERO);
+        for i in 0..3 {
+            assert!(player.enqueue(buffer(i)));
+        }
+        assert_eq!(player.queued_buffers(), 3);
+        assert_eq!(player.queued_duration(), Duration::from_millis(60));
 
-        let format = AudioFormat {
-            codec: Codec::Pcm,
-            sample_rate: 44100,
-            channels: 2,
-            bit_depth: 16,
-            codec_header: None,
+        // Stopped from the start: nothing played, no output
+        assert!(!player.is_playing());
+        assert!(!player.output_open());
+        assert_eq!(player.last_played_timestamp(), None);
+
+        // Resumed, the buffers wait for their play time
+        player.resume();
+        assert!(wait_for(|| player.is_playing()));
+        assert_eq!(player.queued_duration(), Duration::from_millis(60));
+
+        // A stop discards them
+        player.stop();
+        assert!(wait_for(|| !player.is_playing()));
+        assert!(wait_for(|| player.queued_buffers() == 0));
+        assert_eq!(player.queued_duration(), Duration::ZERO);
+        assert_eq!(player.queued_buffers(), 0);
+    }
+
+    #[test]
+    fn test_stop_resets_playback_speed() {
+        let player = Player::new(100);
+        assert_eq!(player.playback_speed(), 1.0);
+        player.set_playback_speed(1.5);
+        assert!(wait_for(|| player.playback_speed() == 1.5));
+
+        // The owner learns the player went back to normal speed
+        player.stop();
+        assert!(wait_for(|| player.playback_speed() == 1.0));
+    }
+
+    #[test]
+    fn test_replay_gain_applied_is_published() {
+        let player = Player::new(100);
+        assert_eq!(player.replay_gain(), 1.0);
+        player.set_replay_gain(0.5);
+        assert!(wait_for(|| player.replay_gain() == 0.5));
+    }
+
+    #[test]
+    fn test_enqueue_honors_buffer_capacity() {
+        // Room for exactly two 1024-sample 16-bit buffers
+        let player = Player::with_config(PlayerConfig {
+            buffer_capacity: 4096,
+            ..Default::default()
+        });
+        let buffer = || AudioBuffer {
+            timestamp: 0,
+            format: format(),
+            samples: Arc::from(vec![Sample(0); 1024].into_boxed_slice()),
+            play_at: Instant::now() + Duration::from_secs(60),
+        };
+
+        assert!(player.enqueue(buffer()));
+        assert!(player.enqueue(buffer()));
+        assert!(!player.enqueue(buffer()));
+        assert_eq!(player.queued_buffers(), 2);
+
+        // Room again once the queue drains
+        player.stop();
+        assert!(wait_for(|| player.queued_buffers() == 0));
+        assert!(player.enqueue(buffer()));
+    }
+
+    fn buffer_at(timestamp: i64, frames: usize) -> AudioBuffer {
+        AudioBuffer {
+            timestamp,
+            play_at: Instant::now(),
+            samples: (0..frames * 2).map(|i| Sample(i as i32)).collect(),
+            format: format(),
+        }
+    }
+
+    #[test]
+    fn test_skip_heard_after_pause() {
+        // 480 frames = 10 ms starting at 1 s
+        let buffer = buffer_at(1_000_000, 480);
+        assert_eq!(end_timestamp(&buffer), 1_010_000);
+
+        // Entirely heard before the pause point: dropped
+        assert!(skip_heard(buffer.clone(), 1_010_000).is_none());
+        // Entirely after it: untouched
+        let kept = skip_heard(buffer.clone(), 1_000_000).unwrap();
+        assert_eq!(kept.samples.len(), 960);
+
+        // Straddling it: starts exactly where playback paused
+        let play_at = buffer.play_at;
+        let trimmed = skip_heard(buffer, 1_002_500).unwrap();
+        assert_eq!(trimmed.timestamp, 1_002_500);
+        assert_eq!(trimmed.samples.len(), (480 - 120) * 2);
+        assert_eq!(trimmed.samples[0].0, 240); // Frame 120, left channel
+        assert_eq!(trimmed.play_at, play_at + Duration::from_micros(2500));
+    }
+
+    #[test]
+    fn test_pause_position_unset_until_paused() {
+        let player = Player::new(50);
+        assert_eq!(player.pause_position(), None);
+        player.enqueue(buffer_at(0, 960));
+        player.pause();
+        // Nothing was playing: nothing was heard by the time the pause cleared the queue
+        assert!(wait_for(|| player.queued_buffers() == 0));
+        assert_eq!(player.pause_position(), None);
+    }
+
+    #[test]
+    fn test_bit_perfect_output_matches_decoder() {
+        use crate::output::{OutputBackendKind, OutputConfig};
+        use sendspin::audio::decode::{Decoder, PcmDecoder};
+
+        // Fixture: 16-bit stereo, a full-range ramp in three 10 ms chunks
+        let fixture: Vec<u8> = (0..480 * 2 * 3)
+            .flat_map(|i: i32| ((i * 97 % 65536 - 32768) as i16).to_le_bytes())
+            .collect();
+        let decoder = PcmDecoder::new(16);
+        let decoded: Vec<Arc<[Sample]>> = fixture
+            .chunks(480 * 2 * 2)
+            .map(|chunk| decoder.decode(chunk).unwrap())
+            .collect();
+        let expected: Vec<u8> = decoded
+            .iter()
+            .flat_map(|samples| output::to_i16(samples))
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        let path =
+            std::env::temp_dir().join(format!("sendspin-bitperfect-{}.pcm", std::process::id()));
+        // Volume, ReplayGain and the default fade-in would all change samples
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 30,
+            fade_in_ms: 0,
+            bit_perfect: true,
+            output: OutputConfig {
+                backend: OutputBackendKind::File,
+                file: Some(path.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        player.set_replay_gain(0.5);
+        let start = Instant::now() + Duration::from_millis(20);
+        for (i, samples) in decoded.into_iter().enumerate() {
+            player.enqueue(AudioBuffer {
+                timestamp: i as i64 * 10_000,
+                play_at: start + Duration::from_millis(i as u64 * 10),
+                samples,
+                format: format(),
+            });
+        }
+        player.resume();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let written = loop {
+            let written = std::fs::read(&path).unwrap_or_default();
+            if written.len() >= expected.len() || Instant::now() > deadline {
+                break written;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        drop(player);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(written.len(), expected.len());
+        assert!(written == expected, "output differs from the decoder's");
+    }
+
+    #[tokio::test]
+    async fn test_bit_perfect_mute_writes_silence() {
+        let recorder = Recorder::new();
+        let player = Player::with_config(PlayerConfig {
+            fade_in_ms: 0,
+            bit_perfect: true,
+            output: OutputConfig {
+                recorder: Some(recorder.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        player.set_muted(true);
+        player.resume();
+        let start = Instant::now() + Duration::from_millis(20);
+        player.enqueue(level_buffer(0, start, 1000));
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        let samples = recorder.samples();
+        assert_eq!(samples.len(), 960 * 2);
+        assert!(samples.iter().all(|&s| s == Sample(0)));
+    }
+
+    #[test]
+    fn test_envelope_scales_buffer_under_volume() {
+        use crate::output::{OutputBackendKind, OutputConfig};
+
+        let path =
+            std::env::temp_dir().join(format!("sendspin-envelope-{}.pcm", std::process::id()));
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 50,
+            fade_in_ms: 0,
+            output: OutputConfig {
+                backend: OutputBackendKind::File,
+                file: Some(path.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        // 16-bit stereo, 5 frames at 4096 (as i16) fading to silence
+        player.enqueue_with_envelope(
+            AudioBuffer {
+                timestamp: 0,
+                play_at: Instant::now() + Duration::from_millis(20),
+                samples: Arc::from(vec![Sample(4096 << 8); 5 * 2]),
+                format: format(),
+            },
+            Envelope::new(1.0, 0.0),
+        );
+        player.resume();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let written = loop {
+            let written = std::fs::read(&path).unwrap_or_default();
+            if written.len() >= 5 * 2 * 2 || Instant::now() > deadline {
+                break written;
+            }
+            std::thread::sleep(Duration::from_millis(10));
         };
+        drop(player);
+        let _ = std::fs::remove_file(&path);
+        let left: Vec<i16> = written
+            .chunks_exact(4)
+            .map(|frame| i16::from_le_bytes([frame[0], frame[1]]))
+            .collect();
+        // Half volume times the envelope: 2048 down to silence
+        assert_eq!(left, vec![2048, 1536, 1024, 512, 0]);
+    }
+
+    /// 10 ms chunks of 16-bit stereo, played to a file untouched; returns
+    /// what the file holds once `bytes` have arrived
+    fn play_to_file(scheduling: output::Scheduling, offsets_ms: &[u64], bytes: usize) -> Vec<u8> {
+        use crate::output::{OutputBackendKind, OutputConfig};
+        let path = std::env::temp_dir().join(format!(
+            "sendspin-scheduling-{:?}-{}.pcm",
+            scheduling,
+            std::process::id()
+        ));
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 30,
+            fade_in_ms: 0,
+            bit_perfect: true,
+            output: OutputConfig {
+                backend: OutputBackendKind::File,
+                file: Some(path.clone()),
+                scheduling,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let start = Instant::now() + Duration::from_millis(20);
+        for (i, &offset) in offsets_ms.iter().enumerate() {
+            player.enqueue(AudioBuffer {
+                timestamp: offset as i64 * 1000,
+                play_at: start + Duration::from_millis(offset),
+                samples: vec![Sample((i as i32 + 1) << 8); 960].into(),
+                format: format(),
+            });
+        }
+        player.resume();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let written = loop {
+            let written = std::fs::read(&path).unwrap_or_default();
+            if written.len() >= bytes || Instant::now() > deadline {
+                break written;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        drop(player);
+        let _ = std::fs::remove_file(&path);
+        written
+    }
+
+    #[test]
+    fn test_callback_scheduling_places_audio_at_play_time() {
+        // Third chunk due 20 ms after the second one ends
+        let offsets = [0, 10, 40];
+        let chunk = |value: i16| -> Vec<u8> { value.to_le_bytes().repeat(960) };
+        let gap = vec![0u8; 960 * 2 * 2];
+
+        // Callback: the gap is kept, to the sample, as silence in the file
+        let expected = [chunk(1), chunk(2), gap, chunk(3)].concat();
+        let placed = play_to_file(output::Scheduling::Callback, &offsets, expected.len());
+        assert_eq!(placed.len(), expected.len());
+        assert!(placed == expected, "audio not at its play time");
+
+        // Write: chunks are written as they come due, and the file (a device
+        // that never underruns) closes the gap: the third chunk lands 20 ms early
+        let expected = [chunk(1), chunk(2), chunk(3)].concat();
+        let written = play_to_file(output::Scheduling::Write, &offsets, expected.len());
+        assert!(written == expected, "write path changed");
+    }
+
+    #[test]
+    fn test_stop_clears_queue() {
+        let player = Player::new(50);
 
         // Add multiple buffers
         for _ in 0..5 {
             let samples = vec![Sample(0); 1024];
             let buffer = AudioBuffer {
                 timestamp: 0,
-                format: format.clone(),
+                format: format(),
                 samples: Arc::from(samples.into_boxed_slice()),
                 play_at: Instant::now(),
             };
@@ -229,12 +2610,503 @@ mod tests {
 
         // Stop should clear queue
         player.stop();
+        assert!(wait_for(|| player.queued_buffers() == 0));
+    }
 
-        // Give the playback thread time to process the stop command
-        std::thread::sleep(Duration::from_millis(50));
+    #[test]
+    fn test_stop_keeps_audio_queued_after_it() {
+        let player = Player::new(50);
+        let buffer = || AudioBuffer {
+            timestamp: 0,
+            format: format(),
+            samples: Arc::from(vec![Sample(0); 1024].into_boxed_slice()),
+            play_at: Instant::now() + Duration::from_secs(60),
+        };
 
-        let queue_size = player.audio_queue.lock().unwrap().len();
-        assert_eq!(queue_size, 0);
+        // The next stream's audio can arrive before the playback thread has
+        // handled the stop: only what was queued before the stop goes
+        for _ in 0..5 {
+            player.enqueue(buffer());
+        }
+        player.stop();
+        for _ in 0..3 {
+            player.enqueue(buffer());
+        }
+        assert!(wait_for(|| player.queued_buffers() == 3));
+    }
+
+    #[test]
+    fn test_coalesced_chunks_at_stream_boundaries() {
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 50,
+            coalesce: Duration::from_millis(40),
+            ..Default::default()
+        });
+        let start = Instant::now() + Duration::from_secs(60);
+        let chunk = |timestamp: i64| AudioBuffer {
+            timestamp,
+            format: format(),
+            samples: Arc::from(vec![Sample(0); 960].into_boxed_slice()), // 10 ms
+            play_at: start + Duration::from_micros(timestamp as u64),
+        };
+
+        for i in 0..6 {
+            player.enqueue(chunk(i * 10_000));
+        }
+        // Four chunks made one buffer, two are held back
+        assert_eq!(player.queued_buffers(), 1);
+
+        // A clear takes the held-back chunks with the rest of the stream
+        player.stop();
+        assert!(wait_for(|| player.queued_buffers() == 0));
+
+        // The next stream starts a buffer of its own, queued by the drain at
+        // its end even though it's short
+        player.enqueue(chunk(60_000));
+        assert_eq!(player.queued_buffers(), 0);
+        player.drain();
+        assert_eq!(player.queued_buffers(), 1);
+    }
+
+    #[test]
+    fn test_coalesce_window_sends_held_chunks_on() {
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 50,
+            coalesce: Duration::from_millis(40),
+            coalesce_window: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        let start = Instant::now() + Duration::from_secs(60);
+        player.enqueue(AudioBuffer {
+            timestamp: 0,
+            format: format(),
+            samples: Arc::from(vec![Sample(0); 960].into_boxed_slice()), // 10 ms
+            play_at: start,
+        });
+        assert_eq!(player.queued_buffers(), 0);
+        // No chunk follows: the network side's timer queues it once the window is over
+        let deadline = player.coalesce_deadline().unwrap();
+        player.flush_expired();
+        assert_eq!(player.queued_buffers(), 0);
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        player.flush_expired();
+        assert_eq!(player.queued_buffers(), 1);
+        assert_eq!(player.coalesce_deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn test_drain_keeps_queued_tail() {
+        let (player, recorder) = recording_player(100);
+
+        // Tail of a stream still waiting to be played
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..5 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+
+        // Unlike stop, drain must not discard what is already queued
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        let samples = recorder.samples();
+        assert_eq!(samples.len(), 5 * 960 * 2);
+        assert!(samples.iter().all(|&s| s == Sample(1000)));
+    }
+
+    /// A player on the null backend, resumed, and a 20 ms buffer maker
+    fn null_player() -> (Player, impl Fn(u32, Instant) -> AudioBuffer) {
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 50,
+            output: OutputConfig {
+                backend: OutputBackendKind::Null,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        player.resume();
+        let buffer = |i: u32, start: Instant| AudioBuffer {
+            timestamp: i as i64 * 20_000,
+            format: format(),
+            samples: Arc::from(vec![Sample(0); 960 * 2].into_boxed_slice()),
+            play_at: start + Duration::from_millis(20) * i,
+        };
+        (player, buffer)
+    }
+
+    #[tokio::test]
+    async fn test_drain_of_empty_queue_resolves_at_once() {
+        let (player, _) = null_player();
+        let drained = tokio::time::timeout(Duration::from_millis(500), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+
+        // Stopped with nothing queued: nothing left to play either
+        player.stop();
+        assert_eq!(player.drain().await, Drained::Played);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_queued_audio_to_play() {
+        let (player, buffer) = null_player();
+        let start = Instant::now() + Duration::from_millis(50);
+        for i in 0..5 {
+            player.enqueue(buffer(i, start));
+        }
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        // The last buffer ends 100 ms after the first one starts
+        assert!(Instant::now() >= start + Duration::from_millis(100));
+        assert_eq!(player.queued_duration(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_stop_interrupts_drain() {
+        let (player, buffer) = null_player();
+        let start = Instant::now() + Duration::from_secs(5);
+        for i in 0..5 {
+            player.enqueue(buffer(i, start));
+        }
+        let drain = player.drain();
+        player.stop();
+        let drained = tokio::time::timeout(Duration::from_secs(1), drain).await;
+        assert_eq!(drained, Ok(Drained::Interrupted));
+    }
+
+    #[tokio::test]
+    async fn test_output_open_failure_reaches_owner() {
+        // The file backend can't open without a path, which ends the thread
+        let player = Player::with_config(PlayerConfig {
+            output: OutputConfig {
+                backend: OutputBackendKind::File,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(player.failure(), None);
+        player.resume();
+        player.enqueue(buffer_at(0, 960));
+        let failed = tokio::time::timeout(Duration::from_secs(2), player.playback_failed())
+            .await
+            .expect("the failure is reported");
+        assert!(failed.reason.contains("--output-file"), "{}", failed);
+        assert_eq!(player.failure(), Some(failed));
+    }
+
+    /// A playing player writing to an in-memory recorder, without fade-in
+    fn recording_player(initial_volume: u8) -> (Player, Recorder) {
+        let recorder = Recorder::new();
+        let player = Player::with_config(PlayerConfig {
+            initial_volume,
+            fade_in_ms: 0,
+            output: OutputConfig {
+                recorder: Some(recorder.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        player.resume();
+        (player, recorder)
+    }
+
+    /// 20 ms of 16-bit stereo at a constant level, the `i`th after `start`
+    fn level_buffer(i: u32, start: Instant, level: i32) -> AudioBuffer {
+        AudioBuffer {
+            timestamp: i as i64 * 20_000,
+            play_at: start + Duration::from_millis(20) * i,
+            samples: Arc::from(vec![Sample(level); 960 * 2]),
+            format: format(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_stream_after_pause_plays_from_its_start() {
+        let (player, recorder) = recording_player(100);
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..10 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+        assert!(wait_for(|| recorder.frames() > 0));
+        player.pause();
+        assert!(wait_for(|| player.pause_position().is_some()));
+        assert!(player.pause_position().is_some_and(|at| at > 0));
+
+        // The next stream's timestamps start over, below the pause point:
+        // none of it counts as heard before the pause
+        let before = recorder.samples().len();
+        player.crossfade();
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..3 {
+            player.enqueue(level_buffer(i, start, 2000));
+        }
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        assert_eq!(recorder.samples().len() - before, 3 * 960 * 2);
+    }
+
+    #[test]
+    fn test_stop_after_interrupted_pause_is_not_a_pause() {
+        let (player, recorder) = recording_player(100);
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..10 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+        assert!(wait_for(|| recorder.frames() > 0));
+        // Played again while the pause was still fading out, then stopped
+        player.pause();
+        player.resume();
+        player.stop();
+        assert!(wait_for(|| !player.is_playing()));
+        assert_eq!(player.pause_position(), None);
+    }
+
+    #[tokio::test]
+    async fn test_recorded_writes_start_at_play_time() {
+        let (player, recorder) = recording_player(100);
+        let start = Instant::now() + Duration::from_millis(50);
+        for i in 0..3 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+
+        let writes = recorder.writes();
+        assert_eq!(writes.len(), 3);
+        for (i, write) in writes.iter().enumerate() {
+            let play_at = start + Duration::from_millis(20) * i as u32;
+            // Never early; late only by scheduling noise
+            assert!(write.at >= play_at, "write {} early", i);
+            assert!(
+                write.at < play_at + Duration::from_millis(15),
+                "write {} late",
+                i
+            );
+        }
+        assert_eq!(recorder.frames(), 3 * 960);
+
+        let stats = player.stats().snapshot();
+        assert_eq!(stats.buffers_played, 3);
+        assert_eq!(stats.frames_played, 3 * 960);
+        assert_eq!(stats.device_opens, 1);
+        assert_eq!(stats.dropped_buffers, 0);
+        assert_eq!(player.stats().reset().buffers_played, 3);
+        assert_eq!(player.stats().snapshot().buffers_played, 0);
+    }
+
+    #[tokio::test]
+    async fn test_timing_trace_records_writes() {
+        let recorder = Recorder::new();
+        let (trace, mut records) = TimingTrace::channel(16);
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 100,
+            fade_in_ms: 0,
+            output: OutputConfig {
+                recorder: Some(recorder.clone()),
+                ..Default::default()
+            },
+            timing_trace: Some(trace),
+            ..Default::default()
+        });
+        player.resume();
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..2 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+
+        let writes = recorder.writes();
+        for (i, write) in writes.iter().enumerate() {
+            match records.try_recv() {
+                Ok(TimingRecord::Written(record)) => {
+                    assert_eq!(record.timestamp, i as i64 * 20_000);
+                    assert_eq!(record.end, record.timestamp + 20_000);
+                    assert!(record.written_at <= write.at);
+                    // On time, give or take scheduling noise
+                    assert!(record.lateness_us.abs() < 15_000, "{:?}", record);
+                }
+                other => panic!("expected a write, got {:?}", other),
+            }
+        }
+        assert!(records.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_debug_state() {
+        let (player, _recorder) = recording_player(50);
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..2 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        player.stats().reset();
+
+        let state = player.debug_state();
+        assert_eq!(state.current_volume, 50);
+        assert_eq!(state.requested_volume, 50);
+        assert_eq!(state.queue_len, 0);
+        // Frames since start, whatever --stats took
+        assert_eq!(state.frames_played, 2 * 960);
+        assert_eq!(state.since_underrun, None);
+        assert!(state.wakeups > 0);
+        assert_eq!(state.failure, None);
+
+        let state = PlayerDebugState {
+            stopped: false,
+            current_volume: 50,
+            requested_volume: 30,
+            queue_len: 12,
+            queued: Duration::from_millis(240),
+            output_open: true,
+            frames_played: 1920,
+            since_underrun: Some(Duration::from_millis(3200)),
+            wakeups: 7,
+            failure: None,
+        };
+        assert_eq!(
+            state.to_string(),
+            "player: playing, volume 50 (30 requested), queue 12 buffers (240 ms), output open, 1920 frames played, last underrun 3.2 s ago, 7 wakeups"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_volume_scales_recorded_samples() {
+        let (player, recorder) = recording_player(50);
+        let start = Instant::now() + Duration::from_millis(20);
+        for i in 0..2 {
+            player.enqueue(level_buffer(i, start, 1000));
+        }
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        let samples = recorder.samples();
+        assert_eq!(samples.len(), 2 * 960 * 2);
+        assert!(samples.iter().all(|&s| s == Sample(500)));
+
+        // Muted, the next stream is written as silence at the same pace
+        player.set_muted(true);
+        player.resume();
+        let start = Instant::now() + Duration::from_millis(20);
+        player.enqueue(level_buffer(2, start, 1000));
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+        let samples = recorder.samples();
+        assert_eq!(samples.len(), 3 * 960 * 2);
+        assert!(samples[2 * 960 * 2..].iter().all(|&s| s == Sample(0)));
+    }
+
+    #[tokio::test]
+    async fn test_underrun_is_concealed_with_silence() {
+        let (player, recorder) = recording_player(100);
+        let start = Instant::now() + Duration::from_millis(30);
+        player.enqueue(level_buffer(0, start, 1000));
+        // The next audio arrives 80 ms after the first ran out
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        player.enqueue(level_buffer(5, start, 1000));
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+
+        let writes = recorder.writes();
+        assert!(writes.len() > 3, "{} writes", writes.len());
+        let (first, rest) = writes.split_first().unwrap();
+        let (last, gap) = rest.split_last().unwrap();
+        assert!(first.samples.iter().all(|&s| s == Sample(1000)));
+        // The silence glides down from the last level, then stays at zero
+        let glide = &gap[0].samples;
+        assert!(glide[0].0 > 900 && glide[0].0 < 1000, "{:?}", glide[0]);
+        assert_eq!(glide[glide.len() - 1], Sample(0));
+        assert!(gap[1..]
+            .iter()
+            .all(|write| write.samples.iter().all(|&s| s == Sample(0))));
+        // The audio after it fades in
+        assert!(last.samples[0].0 < 100);
+        assert_eq!(last.samples[last.samples.len() - 1], Sample(1000));
+
+        let stats = player.stats().snapshot();
+        assert_eq!(stats.underruns, 1);
+        let concealed = stats.concealed.as_millis();
+        assert!(
+            (50..=120).contains(&concealed),
+            "{} ms concealed",
+            concealed
+        );
+        assert_eq!(stats.buffers_played, 2);
+    }
+
+    #[tokio::test]
+    async fn test_channel_mismatch_mid_stream() {
+        let (player, recorder) = recording_player(100);
+        let start = Instant::now() + Duration::from_millis(30);
+        player.enqueue(level_buffer(0, start, 1000));
+        // A mono buffer in a stereo stream is remapped, not reopened for
+        let mut mono = level_buffer(1, start, 1000);
+        mono.samples = Arc::from(vec![Sample(1000); 960]);
+        mono.format.channels = 1;
+        player.enqueue(mono);
+        player.enqueue(level_buffer(2, start, 1000));
+        // Half a frame short: dropped
+        let mut ragged = level_buffer(3, start, 1000);
+        ragged.samples = Arc::from(vec![Sample(1000); 960 * 2 - 1]);
+        player.enqueue(ragged);
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+
+        let writes = recorder.writes();
+        assert!(writes.len() >= 3, "{} writes", writes.len());
+        for write in &writes[..3] {
+            assert_eq!(write.format.channels, 2);
+            assert_eq!(write.samples.len(), 960 * 2);
+            assert!(write.samples.iter().all(|&s| s == Sample(1000)));
+        }
+        let stats = player.stats().snapshot();
+        assert_eq!(stats.device_opens, 1);
+        assert_eq!(stats.buffers_played, 3);
+        assert_eq!(stats.dropped_buffers, 1);
+    }
+
+    /// A stopped player with five 20 ms buffers (ts 0 to 100 000) queued
+    fn seek_player() -> Player {
+        let player = Player::new(50);
+        let start = Instant::now() + Duration::from_secs(60);
+        for i in 0..5u32 {
+            player.enqueue(AudioBuffer {
+                timestamp: i as i64 * 20_000,
+                format: format(),
+                samples: Arc::from(vec![Sample(0); 960 * 2].into_boxed_slice()),
+                play_at: start + Duration::from_millis(20) * i,
+            });
+        }
+        player
+    }
+
+    #[test]
+    fn test_seek_to_buffer_boundary() {
+        let player = seek_player();
+        player.seek_to(40_000);
+        assert!(wait_for(|| player.seek_dropped() > 0));
+        // The first two end exactly at the target; the rest stay whole
+        assert_eq!(player.seek_dropped(), 2);
+        assert_eq!(player.queued_buffers(), 3);
+        assert_eq!(player.queued_duration(), Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_seek_to_middle_of_buffer() {
+        let player = seek_player();
+        player.seek_to(50_000);
+        assert!(wait_for(|| player.seek_dropped() > 0));
+        // Two dropped, the one across the target trimmed and held out of the queue
+        assert_eq!(player.seek_dropped(), 2);
+        assert_eq!(player.queued_buffers(), 2);
+        assert_eq!(player.queued_duration(), Duration::from_millis(40));
+        let stats = player.stats().snapshot();
+        assert_eq!(stats.dropped_buffers, 2);
+        assert_eq!(stats.trimmed_frames, 480); // 10 ms at 48 kHz
+
+        // A target behind everything queued drops nothing; the volume change
+        // is applied once the seek sent before it has been handled
+        player.seek_to(0);
+        player.set_volume(60);
+        assert!(wait_for(|| player.debug_state().current_volume == 60));
+        assert_eq!(player.seek_dropped(), 2);
+        assert_eq!(player.queued_buffers(), 2);
     }
 
     #[test]
@@ -244,6 +3116,15 @@ mod tests {
         // Test all control commands send successfully
         assert!(player.control_tx.send(PlaybackControl::Stop).is_ok());
         assert!(player.control_tx.send(PlaybackControl::Resume).is_ok());
+        let (done, _) = oneshot::channel();
+        assert!(player.control_tx.send(PlaybackControl::Drain(done)).is_ok());
+        assert!(player.control_tx.send(PlaybackControl::Crossfade).is_ok());
+        assert!(player.control_tx.send(PlaybackControl::FadeOut).is_ok());
+        assert!(player.control_tx.send(PlaybackControl::CloseOutput).is_ok());
+        assert!(player
+            .control_tx
+            .send(PlaybackControl::SetPlaybackSpeed(1.02))
+            .is_ok());
         assert!(player
             .control_tx
             .send(PlaybackControl::SetVolume(80))
@@ -258,9 +3139,192 @@ mod tests {
         player.set_volume(0);
         player.set_volume(50);
         player.set_volume(100);
+        assert!(wait_for(|| player.debug_state().current_volume == 100));
+    }
+
+    #[test]
+    fn test_idle_player_sleeps() {
+        let player = Player::new(50);
+        let idle = Duration::from_millis(500);
+
+        // Stopped: only the periodic check, not a 10 ms poll
+        std::thread::sleep(idle);
+        let stopped = player.playback_wakeups();
+        assert!(stopped <= 3, "{} wakeups while stopped", stopped);
+
+        // A command wakes it straight away
+        player.resume();
+        assert!(wait_for(|| player.is_playing()));
+        assert!(player.playback_wakeups() > stopped);
+
+        // Playing an empty queue: parked until audio arrives
+        let resumed = player.playback_wakeups();
+        std::thread::sleep(idle);
+        let empty = player.playback_wakeups() - resumed;
+        assert!(empty <= 3, "{} wakeups with an empty queue", empty);
+    }
+
+    #[test]
+    fn test_dropped_players_end_their_threads() {
+        for _ in 0..5 {
+            let player = Player::new(50);
+            player.resume();
+            // The playback thread's end of the queue shares this
+            let shared = Arc::clone(&player.queue_shared);
+            assert!(Arc::strong_count(&shared) > 2);
+            drop(player);
+            assert_eq!(
+                Arc::strong_count(&shared),
+                1,
+                "playback thread still running"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_balance_applies_after_mono_downmix() {
+        let recorder = Recorder::new();
+        let player = Player::with_config(PlayerConfig {
+            initial_volume: 100,
+            fade_in_ms: 0,
+            mono: Some(mono::DEFAULT_GAIN_DB),
+            balance: 50,
+            output: OutputConfig {
+                recorder: Some(recorder.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        player.resume();
+        // Right channel only: the downmix puts it on both, balance halves the left
+        let mut buffer = level_buffer(0, Instant::now() + Duration::from_millis(20), 0);
+        let samples: Vec<Sample> = (0..960 * 2)
+            .map(|i| Sample(if i % 2 == 1 { 100_000 } else { 0 }))
+            .collect();
+        buffer.samples = Arc::from(samples);
+        player.enqueue(buffer);
+        let drained = tokio::time::timeout(Duration::from_secs(2), player.drain()).await;
+        assert_eq!(drained, Ok(Drained::Played));
+
+        let samples = recorder.samples();
+        let (left, right) = (samples[0].0, samples[1].0);
+        assert!(right > 45_000, "right {}", right);
+        assert!(
+            (2 * left - right).abs() <= 2,
+            "left {} right {}",
+            left,
+            right
+        );
+    }
+
+    #[test]
+    fn test_distant_buffer_waits_in_few_wakeups() {
+        let (player, recorder) = recording_player(100);
+        assert!(wait_for(|| player.is_playing()));
+        let before = player.playback_wakeups();
+        let start = Instant::now() + Duration::from_secs(2);
+        player.enqueue(level_buffer(0, start, 1000));
+
+        let deadline = start + Duration::from_secs(1);
+        while recorder.frames() == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let writes = recorder.writes();
+        assert!(!writes.is_empty(), "nothing written");
+        assert!(writes[0].at >= start);
+        // The enqueue, the wait up to the margin, the write: not a 1 ms poll
+        let wakeups = player.playback_wakeups() - before;
+        assert!(
+            wakeups <= 6,
+            "{} wakeups waiting for a buffer 2 s out",
+            wakeups
+        );
+    }
+
+    #[test]
+    fn test_adjust_volume_clamps() {
+        assert_eq!(adjusted_volume(50, 5), 55);
+        assert_eq!(adjusted_volume(98, 5), 100);
+        assert_eq!(adjusted_volume(3, -5), 0);
+        assert_eq!(adjusted_volume(100, i8::MIN), 0);
+
+        // The reported volume follows absolute and relative changes in order
+        let player = Player::new(50);
+        assert_eq!(player.adjust_volume(10), 60);
+        assert_eq!(player.adjust_volume(60), 100);
+        assert_eq!(player.volume(), 100);
+        player.set_volume(20);
+        assert_eq!(player.adjust_volume(-30), 0);
+        assert_eq!(player.volume(), 0);
+    }
+
+    #[test]
+    fn test_fade_out_reaches_silence() {
+        // 100-frame fade at 2 kHz over two stereo buffers of a full-scale tone
+        let mut fade = Ramp::down(2000, FADE_OUT);
+        assert_eq!(fade.total_frames, 100);
+
+        let tone = vec![Sample(SAMPLE_MAX); 120];
+        let mut first = tone.clone();
+        fade.apply(&mut first, 2);
+        assert!(!fade.is_done());
+        let mut second = tone.clone();
+        fade.apply(&mut second, 2);
+        assert!(fade.is_done());
+
+        // Envelope starts near full scale and never rises
+        assert!(first[0].0 > SAMPLE_MAX / 100 * 98);
+        let written: Vec<i32> = first.iter().chain(second.iter()).map(|s| s.0).collect();
+        assert!(written.windows(2).all(|pair| pair[1] <= pair[0]));
+
+        // The last frames written before the output closes are silent
+        assert_eq!(second[78].0, 0);
+        assert_eq!(second[79].0, 0);
+        assert!(second[76].0 < SAMPLE_MAX / 50);
+        let mut after = tone;
+        fade.apply(&mut after, 2);
+        assert!(after.iter().all(|s| s.0 == 0));
+    }
+
+    #[test]
+    fn test_fade_in_rises_to_full_level() {
+        // 10 ms at 2 kHz = 20 frames
+        let mut fade = Ramp::up(2000, Duration::from_millis(10));
+        let mut out = vec![Sample(SAMPLE_MAX); 60];
+        fade.apply(&mut out, 2);
+        assert!(fade.is_done());
+
+        let written: Vec<i32> = out.iter().map(|s| s.0).collect();
+        assert!(written[0] < SAMPLE_MAX / 10);
+        assert!(written.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(written[40..].iter().all(|&s| s == SAMPLE_MAX));
+
+        // f32 pipeline follows the same envelope
+        let mut fade = Ramp::up(2000, Duration::from_millis(10));
+        let mut pcm = vec![1.0f32; 60];
+        fade.apply_f32(&mut pcm, 2);
+        assert_eq!(pcm[0], 0.05);
+        assert_eq!(pcm[59], 1.0);
+    }
+
+    #[test]
+    fn test_fade_out_stops_and_clears() {
+        let player = Player::new(50);
+
+        for _ in 0..5 {
+            let samples = vec![Sample(0); 1024];
+            let buffer = AudioBuffer {
+                timestamp: 0,
+                format: format(),
+                samples: Arc::from(samples.into_boxed_slice()),
+                play_at: Instant::now(),
+            };
+            player.enqueue(buffer);
+        }
 
-        // Give thread time to process
-        std::thread::sleep(Duration::from_millis(10));
+        // Without an open output there's nothing to fade, so this behaves like stop
+        player.fade_out();
+        assert!(wait_for(|| player.queued_buffers() == 0));
     }
 
     #[test]
@@ -0,0 +1,266 @@
+// Sample Buffer Pool
+//
+// Every chunk used to cost several allocations on its way to the device: the
+// decoder collected a fresh `Arc<[Sample]>`, the coalescer built another for
+// each merged buffer and volume scaling a third. Chunk sizes are steady within
+// a stream, so buffers the player has finished with are handed back here and
+// the next decode or merge of the same length reuses one. Only buffers nobody
+// else holds come back (a buffer kept for the tail or a crossfade is simply
+// dropped when its last holder lets go), and the pool keeps a handful at most.
+//
+// The buffers stay plain `Arc<[Sample]>` in `AudioBuffer`; the pool only
+// decides where their memory comes from. A request it can't serve (nothing
+// free of that length) is a miss and falls back to the heap rather than
+// waiting for a buffer to come back. A new stream format changes every
+// chunk's length, so the free buffers are let go when the format does.
+// Hits and misses are reported at the end of each session.
+
+use sendspin::audio::{AudioFormat, Sample};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Buffers kept for reuse; enough for the queue's hand-offs at any moment
+const MAX_FREE: usize = 16;
+
+/// How often the pool could serve a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub hits: u64,   // Served from a returned buffer
+    pub misses: u64, // Allocated
+}
+
+impl PoolStats {
+    /// Fraction of requests served without allocating, 0-1
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% reused)",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0
+        )
+    }
+}
+
+#[derive(Default)]
+struct Free {
+    buffers: Vec<Arc<[Sample]>>,
+    format: Option<(u32, u8)>, // Sample rate and channels the buffers were sized for
+}
+
+#[derive(Default)]
+struct Inner {
+    free: Mutex<Free>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Shared pool of sample buffers, cheap to clone
+#[derive(Clone, Default)]
+pub struct SamplePool {
+    inner: Arc<Inner>,
+}
+
+impl SamplePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size the pool for a stream in `format`; buffers kept for another
+    /// format are dropped
+    pub fn prepare(&self, format: &AudioFormat) {
+        let key = (format.sample_rate, format.channels);
+        let mut free = self.inner.free.lock().unwrap();
+        if free.format != Some(key) {
+            free.buffers.clear();
+            free.format = Some(key);
+        }
+    }
+
+    /// A buffer of `len` samples that only the caller holds; its contents
+    /// are whatever it last held, so overwrite all of it
+    pub fn take(&self, len: usize) -> Arc<[Sample]> {
+        let reused = {
+            let mut free = self.inner.free.lock().unwrap();
+            let found = free.buffers.iter().position(|buffer| buffer.len() == len);
+            found.map(|i| free.buffers.swap_remove(i))
+        };
+        match reused {
+            Some(buffer) => {
+                self.inner.hits.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                (0..len).map(|_| Sample(0)).collect()
+            }
+        }
+    }
+
+    /// A pooled copy of `samples`
+    pub fn copy_from(&self, samples: &[Sample]) -> Arc<[Sample]> {
+        let mut buffer = self.take(samples.len());
+        Arc::get_mut(&mut buffer)
+            .expect("pooled buffers are unshared")
+            .copy_from_slice(samples);
+        buffer
+    }
+
+    /// Hand a finished buffer back; kept only if nothing else holds it
+    pub fn give(&self, mut buffer: Arc<[Sample]>) {
+        if buffer.is_empty() || Arc::get_mut(&mut buffer).is_none() {
+            return;
+        }
+        let mut free = self.inner.free.lock().unwrap();
+        if free.buffers.len() >= MAX_FREE {
+            // Oldest first: odd lengths (a stream's last chunk) go before current ones
+            free.buffers.remove(0);
+        }
+        free.buffers.push(buffer);
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Run an in-place stage over `samples`, first copying them into a pooled
+/// buffer if anything else still holds them (a crossfade tail, say)
+pub fn in_place(
+    mut samples: Arc<[Sample]>,
+    pool: &SamplePool,
+    stage: impl FnOnce(&mut [Sample]),
+) -> Arc<[Sample]> {
+    if Arc::get_mut(&mut samples).is_none() {
+        samples = pool.copy_from(&samples);
+    }
+    stage(Arc::get_mut(&mut samples).expect("pooled buffers are unshared"));
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    fn format(sample_rate: u32) -> AudioFormat {
+        AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate,
+            channels: 2,
+            bit_depth: 24,
+            codec_header: None,
+        }
+    }
+
+    fn values(samples: &[Sample]) -> Vec<i32> {
+        samples.iter().map(|s| s.0).collect()
+    }
+
+    fn double(samples: &mut [Sample]) {
+        samples.iter_mut().for_each(|s| s.0 *= 2);
+    }
+
+    #[test]
+    fn test_returned_buffer_is_reused_for_same_length() {
+        let pool = SamplePool::new();
+        let buffer = pool.take(960);
+        let address = buffer.as_ptr();
+        pool.give(buffer);
+
+        // Another length can't use it
+        assert_eq!(pool.take(480).len(), 480);
+        let again = pool.take(960);
+        assert_eq!(again.as_ptr(), address);
+        assert_eq!(pool.stats(), PoolStats { hits: 1, misses: 2 });
+    }
+
+    #[test]
+    fn test_reuse_after_return_sees_new_contents_only() {
+        let pool = SamplePool::new();
+        let first = pool.copy_from(&[Sample(1), Sample(-2), Sample(3)]);
+        let held = Arc::clone(&first);
+        // Still held elsewhere: not taken back, so it can't be overwritten
+        pool.give(first);
+        let second = pool.copy_from(&[Sample(7), Sample(8), Sample(9)]);
+        assert_eq!(values(&held), [1, -2, 3]);
+        assert_eq!(pool.stats().hits, 0);
+
+        // Let go of everywhere: the next copy lands in it, fully replaced
+        drop(held);
+        pool.give(second);
+        let third = pool.copy_from(&[Sample(4), Sample(5), Sample(6)]);
+        assert_eq!(values(&third), [4, 5, 6]);
+        assert_eq!(pool.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_exhausted_pool_falls_back_to_allocating() {
+        let pool = SamplePool::new();
+        pool.prepare(&format(48000));
+        // More buffers in flight than the pool keeps: every request is served
+        let in_flight: Vec<_> = (0..MAX_FREE * 2).map(|_| pool.take(960)).collect();
+        assert!(in_flight.iter().all(|buffer| buffer.len() == 960));
+        assert_eq!(pool.stats().misses, MAX_FREE as u64 * 2);
+
+        // Only MAX_FREE of them are kept when they come back
+        for buffer in in_flight {
+            pool.give(buffer);
+        }
+        for _ in 0..MAX_FREE * 2 {
+            pool.take(960);
+        }
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                hits: MAX_FREE as u64,
+                misses: MAX_FREE as u64 * 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_format_change_drops_free_buffers() {
+        let pool = SamplePool::new();
+        pool.prepare(&format(48000));
+        pool.give(pool.take(960));
+        // Same format again (a new stream at the same rate) keeps them
+        pool.prepare(&format(48000));
+        pool.give(pool.take(960));
+        assert_eq!(pool.stats().hits, 1);
+
+        pool.prepare(&format(44100));
+        pool.take(960);
+        assert_eq!(pool.stats().hits, 1);
+        assert!((pool.stats().hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_in_place_copies_only_shared_buffers() {
+        let pool = SamplePool::new();
+        let samples: Arc<[Sample]> = (0..4).map(Sample).collect();
+        let held = Arc::clone(&samples);
+        let doubled = in_place(samples, &pool, double);
+        assert_eq!(doubled[3].0, 6);
+        assert_eq!(held[3].0, 3, "a shared buffer is never written");
+
+        let address = doubled.as_ptr();
+        let again = in_place(doubled, &pool, double);
+        assert_eq!(again.as_ptr(), address);
+        assert_eq!(again[3].0, 12);
+    }
+}
@@ -0,0 +1,130 @@
+// Playback Position
+//
+// Music Assistant can drive its progress bar from the position a player
+// reports, which is worth doing because buffering leaves the client behind
+// the server's own idea of where the stream is. The player tells how far the
+// audio written to the output reaches (a stream timestamp); this turns that
+// into a position from the stream's start.
+//
+// The stream's first timestamp is position zero. A seek moves the origin so
+// the target timestamp lands on the requested position, a new stream clears
+// it, and a pause freezes the position where playback was until it resumes.
+// With `--report-position-secs` the position is sent in client/state at that
+// interval; the client/state answering a pause or play command carries it
+// either way.
+
+use std::time::Duration;
+
+/// Position in the current stream, from the timestamps the player reports
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PositionTracker {
+    origin: Option<i64>,      // Stream timestamp at position zero
+    frozen: Option<Duration>, // Paused here
+}
+
+impl PositionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first chunk of a new stream, at position zero
+    pub fn start(&mut self, timestamp: i64) {
+        self.origin = Some(timestamp);
+        self.frozen = None;
+    }
+
+    /// A seek put stream timestamp `target` at `position`
+    pub fn seek(&mut self, target: i64, position: Duration) {
+        self.origin = Some(target - position.as_micros() as i64);
+        if self.frozen.is_some() {
+            self.frozen = Some(position);
+        }
+    }
+
+    /// A new stream is starting; no position until its first chunk
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Hold the position playback reached (`played_until`) until resumed
+    pub fn pause(&mut self, played_until: Option<i64>) {
+        if self.frozen.is_none() {
+            self.frozen = self.position(played_until);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.frozen = None;
+    }
+
+    /// Position of the audio played through `played_until`, None with no
+    /// stream or nothing played yet
+    pub fn position(&self, played_until: Option<i64>) -> Option<Duration> {
+        if self.frozen.is_some() {
+            return self.frozen;
+        }
+        let played = played_until? - self.origin?;
+        Some(Duration::from_micros(played.max(0) as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: i64 = 7_000_000;
+
+    fn ms(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn test_position_from_stream_start() {
+        let mut tracker = PositionTracker::new();
+        assert_eq!(tracker.position(Some(START)), None);
+        tracker.start(START);
+        assert_eq!(tracker.position(None), None);
+        assert_eq!(tracker.position(Some(START + 1_500_000)), ms(1500));
+        // Written audio from before the first chunk doesn't count backwards
+        assert_eq!(tracker.position(Some(START - 20_000)), ms(0));
+
+        tracker.reset();
+        assert_eq!(tracker.position(Some(START + 1_500_000)), None);
+    }
+
+    #[test]
+    fn test_pause_freezes_until_resume() {
+        let mut tracker = PositionTracker::new();
+        tracker.start(START);
+        tracker.pause(Some(START + 2_000_000));
+        // The player stops reporting (or keeps writing its fade-out): still 2 s
+        assert_eq!(tracker.position(None), ms(2000));
+        assert_eq!(tracker.position(Some(START + 2_050_000)), ms(2000));
+        // A second pause doesn't move it
+        tracker.pause(Some(START + 2_050_000));
+        assert_eq!(tracker.position(None), ms(2000));
+
+        // Resumed where it was heard last, it carries on from there
+        tracker.resume();
+        assert_eq!(tracker.position(Some(START + 2_500_000)), ms(2500));
+    }
+
+    #[test]
+    fn test_seek_moves_origin() {
+        let mut tracker = PositionTracker::new();
+        tracker.start(START);
+        assert_eq!(tracker.position(Some(START + 1_000_000)), ms(1000));
+
+        // Seek to 60 s: the target timestamp is 60 s from the stream start
+        let target = START + 60_000_000;
+        tracker.seek(target, Duration::from_secs(60));
+        assert_eq!(tracker.position(Some(target + 250_000)), ms(60_250));
+
+        // Seeking while paused moves the frozen position too
+        tracker.pause(Some(target + 500_000));
+        tracker.seek(START + 10_000_000, Duration::from_secs(10));
+        assert_eq!(tracker.position(None), ms(10_000));
+        tracker.resume();
+        assert_eq!(tracker.position(Some(START + 11_000_000)), ms(11_000));
+    }
+}
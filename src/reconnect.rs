@@ -0,0 +1,167 @@
+// Server Reconnection
+//
+// When the connection to the server drops, the client reconnects with
+// exponential backoff. Each delay is randomized by ±jitter (20% by default)
+// so that a house full of players that lost the same server (e.g. it
+// rebooted) don't all retry in lockstep and hit it in one burst when it
+// comes back. The random source is seeded per instance.
+//
+// Which disconnects are retried at all is `--reconnect-on`: `transient` (the
+// default) retries dropped networks and servers going away or restarting,
+// and exits when the server rejects the client (a policy or auth close
+// code, a 4xx on the upgrade), so a server that refuses us isn't hammered
+// forever. `always` retries whatever the cause, for a server expected to
+// change its mind.
+
+use crate::rng::Rng;
+use clap::ValueEnum;
+use std::time::Duration;
+
+/// Default jitter: each delay is randomized by up to ±20%
+pub const DEFAULT_JITTER: f64 = 0.2;
+
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Which disconnects are reconnected after
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReconnectOn {
+    #[default]
+    Transient, // Only those that can clear up; exit on a rejection
+    Always, // Every one, rejections included
+}
+
+impl ReconnectOn {
+    /// Whether a disconnect is retried, given whether its cause is transient
+    pub fn retries(self, transient: bool) -> bool {
+        self == ReconnectOn::Always || transient
+    }
+}
+
+/// Parse a jitter factor between 0 (none) and 1 (±100%)
+pub fn parse_jitter(s: &str) -> Result<f64, String> {
+    let jitter: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid jitter '{}'", s))?;
+    if (0.0..=1.0).contains(&jitter) {
+        Ok(jitter)
+    } else {
+        Err(format!("jitter must be between 0 and 1, got {}", jitter))
+    }
+}
+
+/// Delays between reconnect attempts
+#[derive(Debug)]
+pub struct ReconnectBackoff {
+    attempts: u32,
+    jitter: f64,
+    rng: Rng,
+}
+
+impl ReconnectBackoff {
+    /// Backoff with a random seed for this instance
+    pub fn new(jitter: f64) -> Self {
+        ReconnectBackoff {
+            attempts: 0,
+            jitter: jitter.clamp(0.0, 1.0),
+            rng: Rng::new(),
+        }
+    }
+
+    /// Backoff with a fixed seed (reproducible delays)
+    pub fn with_seed(jitter: f64, seed: u64) -> Self {
+        ReconnectBackoff {
+            attempts: 0,
+            jitter: jitter.clamp(0.0, 1.0),
+            rng: Rng::with_seed(seed),
+        }
+    }
+
+    /// Connected again: the next outage starts from the shortest delay
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Attempts since the last connection
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Delay before the next attempt: doubles per attempt up to the cap,
+    /// then randomized by ±jitter
+    pub fn next_delay(&mut self) -> Duration {
+        let base = BACKOFF_MIN
+            .saturating_mul(1 << self.attempts.min(5))
+            .min(BACKOFF_MAX);
+        self.attempts = self.attempts.saturating_add(1);
+        let spread = (self.rng.next_unit() * 2.0 - 1.0) * self.jitter;
+        base.mul_f64(1.0 + spread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jitter() {
+        assert_eq!(parse_jitter("0.2"), Ok(0.2));
+        assert_eq!(parse_jitter("0"), Ok(0.0));
+        assert!(parse_jitter("1.5").is_err());
+        assert!(parse_jitter("-0.1").is_err());
+        assert!(parse_jitter("lots").is_err());
+    }
+
+    #[test]
+    fn test_reconnect_on() {
+        assert!(ReconnectOn::Transient.retries(true));
+        assert!(!ReconnectOn::Transient.retries(false));
+        assert!(ReconnectOn::Always.retries(true));
+        assert!(ReconnectOn::Always.retries(false));
+        assert_eq!(ReconnectOn::default(), ReconnectOn::Transient);
+    }
+
+    #[test]
+    fn test_no_jitter_doubles_and_caps() {
+        let mut backoff = ReconnectBackoff::with_seed(0.0, 1);
+        let delays: Vec<u64> = (0..8).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30, 30]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), BACKOFF_MIN);
+    }
+
+    #[test]
+    fn test_jitter_stays_in_bounds() {
+        let mut backoff = ReconnectBackoff::with_seed(DEFAULT_JITTER, 42);
+        let delays: Vec<Duration> = (0..200)
+            .map(|_| {
+                backoff.reset();
+                backoff.next_delay()
+            })
+            .collect();
+        assert!(delays
+            .iter()
+            .all(|d| (0.8..=1.2).contains(&d.as_secs_f64())));
+        // Actually spread out, not stuck on one value
+        let min = delays.iter().min().unwrap().as_secs_f64();
+        let max = delays.iter().max().unwrap().as_secs_f64();
+        assert!(max - min > 0.2, "spread {}..{}", min, max);
+    }
+
+    #[test]
+    fn test_instances_spread_apart() {
+        // Two players losing the server at once must not retry in lockstep
+        let mut a = ReconnectBackoff::with_seed(DEFAULT_JITTER, 1);
+        let mut b = ReconnectBackoff::with_seed(DEFAULT_JITTER, 2);
+        let a: Vec<Duration> = (0..5).map(|_| a.next_delay()).collect();
+        let b: Vec<Duration> = (0..5).map(|_| b.next_delay()).collect();
+        assert_ne!(a, b);
+
+        // The same seed reproduces the same delays
+        let mut c = ReconnectBackoff::with_seed(DEFAULT_JITTER, 1);
+        let c: Vec<Duration> = (0..5).map(|_| c.next_delay()).collect();
+        assert_eq!(a, c);
+    }
+}
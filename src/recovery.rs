@@ -0,0 +1,340 @@
+// Output Device Recovery
+//
+// A USB DAC can vanish mid-playback. After a few consecutive failed writes
+// (or a cpal stream error) the output is dropped and the playback thread
+// retries opening it with exponential backoff. Meanwhile buffers are still
+// taken off the queue at their play_at time and discarded, so the queue
+// doesn't grow and playback picks up in sync once the device is back.
+//
+// With `--device-fallback N` the configured device is given N attempts,
+// after which the default device is tried instead. With
+// `--device-retry-attempts N` the player gives up after N attempts in all:
+// the rest of the stream is discarded, the owner is told so it can report the
+// failure to the server, and the next stream starts a fresh round of attempts
+// (unless `--device-retry-exhausted exit` ends the client first).
+//
+// A device that can't be opened at all (not lost mid-stream) ends the
+// playback thread. The owner is told why, so it can replace the player
+// instead of queueing audio nothing will ever play.
+
+use crate::output::OutputConfig;
+use clap::ValueEnum;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Consecutive write errors before the device counts as gone
+pub const WRITE_FAILURE_LIMIT: u32 = 3;
+
+const BACKOFF_MIN: Duration = Duration::from_millis(250);
+const BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// What the client does once the device retries are used up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum RetryExhausted {
+    /// Exit with an error (a supervisor can restart the client)
+    Exit,
+    /// Stay connected and try the device again on the next stream
+    #[default]
+    Wait,
+}
+
+/// The output device couldn't be reopened within the allowed attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetriesExhausted {
+    pub attempts: u32,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "audio device still unavailable after {} attempts",
+            self.attempts
+        )
+    }
+}
+
+impl std::error::Error for RetriesExhausted {}
+
+/// The playback thread ended on an error; nothing plays until the player is
+/// replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaybackFailed {
+    pub reason: String,
+}
+
+impl fmt::Display for PlaybackFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "playback stopped: {}", self.reason)
+    }
+}
+
+impl std::error::Error for PlaybackFailed {}
+
+/// Disconnect/reconnect counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceStats {
+    pub disconnects: u64,
+    pub reconnects: u64,
+    pub discarded_buffers: u64, // Consumed on schedule while the device was gone
+    pub idle_releases: u64,     // Output closed after a long silence (--idle-release-secs)
+    pub idle_reacquires: u64,   // Reopened when audio came back
+    pub write_errors: u64,      // Failed writes, whether or not the device was dropped
+}
+
+/// Tracks write failures and reopen attempts for the output device
+#[derive(Debug)]
+pub struct DeviceRecovery {
+    fallback_after: Option<u32>,
+    max_attempts: Option<u32>,
+    write_failures: u32,
+    lost_since: Option<Instant>,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    pub stats: DeviceStats,
+}
+
+impl DeviceRecovery {
+    pub fn new(fallback_after: Option<u32>) -> Self {
+        DeviceRecovery {
+            fallback_after,
+            max_attempts: None,
+            write_failures: 0,
+            lost_since: None,
+            attempts: 0,
+            next_attempt: None,
+            stats: DeviceStats::default(),
+        }
+    }
+
+    /// Give up after `max_attempts` failed reopen attempts (None = never)
+    pub fn with_max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Whether the device is currently considered disconnected
+    pub fn is_lost(&self) -> bool {
+        self.lost_since.is_some()
+    }
+
+    /// A write succeeded
+    pub fn write_ok(&mut self) {
+        self.write_failures = 0;
+    }
+
+    /// A write failed; returns true once the device should be treated as gone
+    pub fn write_failed(&mut self) -> bool {
+        self.write_failures += 1;
+        self.stats.write_errors += 1;
+        self.write_failures >= WRITE_FAILURE_LIMIT
+    }
+
+    /// The output was dropped after a disconnect
+    pub fn disconnected(&mut self, now: Instant) {
+        self.write_failures = 0;
+        self.lost_since = Some(now);
+        self.attempts = 0;
+        self.next_attempt = Some(now + BACKOFF_MIN);
+        self.stats.disconnects += 1;
+    }
+
+    /// Whether opening the output should be attempted now
+    pub fn retry_due(&self, now: Instant) -> bool {
+        !self.exhausted() && self.next_attempt.is_none_or(|at| now >= at)
+    }
+
+    /// Whether the allowed reopen attempts are used up
+    pub fn exhausted(&self) -> bool {
+        self.is_lost()
+            && self
+                .max_attempts
+                .is_some_and(|limit| self.attempts >= limit)
+    }
+
+    /// Allow a fresh round of attempts, e.g. for a new stream
+    pub fn retry_again(&mut self) {
+        if self.is_lost() {
+            self.attempts = 0;
+            self.next_attempt = None;
+        }
+    }
+
+    /// Reopen attempts made since the device was lost
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// A reopen attempt failed; schedule the next one
+    pub fn retry_failed(&mut self, now: Instant) {
+        self.attempts += 1;
+        self.next_attempt = Some(now + self.backoff());
+    }
+
+    /// Output opened again; returns how long the device was gone
+    pub fn reconnected(&mut self, now: Instant) -> Option<Duration> {
+        let lost_since = self.lost_since.take()?;
+        self.attempts = 0;
+        self.next_attempt = None;
+        self.stats.reconnects += 1;
+        Some(now - lost_since)
+    }
+
+    /// A buffer was dropped because there is no device to play it on
+    pub fn discard(&mut self) {
+        self.stats.discarded_buffers += 1;
+    }
+
+    /// Delay before the next attempt: doubles per failure, capped
+    pub fn backoff(&self) -> Duration {
+        BACKOFF_MIN
+            .saturating_mul(1 << self.attempts.min(5))
+            .min(BACKOFF_MAX)
+    }
+
+    /// Whether reopen attempts have switched to the default device
+    pub fn using_fallback(&self) -> bool {
+        self.is_lost()
+            && self
+                .fallback_after
+                .is_some_and(|limit| self.attempts >= limit)
+    }
+
+    /// Output settings for the next attempt
+    ///
+    /// While recovering, a missing device must fail the attempt instead of
+    /// silently opening the null backend, so `require_audio` is forced on.
+    pub fn output_config(&self, config: &OutputConfig) -> OutputConfig {
+        let mut config = config.clone();
+        if self.is_lost() {
+            config.require_audio = true;
+        }
+        if self.using_fallback() {
+            config.host = None;
+            config.device = None;
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_failures_must_be_consecutive() {
+        let mut recovery = DeviceRecovery::new(None);
+        assert!(!recovery.write_failed());
+        assert!(!recovery.write_failed());
+        recovery.write_ok();
+        assert!(!recovery.write_failed());
+        assert!(!recovery.write_failed());
+        assert!(recovery.write_failed());
+        // Every failure is counted, consecutive or not
+        assert_eq!(recovery.stats.write_errors, 5);
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let now = Instant::now();
+        let mut recovery = DeviceRecovery::new(None);
+        recovery.disconnected(now);
+        assert!(recovery.is_lost());
+        assert!(!recovery.retry_due(now));
+        assert!(recovery.retry_due(now + BACKOFF_MIN));
+
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            recovery.retry_failed(now);
+            delays.push(recovery.backoff());
+        }
+        assert_eq!(delays[0], Duration::from_millis(500));
+        assert_eq!(delays[1], Duration::from_secs(1));
+        assert_eq!(*delays.last().unwrap(), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_reconnect_counts_and_resets() {
+        let now = Instant::now();
+        let mut recovery = DeviceRecovery::new(None);
+        assert!(recovery.reconnected(now).is_none());
+
+        recovery.disconnected(now);
+        recovery.discard();
+        recovery.discard();
+        recovery.retry_failed(now);
+        let gone = recovery.reconnected(now + Duration::from_secs(2)).unwrap();
+        assert_eq!(gone, Duration::from_secs(2));
+        assert!(!recovery.is_lost());
+        assert!(recovery.retry_due(now));
+        assert_eq!(
+            recovery.stats,
+            DeviceStats {
+                disconnects: 1,
+                reconnects: 1,
+                discarded_buffers: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_fallback_to_default_device() {
+        let now = Instant::now();
+        let config = OutputConfig {
+            host: Some("JACK".to_string()),
+            device: Some("hw:CARD=DAC,DEV=0".to_string()),
+            ..Default::default()
+        };
+        let mut recovery = DeviceRecovery::new(Some(2));
+        assert!(!recovery.output_config(&config).require_audio);
+
+        recovery.disconnected(now);
+        recovery.retry_failed(now);
+        let retry = recovery.output_config(&config);
+        assert!(retry.require_audio);
+        assert_eq!(retry.device, config.device);
+
+        recovery.retry_failed(now);
+        assert!(recovery.using_fallback());
+        let retry = recovery.output_config(&config);
+        assert_eq!(retry.host, None);
+        assert_eq!(retry.device, None);
+
+        // Without --device-fallback the configured device is retried forever
+        let mut recovery = DeviceRecovery::new(None);
+        recovery.disconnected(now);
+        for _ in 0..20 {
+            recovery.retry_failed(now);
+        }
+        assert_eq!(recovery.output_config(&config).device, config.device);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let now = Instant::now();
+        let mut recovery = DeviceRecovery::new(None).with_max_attempts(Some(3));
+        recovery.disconnected(now);
+        for _ in 0..3 {
+            assert!(!recovery.exhausted());
+            recovery.retry_failed(now);
+        }
+        assert!(recovery.exhausted());
+        assert_eq!(recovery.attempts(), 3);
+        // No more attempts, however long we wait
+        assert!(!recovery.retry_due(now + Duration::from_secs(3600)));
+
+        // A new stream tries again
+        recovery.retry_again();
+        assert!(!recovery.exhausted());
+        assert!(recovery.retry_due(now));
+
+        // Without a limit it never gives up
+        let mut recovery = DeviceRecovery::new(None);
+        recovery.disconnected(now);
+        for _ in 0..100 {
+            recovery.retry_failed(now);
+        }
+        assert!(!recovery.exhausted());
+    }
+}
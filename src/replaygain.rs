@@ -0,0 +1,111 @@
+// ReplayGain / loudness metadata
+//
+// Music Assistant attaches per-track gain information to some streams, either
+// in the stream/start player config or in the server/state metadata. The gain
+// is read from the raw JSON payload and handed to the player as a linear factor.
+
+use serde_json::Value;
+
+/// Loudness reference used when only a measured loudness is available (ReplayGain 2.0)
+pub const REFERENCE_LUFS: f32 = -18.0;
+
+/// Keys carrying a gain in dB
+const GAIN_KEYS: [&str; 4] = [
+    "replay_gain",
+    "replaygain_track_gain",
+    "track_gain",
+    "gain_db",
+];
+
+/// Keys carrying a measured integrated loudness in LUFS
+const LOUDNESS_KEYS: [&str; 2] = ["loudness", "integrated_loudness"];
+
+/// Find a gain (in dB) in a stream/start or server/state payload
+pub fn gain_db_from_payload(payload: &Value) -> Option<f32> {
+    ["player", "metadata"]
+        .iter()
+        .filter_map(|section| payload.get(section))
+        .chain(std::iter::once(payload))
+        .find_map(gain_db_from_object)
+}
+
+fn gain_db_from_object(obj: &Value) -> Option<f32> {
+    if let Some(db) = GAIN_KEYS
+        .iter()
+        .find_map(|key| obj.get(key).and_then(parse_db))
+    {
+        return Some(db);
+    }
+    LOUDNESS_KEYS
+        .iter()
+        .find_map(|key| obj.get(key).and_then(parse_db))
+        .map(|lufs| REFERENCE_LUFS - lufs)
+}
+
+/// Accept plain numbers as well as tag-style strings such as "-6.52 dB"
+fn parse_db(value: &Value) -> Option<f32> {
+    match value {
+        Value::Number(n) => n.as_f64().map(|v| v as f32),
+        Value::String(s) => s
+            .trim()
+            .trim_end_matches("LUFS")
+            .trim_end_matches("dB")
+            .trim()
+            .parse()
+            .ok(),
+        _ => None,
+    }
+    .filter(|db: &f32| db.is_finite())
+}
+
+/// Convert a gain in dB to a linear factor
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Convert a linear factor back to dB, for reporting the gain applied
+pub fn linear_to_db(factor: f32) -> f32 {
+    20.0 * factor.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_gain_from_stream_start_player() {
+        let payload = json!({
+            "player": { "codec": "pcm", "sample_rate": 48000, "replay_gain": -6.5 }
+        });
+        assert_eq!(gain_db_from_payload(&payload), Some(-6.5));
+    }
+
+    #[test]
+    fn test_gain_from_metadata_string() {
+        let payload = json!({ "metadata": { "track_gain": "+2.25 dB" } });
+        assert_eq!(gain_db_from_payload(&payload), Some(2.25));
+    }
+
+    #[test]
+    fn test_gain_from_loudness() {
+        let payload = json!({ "metadata": { "loudness": -12.0 } });
+        assert_eq!(gain_db_from_payload(&payload), Some(-6.0));
+    }
+
+    #[test]
+    fn test_no_gain() {
+        let payload = json!({ "player": { "codec": "pcm" }, "metadata": { "title": "x" } });
+        assert_eq!(gain_db_from_payload(&payload), None);
+        assert_eq!(gain_db_from_payload(&json!({ "gain_db": "loud" })), None);
+    }
+
+    #[test]
+    fn test_db_to_linear() {
+        assert!((db_to_linear(0.0) - 1.0).abs() < 1e-6);
+        assert!((db_to_linear(-6.0206) - 0.5).abs() < 1e-4);
+        assert!((db_to_linear(20.0) - 10.0).abs() < 1e-4);
+        assert!((linear_to_db(db_to_linear(-6.5)) + 6.5).abs() < 1e-4);
+        assert_eq!(linear_to_db(1.0), 0.0);
+    }
+}
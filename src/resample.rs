@@ -0,0 +1,572 @@
+// Resampling
+//
+// Stretches or compresses interleaved audio by a ratio (input frames consumed
+// per output frame), keeping state across buffers so there are no seams.
+// Used for server-requested playback speed and for converting a stream to a
+// rate the output device supports. The linear interpolator is cheap and good
+// enough for the small speed ratios; rate conversion uses the quality picked
+// with `--resample-quality`:
+//
+// - fast: linear interpolation (aliases, but almost free)
+// - medium: polyphase windowed sinc from a precomputed table, 8 zero crossings
+// - high: windowed sinc computed per output frame, 16 zero crossings
+//
+// The sinc resamplers hold back the last few input frames until the next
+// buffer arrives; `flush` plays them out at stream end and `latency_frames`
+// tells the player how much earlier to write so they don't arrive late.
+// Anything implementing `Resampler` can replace any of them.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use clap::ValueEnum;
+use sendspin::audio::Sample;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Zero crossings of the sinc kernel on each side of the interpolation point
+const SINC_ZERO_CROSSINGS: usize = 16;
+
+/// Zero crossings and table resolution of the polyphase filter
+const POLYPHASE_ZERO_CROSSINGS: usize = 8;
+const POLYPHASE_PHASES: usize = 128;
+
+/// Rate conversion quality vs CPU, selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ResampleQuality {
+    Fast,
+    Medium,
+    #[default]
+    #[value(alias = "best")]
+    High,
+}
+
+/// Resampler for converting between stream and device rates
+pub fn for_quality(quality: ResampleQuality) -> Box<dyn Resampler> {
+    match quality {
+        ResampleQuality::Fast => Box::new(LinearResampler::new()),
+        ResampleQuality::Medium => Box::new(PolyphaseResampler::new()),
+        ResampleQuality::High => Box::new(SincResampler::new()),
+    }
+}
+
+/// Streaming resampler over interleaved samples
+pub trait Resampler: Send {
+    /// Resample the next buffer; `ratio` > 1.0 consumes input faster (plays faster)
+    fn process(&mut self, samples: &[Sample], channels: usize, ratio: f64) -> Arc<[Sample]>;
+
+    /// Forget carried-over state, e.g. on stream change
+    fn reset(&mut self);
+
+    /// Input frames held back until later input arrives
+    fn latency_frames(&self) -> usize {
+        0
+    }
+
+    /// Stream end: return the held-back frames and start over
+    fn flush(&mut self, channels: usize, ratio: f64) -> Arc<[Sample]> {
+        let padding = vec![Sample(0); self.latency_frames() * channels.max(1)];
+        let out = self.process(&padding, channels, ratio);
+        self.reset();
+        out
+    }
+}
+
+/// Linear interpolation between neighbouring frames
+#[derive(Debug, Default)]
+pub struct LinearResampler {
+    last_frame: Vec<Sample>, // Final input frame of the previous buffer
+    position: f64,           // Read position relative to `last_frame`
+}
+
+impl LinearResampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Resampler for LinearResampler {
+    fn process(&mut self, samples: &[Sample], channels: usize, ratio: f64) -> Arc<[Sample]> {
+        let channels = channels.max(1);
+        if self.last_frame.len() != channels {
+            // First buffer (or channel count changed): start at its first frame
+            self.last_frame.clear();
+            self.position = 0.0;
+        }
+
+        // Frame 0 is the previous buffer's last frame when there is one
+        let input: Vec<Sample> = self
+            .last_frame
+            .iter()
+            .chain(samples.iter())
+            .copied()
+            .collect();
+        let frames = input.len() / channels;
+        if frames < 2 {
+            self.last_frame = input;
+            return Arc::from(Vec::new());
+        }
+
+        let mut out = Vec::with_capacity(((frames as f64 / ratio) as usize + 1) * channels);
+        while self.position + 1.0 < frames as f64 {
+            let index = self.position as usize;
+            let frac = self.position - index as f64;
+            let a = &input[index * channels..(index + 1) * channels];
+            let b = &input[(index + 1) * channels..(index + 2) * channels];
+            for (x, y) in a.iter().zip(b) {
+                let v = x.0 as f64 + (y.0 as f64 - x.0 as f64) * frac;
+                out.push(Sample(v.round() as i32));
+            }
+            self.position += ratio;
+        }
+
+        self.last_frame = input[(frames - 1) * channels..frames * channels].to_vec();
+        self.position -= (frames - 1) as f64;
+        Arc::from(out)
+    }
+
+    fn reset(&mut self) {
+        self.last_frame.clear();
+        self.position = 0.0;
+    }
+
+    fn latency_frames(&self) -> usize {
+        1
+    }
+}
+
+/// Input history and read position shared by the sinc resamplers
+///
+/// Output starts at the first input frame; the last few frames are held back
+/// until the next buffer supplies the samples the kernel needs after them.
+#[derive(Debug, Default)]
+struct FirState {
+    history: Vec<f64>, // Interleaved input frames the kernel can still reach
+    channels: usize,
+    position: f64,     // Read position in frames within `history`
+    half_width: usize, // Kernel taps on each side of the read position
+    weights: Vec<f64>,
+}
+
+impl FirState {
+    /// Filter the next buffer with `half_width` taps on each side; `fill`
+    /// sets the taps for a fractional read position
+    fn process(
+        &mut self,
+        samples: &[Sample],
+        channels: usize,
+        ratio: f64,
+        half_width: usize,
+        mut fill: impl FnMut(f64, &mut [f64]),
+    ) -> Arc<[Sample]> {
+        let channels = channels.max(1);
+        let n = half_width;
+        if self.channels != channels {
+            // Silence before the first frame, so output starts at input frame 0
+            self.channels = channels;
+            self.history = vec![0.0; (n - 1) * channels];
+            self.position = (n - 1) as f64;
+        } else if n > self.half_width {
+            // Wider kernel after a ratio change: it needs that much more history
+            let grow = n - self.half_width;
+            self.history
+                .splice(0..0, std::iter::repeat_n(0.0, grow * channels));
+            self.position += grow as f64;
+        }
+        self.half_width = n;
+        self.weights.resize(2 * n, 0.0);
+        let weights = &mut self.weights;
+        self.history.extend(samples.iter().map(|s| s.0 as f64));
+        let frames = self.history.len() / channels;
+
+        let mut out = Vec::with_capacity(((frames as f64 / ratio) as usize + 1) * channels);
+        while self.position as usize + n < frames {
+            let first = self.position as usize + 1 - n;
+            fill(self.position.fract(), weights);
+            for ch in 0..channels {
+                let acc: f64 = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, w)| w * self.history[(first + tap) * channels + ch])
+                    .sum();
+                out.push(Sample(
+                    acc.round().clamp(SAMPLE_MIN as f64, SAMPLE_MAX as f64) as i32,
+                ));
+            }
+            self.position += ratio;
+        }
+
+        // Drop frames the kernel won't reach again
+        let consumed = (self.position as usize + 1).saturating_sub(n).min(frames);
+        self.history.drain(..consumed * channels);
+        self.position -= consumed as f64;
+        Arc::from(out)
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+        self.channels = 0;
+        self.position = 0.0;
+        self.half_width = 0;
+    }
+
+    /// Input frames held back, `default` before the first buffer
+    fn latency_frames(&self, default: usize) -> usize {
+        if self.half_width > 0 {
+            self.half_width
+        } else {
+            default
+        }
+    }
+}
+
+/// Band-limited interpolation kernel at distance `x` input frames
+fn sinc_kernel(x: f64, cutoff: f64, zero_crossings: usize) -> f64 {
+    let t = x / zero_crossings as f64;
+    if t.abs() >= 1.0 {
+        return 0.0;
+    }
+    let window = 0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos();
+    let arg = PI * cutoff * x;
+    let sinc = if arg.abs() < 1e-12 {
+        1.0
+    } else {
+        arg.sin() / arg
+    };
+    cutoff * sinc * window
+}
+
+/// Kernel taps for reading at fraction `frac` past a frame, normalised so
+/// DC passes at exactly unity gain
+fn sinc_weights(frac: f64, cutoff: f64, weights: &mut [f64]) {
+    let n = weights.len() / 2;
+    for (tap, weight) in weights.iter_mut().enumerate() {
+        *weight = sinc_kernel(tap as f64 + 1.0 - n as f64 - frac, cutoff, n);
+    }
+    let total: f64 = weights.iter().sum();
+    weights.iter_mut().for_each(|w| *w /= total);
+}
+
+/// Stay below the Nyquist frequency of the lower of the two rates
+fn cutoff_for(ratio: f64) -> f64 {
+    0.95 * (1.0 / ratio).min(1.0)
+}
+
+/// Taps per side: the kernel widens when downsampling so it still spans
+/// `zero_crossings` zero crossings of the lowered cutoff
+fn half_width_for(zero_crossings: usize, ratio: f64) -> usize {
+    (zero_crossings as f64 * ratio.max(1.0)).ceil() as usize
+}
+
+/// Windowed-sinc interpolation (Blackman window), kernel computed exactly
+/// for every output frame
+#[derive(Debug, Default)]
+pub struct SincResampler {
+    state: FirState,
+}
+
+impl SincResampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Resampler for SincResampler {
+    fn process(&mut self, samples: &[Sample], channels: usize, ratio: f64) -> Arc<[Sample]> {
+        let cutoff = cutoff_for(ratio);
+        let half_width = half_width_for(SINC_ZERO_CROSSINGS, ratio);
+        self.state
+            .process(samples, channels, ratio, half_width, |frac, weights| {
+                sinc_weights(frac, cutoff, weights)
+            })
+    }
+
+    fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.state.latency_frames(SINC_ZERO_CROSSINGS)
+    }
+}
+
+/// Polyphase windowed sinc: a shorter kernel precomputed at a fixed number
+/// of fractional positions, interpolated between neighbouring phases
+#[derive(Debug, Default)]
+pub struct PolyphaseResampler {
+    state: FirState,
+    table: Vec<f64>, // (PHASES + 1) rows of taps
+    table_cutoff: f64,
+    table_half_width: usize,
+}
+
+impl PolyphaseResampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// (Re)build the filter table for a ratio, e.g. after a speed change
+    fn prepare(&mut self, ratio: f64) {
+        let cutoff = cutoff_for(ratio);
+        if !self.table.is_empty() && self.table_cutoff == cutoff {
+            return;
+        }
+        let half_width = half_width_for(POLYPHASE_ZERO_CROSSINGS, ratio);
+        let taps = 2 * half_width;
+        self.table = vec![0.0; (POLYPHASE_PHASES + 1) * taps];
+        for (phase, row) in self.table.chunks_exact_mut(taps).enumerate() {
+            sinc_weights(phase as f64 / POLYPHASE_PHASES as f64, cutoff, row);
+        }
+        self.table_cutoff = cutoff;
+        self.table_half_width = half_width;
+    }
+}
+
+impl Resampler for PolyphaseResampler {
+    fn process(&mut self, samples: &[Sample], channels: usize, ratio: f64) -> Arc<[Sample]> {
+        self.prepare(ratio);
+        let table = &self.table;
+        let half_width = self.table_half_width;
+        let taps = 2 * half_width;
+        self.state
+            .process(samples, channels, ratio, half_width, |frac, weights| {
+                let phase = frac * POLYPHASE_PHASES as f64;
+                let row = (phase as usize).min(POLYPHASE_PHASES - 1);
+                let t = phase - row as f64;
+                let a = &table[row * taps..(row + 1) * taps];
+                let b = &table[(row + 1) * taps..(row + 2) * taps];
+                for ((w, x), y) in weights.iter_mut().zip(a).zip(b) {
+                    *w = x + (y - x) * t;
+                }
+            })
+    }
+
+    fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.state.latency_frames(POLYPHASE_ZERO_CROSSINGS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(frames: usize, start: i32) -> Vec<Sample> {
+        (0..frames as i32)
+            .flat_map(|i| [Sample(start + i), Sample(-(start + i))])
+            .collect()
+    }
+
+    /// Total output frames for `buffers` stereo buffers of `frames` frames
+    fn output_frames(ratio: f64, buffers: usize, frames: usize) -> usize {
+        let mut resampler = LinearResampler::new();
+        (0..buffers)
+            .map(|_| resampler.process(&ramp(frames, 0), 2, ratio).len() / 2)
+            .sum()
+    }
+
+    #[test]
+    fn test_frame_counts_for_known_factors() {
+        // 10 000 input frames; one frame is always held back for interpolation
+        assert_eq!(output_frames(1.0, 10, 1000), 9999);
+        let faster = output_frames(1.02, 10, 1000) as f64;
+        assert!((faster - 10000.0 / 1.02).abs() <= 1.0, "{}", faster);
+        let slower = output_frames(0.98, 10, 1000) as f64;
+        assert!((slower - 10000.0 / 0.98).abs() <= 1.0, "{}", slower);
+        assert_eq!(output_frames(2.0, 1, 1000), 500);
+    }
+
+    #[test]
+    fn test_unity_ratio_is_transparent_across_buffers() {
+        let mut resampler = LinearResampler::new();
+        let mut out: Vec<i32> = Vec::new();
+        out.extend(resampler.process(&ramp(4, 0), 2, 1.0).iter().map(|s| s.0));
+        out.extend(resampler.process(&ramp(4, 4), 2, 1.0).iter().map(|s| s.0));
+
+        // Seven frames out (the last is held back), no seam between buffers
+        let expected: Vec<i32> = ramp(7, 0).iter().map(|s| s.0).collect();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_interpolates_between_frames() {
+        let mut resampler = LinearResampler::new();
+        let input = [Sample(0), Sample(0), Sample(100), Sample(-100)];
+        let out = resampler.process(&input, 2, 0.5);
+        let values: Vec<i32> = out.iter().map(|s| s.0).collect();
+        assert_eq!(values, vec![0, 0, 50, -50]);
+    }
+
+    fn sine(rate: u32, freq: f64, frames: usize) -> Vec<Sample> {
+        (0..frames)
+            .map(|i| {
+                let v = (2.0 * PI * freq * i as f64 / rate as f64).sin() * 4_000_000.0;
+                Sample(v.round() as i32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sinc_converts_44k1_to_48k_accurately() {
+        let ratio = 44100.0 / 48000.0;
+        let input = sine(44100, 1000.0, 44100);
+        let mut resampler = SincResampler::new();
+        let out: Vec<Sample> = input
+            .chunks(441)
+            .flat_map(|chunk| resampler.process(chunk, 1, ratio).to_vec())
+            .collect();
+
+        // One second in, one second out (less the frames held back)
+        assert!((out.len() as i64 - 48000).abs() <= SINC_ZERO_CROSSINGS as i64 + 1);
+
+        // Compare with the ideal 48 kHz sine, away from the start-up edge
+        let ideal = sine(48000, 1000.0, out.len());
+        let max_error = out[100..]
+            .iter()
+            .zip(&ideal[100..])
+            .map(|(a, b)| (a.0 - b.0).abs())
+            .max()
+            .unwrap();
+        // Better than -60 dB relative to the signal
+        assert!(max_error < 4_000, "max error {}", max_error);
+    }
+
+    #[test]
+    fn test_sinc_downsampling_keeps_timing() {
+        // 96 kHz -> 48 kHz stereo, in uneven buffers
+        let mut resampler = SincResampler::new();
+        let mut out_frames = 0;
+        for frames in [100, 1000, 37, 863, 2000] {
+            out_frames += resampler.process(&ramp(frames, 0), 2, 2.0).len() / 2;
+        }
+        // The kernel widens for downsampling and holds back that much input
+        let held = resampler.latency_frames() as i64;
+        assert_eq!(held, 2 * SINC_ZERO_CROSSINGS as i64);
+        assert!((out_frames as i64 - 2000).abs() <= held / 2 + 1);
+
+        // Flushing at stream end plays the held-back part too
+        out_frames += resampler.flush(2, 2.0).len() / 2;
+        assert!((out_frames as i64 - 2000).abs() <= 1, "{}", out_frames);
+    }
+
+    #[test]
+    fn test_sinc_passes_dc_and_clamps() {
+        let mut resampler = SincResampler::new();
+        let input = vec![Sample(SAMPLE_MAX); 2000];
+        let out = resampler.process(&input, 1, 44100.0 / 48000.0);
+        assert!(out[100..].iter().all(|s| (s.0 - SAMPLE_MAX).abs() <= 1));
+    }
+
+    #[test]
+    fn test_reset_drops_history() {
+        let mut resampler = LinearResampler::new();
+        resampler.process(&ramp(10, 0), 2, 1.0);
+        resampler.reset();
+        let out = resampler.process(&ramp(3, 100), 2, 1.0);
+        assert_eq!(out[0].0, 100);
+    }
+
+    /// Linear chirp from `f0` to `f1` Hz over `frames` frames at `rate`
+    fn sweep(rate: u32, f0: f64, f1: f64, seconds: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|i| {
+                let t = i as f64 / rate as f64;
+                let phase = 2.0 * PI * (f0 * t + (f1 - f0) * t * t / (2.0 * seconds));
+                phase.sin() * 4_000_000.0
+            })
+            .collect()
+    }
+
+    /// Convert mono audio in 480-frame buffers, flushing at the end
+    fn convert(quality: ResampleQuality, input: &[f64], ratio: f64) -> Vec<f64> {
+        let mut resampler = for_quality(quality);
+        let input: Vec<Sample> = input.iter().map(|v| Sample(v.round() as i32)).collect();
+        let mut out: Vec<f64> = input
+            .chunks(480)
+            .flat_map(|chunk| resampler.process(chunk, 1, ratio).to_vec())
+            .map(|s| s.0 as f64)
+            .collect();
+        out.extend(resampler.flush(1, ratio).iter().map(|s| s.0 as f64));
+        out
+    }
+
+    fn rms(samples: &[f64]) -> f64 {
+        (samples.iter().map(|v| v * v).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    /// Signal to error ratio against the ideal output, in dB
+    fn snr_db(out: &[f64], ideal: &[f64]) -> f64 {
+        let error: Vec<f64> = out.iter().zip(ideal).map(|(a, b)| a - b).collect();
+        20.0 * (rms(ideal) / rms(&error)).log10()
+    }
+
+    #[test]
+    fn test_quality_names() {
+        let parse = |name| ResampleQuality::from_str(name, false);
+        assert_eq!(parse("fast"), Ok(ResampleQuality::Fast));
+        assert_eq!(parse("medium"), Ok(ResampleQuality::Medium));
+        assert_eq!(parse("high"), Ok(ResampleQuality::High));
+        assert_eq!(parse("best"), Ok(ResampleQuality::High));
+        assert!(parse("slow").is_err());
+    }
+
+    #[test]
+    fn test_quality_levels_on_sine_sweep() {
+        // 100 Hz - 10 kHz sweep, 44.1 kHz -> 48 kHz
+        let input = sweep(44100, 100.0, 10_000.0, 1.0, 44100);
+        let ideal = sweep(48000, 100.0, 10_000.0, 1.0, 48000);
+        let snr: Vec<f64> = [
+            ResampleQuality::Fast,
+            ResampleQuality::Medium,
+            ResampleQuality::High,
+        ]
+        .into_iter()
+        .map(|quality| {
+            let out = convert(quality, &input, 44100.0 / 48000.0);
+            snr_db(&out[100..47000], &ideal[100..47000])
+        })
+        .collect();
+        assert!(snr[0] < snr[1] && snr[1] < snr[2], "{:?}", snr);
+        // Linear is audibly rough on high frequencies; the sinc levels are clean
+        assert!(snr[0] > 15.0, "{:?}", snr);
+        assert!(snr[1] > 70.0, "{:?}", snr);
+        assert!(snr[2] > 90.0, "{:?}", snr);
+    }
+
+    #[test]
+    fn test_quality_levels_reject_aliasing() {
+        // 30 kHz can't exist at 48 kHz: 96 kHz -> 48 kHz must remove it
+        let input = sweep(96000, 30_000.0, 30_000.0, 1.0, 96000);
+        let level: Vec<f64> = [
+            ResampleQuality::Fast,
+            ResampleQuality::Medium,
+            ResampleQuality::High,
+        ]
+        .into_iter()
+        .map(|quality| {
+            let out = convert(quality, &input, 2.0);
+            20.0 * (rms(&out[100..47000]) / rms(&input)).log10()
+        })
+        .collect();
+        // Linear lets it fold straight back to 18 kHz
+        assert!(level[0] > -6.0, "{:?}", level);
+        assert!(level[1] < -50.0, "{:?}", level);
+        assert!(level[2] < -75.0, "{:?}", level);
+    }
+
+    #[test]
+    fn test_flush_returns_held_back_frames() {
+        // 0.1 s at 44.1 kHz is 4800 frames at 48 kHz, none lost at the end
+        let input = vec![1_000_000.0; 4410];
+        for quality in [
+            ResampleQuality::Fast,
+            ResampleQuality::Medium,
+            ResampleQuality::High,
+        ] {
+            let out = convert(quality, &input, 44100.0 / 48000.0);
+            assert!((out.len() as i64 - 4800).abs() <= 1, "{:?}", quality);
+            assert!((out[4700] - 1_000_000.0).abs() < 1.0, "{:?}", quality);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Audio Ring Buffer
+//
+// Decoded buffers go from the network task to the playback thread through a
+// fixed-size ring. There is exactly one writer and one reader, so each side
+// owns one index (the writer the tail, the reader the head) and only reads the
+// other's: no lock, and the playback thread never waits for the network task
+// or the other way round. The reader can look at the next item without taking
+// it, which is how the playback thread waits for a buffer's play time.
+//
+// Indices count up forever (wrapping) and are reduced to a slot on access, so
+// a full ring and an empty one are told apart by their difference.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize, // Next slot to read, advanced by the reader
+    tail: AtomicUsize, // Next slot to write, advanced by the writer
+}
+
+// Each slot is accessed by one side at a time, handed over by the indices
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+
+    fn len(&self) -> usize {
+        // Head first: the tail read after it can only be further along
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            // Written and not yet read
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Writing end of a ring
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// Reading end of a ring
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// A ring holding up to `capacity` items
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let slots = (0..capacity.max(1))
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let ring = Arc::new(Ring {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            ring: Arc::clone(&ring),
+        },
+        Consumer { ring },
+    )
+}
+
+impl<T> Producer<T> {
+    /// Add an item, or hand it back when the ring is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(ring.head.load(Ordering::Acquire)) == ring.slots.len() {
+            return Err(value);
+        }
+        unsafe { (*ring.slot(tail)).write(value) };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Items waiting to be read
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Consumer<T> {
+    /// The next item, left in the ring
+    pub fn peek(&self) -> Option<&T> {
+        let ring = &self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(unsafe { (*ring.slot(head)).assume_init_ref() })
+    }
+
+    /// Take the next item
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*ring.slot(head)).assume_init_read() };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Items waiting to be read
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_and_full() {
+        let (mut tx, mut rx) = ring(3);
+        assert!(rx.peek().is_none());
+        for i in 0..3 {
+            assert!(tx.push(i).is_ok());
+        }
+        assert_eq!(tx.push(3), Err(3));
+        assert_eq!(rx.len(), 3);
+        assert_eq!(rx.peek(), Some(&0));
+        assert_eq!(rx.pop(), Some(0));
+        // A slot freed by the reader is writable again, across the wrap
+        assert!(tx.push(3).is_ok());
+        assert_eq!(
+            std::iter::from_fn(|| rx.pop()).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert!(rx.is_empty() && tx.is_empty());
+    }
+
+    #[test]
+    fn test_unread_items_are_dropped() {
+        let item = Arc::new(());
+        let (mut tx, rx) = ring(4);
+        tx.push(Arc::clone(&item)).unwrap();
+        tx.push(Arc::clone(&item)).unwrap();
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn test_concurrent_writer_and_reader() {
+        // Everything written arrives once, in order, whatever the interleaving
+        const COUNT: u64 = 20_000;
+        let (mut tx, mut rx) = ring(64);
+        let writer = std::thread::spawn(move || {
+            for i in 0..COUNT {
+                let mut item = Box::new(i);
+                loop {
+                    match tx.push(item) {
+                        Ok(()) => break,
+                        Err(back) => {
+                            item = back;
+                            std::thread::yield_now();
+                        }
+                    }
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < COUNT {
+            if let Some(next) = rx.peek().map(|item| **item) {
+                assert_eq!(next, expected);
+                assert_eq!(*rx.pop().unwrap(), expected);
+                expected += 1;
+            } else {
+                std::thread::yield_now();
+            }
+            assert!(rx.len() <= 64);
+        }
+        writer.join().unwrap();
+        assert!(rx.pop().is_none());
+    }
+}
@@ -0,0 +1,65 @@
+// Random Numbers
+//
+// Dither noise and reconnect jitter need cheap, uniformly spread numbers,
+// not secure ones. Both draw them from the same xorshift64* generator,
+// seeded from the standard library's per-process random hasher keys (and
+// the process ID) unless a test fixes the seed to get reproducible output.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// xorshift64* generator
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Generator with a random seed for this instance
+    pub fn new() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        Self::with_seed(hasher.finish())
+    }
+
+    /// Generator with a fixed seed (reproducible numbers)
+    pub fn with_seed(seed: u64) -> Self {
+        Rng {
+            state: seed | 1, // xorshift must not start at zero
+        }
+    }
+
+    /// Uniform random number in [0, 1)
+    pub fn next_unit(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_range_and_spread() {
+        let mut rng = Rng::with_seed(7);
+        let values: Vec<f64> = (0..10_000).map(|_| rng.next_unit()).collect();
+        assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean - 0.5).abs() < 0.02, "mean {}", mean);
+
+        // The same seed gives the same numbers, a zero seed still works
+        let mut again = Rng::with_seed(7);
+        assert_eq!(again.next_unit(), values[0]);
+        assert_ne!(Rng::with_seed(0).next_unit(), 0.0);
+    }
+}
@@ -0,0 +1,127 @@
+// Playback Thread Priority
+//
+// On a busy single-board computer the playback thread can be preempted long
+// enough for the device to run dry. `--rt-priority[=PRIO]` asks Linux to run
+// it under SCHED_FIFO at that priority (1-99, 10 without a value: below
+// what JACK and PipeWire use for their own threads, so the sound server
+// still goes first). An unprivileged process usually isn't allowed to, so
+// when that's refused the thread gets an elevated nice level instead, and
+// when that's refused as well it stays as it was. It's best effort either
+// way: what was granted is logged, playback goes ahead regardless.
+//
+// Real-time scheduling needs an rtprio limit (`LimitRTPRIO=` in a systemd
+// unit, `rtprio` in limits.conf) or CAP_SYS_NICE; a negative nice level
+// needs a nice limit or the same capability. Elsewhere the option does
+// nothing but say so.
+
+use std::fmt;
+
+/// Priority of a bare `--rt-priority`
+pub const DEFAULT_PRIORITY: u8 = 10;
+
+/// Nice level tried when SCHED_FIFO is refused
+pub const FALLBACK_NICE: i32 = -10;
+
+/// What the thread ended up with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granted {
+    RealTime(u8), // SCHED_FIFO at this priority
+    Nice(i32),    // Normal scheduling at this nice level
+    Unchanged,
+}
+
+impl fmt::Display for Granted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Granted::RealTime(priority) => write!(f, "SCHED_FIFO priority {}", priority),
+            Granted::Nice(nice) => write!(f, "nice {}", nice),
+            Granted::Unchanged => write!(f, "normal priority"),
+        }
+    }
+}
+
+/// Raise the calling thread to SCHED_FIFO `priority`, or failing that an
+/// elevated nice level; logs the outcome
+pub fn raise_current_thread(priority: u8) -> Granted {
+    #[cfg(target_os = "linux")]
+    {
+        linux::raise(priority)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = priority;
+        tracing::warn!(
+            "--rt-priority is only supported on Linux, playback runs at normal priority"
+        );
+        Granted::Unchanged
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use std::io;
+    use tracing::{info, warn};
+
+    pub fn raise(priority: u8) -> Granted {
+        let param = libc::sched_param {
+            sched_priority: i32::from(priority),
+        };
+        // SAFETY: pthread_self() is the calling thread, and `param` outlives the call
+        let fifo =
+            unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+        if fifo == 0 {
+            let granted = Granted::RealTime(priority);
+            info!("Playback thread running at {}", granted);
+            return granted;
+        }
+        let refused = io::Error::from_raw_os_error(fifo);
+
+        // On Linux a thread ID given to setpriority changes that thread only
+        // SAFETY: gettid takes no arguments and can't fail
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+        // SAFETY: plain syscall on our own thread ID
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, FALLBACK_NICE) } == 0 {
+            let granted = Granted::Nice(FALLBACK_NICE);
+            warn!(
+                "Real-time scheduling refused ({}), playback thread running at {} instead",
+                refused, granted
+            );
+            return granted;
+        }
+        warn!(
+            "Can't raise the playback thread's priority (SCHED_FIFO: {}; nice: {}); allow it with LimitRTPRIO=/LimitNICE= or CAP_SYS_NICE",
+            refused,
+            io::Error::last_os_error()
+        );
+        Granted::Unchanged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_granted_display() {
+        assert_eq!(
+            Granted::RealTime(DEFAULT_PRIORITY).to_string(),
+            "SCHED_FIFO priority 10"
+        );
+        assert_eq!(Granted::Nice(FALLBACK_NICE).to_string(), "nice -10");
+        assert_eq!(Granted::Unchanged.to_string(), "normal priority");
+    }
+
+    #[test]
+    fn test_raise_is_best_effort() {
+        // Whatever the sandbox allows, asking never fails or panics, and
+        // only touches the thread that asked
+        let granted = std::thread::spawn(|| raise_current_thread(DEFAULT_PRIORITY))
+            .join()
+            .unwrap();
+        assert!(matches!(
+            granted,
+            Granted::RealTime(DEFAULT_PRIORITY) | Granted::Nice(FALLBACK_NICE) | Granted::Unchanged
+        ));
+    }
+}
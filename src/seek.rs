@@ -0,0 +1,84 @@
+// Seeking
+//
+// A seek normally reaches the client as stream/clear followed by chunks from
+// the new position. Some servers instead send a `seek` server/command with the
+// target position and carry on with the same stream, so the queued audio from
+// before the target has to be dropped here or it keeps playing for seconds.
+//
+// The position counts from the start of the stream. It is read as
+// `position_ms` (milliseconds) or `position` (seconds), from the command
+// object or the payload itself, and becomes a stream timestamp by adding it
+// to the stream's first timestamp, rounded down to a whole frame.
+
+use serde_json::Value;
+use std::time::Duration;
+
+/// Target position of a seek server/command, None for any other payload
+pub fn position_from_payload(payload: &Value) -> Option<Duration> {
+    [payload.get("player"), Some(payload)]
+        .into_iter()
+        .flatten()
+        .filter(|obj| obj.get("command").and_then(Value::as_str) == Some("seek"))
+        .find_map(|obj| {
+            let ms = obj.get("position_ms").and_then(Value::as_f64);
+            let secs = obj.get("position").and_then(Value::as_f64);
+            ms.map(|ms| ms / 1000.0)
+                .or(secs)
+                .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                .map(Duration::from_secs_f64)
+        })
+}
+
+/// Stream timestamp (µs) `position` into a stream whose first chunk had
+/// `stream_start`, on a frame boundary at `sample_rate`
+pub fn target_timestamp(stream_start: i64, position: Duration, sample_rate: u32) -> i64 {
+    let rate = sample_rate.max(1) as u128;
+    let frames = position.as_micros() * rate / 1_000_000;
+    stream_start + (frames * 1_000_000 / rate) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_position_from_command() {
+        let payload = json!({ "player": { "command": "seek", "position_ms": 83_500 } });
+        assert_eq!(
+            position_from_payload(&payload),
+            Some(Duration::from_millis(83_500))
+        );
+        let payload = json!({ "command": "seek", "position": 12.25 });
+        assert_eq!(
+            position_from_payload(&payload),
+            Some(Duration::from_millis(12_250))
+        );
+    }
+
+    #[test]
+    fn test_other_commands_and_missing_positions_ignored() {
+        let volume = json!({ "player": { "command": "volume", "position_ms": 1000 } });
+        assert_eq!(position_from_payload(&volume), None);
+        let bare = json!({ "player": { "command": "seek" } });
+        assert_eq!(position_from_payload(&bare), None);
+        let negative = json!({ "player": { "command": "seek", "position": -1 } });
+        assert_eq!(position_from_payload(&negative), None);
+    }
+
+    #[test]
+    fn test_target_timestamp_on_frame_boundary() {
+        // 48 kHz: 1 s is a whole number of frames
+        assert_eq!(
+            target_timestamp(5_000_000, Duration::from_secs(1), 48000),
+            6_000_000
+        );
+        // 44.1 kHz: 10 ms is 441 frames, exactly 10 000 µs
+        assert_eq!(
+            target_timestamp(0, Duration::from_millis(10), 44100),
+            10_000
+        );
+        // Between frames: rounded down (1 frame is ~22.68 µs)
+        assert_eq!(target_timestamp(0, Duration::from_micros(30), 44100), 22);
+    }
+}
@@ -0,0 +1,328 @@
+// Self-Test Tone
+//
+// Plays a sine wave through the normal Player path (queue, time sync, EQ,
+// volume) without connecting to a server. Buffers get synthetic play_at
+// timestamps and are fed at the pace a server would send them, so device
+// output, volume scaling and timing can be checked on a new machine.
+//
+// The channel test writes straight to the output instead: each channel in
+// turn beeps its own number (one beep for channel 1, two for channel 2, ...)
+// while every other channel stays silent, to verify speaker wiring.
+//
+// The connect tone (`--connect-tone`) is a short, quiet beep queued on the
+// player right after the handshake, so a headless setup audibly confirms that
+// it reached the server and that the output device plays.
+
+use crate::output::{self, OutputConfig};
+use crate::player::{Player, SAMPLE_MAX};
+use sendspin::audio::{AudioBuffer, AudioFormat, Codec, Sample};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// How long the tone plays before the self-test exits
+pub const DURATION: Duration = Duration::from_secs(5);
+
+/// Length of each generated buffer
+const CHUNK: Duration = Duration::from_millis(20);
+
+/// Delay before the first buffer plays, like a server's prebuffer
+const LEAD: Duration = Duration::from_millis(200);
+
+/// Tone level relative to full scale (-6 dBFS)
+const AMPLITUDE: f32 = 0.5;
+
+/// Connect tone: pitch, length, level relative to the self-test tone
+/// (-20 dBFS) and fade at each end so it doesn't click
+const CONNECT_FREQ: f32 = 880.0;
+const CONNECT_LENGTH: Duration = Duration::from_millis(200);
+const CONNECT_GAIN: f32 = 0.2;
+const CONNECT_RAMP: Duration = Duration::from_millis(10);
+
+/// Beep and gap lengths for the channel test
+const BEEP: Duration = Duration::from_millis(150);
+const CHANNEL_PAUSE: Duration = Duration::from_millis(600);
+
+/// Speaker names in WAVE channel order
+const CHANNEL_NAMES: [&str; 8] = [
+    "front left",
+    "front right",
+    "center",
+    "LFE",
+    "rear left",
+    "rear right",
+    "side left",
+    "side right",
+];
+
+/// Same format the client advertises first in its hello
+pub fn format() -> AudioFormat {
+    AudioFormat {
+        codec: Codec::Pcm,
+        sample_rate: 48000,
+        channels: 2,
+        bit_depth: 24,
+        codec_header: None,
+    }
+}
+
+/// Sine generator with phase kept continuous across buffers
+pub struct ToneGenerator {
+    freq: f32,
+    format: AudioFormat,
+    frame: u64,
+}
+
+impl ToneGenerator {
+    pub fn new(freq: f32, format: AudioFormat) -> Self {
+        ToneGenerator {
+            freq,
+            format,
+            frame: 0,
+        }
+    }
+
+    /// Next `frames` frames of the tone, same value on every channel
+    pub fn next_samples(&mut self, frames: usize) -> Arc<[Sample]> {
+        let rate = self.format.sample_rate as f64;
+        let channels = self.format.channels as usize;
+        let mut samples = Vec::with_capacity(frames * channels);
+        for i in 0..frames as u64 {
+            let t = (self.frame + i) as f64 / rate;
+            let v = (2.0 * std::f64::consts::PI * self.freq as f64 * t).sin();
+            let sample = Sample((v * (SAMPLE_MAX as f64) * AMPLITUDE as f64) as i32);
+            samples.extend(std::iter::repeat_n(sample, channels));
+        }
+        self.frame += frames as u64;
+        Arc::from(samples)
+    }
+
+    /// Next buffer of `frames` frames, scheduled at `play_at`
+    pub fn next_buffer(&mut self, frames: usize, play_at: Instant) -> AudioBuffer {
+        let timestamp = (self.frame * 1_000_000 / self.format.sample_rate as u64) as i64;
+        AudioBuffer {
+            timestamp,
+            play_at,
+            samples: self.next_samples(frames),
+            format: self.format.clone(),
+        }
+    }
+}
+
+/// Play `freq` Hz through the player for `duration`, then drain and return
+pub fn run(
+    player: &Player,
+    freq: f32,
+    duration: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = format();
+    let nyquist = format.sample_rate as f32 / 2.0;
+    if !(freq > 0.0 && freq < nyquist) {
+        return Err(format!("self-test frequency must be between 0 and {} Hz", nyquist).into());
+    }
+
+    info!(
+        "Self-test: {} Hz tone for {:?} ({}Hz {}ch {}bit)",
+        freq, duration, format.sample_rate, format.channels, format.bit_depth
+    );
+
+    let frames = (format.sample_rate as u128 * CHUNK.as_micros() / 1_000_000) as usize;
+    let chunks = (duration.as_micros() / CHUNK.as_micros()) as u32;
+    let mut generator = ToneGenerator::new(freq, format);
+
+    player.resume();
+    let start = Instant::now() + LEAD;
+    for i in 0..chunks {
+        let play_at = start + CHUNK * i;
+
+        // Stay about one lead ahead of playback, as a server would
+        let send_at = play_at - LEAD;
+        let now = Instant::now();
+        if send_at > now {
+            std::thread::sleep(send_at - now);
+        }
+        player.enqueue(generator.next_buffer(frames, play_at));
+    }
+
+    // Let the tail play out before the process exits
+    player.drain();
+    let end = start + CHUNK * chunks;
+    std::thread::sleep(end.saturating_duration_since(Instant::now()) + CHUNK);
+    info!("Self-test finished");
+    Ok(())
+}
+
+/// Buffers of the connect tone, the first one playing at `start`
+pub fn connect_tone_buffers(start: Instant) -> Vec<AudioBuffer> {
+    let format = format();
+    let rate = format.sample_rate as u128;
+    let channels = format.channels as usize;
+    let total = (rate * CONNECT_LENGTH.as_micros() / 1_000_000) as usize;
+    let ramp = (rate * CONNECT_RAMP.as_micros() / 1_000_000) as usize;
+    let chunk = (rate * CHUNK.as_micros() / 1_000_000) as usize;
+
+    let mut generator = ToneGenerator::new(CONNECT_FREQ, format);
+    let mut buffers = Vec::new();
+    let mut done = 0;
+    while done < total {
+        let frames = chunk.min(total - done);
+        let mut buffer = generator.next_buffer(frames, start + CHUNK * buffers.len() as u32);
+        let shaped: Vec<Sample> = buffer
+            .samples
+            .chunks_exact(channels)
+            .enumerate()
+            .flat_map(|(i, frame)| {
+                // Linear fade over the first and last `ramp` frames
+                let edge = (done + i).min(total - 1 - (done + i));
+                let gain = CONNECT_GAIN * (edge as f32 / ramp as f32).min(1.0);
+                frame
+                    .iter()
+                    .map(move |s| Sample((s.0 as f32 * gain) as i32))
+            })
+            .collect();
+        buffer.samples = Arc::from(shaped);
+        buffers.push(buffer);
+        done += frames;
+    }
+    buffers
+}
+
+/// Queue the connect tone, then let the player stop and close the output
+/// again until a stream starts
+pub fn connect_tone(player: &Player) {
+    debug!("Playing the connect tone");
+    player.resume();
+    for buffer in connect_tone_buffers(Instant::now() + CONNECT_RAMP) {
+        player.enqueue(buffer);
+    }
+    player.drain();
+}
+
+/// Place a mono signal on one channel of an interleaved buffer
+pub fn isolate_channel(mono: &[Sample], channels: usize, active: usize) -> Vec<Sample> {
+    let mut out = vec![Sample(0); mono.len() * channels];
+    for (frame, sample) in out.chunks_exact_mut(channels).zip(mono) {
+        frame[active] = *sample;
+    }
+    out
+}
+
+/// Beep each output channel in sequence, `n` beeps on channel `n`
+pub fn channel_test(config: &OutputConfig, channels: u8) -> Result<(), Box<dyn std::error::Error>> {
+    let format = AudioFormat {
+        channels,
+        ..format()
+    };
+    let mut out = output::open(config, format.clone())?;
+    let channels = channels as usize;
+
+    let mono = AudioFormat {
+        channels: 1,
+        ..format.clone()
+    };
+    let beep_frames = (format.sample_rate as u128 * BEEP.as_millis() / 1000) as usize;
+    let mut tone = ToneGenerator::new(880.0, mono);
+    let silence = |length: Duration| -> Arc<[Sample]> {
+        let frames = (format.sample_rate as u128 * length.as_millis() / 1000) as usize;
+        Arc::from(vec![Sample(0); frames * channels])
+    };
+
+    for channel in 0..channels {
+        let name = CHANNEL_NAMES.get(channel).copied().unwrap_or("extra");
+        info!("Channel test: channel {} ({})", channel + 1, name);
+        for _ in 0..=channel {
+            let beep = isolate_channel(&tone.next_samples(beep_frames), channels, channel);
+            out.write(&Arc::from(beep))?;
+            out.write(&silence(BEEP))?;
+        }
+        out.write(&silence(CHANNEL_PAUSE))?;
+    }
+
+    info!("Channel test finished");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_frame_count_and_level() {
+        let mut generator = ToneGenerator::new(1000.0, format());
+        let samples = generator.next_samples(960);
+        assert_eq!(samples.len(), 960 * 2);
+
+        let peak = samples.iter().map(|s| s.0.abs()).max().unwrap();
+        let expected = (SAMPLE_MAX as f32 * AMPLITUDE) as i32;
+        assert!((peak - expected).abs() < expected / 100);
+    }
+
+    #[test]
+    fn test_tone_phase_continuous_across_buffers() {
+        let mut split = ToneGenerator::new(440.0, format());
+        let mut whole = ToneGenerator::new(440.0, format());
+
+        let mut joined: Vec<i32> = split.next_samples(100).iter().map(|s| s.0).collect();
+        joined.extend(split.next_samples(100).iter().map(|s| s.0));
+        let expected: Vec<i32> = whole.next_samples(200).iter().map(|s| s.0).collect();
+
+        assert_eq!(joined, expected);
+    }
+
+    #[test]
+    fn test_buffer_timestamps_advance() {
+        let mut generator = ToneGenerator::new(440.0, format());
+        let now = Instant::now();
+        let first = generator.next_buffer(960, now);
+        let second = generator.next_buffer(960, now + CHUNK);
+
+        assert_eq!(first.timestamp, 0);
+        assert_eq!(second.timestamp, 20_000);
+        assert_eq!(second.play_at - first.play_at, CHUNK);
+    }
+
+    #[test]
+    fn test_connect_tone_is_short_quiet_and_faded() {
+        let start = Instant::now();
+        let buffers = connect_tone_buffers(start);
+        let samples: Vec<i32> = buffers
+            .iter()
+            .flat_map(|b| b.samples.iter().map(|s| s.0))
+            .collect();
+        assert_eq!(samples.len(), 9600 * 2); // 200 ms of stereo at 48 kHz
+        assert_eq!(buffers[1].play_at - buffers[0].play_at, CHUNK);
+
+        let peak = samples.iter().map(|s| s.abs()).max().unwrap();
+        assert!(peak <= (SAMPLE_MAX as f32 * AMPLITUDE * CONNECT_GAIN) as i32);
+        assert!(peak > 0);
+        // Faded in and out, so starting and stopping doesn't click
+        assert_eq!(samples[0], 0);
+        assert!(samples[samples.len() - 2..]
+            .iter()
+            .all(|s| s.abs() < peak / 100));
+    }
+
+    #[test]
+    fn test_isolate_channel() {
+        let mono = [Sample(5), Sample(-7)];
+        let out: Vec<i32> = isolate_channel(&mono, 3, 1).iter().map(|s| s.0).collect();
+        assert_eq!(out, vec![0, 5, 0, 0, -7, 0]);
+    }
+
+    #[test]
+    fn test_channel_test_on_null_backend() {
+        let config = OutputConfig {
+            backend: output::OutputBackendKind::Null,
+            ..Default::default()
+        };
+        // Runs in real time: one channel is one beep plus the pause (~0.9 s)
+        assert!(channel_test(&config, 1).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_frequency() {
+        let player = Player::new(0);
+        assert!(run(&player, 0.0, Duration::ZERO).is_err());
+        assert!(run(&player, 30000.0, Duration::ZERO).is_err());
+    }
+}
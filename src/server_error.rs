@@ -0,0 +1,125 @@
+// Server Errors
+//
+// Nothing acknowledges a client/state, and the library has no message for an
+// error reply, so a server that refused our state (or anything else we sent)
+// went unnoticed. Servers report such problems as an `error` or `*/error`
+// message, or as an `error` field in another reply. This picks them out of
+// the raw messages so they can be logged, along with which of our messages
+// they answer when the server says so.
+//
+// The only states this client can report are synchronized and error, so a
+// refused client/state can't be adjusted to another state and isn't sent
+// again; the refusal is logged. A reply that carries an error field is still
+// handled as the message it is, so a server/state with an error in it can
+// still set the volume.
+
+use serde_json::Value;
+use std::fmt;
+
+/// An error the server reported
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    pub message: String,
+    pub code: Option<String>,
+    pub about: Option<String>, // Type of the message it answers, e.g. client/state
+}
+
+impl ServerError {
+    /// Whether this refuses the client/state we sent
+    pub fn rejects_state(&self) -> bool {
+        self.about.as_deref() == Some("client/state")
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(ref code) = self.code {
+            write!(f, " (code {})", code)?;
+        }
+        if let Some(ref about) = self.about {
+            write!(f, " in reply to {}", about)?;
+        }
+        Ok(())
+    }
+}
+
+/// A string, or a number written as one
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// The error in a message of type `msg_type`, if it reports one
+pub fn server_error_from(msg_type: &str, payload: &Value) -> Option<ServerError> {
+    let is_error = msg_type == "error" || msg_type.ends_with("/error");
+    let field = payload.get("error").filter(|error| !error.is_null());
+    if !is_error && field.is_none() {
+        return None;
+    }
+    // Details sit in the error object if there is one, otherwise in the payload
+    let details = field.filter(|error| error.is_object()).unwrap_or(payload);
+    let first = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| details.get(*key).or_else(|| payload.get(*key)))
+            .and_then(text)
+    };
+    let message = first(&["message", "reason", "detail"])
+        .or_else(|| field.and_then(text))
+        .unwrap_or_else(|| "no details given".to_string());
+    Some(ServerError {
+        message,
+        code: first(&["code"]),
+        about: first(&["in_reply_to", "request", "message_type"]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_error_message_refusing_state() {
+        let payload = json!({
+            "code": 400,
+            "message": "unexpected state synchronized",
+            "in_reply_to": "client/state"
+        });
+        let error = server_error_from("server/error", &payload).unwrap();
+        assert!(error.rejects_state());
+        assert_eq!(
+            error.to_string(),
+            "unexpected state synchronized (code 400) in reply to client/state"
+        );
+    }
+
+    #[test]
+    fn test_error_field_in_other_reply() {
+        let payload = json!({ "error": "player not registered" });
+        let error = server_error_from("server/state", &payload).unwrap();
+        assert_eq!(error.message, "player not registered");
+        assert!(!error.rejects_state());
+
+        let payload = json!({
+            "error": { "code": "invalid_state", "reason": "buffering expected" },
+            "request": "client/state"
+        });
+        let error = server_error_from("server/state", &payload).unwrap();
+        assert_eq!(error.code.as_deref(), Some("invalid_state"));
+        assert_eq!(error.message, "buffering expected");
+        assert!(error.rejects_state());
+    }
+
+    #[test]
+    fn test_ordinary_messages_are_not_errors() {
+        let state = json!({ "metadata": { "title": "Song" }, "error": null });
+        assert_eq!(server_error_from("server/state", &state), None);
+        assert_eq!(server_error_from("stream/start", &json!({})), None);
+        let bare = server_error_from("error", &json!({})).unwrap();
+        assert_eq!(bare.to_string(), "no details given");
+    }
+}
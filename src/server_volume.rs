@@ -0,0 +1,45 @@
+// Server Volume
+//
+// A player joining a group should start at the group's volume, not jump to
+// its own `--volume` and then get corrected by the first volume command. The
+// server may carry a volume in its server/hello or in an early server/state
+// (the controller section holds the group volume), as a 0-100 number. Unless
+// `--volume` was given, the client adopts the first one it sees on each
+// connection and reports it back in client/state.
+
+use serde_json::Value;
+
+/// Find a 0-100 volume in a server/hello or server/state payload
+pub fn volume_from_payload(payload: &Value) -> Option<u8> {
+    ["player", "controller", "group"]
+        .iter()
+        .filter_map(|section| payload.get(section))
+        .chain(std::iter::once(payload))
+        .find_map(|obj| obj.get("volume").and_then(Value::as_f64))
+        .filter(|volume| (0.0..=100.0).contains(volume))
+        .map(|volume| volume.round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_volume_from_sections() {
+        let state = json!({ "controller": { "volume": 35, "muted": false } });
+        assert_eq!(volume_from_payload(&state), Some(35));
+        let hello = json!({ "server_id": "ma", "name": "Music Assistant", "volume": 60 });
+        assert_eq!(volume_from_payload(&hello), Some(60));
+        let player = json!({ "player": { "volume": 42.4 } });
+        assert_eq!(volume_from_payload(&player), Some(42));
+    }
+
+    #[test]
+    fn test_missing_or_invalid_volume() {
+        assert_eq!(volume_from_payload(&json!({ "metadata": {} })), None);
+        assert_eq!(volume_from_payload(&json!({ "volume": 150 })), None);
+        assert_eq!(volume_from_payload(&json!({ "volume": -1 })), None);
+        assert_eq!(volume_from_payload(&json!({ "volume": "loud" })), None);
+    }
+}
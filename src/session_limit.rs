@@ -0,0 +1,110 @@
+// Session Limit
+//
+// Demo and kiosk setups want the player gone after a while whatever it is
+// doing. `--max-session` caps the time from startup: when it runs out the
+// player stops, reports its state, closes the connection and the process
+// exits 0. The audio playing over the last `FADE` fades out on its way there,
+// so the stop doesn't cut a track off mid-note. Reconnects don't restart the
+// clock, and a limit that runs out while disconnected ends the process
+// without reconnecting.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Fade-out ahead of the limit
+pub const FADE: Duration = Duration::from_secs(2);
+
+/// Parse a session length: seconds, or a number with h, m or s units,
+/// combined as in `1h30m`
+pub fn parse_length(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<u64>() {
+        return check(Duration::from_secs(secs), s);
+    }
+    let mut total = Duration::ZERO;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("unknown unit '{}' in '{}' (use h, m or s)", c, s)),
+        };
+        let number = std::mem::take(&mut number);
+        if number.is_empty() {
+            return Err(format!("missing number before '{}' in '{}'", c, s));
+        }
+        total = number
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(unit))
+            .and_then(|secs| total.checked_add(Duration::from_secs(secs)))
+            .ok_or_else(|| format!("session length '{}' is too long", s))?;
+    }
+    if !number.is_empty() {
+        return Err(format!("missing unit after {} in '{}'", number, s));
+    }
+    check(total, s)
+}
+
+fn check(length: Duration, s: &str) -> Result<Duration, String> {
+    if length.is_zero() {
+        return Err(format!("session length '{}' must be more than zero", s));
+    }
+    // The limit becomes an Instant, which can't be pushed out arbitrarily far
+    if Instant::now().checked_add(length).is_none() {
+        return Err(format!("session length '{}' is too long", s));
+    }
+    Ok(length)
+}
+
+/// Resolves at `ends_at`, never when there's no limit
+pub async fn expired(ends_at: Option<Instant>) {
+    match ends_at {
+        Some(end) => tokio::time::sleep_until(end.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The session ran for its whole --max-session
+#[derive(Debug)]
+pub struct SessionLimitReached {
+    pub limit: Duration,
+}
+
+impl fmt::Display for SessionLimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "session limit of {:?} reached", self.limit)
+    }
+}
+
+impl std::error::Error for SessionLimitReached {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_length() {
+        assert_eq!(parse_length("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_length("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_length("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_length("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_length("2h"), Ok(Duration::from_secs(7200)));
+
+        assert!(parse_length("0").is_err());
+        assert!(parse_length("10x").is_err());
+        assert!(parse_length("1h30").is_err());
+        assert!(parse_length("m").is_err());
+        assert!(parse_length("").is_err());
+
+        // Too long to count, or to set a deadline with
+        assert!(parse_length("99999999999999999999h").is_err());
+        assert!(parse_length("9999999999999999h").is_err());
+        assert!(parse_length("18446744073709551615").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// Playback Speed
+//
+// Some Music Assistant integrations nudge the playback speed slightly to keep
+// long group sessions aligned. The speed arrives as `playback_speed` in the
+// track progress metadata (server/state) or in a server/command, either as a
+// plain factor or scaled by 1000 as in the protocol's TrackProgress.
+
+use serde_json::Value;
+
+/// Speeds outside this range are ignored rather than applied
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+
+/// Find a playback speed factor in a server/state or server/command payload
+pub fn playback_speed_from_payload(payload: &Value) -> Option<f32> {
+    let metadata = payload.get("metadata");
+    [
+        metadata.and_then(|m| m.get("progress")),
+        payload.get("progress"),
+        metadata,
+        payload.get("player"),
+        Some(payload),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|obj| obj.get("playback_speed").and_then(Value::as_f64))
+    .and_then(|raw| {
+        // 1000 = normal speed in TrackProgress; small values are plain factors
+        let speed = if raw > 10.0 { raw / 1000.0 } else { raw } as f32;
+        // Zero means paused, which is handled by the play/pause commands
+        (MIN_SPEED..=MAX_SPEED).contains(&speed).then_some(speed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_speed_from_progress_metadata() {
+        let payload = json!({
+            "metadata": { "progress": { "track_progress": 1000, "playback_speed": 1020 } }
+        });
+        assert_eq!(playback_speed_from_payload(&payload), Some(1.02));
+    }
+
+    #[test]
+    fn test_speed_from_command_factor() {
+        let payload = json!({ "player": { "command": "speed", "playback_speed": 0.98 } });
+        assert_eq!(playback_speed_from_payload(&payload), Some(0.98));
+    }
+
+    #[test]
+    fn test_speed_ignored_when_missing_or_out_of_range() {
+        assert_eq!(
+            playback_speed_from_payload(&json!({ "metadata": {} })),
+            None
+        );
+        let paused = json!({ "metadata": { "progress": { "playback_speed": 0 } } });
+        assert_eq!(playback_speed_from_payload(&paused), None);
+        assert_eq!(
+            playback_speed_from_payload(&json!({ "playback_speed": 5.0 })),
+            None
+        );
+    }
+}
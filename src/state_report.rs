@@ -0,0 +1,306 @@
+// State Report
+//
+// A player that misbehaves in the middle of the night should be inspectable
+// without restarting it. On Unix, SIGUSR1 logs one report of everything the
+// client knows at that moment: the connection and server, the stream being
+// played, the queue, the clock estimate and drift correction, volume, the
+// playback counters since start, and the last message types received.
+//
+//     kill -USR1 $(pidof sendspin-rs-cli)
+//
+// The message router records each message it receives in `RecentMessages`,
+// a short ring that outlives reconnects, so the report still shows what the
+// server sent last while the client is trying to get back to it. Runs of the
+// same type (audio chunks, mostly) take one entry with a count.
+
+use crate::stats::StatsSnapshot;
+use sendspin::audio::AudioFormat;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Entries kept in the recent-messages ring
+pub const RECENT_MESSAGES: usize = 16;
+
+/// Message types received lately, oldest first
+#[derive(Debug, Default)]
+pub struct RecentMessages {
+    entries: VecDeque<(String, u64, Instant)>, // Type, times in a row, last seen
+}
+
+/// The ring as shared between the router and the session
+pub type SharedRecent = Arc<Mutex<RecentMessages>>;
+
+impl RecentMessages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared() -> SharedRecent {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    /// A message of type `kind` arrived at `at`
+    pub fn push(&mut self, kind: &str, at: Instant) {
+        if let Some((last, count, seen)) = self.entries.back_mut() {
+            if last == kind {
+                *count += 1;
+                *seen = at;
+                return;
+            }
+        }
+        if self.entries.len() == RECENT_MESSAGES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((kind.to_string(), 1, at));
+    }
+
+    /// The entries as of `now`, oldest first
+    pub fn recent(&self, now: Instant) -> Vec<RecentMessage> {
+        self.entries
+            .iter()
+            .map(|(kind, count, seen)| RecentMessage {
+                kind: kind.clone(),
+                count: *count,
+                age: now.saturating_duration_since(*seen),
+            })
+            .collect()
+    }
+}
+
+/// One entry of the ring in a report
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentMessage {
+    pub kind: String,
+    pub count: u64,    // Received in a row
+    pub age: Duration, // Since the last of them
+}
+
+/// Everything the SIGUSR1 report shows
+#[derive(Debug, Clone, Default)]
+pub struct StateReport {
+    pub url: String,
+    pub connected: bool,
+    pub server_id: Option<String>,   // Of the last server reached
+    pub format: Option<AudioFormat>, // Current stream, None between streams
+    pub playing: bool,
+    pub output_open: bool,
+    pub queued_buffers: usize,
+    pub buffered: Duration,
+    pub offset_us: Option<i64>,
+    pub rtt_us: Option<i64>,
+    pub drift_ppm: Option<f64>, // None with drift correction off
+    pub volume: u8,
+    pub muted: bool,
+    pub replay_gain_db: f32,   // Applied, preamp included
+    pub totals: StatsSnapshot, // Since start
+    pub recent: Vec<RecentMessage>,
+}
+
+impl fmt::Display for StateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "state report:")?;
+        let state = if self.connected {
+            "connected to"
+        } else {
+            "disconnected from"
+        };
+        write!(f, "  connection: {} {}", state, self.url)?;
+        match self.server_id {
+            Some(ref id) => writeln!(f, " (server {})", id)?,
+            None => writeln!(f)?,
+        }
+        match self.format {
+            Some(ref format) => write!(
+                f,
+                "  stream: {} Hz, {} ch, {}-bit",
+                format.sample_rate, format.channels, format.bit_depth
+            )?,
+            None => write!(f, "  stream: none")?,
+        }
+        writeln!(
+            f,
+            ", {}, output {}",
+            if self.playing { "playing" } else { "stopped" },
+            if self.output_open { "open" } else { "closed" }
+        )?;
+        writeln!(
+            f,
+            "  queue: {} buffers, {} ms",
+            self.queued_buffers,
+            self.buffered.as_millis()
+        )?;
+        write!(f, "  clock: ")?;
+        match (self.offset_us, self.rtt_us) {
+            (Some(offset), Some(rtt)) => write!(
+                f,
+                "offset {:+.3} ms, round trip {:.3} ms",
+                offset as f64 / 1000.0,
+                rtt as f64 / 1000.0
+            )?,
+            _ => write!(f, "not synced")?,
+        }
+        match self.drift_ppm {
+            Some(ppm) => writeln!(f, ", drift correction {:+.1} ppm", ppm)?,
+            None => writeln!(f, ", drift correction off")?,
+        }
+        writeln!(
+            f,
+            "  volume: {}{}, replaygain {:+.2} dB",
+            self.volume,
+            if self.muted { ", muted" } else { "" },
+            self.replay_gain_db
+        )?;
+        writeln!(f, "  since start: {}", self.totals)?;
+        if self.recent.is_empty() {
+            return write!(f, "  recent messages: none");
+        }
+        write!(f, "  recent messages (newest last):")?;
+        for message in &self.recent {
+            write!(f, "\n    {}", message.kind)?;
+            if message.count > 1 {
+                write!(f, " x{}", message.count)?;
+            }
+            write!(f, ", {:.1} s ago", message.age.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// SIGUSR1 as a stream of report requests; never fires where there's none
+pub struct ReportSignal {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl ReportSignal {
+    /// Install the handler; must run inside the Tokio runtime
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = signal(SignalKind::user_defined1())
+                .map_err(|e| tracing::warn!("SIGUSR1 state reports unavailable: {}", e))
+                .ok();
+            ReportSignal { signal }
+        }
+        #[cfg(not(unix))]
+        {
+            ReportSignal {}
+        }
+    }
+
+    /// Resolves when a report is asked for
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = self.signal.as_mut() {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+impl Default for ReportSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    #[test]
+    fn test_recent_messages_ring() {
+        let start = Instant::now();
+        let mut recent = RecentMessages::new();
+        recent.push("server/hello", start);
+        for i in 0..48 {
+            recent.push("audio", start + Duration::from_millis(i));
+        }
+        recent.push("server/time", start + Duration::from_millis(100));
+        let entries = recent.recent(start + Duration::from_millis(1100));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].kind, "audio");
+        assert_eq!(entries[1].count, 48);
+        assert_eq!(entries[2].age, Duration::from_secs(1));
+
+        // Only the newest entries are kept
+        for i in 0..RECENT_MESSAGES {
+            recent.push(&format!("type/{}", i), start);
+        }
+        let entries = recent.recent(start);
+        assert_eq!(entries.len(), RECENT_MESSAGES);
+        assert_eq!(entries[0].kind, "type/0");
+    }
+
+    #[test]
+    fn test_report_formatting() {
+        let report = StateReport {
+            url: "ws://speaker:8927/sendspin".to_string(),
+            connected: true,
+            server_id: Some("ma".to_string()),
+            format: Some(AudioFormat {
+                codec: Codec::Pcm,
+                sample_rate: 48000,
+                channels: 2,
+                bit_depth: 24,
+                codec_header: None,
+            }),
+            playing: true,
+            output_open: true,
+            queued_buffers: 12,
+            buffered: Duration::from_millis(480),
+            offset_us: Some(-1234),
+            rtt_us: Some(2500),
+            drift_ppm: Some(1.5),
+            volume: 40,
+            muted: true,
+            replay_gain_db: -6.5,
+            totals: StatsSnapshot {
+                buffers_played: 3,
+                frames_played: 2880,
+                ..Default::default()
+            },
+            recent: vec![
+                RecentMessage {
+                    kind: "server/time".to_string(),
+                    count: 1,
+                    age: Duration::from_millis(1200),
+                },
+                RecentMessage {
+                    kind: "audio".to_string(),
+                    count: 48,
+                    age: Duration::ZERO,
+                },
+            ],
+        };
+        assert_eq!(
+            report.to_string(),
+            "state report:\n\
+             \x20 connection: connected to ws://speaker:8927/sendspin (server ma)\n\
+             \x20 stream: 48000 Hz, 2 ch, 24-bit, playing, output open\n\
+             \x20 queue: 12 buffers, 480 ms\n\
+             \x20 clock: offset -1.234 ms, round trip 2.500 ms, drift correction +1.5 ppm\n\
+             \x20 volume: 40, muted, replaygain -6.50 dB\n\
+             \x20 since start: 3 buffers (2880 frames) played, 0 underruns (0 ms concealed), 0 buffers dropped, 0 frames trimmed, 0 device opens, output latency 0 ms\n\
+             \x20 recent messages (newest last):\n\
+             \x20   server/time, 1.2 s ago\n\
+             \x20   audio x48, 0.0 s ago"
+        );
+
+        let idle = StateReport {
+            url: "ws://speaker:8927/sendspin".to_string(),
+            ..Default::default()
+        };
+        let text = idle.to_string();
+        assert!(text.contains("disconnected from ws://speaker:8927/sendspin\n"));
+        assert!(text.contains("stream: none, stopped, output closed"));
+        assert!(text.contains("clock: not synced, drift correction off"));
+        assert!(text.ends_with("recent messages: none"));
+    }
+}
@@ -0,0 +1,438 @@
+// Playback Statistics
+//
+// Counters the playback thread bumps as it goes, readable from the owner's
+// side at any time without locking. `reset` hands back what was counted and
+// starts from zero; the output latency is a reading rather than a count and
+// carries over. Reports over an interval that share the counters with other
+// readers take a snapshot instead and subtract the previous one (`since`).
+//
+// Nothing drops a buffer for being late: a late buffer is still written and
+// shows up as an underrun when the device ran dry waiting for it. Dropped
+// buffers are those taken off the queue unplayed (stop, new stream, seek,
+// audio already heard before a pause, or the device being gone), trimmed
+// frames the part of a buffer a seek or resume cut off its front. A queue
+// that runs dry mid-stream is bridged with silence (see conceal.rs), which
+// counts as an underrun and adds to the concealed time.
+//
+// With `--stats[=SECS]` the client logs one line per interval with these
+// counts and what only the session knows (chunks in, decode time, the clock
+// estimate, gaps and duplicates in the timestamps), as stable `key=value`
+// pairs so it can be grepped or parsed.
+//
+// The drift correction's current rate is published here as well, as a
+// reading, for the SIGUSR1 state report. So are the frames played since
+// start and the time of the last underrun, which `reset` leaves alone, for
+// `Player::debug_state`.
+
+use crate::continuity::StreamStats;
+use sendspin::audio::AudioFormat;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Default interval of `--stats` without a value
+pub const DEFAULT_INTERVAL_SECS: u64 = 10;
+
+/// Time exchanges the clock figures are the median of
+const SYNC_SAMPLES: usize = 16;
+
+/// Live playback counters, shared with the playback thread
+#[derive(Debug, Default)]
+pub struct PlayerStats {
+    frames_played: AtomicU64,
+    buffers_played: AtomicU64,
+    underruns: AtomicU64,
+    concealed_us: AtomicU64,
+    dropped_buffers: AtomicU64,
+    trimmed_frames: AtomicU64,
+    device_opens: AtomicU64,
+    latency_us: AtomicU64,
+    drift_ppb: AtomicI64,    // Drift correction in parts per billion
+    frames_total: AtomicU64, // Frames played since start, never reset
+    last_underrun: Mutex<Option<Instant>>, // When the device last ran dry
+    underrun_rung: Notify,   // For owners waiting on the next underrun
+}
+
+/// Counts at one moment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub frames_played: u64,       // Output frames written to the device
+    pub buffers_played: u64,      // Writes, one per buffer
+    pub underruns: u64,           // Times the device ran dry before audio that was due
+    pub concealed: Duration,      // Silence written while the queue was dry
+    pub dropped_buffers: u64,     // Taken off the queue without being played
+    pub trimmed_frames: u64,      // Cut off the front of buffers by seeks and resumes
+    pub device_opens: u64,        // Output opened, the first time included
+    pub output_latency: Duration, // Device buffering at the last write
+}
+
+impl PlayerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn played(&self, frames: usize) {
+        self.frames_played
+            .fetch_add(frames as u64, Ordering::Relaxed);
+        self.buffers_played.fetch_add(1, Ordering::Relaxed);
+        self.frames_total
+            .fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+        *self.last_underrun.lock().unwrap() = Some(Instant::now());
+        self.underrun_rung.notify_one();
+    }
+
+    /// Frames played since the player started, whatever `reset` took
+    pub fn frames_total(&self) -> u64 {
+        self.frames_total.load(Ordering::Relaxed)
+    }
+
+    /// When the device last ran dry, None if it never did
+    pub fn last_underrun(&self) -> Option<Instant> {
+        *self.last_underrun.lock().unwrap()
+    }
+
+    /// Resolves at the next underrun, or at once for one since the last call
+    pub async fn next_underrun(&self) {
+        self.underrun_rung.notified().await;
+    }
+
+    pub(crate) fn concealed(&self, silence: Duration) {
+        self.concealed_us
+            .fetch_add(silence.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self, buffers: usize) {
+        self.dropped_buffers
+            .fetch_add(buffers as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn trimmed(&self, frames: usize) {
+        self.trimmed_frames
+            .fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn device_opened(&self) {
+        self.device_opens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_latency(&self, latency: Duration) {
+        self.latency_us
+            .store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_drift(&self, ppm: f64) {
+        self.drift_ppb
+            .store((ppm * 1000.0).round() as i64, Ordering::Relaxed);
+    }
+
+    /// Current drift correction in ppm (positive = playing faster)
+    pub fn drift_ppm(&self) -> f64 {
+        self.drift_ppb.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    /// The counts so far
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            frames_played: self.frames_played.load(Ordering::Relaxed),
+            buffers_played: self.buffers_played.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            concealed: Duration::from_micros(self.concealed_us.load(Ordering::Relaxed)),
+            dropped_buffers: self.dropped_buffers.load(Ordering::Relaxed),
+            trimmed_frames: self.trimmed_frames.load(Ordering::Relaxed),
+            device_opens: self.device_opens.load(Ordering::Relaxed),
+            output_latency: Duration::from_micros(self.latency_us.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// The counts so far, starting again from zero
+    pub fn reset(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            frames_played: self.frames_played.swap(0, Ordering::Relaxed),
+            buffers_played: self.buffers_played.swap(0, Ordering::Relaxed),
+            underruns: self.underruns.swap(0, Ordering::Relaxed),
+            concealed: Duration::from_micros(self.concealed_us.swap(0, Ordering::Relaxed)),
+            dropped_buffers: self.dropped_buffers.swap(0, Ordering::Relaxed),
+            trimmed_frames: self.trimmed_frames.swap(0, Ordering::Relaxed),
+            device_opens: self.device_opens.swap(0, Ordering::Relaxed),
+            output_latency: Duration::from_micros(self.latency_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl StatsSnapshot {
+    /// Counts since `earlier`, a snapshot of the same counters; the latency
+    /// is this reading
+    pub fn since(&self, earlier: &StatsSnapshot) -> StatsSnapshot {
+        StatsSnapshot {
+            frames_played: self.frames_played.saturating_sub(earlier.frames_played),
+            buffers_played: self.buffers_played.saturating_sub(earlier.buffers_played),
+            underruns: self.underruns.saturating_sub(earlier.underruns),
+            concealed: self.concealed.saturating_sub(earlier.concealed),
+            dropped_buffers: self.dropped_buffers.saturating_sub(earlier.dropped_buffers),
+            trimmed_frames: self.trimmed_frames.saturating_sub(earlier.trimmed_frames),
+            device_opens: self.device_opens.saturating_sub(earlier.device_opens),
+            output_latency: self.output_latency,
+        }
+    }
+
+    /// Add the counts of a later interval; the latency is the later reading
+    pub fn add(&mut self, later: &StatsSnapshot) {
+        self.frames_played += later.frames_played;
+        self.buffers_played += later.buffers_played;
+        self.underruns += later.underruns;
+        self.concealed += later.concealed;
+        self.dropped_buffers += later.dropped_buffers;
+        self.trimmed_frames += later.trimmed_frames;
+        self.device_opens += later.device_opens;
+        self.output_latency = later.output_latency;
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} buffers ({} frames) played, {} underruns ({} ms concealed), {} buffers dropped, {} frames trimmed, {} device opens, output latency {} ms",
+            self.buffers_played,
+            self.frames_played,
+            self.underruns,
+            self.concealed.as_millis(),
+            self.dropped_buffers,
+            self.trimmed_frames,
+            self.device_opens,
+            self.output_latency.as_millis()
+        )
+    }
+}
+
+/// Recent clock offset and round-trip measurements from time exchanges
+#[derive(Debug, Default)]
+pub struct SyncSamples {
+    offsets_us: VecDeque<i64>,
+    rtts_us: VecDeque<i64>,
+}
+
+impl SyncSamples {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// One exchange: client sent at t1, server received at t2 and answered
+    /// at t3, client received at t4 (µs, each on its own clock)
+    pub fn add(&mut self, t1: i64, t2: i64, t3: i64, t4: i64) {
+        if self.offsets_us.len() == SYNC_SAMPLES {
+            self.offsets_us.pop_front();
+            self.rtts_us.pop_front();
+        }
+        self.offsets_us.push_back(((t2 - t1) + (t3 - t4)) / 2);
+        self.rtts_us.push_back((t4 - t1) - (t3 - t2));
+    }
+
+    /// Median server-minus-client clock offset in µs
+    pub fn offset_us(&self) -> Option<i64> {
+        median(&self.offsets_us)
+    }
+
+    /// Median round trip in µs
+    pub fn rtt_us(&self) -> Option<i64> {
+        median(&self.rtts_us)
+    }
+}
+
+fn median(values: &VecDeque<i64>) -> Option<i64> {
+    let mut sorted: Vec<i64> = values.iter().copied().collect();
+    sorted.sort_unstable();
+    sorted.get(sorted.len() / 2).copied()
+}
+
+/// One `--stats` line
+#[derive(Debug, Clone, Default)]
+pub struct StatsLine {
+    pub connected: bool,
+    pub format: Option<AudioFormat>, // Current stream, None between streams
+    pub buffered: Duration,          // Queued in the player
+    pub chunks_received: u64,        // This interval
+    pub chunks_decoded: u64,         // This interval, queued for playback
+    pub decode_time: Duration,       // Spent decoding this interval's chunks
+    pub playback: StatsSnapshot,     // This interval
+    pub offset_us: Option<i64>,
+    pub rtt_us: Option<i64>,
+    pub continuity: StreamStats, // Gaps and duplicates this interval
+    pub replay_gain_db: f32,     // Applied now, preamp included
+}
+
+impl fmt::Display for StatsLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.connected {
+            "connected"
+        } else {
+            "disconnected"
+        };
+        write!(f, "stats: state={}", state)?;
+        match self.format {
+            Some(ref format) => write!(
+                f,
+                " format={}/{}/{}",
+                format.sample_rate, format.channels, format.bit_depth
+            )?,
+            None => write!(f, " format=-")?,
+        }
+        write!(
+            f,
+            " buffered_ms={} chunks_received={} chunks_decoded={} underruns={} concealed_ms={} dropped={} trimmed_frames={} latency_ms={}",
+            self.buffered.as_millis(),
+            self.chunks_received,
+            self.chunks_decoded,
+            self.playback.underruns,
+            self.playback.concealed.as_millis(),
+            self.playback.dropped_buffers,
+            self.playback.trimmed_frames,
+            self.playback.output_latency.as_millis()
+        )?;
+        match self.offset_us {
+            Some(offset) => write!(f, " offset_ms={:+.3}", offset as f64 / 1000.0)?,
+            None => write!(f, " offset_ms=-")?,
+        }
+        match self.rtt_us {
+            Some(rtt) => write!(f, " rtt_ms={:.3}", rtt as f64 / 1000.0)?,
+            None => write!(f, " rtt_ms=-")?,
+        }
+        match self.decode_time.checked_div(self.chunks_decoded as u32) {
+            Some(per_chunk) => write!(f, " decode_us={}", per_chunk.as_micros())?,
+            None => write!(f, " decode_us=-")?,
+        }
+        write!(
+            f,
+            " gaps={} duplicates={} replaygain_db={:+.2}",
+            self.continuity.gaps, self.continuity.duplicates, self.replay_gain_db
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    #[test]
+    fn test_reset_starts_a_new_interval() {
+        let stats = PlayerStats::new();
+        stats.device_opened();
+        stats.played(960);
+        stats.played(960);
+        stats.underrun();
+        stats.concealed(Duration::from_millis(30));
+        stats.dropped(3);
+        stats.trimmed(480);
+        stats.set_latency(Duration::from_millis(42));
+
+        let counted = stats.reset();
+        assert_eq!(
+            counted,
+            StatsSnapshot {
+                frames_played: 1920,
+                buffers_played: 2,
+                underruns: 1,
+                concealed: Duration::from_millis(30),
+                dropped_buffers: 3,
+                trimmed_frames: 480,
+                device_opens: 1,
+                output_latency: Duration::from_millis(42),
+            }
+        );
+        assert_eq!(
+            counted.to_string(),
+            "2 buffers (1920 frames) played, 1 underruns (30 ms concealed), 3 buffers dropped, 480 frames trimmed, 1 device opens, output latency 42 ms"
+        );
+
+        // Counts start over, the latency reading stays
+        let after = stats.snapshot();
+        assert_eq!(after.frames_played, 0);
+        assert_eq!(after.device_opens, 0);
+        assert_eq!(after.output_latency, Duration::from_millis(42));
+    }
+
+    #[test]
+    fn test_since_leaves_the_counters_alone() {
+        let stats = PlayerStats::new();
+        stats.played(960);
+        stats.underrun();
+        let earlier = stats.snapshot();
+        stats.played(480);
+        stats.dropped(2);
+        stats.set_latency(Duration::from_millis(42));
+
+        let interval = stats.snapshot().since(&earlier);
+        assert_eq!(interval.frames_played, 480);
+        assert_eq!(interval.buffers_played, 1);
+        assert_eq!(interval.underruns, 0);
+        assert_eq!(interval.dropped_buffers, 2);
+        assert_eq!(interval.output_latency, Duration::from_millis(42));
+        // Still counted in full for everyone else
+        assert_eq!(stats.snapshot().frames_played, 1440);
+    }
+
+    #[test]
+    fn test_sync_medians() {
+        let mut sync = SyncSamples::new();
+        assert_eq!(sync.offset_us(), None);
+        // Server 1 s ahead, 2 ms each way, 1 ms at the server
+        sync.add(0, 1_002_000, 1_003_000, 5_000);
+        sync.add(10_000, 1_012_000, 1_013_000, 15_000);
+        // An outlier with a slow return leg doesn't move the median
+        sync.add(20_000, 1_022_000, 1_023_000, 60_000);
+        assert_eq!(sync.offset_us(), Some(1_000_000));
+        assert_eq!(sync.rtt_us(), Some(4_000));
+    }
+
+    #[test]
+    fn test_stats_line_renders() {
+        let line = StatsLine {
+            connected: true,
+            format: Some(AudioFormat {
+                codec: Codec::Pcm,
+                sample_rate: 48000,
+                channels: 2,
+                bit_depth: 24,
+                codec_header: None,
+            }),
+            buffered: Duration::from_millis(480),
+            chunks_received: 500,
+            chunks_decoded: 498,
+            decode_time: Duration::from_micros(7470),
+            playback: StatsSnapshot {
+                underruns: 1,
+                concealed: Duration::from_millis(40),
+                dropped_buffers: 2,
+                trimmed_frames: 480,
+                output_latency: Duration::from_millis(21),
+                ..Default::default()
+            },
+            offset_us: Some(-1234),
+            rtt_us: Some(3200),
+            continuity: StreamStats {
+                gaps: 2,
+                silence_inserted_us: 40_000,
+                duplicates: 1,
+            },
+            replay_gain_db: -6.5,
+        };
+        assert_eq!(
+            line.to_string(),
+            "stats: state=connected format=48000/2/24 buffered_ms=480 chunks_received=500 chunks_decoded=498 underruns=1 concealed_ms=40 dropped=2 trimmed_frames=480 latency_ms=21 offset_ms=-1.234 rtt_ms=3.200 decode_us=15 gaps=2 duplicates=1 replaygain_db=-6.50"
+        );
+
+        let idle = StatsLine::default();
+        assert_eq!(
+            idle.to_string(),
+            "stats: state=disconnected format=- buffered_ms=0 chunks_received=0 chunks_decoded=0 underruns=0 concealed_ms=0 dropped=0 trimmed_frames=0 latency_ms=0 offset_ms=- rtt_ms=- decode_us=- gaps=0 duplicates=0 replaygain_db=+0.00"
+        );
+    }
+}
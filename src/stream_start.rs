@@ -0,0 +1,59 @@
+// Repeated stream/start
+//
+// Servers sometimes send stream/start again with the same player config
+// while the stream is playing, e.g. when they query the client's state once
+// more. Handled as a new stream it would stop the player, drop the queue and
+// start over: an audible glitch for nothing. When the format matches the
+// stream being played, the client keeps playing and only acknowledges the
+// message with its state. A stream/start after stream/end or stream/clear,
+// or while paused, still starts afresh.
+
+use sendspin::audio::AudioFormat;
+
+/// Whether a stream/start for `format` repeats the stream already playing
+/// (`active` is its format, None between streams); only PCM streams are
+/// played, so the codec isn't compared
+pub fn is_repeat(active: Option<&AudioFormat>, format: &AudioFormat, playing: bool) -> bool {
+    playing
+        && active.is_some_and(|active| {
+            active.sample_rate == format.sample_rate
+                && active.channels == format.channels
+                && active.bit_depth == format.bit_depth
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sendspin::audio::Codec;
+
+    fn pcm(sample_rate: u32) -> AudioFormat {
+        AudioFormat {
+            codec: Codec::Pcm,
+            sample_rate,
+            channels: 2,
+            bit_depth: 24,
+            codec_header: None,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_stream_start() {
+        let active = pcm(48000);
+        // Same config while playing: nothing to do
+        assert!(is_repeat(Some(&active), &pcm(48000), true));
+        // A different rate or depth is a new stream
+        assert!(!is_repeat(Some(&active), &pcm(44100), true));
+        assert!(!is_repeat(
+            Some(&active),
+            &AudioFormat {
+                bit_depth: 16,
+                ..pcm(48000)
+            },
+            true
+        ));
+        // Between streams or paused, start afresh
+        assert!(!is_repeat(None, &pcm(48000), true));
+        assert!(!is_repeat(Some(&active), &pcm(48000), false));
+    }
+}
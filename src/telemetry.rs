@@ -0,0 +1,225 @@
+// Logging and Tracing
+//
+// Log lines and spans come from `tracing`. The console shows them on stderr
+// as before, filtered by RUST_LOG (errors only when it isn't set), so stdout
+// stays free for --json-events and --format-report. Lines logged inside a
+// span carry its name and fields:
+//
+// - `connection` (server, server_id): one connection to the server
+// - `stream` (format): from stream/start to stream/end or clear
+// - `chunk` (ts, frames, lead_ms), trace level: one audio chunk through
+//   decode and scheduling
+// - `playback` (backend): the playback thread
+//
+// `--otlp <endpoint>` also exports the spans to an OpenTelemetry collector
+// over OTLP/gRPC, so a connection, its streams and their chunks can be
+// followed in Jaeger or Tempo next to each other on one timeline. Export has
+// its own level, info unless RUST_LOG asks for more (`RUST_LOG=trace` sends
+// every chunk). It needs a build with the `otlp` feature.
+//
+// `--log-file <path>` writes the same lines to a file as well, rotated by
+// size (see log_file.rs) and without colours. The file has its own level,
+// info unless RUST_LOG says otherwise, so a headless player keeps a useful
+// history even with the console left at errors. Lines are handed to a writer
+// thread, so a slow SD card or a rotation never holds up the thread that
+// logged them; should that thread fall far behind, lines are dropped rather
+// than waited for.
+//
+// On Unix, SIGUSR2 switches the client's own debug lines on the console on
+// and off without a restart (on top of whatever RUST_LOG enables), for a
+// player that misbehaves now and may not after restarting it.
+
+use crate::log_file::RotatingFile;
+use std::error::Error;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+/// Console level without RUST_LOG (what env_logger showed)
+pub const CONSOLE_DEFAULT: &str = "error";
+
+/// Export level without RUST_LOG: connection and stream spans, no chunks
+pub const EXPORT_DEFAULT: &str = "info";
+
+/// Log file level without RUST_LOG
+pub const FILE_DEFAULT: &str = "info";
+
+/// Added to the console filter while SIGUSR2 has debug logging on
+pub const DEBUG_DIRECTIVE: &str = "sendspin_rs_cli=debug";
+
+/// Filter from `rust_log` (RUST_LOG's value), `default` when unset or invalid
+pub fn filter(rust_log: Option<&str>, default: &str) -> EnvFilter {
+    rust_log
+        .and_then(|directives| EnvFilter::try_new(directives).ok())
+        .unwrap_or_else(|| EnvFilter::new(default))
+}
+
+/// Console filter with debug lines on or off
+pub fn console_filter(rust_log: Option<&str>, debug: bool) -> EnvFilter {
+    let base = filter(rust_log, CONSOLE_DEFAULT);
+    if debug {
+        filter(
+            Some(&format!("{},{}", base, DEBUG_DIRECTIVE)),
+            CONSOLE_DEFAULT,
+        )
+    } else {
+        base
+    }
+}
+
+/// Flushes exported spans and the log file when dropped; keep it until the
+/// client exits
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct Telemetry {
+    console: reload::Handle<EnvFilter, Registry>,
+    rust_log: Option<String>,
+    _log_file: Option<WorkerGuard>, // Writes out the queued lines when dropped
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Telemetry {
+    /// Switch debug logging with SIGUSR2 from now on (Unix only)
+    pub fn toggle_debug_on_sigusr2(&self) {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut sigusr2 = match signal(SignalKind::user_defined2()) {
+                Ok(sigusr2) => sigusr2,
+                Err(e) => {
+                    tracing::warn!("SIGUSR2 debug toggle unavailable: {}", e);
+                    return;
+                }
+            };
+            let console = self.console.clone();
+            let rust_log = self.rust_log.clone();
+            tokio::spawn(async move {
+                let mut debug = false;
+                while sigusr2.recv().await.is_some() {
+                    debug = !debug;
+                    // Said while debug lines show: after switching on, before off
+                    if !debug {
+                        tracing::info!("SIGUSR2: debug logging off");
+                    }
+                    if let Err(e) = console.reload(console_filter(rust_log.as_deref(), debug)) {
+                        tracing::warn!("SIGUSR2: switching debug logging failed: {}", e);
+                    } else if debug {
+                        tracing::info!("SIGUSR2: debug logging on");
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Exporting the last spans failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Install the console output, with `log_file` the file output and, with
+/// `otlp`, the span exporter; must run inside the Tokio runtime
+pub fn init(
+    otlp: Option<&str>,
+    log_file: Option<RotatingFile>,
+) -> Result<Telemetry, Box<dyn Error>> {
+    let rust_log = std::env::var("RUST_LOG").ok();
+    let (reloadable, console) = reload::Layer::new(console_filter(rust_log.as_deref(), false));
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(reloadable);
+    let (file, log_file) = match log_file.map(tracing_appender::non_blocking) {
+        Some((writer, guard)) => {
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_filter(filter(rust_log.as_deref(), FILE_DEFAULT));
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    let registry = tracing_subscriber::registry().with(layer).with(file);
+
+    let Some(endpoint) = otlp else {
+        registry.try_init()?;
+        return Ok(Telemetry {
+            console,
+            rust_log,
+            _log_file: log_file,
+            #[cfg(feature = "otlp")]
+            provider: None,
+        });
+    };
+
+    #[cfg(feature = "otlp")]
+    {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::KeyValue;
+        use opentelemetry_otlp::WithExportConfig;
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            .with_resource(opentelemetry_sdk::Resource::new([KeyValue::new(
+                "service.name",
+                env!("CARGO_PKG_NAME"),
+            )]))
+            .build();
+        let export = tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+            .with_filter(filter(rust_log.as_deref(), EXPORT_DEFAULT));
+        registry.with(export).try_init()?;
+        Ok(Telemetry {
+            console,
+            rust_log,
+            _log_file: log_file,
+            provider: Some(provider),
+        })
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = (registry, endpoint, console, log_file);
+        Err("--otlp needs a build with the otlp feature".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_defaults_and_rust_log() {
+        assert_eq!(filter(None, CONSOLE_DEFAULT).to_string(), "error");
+        assert_eq!(
+            filter(Some("sendspin_rs_cli=trace"), EXPORT_DEFAULT).to_string(),
+            "sendspin_rs_cli=trace"
+        );
+        // A typo in RUST_LOG doesn't silence errors
+        assert_eq!(
+            filter(Some("debug=["), CONSOLE_DEFAULT).to_string(),
+            "error"
+        );
+    }
+
+    #[test]
+    fn test_debug_toggle_adds_to_rust_log() {
+        assert_eq!(console_filter(None, false).to_string(), "error");
+        let debug = console_filter(None, true).to_string();
+        assert!(debug.contains("sendspin_rs_cli=debug"), "{}", debug);
+        assert!(debug.contains("error"), "{}", debug);
+        let debug = console_filter(Some("info"), true).to_string();
+        assert!(debug.contains("info"), "{}", debug);
+        assert!(debug.contains("sendspin_rs_cli=debug"), "{}", debug);
+    }
+}
@@ -0,0 +1,360 @@
+// Chunk Timing Trace
+//
+// Sync error can come from the network (chunks arriving late), the clock
+// estimate (play times jumping with the offset) or the output device
+// (buffers written on time that still start late). `--timing-trace <file>`
+// appends one CSV row per audio chunk to tell them apart offline:
+//
+//     chunk_ts_us,arrival_ms,play_at_ms,write_ms,lateness_ms,duration_ms,queue_depth,offset_us
+//
+// The session records each chunk as it's queued (arrival, play time, queue
+// depth, clock offset), the playback thread each buffer as it's written (the
+// write's instant and how late its first frame reaches the device). A writer
+// task joins the two by stream timestamp and does the file I/O, so neither
+// side waits on the disk. Instants are milliseconds since the trace started.
+// Chunks merged into one buffer share its write; chunks never written
+// (stopped, seeked past, replaced by a new stream) get empty write columns.
+//
+// Records go through a bounded channel. When the writer falls behind, new
+// records are dropped rather than waited for, so tracing doesn't change the
+// timing it measures; the first drop is logged.
+
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// First line of a new trace file
+pub const HEADER: &str =
+    "chunk_ts_us,arrival_ms,play_at_ms,write_ms,lateness_ms,duration_ms,queue_depth,offset_us";
+
+/// Records in flight to the writer before new ones are dropped
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// Chunks waiting for their write before the oldest counts as never written
+const PENDING_LIMIT: usize = 4096;
+
+/// How often the writer flushes to the file
+const FLUSH_EVERY: Duration = Duration::from_secs(1);
+
+/// A chunk as the session queued it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enqueued {
+    pub timestamp: i64,         // Stream timestamp (µs)
+    pub arrival: Instant,       // Taken off the connection
+    pub play_at: Instant,       // When its first frame is due
+    pub duration: Duration,     // Play time
+    pub queue_depth: usize,     // Buffers queued ahead of it
+    pub offset_us: Option<i64>, // Clock offset estimate, None before the first exchange
+}
+
+/// A buffer as the playback thread wrote it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Written {
+    pub timestamp: i64,      // First frame's stream timestamp (µs)
+    pub end: i64,            // Just past its last frame
+    pub written_at: Instant, // When it was handed to the device
+    pub lateness_us: i64,    // First frame on the device against its play time, negative = early
+}
+
+/// What the writer task receives
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimingRecord {
+    Enqueued(Enqueued),
+    Written(Written),
+}
+
+/// One chunk's line in the trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub chunk: Enqueued,
+    pub written: Option<(Instant, i64)>, // Write instant and lateness (µs), None if never written
+}
+
+impl Row {
+    /// The CSV line, instants in ms since `epoch`
+    pub fn to_csv(&self, epoch: Instant) -> String {
+        let (write, lateness) = match self.written {
+            Some((at, lateness_us)) => (
+                format!("{:.3}", since_ms(epoch, at)),
+                format!("{:.3}", lateness_us as f64 / 1000.0),
+            ),
+            None => (String::new(), String::new()),
+        };
+        format!(
+            "{},{:.3},{:.3},{},{},{:.3},{},{}",
+            self.chunk.timestamp,
+            since_ms(epoch, self.chunk.arrival),
+            since_ms(epoch, self.chunk.play_at),
+            write,
+            lateness,
+            self.chunk.duration.as_secs_f64() * 1000.0,
+            self.chunk.queue_depth,
+            self.chunk
+                .offset_us
+                .map_or(String::new(), |offset| offset.to_string())
+        )
+    }
+}
+
+/// Milliseconds from `epoch` to `at`, negative before it
+fn since_ms(epoch: Instant, at: Instant) -> f64 {
+    at.saturating_duration_since(epoch).as_secs_f64() * 1000.0
+        - epoch.saturating_duration_since(at).as_secs_f64() * 1000.0
+}
+
+/// Matches queued chunks with the buffer writes that played them
+#[derive(Debug, Default)]
+pub struct Joiner {
+    pending: VecDeque<Enqueued>, // Queued, not written yet, in queueing order
+}
+
+impl Joiner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A chunk was queued; returns the oldest one when too many are waiting
+    pub fn enqueued(&mut self, chunk: Enqueued) -> Option<Row> {
+        self.pending.push_back(chunk);
+        if self.pending.len() <= PENDING_LIMIT {
+            return None;
+        }
+        self.pending.pop_front().map(|chunk| Row {
+            chunk,
+            written: None,
+        })
+    }
+
+    /// A buffer was written: rows for the chunks it played, and for those
+    /// queued before them that it skipped, which were never written
+    pub fn written(&mut self, write: &Written) -> Vec<Row> {
+        let played = |chunk: &Enqueued| (write.timestamp..write.end).contains(&chunk.timestamp);
+        // Pre-roll silence and the previous stream's tail match nothing
+        let Some(last) = self.pending.iter().rposition(played) else {
+            return Vec::new();
+        };
+        self.pending
+            .drain(..=last)
+            .map(|chunk| {
+                let written = played(&chunk).then_some((write.written_at, write.lateness_us));
+                Row { chunk, written }
+            })
+            .collect()
+    }
+
+    /// Rows for the chunks still waiting, once nothing more will be written
+    pub fn finish(&mut self) -> Vec<Row> {
+        self.pending
+            .drain(..)
+            .map(|chunk| Row {
+                chunk,
+                written: None,
+            })
+            .collect()
+    }
+}
+
+/// Sends timing records to the writer without ever blocking
+#[derive(Debug, Clone)]
+pub struct TimingTrace {
+    tx: mpsc::Sender<TimingRecord>,
+    dropped: Arc<AtomicU64>, // Records the full channel turned away
+}
+
+impl TimingTrace {
+    /// A sender and the receiver its records arrive at, holding `capacity`
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<TimingRecord>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let trace = TimingTrace {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (trace, rx)
+    }
+
+    pub fn send(&self, record: TimingRecord) {
+        // A closed writer already said why
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(record) {
+            if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                warn!("Timing trace writer is falling behind, dropping records");
+            }
+        }
+    }
+
+    /// Records dropped because the writer was behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Open `path` for appending (with a header when it's new) and start the
+/// writer task; must run inside the Tokio runtime
+pub fn start(path: &Path) -> io::Result<TimingTrace> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut out = BufWriter::new(file);
+    if out.get_ref().metadata()?.len() == 0 {
+        writeln!(out, "{}", HEADER)?;
+    }
+    let (trace, rx) = TimingTrace::channel(CHANNEL_CAPACITY);
+    let path = path.display().to_string();
+    tokio::spawn(async move {
+        if let Err(e) = write_rows(rx, &mut out).await {
+            warn!("Timing trace {} stopped: {}", path, e);
+        }
+    });
+    Ok(trace)
+}
+
+/// Writer task: join the records and write their rows until every sender
+/// is gone
+async fn write_rows(mut rx: mpsc::Receiver<TimingRecord>, out: &mut impl Write) -> io::Result<()> {
+    let epoch = Instant::now();
+    let mut joiner = Joiner::new();
+    let mut flush = tokio::time::interval(FLUSH_EVERY);
+    flush.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        let rows = tokio::select! {
+            record = rx.recv() => match record {
+                Some(TimingRecord::Enqueued(chunk)) => joiner.enqueued(chunk).into_iter().collect(),
+                Some(TimingRecord::Written(write)) => joiner.written(&write),
+                None => break,
+            },
+            _ = flush.tick() => {
+                out.flush()?;
+                continue;
+            }
+        };
+        for row in rows {
+            writeln!(out, "{}", row.to_csv(epoch))?;
+        }
+    }
+    for row in joiner.finish() {
+        writeln!(out, "{}", row.to_csv(epoch))?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(timestamp: i64, play_at: Instant) -> Enqueued {
+        Enqueued {
+            timestamp,
+            arrival: play_at - Duration::from_millis(200),
+            play_at,
+            duration: Duration::from_millis(20),
+            queue_depth: 10,
+            offset_us: Some(-1500),
+        }
+    }
+
+    #[test]
+    fn test_row_formatting() {
+        let epoch = Instant::now();
+        let written = Row {
+            chunk: chunk(1_000_000, epoch + Duration::from_millis(250)),
+            written: Some((epoch + Duration::from_micros(249_500), 1250)),
+        };
+        assert_eq!(
+            written.to_csv(epoch),
+            "1000000,50.000,250.000,249.500,1.250,20.000,10,-1500"
+        );
+
+        let never = Row {
+            chunk: Enqueued {
+                offset_us: None,
+                ..chunk(1_020_000, epoch + Duration::from_millis(270))
+            },
+            written: None,
+        };
+        assert_eq!(never.to_csv(epoch), "1020000,70.000,270.000,,,20.000,10,");
+        assert_eq!(HEADER.split(',').count(), 8);
+    }
+
+    #[test]
+    fn test_joiner_matches_merged_and_skipped_chunks() {
+        let start = Instant::now();
+        let mut joiner = Joiner::new();
+        for i in 0..4 {
+            let at = start + Duration::from_millis(20) * i as u32;
+            assert_eq!(joiner.enqueued(chunk(i * 20_000, at)), None);
+        }
+        // Pre-roll silence ahead of the stream matches no chunk
+        let preroll = Written {
+            timestamp: -40_000,
+            end: 0,
+            written_at: start,
+            lateness_us: 0,
+        };
+        assert!(joiner.written(&preroll).is_empty());
+        // The first two chunks merged into one buffer; the third was seeked past
+        let rows = joiner.written(&Written {
+            timestamp: 0,
+            end: 40_000,
+            written_at: start,
+            lateness_us: 300,
+        });
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.written == Some((start, 300))));
+        let rows = joiner.written(&Written {
+            timestamp: 60_000,
+            end: 80_000,
+            written_at: start + Duration::from_millis(60),
+            lateness_us: -100,
+        });
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].chunk.timestamp, 40_000);
+        assert_eq!(rows[0].written, None);
+        assert_eq!(rows[1].written.map(|(_, late)| late), Some(-100));
+        assert!(joiner.finish().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_full_channel_drops_instead_of_blocking() {
+        let (trace, mut rx) = TimingTrace::channel(2);
+        let record = || TimingRecord::Enqueued(chunk(0, Instant::now()));
+        for _ in 0..5 {
+            trace.send(record());
+        }
+        assert_eq!(trace.dropped(), 3);
+        assert!(rx.recv().await.is_some());
+        // Room again once the writer catches up
+        trace.send(record());
+        assert_eq!(trace.dropped(), 3);
+
+        // A writer that's gone isn't the sender's problem
+        drop(rx);
+        trace.send(record());
+        assert_eq!(trace.dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_writer_flushes_rows_when_senders_are_gone() {
+        let (trace, rx) = TimingTrace::channel(CHANNEL_CAPACITY);
+        let start = Instant::now();
+        trace.send(TimingRecord::Enqueued(chunk(0, start)));
+        trace.send(TimingRecord::Enqueued(chunk(20_000, start)));
+        trace.send(TimingRecord::Written(Written {
+            timestamp: 0,
+            end: 20_000,
+            written_at: start,
+            lateness_us: 0,
+        }));
+        drop(trace);
+        let mut out = Vec::new();
+        write_rows(rx, &mut out).await.unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0,"));
+        // Never written: the write columns stay empty
+        assert!(lines[1].starts_with("20000,") && lines[1].contains(",,"));
+    }
+}
@@ -0,0 +1,284 @@
+// Volume Backends
+//
+// Volume is applied either by scaling samples in the playback thread
+// (software, the default) or through the ALSA hardware mixer on Linux, which
+// leaves the samples untouched and so keeps the full bit depth at low volume.
+// The mixer is the one of the card the output plays on (`--alsa-device`),
+// or the default card's when the output is the default device. If the
+// hardware control can't be used, playback falls back to software.
+
+use crate::player::{SAMPLE_MAX, SAMPLE_MIN};
+use clap::ValueEnum;
+use sendspin::audio::Sample;
+use tracing::{info, warn};
+
+/// Volume backend selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum VolumeBackendKind {
+    #[default]
+    Software,
+    Alsa,
+}
+
+/// Where volume changes are applied
+pub trait VolumeBackend {
+    fn name(&self) -> &'static str;
+
+    /// Apply a 0-100 volume, returning the linear gain the playback thread
+    /// still has to apply to samples (1.0 when handled in hardware)
+    fn set_volume(&mut self, volume: u8) -> Result<f32, Box<dyn std::error::Error>>;
+}
+
+/// Scale samples in the playback thread
+pub struct SoftwareVolume;
+
+impl VolumeBackend for SoftwareVolume {
+    fn name(&self) -> &'static str {
+        "software"
+    }
+
+    fn set_volume(&mut self, volume: u8) -> Result<f32, Box<dyn std::error::Error>> {
+        Ok(volume.min(100) as f32 / 100.0)
+    }
+}
+
+/// ALSA control device (`hw:CARD`) of the card an output device string
+/// plays on, e.g. `hw:CARD=DAC` for `plughw:CARD=DAC,DEV=0`; the default
+/// card's for the default device or a plugin with no card (`pulse`, `dmix`)
+pub fn mixer_device(output_device: Option<&str>) -> String {
+    let card = output_device
+        .and_then(|device| device.split_once(':'))
+        .and_then(|(_, params)| {
+            let mut params = params.split(',').map(str::trim);
+            let first = params.clone().next().filter(|p| !p.contains('='));
+            params
+                .find_map(|p| p.strip_prefix("CARD="))
+                .or(first)
+                .filter(|card| !card.is_empty())
+        });
+    match card {
+        Some(card) if card.chars().all(|c| c.is_ascii_digit()) => format!("hw:{}", card),
+        Some(card) => format!("hw:CARD={}", card),
+        None => "default".to_string(),
+    }
+}
+
+/// Open the requested backend for the card `output_device` plays on,
+/// falling back to software if it is unavailable
+pub fn open_backend(
+    kind: VolumeBackendKind,
+    output_device: Option<&str>,
+) -> Box<dyn VolumeBackend> {
+    match kind {
+        VolumeBackendKind::Software => Box::new(SoftwareVolume),
+        #[cfg(target_os = "linux")]
+        VolumeBackendKind::Alsa => {
+            match alsa_mixer::AlsaMixerVolume::open(&mixer_device(output_device)) {
+                Ok(mixer) => {
+                    info!(
+                        "Using ALSA hardware mixer control '{}' on {}",
+                        mixer.control(),
+                        mixer.card()
+                    );
+                    Box::new(mixer)
+                }
+                Err(e) => {
+                    warn!(
+                        "ALSA mixer unavailable ({}), falling back to software volume",
+                        e
+                    );
+                    Box::new(SoftwareVolume)
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        VolumeBackendKind::Alsa => {
+            let _ = output_device;
+            warn!("ALSA mixer is only available on Linux, falling back to software volume");
+            Box::new(SoftwareVolume)
+        }
+    }
+}
+
+/// Apply a volume, switching to software volume if the backend fails
+pub fn set_volume_with_fallback(backend: &mut Box<dyn VolumeBackend>, volume: u8) -> f32 {
+    match backend.set_volume(volume) {
+        Ok(gain) => gain,
+        Err(e) => {
+            warn!(
+                "{} volume failed ({}), falling back to software volume",
+                backend.name(),
+                e
+            );
+            *backend = Box::new(SoftwareVolume);
+            volume.min(100) as f32 / 100.0
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod alsa_mixer {
+    use super::VolumeBackend;
+    use alsa::mixer::{Mixer, Selem, SelemId};
+
+    /// Controls tried in order before falling back to any playback volume control
+    const PREFERRED_CONTROLS: [&str; 2] = ["Master", "PCM"];
+
+    /// Hardware volume through a card's ALSA mixer
+    pub struct AlsaMixerVolume {
+        mixer: Mixer,
+        selem_id: SelemId,
+        name: String,
+        card: String, // Control device, e.g. hw:CARD=DAC
+    }
+
+    impl AlsaMixerVolume {
+        /// Open the mixer of a control device (`hw:CARD=DAC`, or `default`)
+        pub fn open(card: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            let mixer = Mixer::new(card, false)?;
+
+            let preferred = PREFERRED_CONTROLS.iter().find_map(|name| {
+                let id = SelemId::new(name, 0);
+                mixer
+                    .find_selem(&id)
+                    .filter(|selem| selem.has_playback_volume())
+                    .map(|_| (id, name.to_string()))
+            });
+            let any = || {
+                mixer.iter().filter_map(Selem::new).find_map(|selem| {
+                    if !selem.has_playback_volume() {
+                        return None;
+                    }
+                    let id = selem.get_id();
+                    let name = id.get_name().ok()?.to_string();
+                    Some((id, name))
+                })
+            };
+
+            let (selem_id, name) = preferred
+                .or_else(any)
+                .ok_or_else(|| format!("no playback volume control on {}", card))?;
+
+            Ok(AlsaMixerVolume {
+                mixer,
+                selem_id,
+                name,
+                card: card.to_string(),
+            })
+        }
+
+        pub fn control(&self) -> &str {
+            &self.name
+        }
+
+        pub fn card(&self) -> &str {
+            &self.card
+        }
+    }
+
+    impl VolumeBackend for AlsaMixerVolume {
+        fn name(&self) -> &'static str {
+            "alsa"
+        }
+
+        fn set_volume(&mut self, volume: u8) -> Result<f32, Box<dyn std::error::Error>> {
+            let selem = self
+                .mixer
+                .find_selem(&self.selem_id)
+                .ok_or_else(|| format!("mixer control '{}' disappeared", self.name))?;
+            let (min, max) = selem.get_playback_volume_range();
+            let raw = min + (max - min) * volume.min(100) as i64 / 100;
+            selem.set_playback_volume_all(raw)?;
+            Ok(1.0)
+        }
+    }
+}
+
+/// Scale samples by a linear gain, clamping to the sample range
+pub fn apply_gain(samples: &mut [Sample], gain: f32) {
+    for sample in samples.iter_mut() {
+        let scaled = sample.0 as f32 * gain;
+        *sample = Sample((scaled as i32).clamp(SAMPLE_MIN, SAMPLE_MAX));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingVolume;
+
+    impl VolumeBackend for FailingVolume {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn set_volume(&mut self, _volume: u8) -> Result<f32, Box<dyn std::error::Error>> {
+            Err("device gone".into())
+        }
+    }
+
+    #[test]
+    fn test_software_volume_gain() {
+        let mut backend = SoftwareVolume;
+        assert_eq!(backend.set_volume(0).unwrap(), 0.0);
+        assert_eq!(backend.set_volume(50).unwrap(), 0.5);
+        assert_eq!(backend.set_volume(100).unwrap(), 1.0);
+        assert_eq!(backend.set_volume(150).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_open_software_backend() {
+        let backend = open_backend(VolumeBackendKind::Software, None);
+        assert_eq!(backend.name(), "software");
+    }
+
+    #[test]
+    fn test_mixer_follows_the_output_card() {
+        assert_eq!(mixer_device(None), "default");
+        assert_eq!(mixer_device(Some("default")), "default");
+        assert_eq!(mixer_device(Some("hw:CARD=DAC,DEV=0")), "hw:CARD=DAC");
+        assert_eq!(mixer_device(Some("plughw:CARD=DAC,DEV=0")), "hw:CARD=DAC");
+        assert_eq!(
+            mixer_device(Some("front:CARD=Audio,DEV=0")),
+            "hw:CARD=Audio"
+        );
+        assert_eq!(mixer_device(Some("hw:1,0")), "hw:1");
+        assert_eq!(mixer_device(Some("plughw:2")), "hw:2");
+        assert_eq!(mixer_device(Some("hw:DAC")), "hw:CARD=DAC");
+        // Plugins without a card use the default card's mixer
+        assert_eq!(mixer_device(Some("pulse")), "default");
+        assert_eq!(mixer_device(Some("dmix")), "default");
+    }
+
+    #[test]
+    fn test_fallback_to_software_on_failure() {
+        let mut backend: Box<dyn VolumeBackend> = Box::new(FailingVolume);
+        let gain = set_volume_with_fallback(&mut backend, 40);
+
+        assert_eq!(gain, 0.4);
+        assert_eq!(backend.name(), "software");
+    }
+
+    #[test]
+    fn test_apply_gain_scales_and_clamps() {
+        let samples = [
+            Sample(1000),
+            Sample(-1000),
+            Sample(SAMPLE_MAX),
+            Sample(SAMPLE_MIN),
+        ];
+
+        let mut half = samples;
+        apply_gain(&mut half, 0.5);
+        assert_eq!(half[0].0, 500);
+        assert_eq!(half[1].0, -500);
+
+        // Positive ReplayGain must not wrap around past full scale
+        let mut boosted = samples;
+        apply_gain(&mut boosted, 2.0);
+        assert_eq!(boosted[0].0, 2000);
+        assert_eq!(boosted[2].0, SAMPLE_MAX);
+        assert_eq!(boosted[3].0, SAMPLE_MIN);
+    }
+}
@@ -0,0 +1,152 @@
+// Precise Wake-Ups
+//
+// `thread::sleep` returns whenever the scheduler gets round to the thread,
+// commonly 1-10 ms after the deadline, and every millisecond late is a
+// millisecond out of sync with the other players. The playback thread sleeps
+// until shortly before a buffer is due (--wake-spin-us, 2 ms by default) and
+// then spins on the clock, yielding between checks, so it wakes within tens
+// of microseconds. Spinning keeps a core busy for that window on every
+// buffer; --no-wake-spin goes back to a plain sleep for battery-powered
+// devices. A thread granted SCHED_FIFO by --rt-priority doesn't spin: its
+// sleeps already wake on time, and spinning at that priority would starve
+// everything else on the core. How late each wake-up was is kept in a
+// histogram.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Default for --wake-spin-us
+pub const DEFAULT_SPIN_US: u64 = 2000;
+
+/// Upper bounds of the histogram buckets; the last bucket takes the rest
+const BUCKETS_US: [u64; 7] = [50, 100, 250, 500, 1000, 2000, 5000];
+
+/// Sleep until `deadline`, spinning through the last `spin` of the wait
+/// (zero = sleep only); returns how late the thread woke
+pub fn sleep_until(deadline: Instant, spin: Duration) -> Duration {
+    let now = Instant::now();
+    if deadline <= now {
+        return now - deadline;
+    }
+    let wait = deadline - now;
+    if spin.is_zero() {
+        std::thread::sleep(wait);
+    } else {
+        if wait > spin {
+            std::thread::sleep(wait - spin);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+            std::thread::yield_now();
+        }
+    }
+    Instant::now().saturating_duration_since(deadline)
+}
+
+/// Wake error (time past the deadline) counts per bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WakeHistogram {
+    counts: [u64; BUCKETS_US.len() + 1],
+}
+
+impl WakeHistogram {
+    pub fn record(&mut self, late: Duration) {
+        let us = late.as_micros();
+        let bucket = BUCKETS_US
+            .iter()
+            .position(|&bound| us < bound as u128)
+            .unwrap_or(BUCKETS_US.len());
+        self.counts[bucket] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of the bucket holding the `p`th percentile (0-100),
+    /// None when nothing was recorded or it falls in the open last bucket
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((p / 100.0 * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKETS_US.get(i).map(|&us| Duration::from_micros(us));
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for WakeHistogram {
+    /// Bucket counts, e.g. "<50µs: 940, <100µs: 52, ..., ≥5ms: 0"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, count) in self.counts.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match BUCKETS_US.get(i) {
+                Some(&us) => write!(f, "<{:?}: {}", Duration::from_micros(us), count)?,
+                None => write!(
+                    f,
+                    "≥{:?}: {}",
+                    Duration::from_micros(BUCKETS_US[BUCKETS_US.len() - 1]),
+                    count
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_and_percentiles() {
+        let mut histogram = WakeHistogram::default();
+        assert_eq!(histogram.percentile(95.0), None);
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(20));
+        }
+        for _ in 0..8 {
+            histogram.record(Duration::from_micros(300));
+        }
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_millis(40));
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(50.0), Some(Duration::from_micros(50)));
+        assert_eq!(histogram.percentile(95.0), Some(Duration::from_micros(500)));
+        assert_eq!(histogram.percentile(99.0), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.percentile(100.0), None);
+        assert!(histogram.to_string().starts_with("<50µs: 90, <100µs: 0"));
+    }
+
+    #[test]
+    fn test_past_deadline_returns_immediately() {
+        let deadline = Instant::now() - Duration::from_millis(5);
+        let late = sleep_until(deadline, Duration::from_millis(2));
+        assert!(late >= Duration::from_millis(5));
+    }
+
+    // Timing-sensitive: run on an idle machine with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_spin_wakes_within_threshold() {
+        let mut histogram = WakeHistogram::default();
+        for _ in 0..200 {
+            let deadline = Instant::now() + Duration::from_millis(5);
+            histogram.record(sleep_until(
+                deadline,
+                Duration::from_micros(DEFAULT_SPIN_US),
+            ));
+        }
+        let p95 = histogram.percentile(95.0).expect("p95 beyond 5 ms");
+        assert!(p95 <= Duration::from_micros(250), "{}", histogram);
+    }
+}
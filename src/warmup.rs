@@ -0,0 +1,105 @@
+// Clock Warm-Up Handover
+//
+// With `--clock-warmup-chunks` the first chunks of a connection are paced by
+// arrival while the clock estimate converges. Where synced timing takes over,
+// the paced schedule and the clock's can be milliseconds apart, and jumping
+// straight to the clock would leave a gap or an overlap at that chunk. The
+// difference is carried into the synced play times instead and worked off a
+// little per chunk, never more than half of what the continuity check treats
+// as timestamp rounding, so the schedule glides onto the clock's.
+
+use crate::continuity::TOLERANCE_US;
+use std::time::{Duration, Instant};
+
+/// Most of the offset worked off per chunk (µs)
+pub const STEP_US: i64 = TOLERANCE_US / 2;
+
+/// Offset between the paced schedule and synced timing, still to work off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarmupSlew {
+    offset_us: i64, // Paced minus synced: positive = the paced schedule is later
+}
+
+/// `to - from` in µs, negative when `to` is earlier
+fn signed_us(from: Instant, to: Instant) -> i64 {
+    if to >= from {
+        (to - from).as_micros() as i64
+    } else {
+        -((from - to).as_micros() as i64)
+    }
+}
+
+impl WarmupSlew {
+    /// Start from the paced schedule's next play time and the synced one
+    pub fn new(paced: Instant, synced: Instant) -> Self {
+        WarmupSlew {
+            offset_us: signed_us(synced, paced),
+        }
+    }
+
+    /// Offset still to work off (µs)
+    pub fn offset_us(&self) -> i64 {
+        self.offset_us
+    }
+
+    /// Chunks it takes to work the offset off
+    pub fn chunks_left(&self) -> i64 {
+        (self.offset_us.abs() + STEP_US - 1) / STEP_US
+    }
+
+    /// Whether play times follow the clock again
+    pub fn is_done(&self) -> bool {
+        self.offset_us == 0
+    }
+
+    /// Play time of the next chunk, `synced` moved by what's left of the
+    /// offset, which then shrinks by a step
+    pub fn apply(&mut self, synced: Instant) -> Instant {
+        let offset = Duration::from_micros(self.offset_us.unsigned_abs());
+        let play_at = if self.offset_us >= 0 {
+            synced + offset
+        } else {
+            synced.checked_sub(offset).unwrap_or(synced)
+        };
+        self.offset_us -= self.offset_us.clamp(-STEP_US, STEP_US);
+        play_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glides_onto_synced_timing() {
+        let synced = Instant::now() + Duration::from_secs(1);
+        // The paced schedule runs 1.8 ms behind the clock
+        let mut slew = WarmupSlew::new(synced + Duration::from_micros(1800), synced);
+        assert_eq!(slew.offset_us(), 1800);
+        assert_eq!(slew.chunks_left(), 4);
+
+        let chunk = Duration::from_millis(20);
+        let mut previous: Option<Instant> = None;
+        for i in 0..4 {
+            let play_at = slew.apply(synced + chunk * i);
+            if let Some(previous) = previous {
+                // Consecutive chunks move by at most a step against each other
+                let moved = signed_us(previous + chunk, play_at).abs();
+                assert!(moved <= STEP_US, "{} µs", moved);
+            }
+            previous = Some(play_at);
+        }
+        assert!(slew.is_done());
+        assert_eq!(slew.apply(synced), synced);
+    }
+
+    #[test]
+    fn test_paced_schedule_ahead_of_the_clock() {
+        let synced = Instant::now() + Duration::from_secs(1);
+        let mut slew = WarmupSlew::new(synced - Duration::from_micros(700), synced);
+        assert_eq!(slew.offset_us(), -700);
+        assert_eq!(slew.apply(synced), synced - Duration::from_micros(700));
+        assert_eq!(slew.apply(synced), synced - Duration::from_micros(200));
+        assert!(slew.is_done());
+    }
+}
@@ -43,6 +43,35 @@ fn test_invalid_volume() {
     assert!(!output.status.success() || output.stderr.len() > 0);
 }
 
+#[test]
+fn test_volume_from_environment() {
+    // SENDSPIN_VOLUME is validated like --volume
+    let output = Command::new("cargo")
+        .args(["run", "--", "--list-devices"])
+        .env("SENDSPIN_VOLUME", "loud")
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("loud"), "{}", stderr);
+}
+
+#[test]
+fn test_self_test_plays_through_null_backend() {
+    // Full playback path without a server or an audio device
+    let output = Command::new("cargo")
+        .args(["run", "--", "--self-test", "440", "--backend", "null"])
+        .env("RUST_LOG", "info")
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "Self-test failed: {}", stderr);
+    assert!(stderr.contains("Audio output (null) initialized"));
+    assert!(stderr.contains("Self-test finished"));
+}
+
 #[test]
 fn test_binary_builds() {
     // Test that the binary builds successfully
@@ -57,3 +86,22 @@ fn test_binary_builds() {
         String::from_utf8_lossy(&output.stderr)
     );
 }
+
+#[test]
+fn test_probe_fails_without_server() {
+    // Nothing listens on this port once the listener is dropped
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let output = Command::new("cargo")
+        .args(["run", "--", "--probe", "--server"])
+        .arg(format!("127.0.0.1:{}", port))
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("probe of"), "{}", stderr);
+}